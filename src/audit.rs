@@ -0,0 +1,162 @@
+use std::io::Write;
+
+/// Append-only record of branch rewrites, one JSON object per line
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+    path: std::path::PathBuf,
+    user: String,
+    command: Vec<String>,
+}
+
+impl AuditLog {
+    pub fn new(path: impl Into<std::path::PathBuf>, repo: &git2::Repository) -> Self {
+        let path = repo.path().join(path.into());
+        let user = match repo.signature() {
+            Ok(signature) => signature.to_string(),
+            Err(err) => {
+                log::debug!("Could not identify user: {}", err);
+                "unknown".to_owned()
+            }
+        };
+        let command = std::env::args().collect();
+        Self {
+            path,
+            user,
+            command,
+        }
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Record local branches that were created, moved, or deleted
+    pub fn record_updates(
+        &self,
+        before: &crate::git::Branches,
+        after: &crate::git::Branches,
+    ) -> eyre::Result<()> {
+        let entries = changes(before, after, |b| Some(b.id))
+            .into_iter()
+            .map(|(branch, old_id, new_id)| {
+                let operation = match (old_id, new_id) {
+                    (None, _) => Operation::Create,
+                    (_, None) => Operation::Delete,
+                    (Some(_), Some(_)) => Operation::Update,
+                };
+                self.entry(operation, branch, old_id, new_id)
+            })
+            .collect();
+        self.write(entries)
+    }
+
+    /// Record branches whose push-remote counterpart changed
+    pub fn record_pushes(
+        &self,
+        before: &crate::git::Branches,
+        after: &crate::git::Branches,
+    ) -> eyre::Result<()> {
+        let entries = changes(before, after, |b| b.push_id)
+            .into_iter()
+            .map(|(branch, old_id, new_id)| self.entry(Operation::Push, branch, old_id, new_id))
+            .collect();
+        self.write(entries)
+    }
+
+    fn entry(
+        &self,
+        operation: Operation,
+        branch: String,
+        old_id: Option<git2::Oid>,
+        new_id: Option<git2::Oid>,
+    ) -> Entry {
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Entry {
+            time,
+            user: self.user.clone(),
+            command: self.command.clone(),
+            operation,
+            branch,
+            old_id: old_id.map(|id| id.to_string()),
+            new_id: new_id.map(|id| id.to_string()),
+        }
+    }
+
+    fn write(&self, entries: Vec<Entry>) -> eyre::Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        log::trace!(
+            "Recording {} entries to {}",
+            entries.len(),
+            self.path.display()
+        );
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(&entry)?);
+            lines.push('\n');
+        }
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(lines.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Entry {
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    pub user: String,
+    pub command: Vec<String>,
+    pub operation: Operation,
+    pub branch: String,
+    #[serde(default)]
+    pub old_id: Option<String>,
+    #[serde(default)]
+    pub new_id: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    Create,
+    Update,
+    Delete,
+    Push,
+}
+
+fn changes(
+    before: &crate::git::Branches,
+    after: &crate::git::Branches,
+    id: impl Fn(&crate::git::Branch) -> Option<git2::Oid>,
+) -> Vec<(String, Option<git2::Oid>, Option<git2::Oid>)> {
+    let ids = |branches: &crate::git::Branches| {
+        branches
+            .iter()
+            .flat_map(|(_, branches)| branches.iter())
+            .map(|b| (b.name.clone(), id(b)))
+            .collect::<std::collections::BTreeMap<_, _>>()
+    };
+    let before = ids(before);
+    let after = ids(after);
+
+    let names: std::collections::BTreeSet<_> = before.keys().chain(after.keys()).collect();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let old_id = before.get(name).copied().flatten();
+            let new_id = after.get(name).copied().flatten();
+            (old_id != new_id).then(|| (name.clone(), old_id, new_id))
+        })
+        .collect()
+}
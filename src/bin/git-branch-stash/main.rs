@@ -66,6 +66,7 @@ fn push(args: args::PushArgs) -> proc_exit::ExitResult {
         log::warn!("Working tree is dirty, only capturing committed changes");
     }
 
+    repo.ensure_fingerprint();
     let mut snapshot =
         git_stack::stash::Snapshot::from_repo(&repo).with_code(proc_exit::Code::FAILURE)?;
     if let Some(message) = args.message.as_deref() {
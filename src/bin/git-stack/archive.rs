@@ -0,0 +1,98 @@
+use itertools::Itertools;
+
+/// Where `git stack archive` keeps the branches it deletes
+const ARCHIVE_PREFIX: &str = "refs/archive/";
+
+/// Move the unprotected branches not committed to within `max_age` under `refs/archive/`,
+/// returning whether any were
+pub(crate) fn archive_branches(
+    state: &mut crate::stack::State,
+    max_age: std::time::Duration,
+) -> eyre::Result<bool> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let cutoff = now.as_secs().saturating_sub(max_age.as_secs()) as i64;
+
+    let mut branches: Vec<_> = state
+        .branches
+        .iter()
+        .flat_map(|(_, b)| b.iter())
+        .filter(|branch| {
+            !state
+                .protected_branches
+                .get(branch.id)
+                .into_iter()
+                .flatten()
+                .any(|b| b.name == branch.name)
+        })
+        .cloned()
+        .collect();
+    branches.sort_by(|a, b| a.name.cmp(&b.name));
+    let (stale, fresh): (Vec<_>, Vec<_>) = branches
+        .into_iter()
+        .partition(|b| crate::stack::committed_before(&state.repo, b.id, cutoff));
+
+    let head_branch = state.repo.head_branch();
+    let mut archived = Vec::new();
+    for branch in stale {
+        if head_branch.as_ref().map(|b| b.name.as_str()) == Some(branch.name.as_str()) {
+            log::info!("Keeping `{}`, it is checked out", branch.name);
+            continue;
+        }
+        if state.frozen_branches.contains(&branch.name) {
+            log::info!("Keeping `{}`, it is frozen", branch.name);
+            continue;
+        }
+        if let Some(above) = fresh
+            .iter()
+            .find(|b| state.repo.merge_base(branch.id, b.id) == Some(branch.id))
+        {
+            log::info!(
+                "Keeping `{}`, `{}` is stacked on it",
+                branch.name,
+                above.name
+            );
+            continue;
+        }
+        archived.push(branch);
+    }
+    if archived.is_empty() {
+        log::info!(
+            "No branches older than {} to archive",
+            crate::stack::format_age(max_age)
+        );
+        return Ok(false);
+    }
+
+    if !state.dry_run {
+        let names = archived.iter().map(|b| b.name.as_str()).join(" ");
+        crate::stack::backup_with(state, |snapshot| {
+            snapshot.insert_message(&format!("archive {}", names));
+        })?;
+    }
+    for branch in archived.iter() {
+        let archive_ref = format!("{}{}", ARCHIVE_PREFIX, branch.name);
+        log::info!("Archiving `{}` to `{}`", branch.name, archive_ref);
+        if state.dry_run {
+            continue;
+        }
+        if !state.repo.is_sandboxed() {
+            state
+                .repo
+                .raw()
+                .reference(&archive_ref, branch.id, true, "archive")?;
+        }
+        state.repo.delete_branch(&branch.name)?;
+    }
+    if state.dry_run {
+        return Ok(false);
+    }
+    log::info!(
+        "To bring one back, run `git branch <name> {}<name>`",
+        ARCHIVE_PREFIX
+    );
+    state.update()?;
+
+    Ok(true)
+}
@@ -7,6 +7,9 @@
     )]
 #[structopt(group = structopt::clap::ArgGroup::with_name("mode").multiple(false))]
 pub struct Args {
+    #[structopt(subcommand)]
+    pub subcommand: Option<Subcommand>,
+
     /// Rebase the selected stacks
     #[structopt(short, long, group = "mode")]
     pub rebase: bool,
@@ -15,6 +18,10 @@ pub struct Args {
     #[structopt(long)]
     pub pull: bool,
 
+    /// Rebase within `refs/git-stack/sandbox/`, leaving your branches untouched
+    #[structopt(long, conflicts_with_all(&["pull", "push"]))]
+    pub sandbox: bool,
+
     /// Push all ready branches
     #[structopt(long)]
     pub push: bool,
@@ -28,14 +35,54 @@ pub struct Args {
     )]
     pub stack: Option<git_stack::config::Stack>,
 
-    /// Branch to evaluate from (default: most-recent protected branch)
+    /// Include every branch in `--stack all`, ignoring `stack.all-max-age`
+    #[structopt(long)]
+    pub everything: bool,
+
+    /// Branch, or commit like a release tag, to evaluate from (default: most-recent protected
+    /// branch)
     #[structopt(long)]
     pub base: Option<String>,
 
-    /// Branch to rebase onto (default: base)
+    /// Move the current stack onto this branch (default: base)
     #[structopt(long)]
     pub onto: Option<String>,
 
+    /// Edit the rebase plan before it is applied
+    #[structopt(short, long)]
+    pub interactive: bool,
+
+    /// Resume a rebase stopped by `break`, `edit`, or a conflict
+    #[structopt(long = "continue", group = "mode", conflicts_with_all(&["pull", "interactive"]))]
+    pub resume: bool,
+
+    /// Give up on a stopped rebase, putting the branches back where they were
+    #[structopt(long, group = "mode", conflicts_with_all(&["pull", "interactive", "step"]))]
+    pub abort: bool,
+
+    /// Apply one cherry-pick, squash, or branch update per run
+    #[structopt(long, conflicts_with_all(&["pull", "resume"]))]
+    pub step: bool,
+
+    /// Create the rebased commits but leave branches untouched until `--commit-plan`
+    #[structopt(
+        long,
+        conflicts_with_all(&["pull", "push", "sandbox", "step", "resume"])
+    )]
+    pub stage_only: bool,
+
+    /// Point branches at the commits from `--stage-only`
+    #[structopt(long, group = "mode")]
+    pub commit_plan: bool,
+
+    /// Only move branches matching these globs (`.gitignore` syntax), leaving the rest in place
+    #[structopt(long = "branch", number_of_values = 1)]
+    pub branches: Vec<String>,
+
+    /// Leave out branches matching these globs (`.gitignore` syntax), as if they didn't exist
+    #[structopt(long, number_of_values = 1)]
+    pub exclude: Vec<String>,
+
     /// Action to perform with fixup-commits
     #[structopt(
         long,
@@ -44,9 +91,46 @@ pub struct Args {
     )]
     pub fixup: Option<git_stack::config::Fixup>,
 
+    /// Squash `fixup!` and `squash!` commits into their targets (short for `--fixup squash`)
+    #[structopt(long, conflicts_with = "fixup")]
+    pub fix: bool,
+
+    /// Recreate merge commits within the stack, rather than flattening them
+    #[structopt(long)]
+    pub rebase_merges: bool,
+
+    /// Date rewritten commits' authorship to now, like `git rebase --ignore-date`
+    #[structopt(long, alias = "ignore-date")]
+    pub reset_author_date: bool,
+
+    /// Run a shell command on each restacked branch, failing the branch and its dependents if it fails
+    #[structopt(long, value_name = "CMD")]
+    pub exec: Option<String>,
+
+    /// What to do when a cherry-pick conflicts
+    #[structopt(
+        long,
+        possible_values(&git_stack::config::OnConflict::variants()),
+        case_insensitive(true),
+    )]
+    pub on_conflict: Option<git_stack::config::OnConflict>,
+
+    /// After rebasing, check every branch still makes the same changes against its base
+    #[structopt(long)]
+    pub verify: bool,
+
     #[structopt(short = "n", long)]
     pub dry_run: bool,
 
+    /// Abort unless the rebase plan matches the id reported by a prior `--dry-run`, or at least
+    /// its first 7 digits
+    #[structopt(long, value_name = "ID")]
+    pub expect_plan: Option<String>,
+
+    /// Apply changes `stack.risk-gate` would ask about or refuse
+    #[structopt(long)]
+    pub force: bool,
+
     #[structopt(
         long,
         possible_values(&git_stack::config::Format::variants()),
@@ -54,6 +138,14 @@ pub struct Args {
     )]
     pub format: Option<git_stack::config::Format>,
 
+    /// Show commit summaries in full, rather than cutting them off at the terminal's width
+    #[structopt(long)]
+    pub no_truncate: bool,
+
+    /// Don't contact the forge or remotes, showing the PRs last looked up
+    #[structopt(long, alias = "no-network")]
+    pub offline: bool,
+
     /// See what branches are protected
     #[structopt(long, group = "mode")]
     pub protected: bool,
@@ -73,16 +165,511 @@ pub struct Args {
     pub verbose: clap_verbosity_flag::Verbosity,
 }
 
+#[derive(structopt::StructOpt)]
+pub enum Subcommand {
+    /// Switch to the next branch in the stack
+    Next,
+    /// Switch to the previous branch in the stack
+    Prev,
+    /// Write the rebase plan to a file, to `apply` later
+    Plan(PlanArgs),
+    /// Apply a plan written by `plan`, if the branches haven't moved since
+    Apply(ApplyArgs),
+    /// Edit a commit's message, restacking everything on top of it
+    Reword(RewordArgs),
+    /// Turn staged changes into `fixup!` commits for the commits that last touched them
+    Absorb(AbsorbArgs),
+    /// Move a branch, and everything on top of it, onto another branch
+    Move(MoveArgs),
+    /// Split a commit in two, restacking everything on top of it
+    Split(SplitArgs),
+    /// Remove a commit, restacking everything on top of it onto its parent
+    Drop(DropArgs),
+    /// Squash a range of commits into one, restacking everything on top of them
+    Squash(SquashArgs),
+    /// Reorder a branch's commits in `$EDITOR`, restacking everything on top of them
+    Reorder(ReorderArgs),
+    /// Pull the protected branches, rebase every stack, drop merged branches, and push
+    Sync,
+    /// Push every branch in the stack, opening or retargeting a GitHub PR onto the branch beneath it
+    Submit(SubmitArgs),
+    /// Bundle the stack's branches and their commits into a file, for review without a forge
+    ExportBundle(ExportBundleArgs),
+    /// Fetch the branches from a bundle made by `export-bundle`, under `stack.import-namespace`
+    ImportBundle(ImportBundleArgs),
+    /// Write the stack as email patch series with cover letters, bottom-up, optionally mailing them
+    FormatPatch(FormatPatchArgs),
+    /// Delete branches whose commits have all landed in the protected branch they are based on
+    DeleteMerged,
+    /// Delete branches no one has committed to in a while, keeping them under `refs/archive/`
+    Archive(ArchiveArgs),
+    /// Rename a branch, keeping what it tracks
+    Rename(RenameArgs),
+    /// Create a branch stacked on top of `HEAD` and switch to it
+    Create(CreateArgs),
+    /// Show or record the branch a branch is stacked on top of, rather than guessing from the history
+    Parent(ParentArgs),
+    /// Restack branches left on an old version of a branch that was amended or rebased outside of `git stack`
+    Repair,
+    /// Edit what a branch is for, shown in the tree and used for PRs, like `git branch --edit-description`
+    Describe(DescribeArgs),
+    /// Keep branches in place, never rebasing, pushing, or deleting them until unfrozen
+    Freeze(FreezeArgs),
+    /// Let frozen branches be rebased, pushed, and deleted again
+    Unfreeze(FreezeArgs),
+    /// List commits no branch reaches anymore, like after an interrupted rewrite, or put a branch on them
+    Adopt(AdoptArgs),
+    /// Show the stacks, with `--porcelain` for scripts
+    Status(StatusArgs),
+    /// Rewrite the commits in the stack to meet project requirements
+    Fix(FixArgs),
+    /// Merge the bottom branch of the stack into its protected branch, restacking the rest on top
+    Land(LandArgs),
+    /// Run a shell command on every branch in the stack, bottom-up, reporting which fail
+    Run(RunArgs),
+    /// Check for configuration and repository problems, suggesting fixes
+    Doctor,
+    /// Restore the branches from before the last `git stack` command that changed them
+    Undo(UndoArgs),
+    /// Restore the branches from before the last `undo`
+    Redo(UndoArgs),
+    /// Manage the backups taken before each change to the branches
+    Backup(BackupCommand),
+    /// List, restore, or purge the branches `git stack` deleted, kept for `stack.quarantine-age`
+    Quarantine(QuarantineCommand),
+    /// Write a completion script for a shell to stdout
+    Completions(CompletionsArgs),
+    /// List completion candidates for shells
+    // clap's bash completions can't handle `__` in a subcommand's name, including aliases
+    #[structopt(
+        name = "complete-candidates",
+        setting = structopt::clap::AppSettings::Hidden
+    )]
+    Complete(CompleteArgs),
+    /// Write man pages for `git-stack` and its subcommands, for packagers
+    #[structopt(setting = structopt::clap::AppSettings::Hidden)]
+    Man(ManArgs),
+    /// Restack and undo randomly generated stacks, checking nothing is lost, to find and report bugs
+    #[structopt(setting = structopt::clap::AppSettings::Hidden)]
+    Simulate(SimulateArgs),
+}
+
+#[derive(structopt::StructOpt)]
+pub struct PlanArgs {
+    /// Where to write the plan
+    #[structopt(short, long, parse(from_os_str), default_value = "-")]
+    pub out: std::path::PathBuf,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct AbsorbArgs {
+    /// Squash the fixups into their targets, restacking everything on top of them
+    #[structopt(long)]
+    pub squash: bool,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct MoveArgs {
+    /// Branch to move
+    #[structopt(long)]
+    pub branch: String,
+
+    /// Branch to move it onto
+    #[structopt(long)]
+    pub onto: String,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct SplitArgs {
+    /// Commit to split
+    #[structopt(default_value = "HEAD")]
+    pub rev: String,
+
+    /// Move the changes to these paths into a commit of their own, rather than stopping to split
+    /// it by hand
+    #[structopt(last = true, parse(from_os_str))]
+    pub paths: Vec<std::path::PathBuf>,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct DropArgs {
+    /// Commit to drop
+    #[structopt(default_value = "HEAD")]
+    pub rev: String,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct SquashArgs {
+    /// Commits to squash, as `<from>..<to>`
+    #[structopt(required_unless = "branch", conflicts_with = "branch")]
+    pub range: Option<String>,
+
+    /// Squash all of this branch's commits
+    #[structopt(long)]
+    pub branch: Option<String>,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct ReorderArgs {
+    /// Branch whose commits to reorder, instead of the current branch
+    #[structopt(long)]
+    pub branch: Option<String>,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct SubmitArgs {
+    /// Open new PRs as drafts
+    #[structopt(long)]
+    pub draft: bool,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct ExportBundleArgs {
+    /// Where to write the bundle
+    #[structopt(parse(from_os_str))]
+    pub out: std::path::PathBuf,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct ImportBundleArgs {
+    /// Bundle to import
+    #[structopt(parse(from_os_str))]
+    pub bundle: std::path::PathBuf,
+
+    /// Put the branches under this, rather than `stack.import-namespace`
+    #[structopt(long)]
+    pub namespace: Option<String>,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct FormatPatchArgs {
+    /// Directory to write a subdirectory per series to
+    #[structopt(short, long, parse(from_os_str), default_value = "patches")]
+    pub out: std::path::PathBuf,
+
+    /// Write a series per branch, based on the branch beneath it, rather than one per stack
+    #[structopt(long)]
+    pub per_branch: bool,
+
+    /// Mail each series with `git send-email`, so the next one is sent as a reroll replying to it
+    #[structopt(long)]
+    pub send: bool,
+
+    /// Recipient for `--send`
+    #[structopt(long, number_of_values = 1, requires = "send")]
+    pub to: Vec<String>,
+
+    /// Carbon copy recipient for `--send`
+    #[structopt(long, number_of_values = 1, requires = "send")]
+    pub cc: Vec<String>,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct CreateArgs {
+    /// Name of the branch, filled into `stack.branch-template`
+    pub name: String,
+
+    /// Commit to create the branch at
+    #[structopt(long, default_value = "HEAD")]
+    pub at: String,
+
+    /// Ticket for `stack.branch-template`'s `{ticket}`
+    #[structopt(long)]
+    pub ticket: Option<String>,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct ArchiveArgs {
+    /// Archive branches with no commits this recent, like `90d` (default: `stack.all-max-age`)
+    #[structopt(long)]
+    pub older_than: Option<String>,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct ParentArgs {
+    /// Branch whose parent to show or record (default: the current branch)
+    #[structopt(long)]
+    pub branch: Option<String>,
+
+    /// Record this branch as the parent, to restack onto even after it is rewritten
+    #[structopt(long, conflicts_with = "unset")]
+    pub set: Option<String>,
+
+    /// Forget the recorded parent, going back to guessing from the history
+    #[structopt(long)]
+    pub unset: bool,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct DescribeArgs {
+    /// Branch to describe (default: the current branch)
+    pub branch: Option<String>,
+
+    /// Use this description, rather than editing it
+    #[structopt(short, long, conflicts_with_all(&["unset", "show"]))]
+    pub message: Option<String>,
+
+    /// Forget the description
+    #[structopt(long, conflicts_with = "show")]
+    pub unset: bool,
+
+    /// Print the description, rather than editing it
+    #[structopt(long)]
+    pub show: bool,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct FreezeArgs {
+    /// Branches to freeze or unfreeze (default: the current branch)
+    pub branches: Vec<String>,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct AdoptArgs {
+    /// Orphaned commit to adopt, like `HEAD@{3}` as listed (default: list them)
+    pub rev: Option<String>,
+
+    /// Branch to create, or fast-forward, at the commit (default: the branch it is on top of)
+    #[structopt(long)]
+    pub branch: Option<String>,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct RenameArgs {
+    /// Branch to rename
+    pub old: String,
+
+    /// New name for the branch
+    pub new: String,
+
+    /// Also rename the branch on the push remote, the next time it is pushed
+    #[structopt(long)]
+    pub remote: bool,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct StatusArgs {
+    /// One line per branch in a stable format, see `docs/reference.md`
+    #[structopt(long)]
+    pub porcelain: bool,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct FixArgs {
+    /// Add a `Signed-off-by` trailer for `user.name` and `user.email` to every commit missing one
+    #[structopt(long)]
+    pub sign_off: bool,
+
+    /// Set the author and committer of every commit to `user.name` and `user.email`
+    #[structopt(long)]
+    pub reset_author: bool,
+
+    /// With `--reset-author`, also set the author date to now
+    #[structopt(long, requires = "reset-author")]
+    pub reset_date: bool,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct LandArgs {
+    /// Branch to land, defaulting to the bottom of the current stack
+    pub branch: Option<String>,
+
+    /// Merge the branch's PR on GitHub, or MR on GitLab, and pull it down, rather than merging
+    /// locally
+    #[structopt(long)]
+    pub forge: bool,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct RunArgs {
+    /// Shell command to run with each branch checked out, e.g. `cargo test`
+    pub command: String,
+
+    /// Stop at the first branch the command fails on
+    #[structopt(long)]
+    pub fail_fast: bool,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct UndoArgs {
+    /// Restore the branches without asking
+    #[structopt(short, long)]
+    pub yes: bool,
+}
+
+#[derive(structopt::StructOpt)]
+pub enum BackupCommand {
+    /// List the snapshots, oldest first
+    List(BackupStackArgs),
+    /// Show the branches in a snapshot
+    Show(BackupSnapshotArgs),
+    /// Restore the branches from the latest snapshot, deleting it
+    Pop(BackupStackArgs),
+    /// Restore the branches from a snapshot, keeping it
+    Apply(BackupSnapshotArgs),
+    /// Delete snapshots over `branch-stash.capacity` or referring to commits that no longer exist
+    Prune(BackupStackArgs),
+}
+
+#[derive(structopt::StructOpt)]
+pub enum QuarantineCommand {
+    /// List the deleted branches, oldest first
+    List,
+    /// Bring back a deleted branch, the most recently deleted one by that name
+    Restore(QuarantineRestoreArgs),
+    /// Finally remove the branches kept longer than `stack.quarantine-age`
+    Purge(QuarantinePurgeArgs),
+}
+
+#[derive(structopt::StructOpt)]
+pub struct QuarantineRestoreArgs {
+    /// Deleted branch to restore
+    pub branch: String,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct QuarantinePurgeArgs {
+    /// Remove every deleted branch, however recent
+    #[structopt(long)]
+    pub all: bool,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct BackupStackArgs {
+    /// Backup stack to use, like `git-stack-redo` for what `redo` restores
+    #[structopt(long, default_value = crate::stack::STASH_STACK_NAME)]
+    pub stack: String,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct BackupSnapshotArgs {
+    /// Snapshot, as numbered by `list` (default: the latest)
+    pub snapshot: Option<usize>,
+
+    #[structopt(flatten)]
+    pub stack: BackupStackArgs,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct RewordArgs {
+    /// Commit to reword (default: HEAD)
+    #[structopt(conflicts_with = "all")]
+    pub rev: Option<String>,
+
+    /// Edit the message with a `s/pattern/replacement/[gi]` regex rather than an editor
+    #[structopt(long, value_name = "EXPR")]
+    pub replace: Option<git_stack::git::Substitution>,
+
+    /// Apply `--replace` to every commit in the stacks
+    #[structopt(long, requires = "replace")]
+    pub all: bool,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct CompletionsArgs {
+    /// Shell to write the script for
+    #[structopt(
+        possible_values(&structopt::clap::Shell::variants()),
+        case_insensitive(true),
+    )]
+    pub shell: structopt::clap::Shell,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct ManArgs {
+    /// Directory to write the `.1` pages to
+    #[structopt(parse(from_os_str))]
+    pub dir: std::path::PathBuf,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct SimulateArgs {
+    /// Seed for the first run, each run after it adding one (default: the current time)
+    #[structopt(long)]
+    pub seed: Option<u64>,
+
+    /// How many histories to generate and check
+    #[structopt(long, default_value = "10")]
+    pub runs: u64,
+
+    /// Keep the repositories that passed, under the temp directory
+    #[structopt(long)]
+    pub keep: bool,
+}
+
+#[derive(structopt::StructOpt)]
+pub struct CompleteArgs {
+    /// What to complete
+    #[structopt(
+        possible_values(&Completion::variants()),
+        case_insensitive(true),
+    )]
+    pub kind: Completion,
+
+    /// Only list candidates starting with this
+    #[structopt(default_value = "")]
+    pub prefix: String,
+}
+
+arg_enum! {
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum Completion {
+        Branch,
+        Stack,
+        Backup,
+    }
+}
+
+#[derive(structopt::StructOpt)]
+pub struct ApplyArgs {
+    /// Plan written by `plan`
+    #[structopt(parse(from_os_str))]
+    pub plan: std::path::PathBuf,
+}
+
 impl Args {
     pub fn to_config(&self) -> git_stack::config::RepoConfig {
         git_stack::config::RepoConfig {
             protected_branches: None,
+            protected_tags: None,
             stack: self.stack,
+            all_max_age: None,
             push_remote: None,
             pull_remote: None,
+            push_policy: None,
+            push_wip: None,
             show_format: self.format,
             show_stacked: None,
-            fixup: self.fixup,
+            show_glyphs: None,
+            show_full_hashes: None,
+            show_prs: None,
+            show_prefetch: None,
+            fixup: if self.fix {
+                Some(git_stack::config::Fixup::Squash)
+            } else {
+                self.fixup
+            },
+            rebase_merges: if self.rebase_merges { Some(true) } else { None },
+            dedup_cherry_picks: None,
+            drop_empty: None,
+            exec: self.exec.clone(),
+            on_conflict: self.on_conflict,
+            risk_gate: None,
+            quarantine_age: None,
+            pr_cache_age: None,
+            committer_date: None,
+            audit_log: None,
+            maintenance: None,
+            maintenance_threshold: None,
+            max_history: None,
+            dirty_check: None,
+            theme: None,
+            colors: None,
+            branch_template: None,
+            import_namespace: None,
+            allowed_emails: None,
+            gitlab_hosts: None,
+            crash_report: None,
 
             capacity: None,
         }
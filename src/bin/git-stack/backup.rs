@@ -0,0 +1,216 @@
+use std::io::Write;
+
+use bstr::ByteSlice;
+use proc_exit::WithCodeResultExt;
+
+use crate::args::BackupCommand;
+
+pub fn backup(args: &crate::args::Args, command: &BackupCommand) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo_config = git_stack::config::RepoConfig::from_all(&repo)
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    let mut repo = git_stack::git::GitRepo::new(repo);
+    repo.set_dirty_check(repo_config.dirty_check());
+
+    match command {
+        BackupCommand::List(stack_args) => list(&repo, &stack_args.stack),
+        BackupCommand::Show(snapshot_args) => {
+            show(&repo, &snapshot_args.stack.stack, snapshot_args.snapshot)
+        }
+        BackupCommand::Pop(stack_args) => restore(args, &mut repo, &stack_args.stack, None, true),
+        BackupCommand::Apply(snapshot_args) => restore(
+            args,
+            &mut repo,
+            &snapshot_args.stack.stack,
+            snapshot_args.snapshot,
+            false,
+        ),
+        BackupCommand::Prune(stack_args) => prune(args, &repo, &repo_config, &stack_args.stack),
+    }
+}
+
+fn list(repo: &git_stack::git::GitRepo, name: &str) -> proc_exit::ExitResult {
+    let backups = git_stack::stash::Stack::new(name, repo);
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let mut listed = false;
+    for path in backups.iter() {
+        let snapshot = match git_stack::stash::Snapshot::load(&path) {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                log::error!("Failed to load snapshot {}: {}", path.display(), err);
+                continue;
+            }
+        };
+        let description = snapshot
+            .metadata
+            .get("message")
+            .and_then(|m| m.as_str())
+            .map(ToOwned::to_owned)
+            .unwrap_or_else(|| format!("{} branch(es)", snapshot.branches.len()));
+        let status = match crate::undo::changes(repo, &snapshot) {
+            Ok(changes) if changes.is_empty() => "matches the branches".to_owned(),
+            Ok(changes) => format!("moves {} branch(es)", changes.len()),
+            Err(_) => "unrestorable".to_owned(),
+        };
+        writeln!(
+            stdout,
+            "{}: {}  ({})",
+            snapshot_index(&path).unwrap_or_default(),
+            description,
+            status
+        )?;
+        listed = true;
+    }
+    if !listed {
+        log::info!("No backups in `{}`", name);
+    }
+
+    Ok(())
+}
+
+fn show(repo: &git_stack::git::GitRepo, name: &str, index: Option<usize>) -> proc_exit::ExitResult {
+    let backups = git_stack::stash::Stack::new(name, repo);
+    let path = find_snapshot(&backups, index)?;
+    let snapshot = git_stack::stash::Snapshot::load(&path).with_code(proc_exit::Code::FAILURE)?;
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    if let Some(message) = snapshot.metadata.get("message").and_then(|m| m.as_str()) {
+        writeln!(stdout, "Message: {}", message)?;
+    }
+    for branch in snapshot.branches.iter() {
+        let name = match branch.metadata.get("parent").and_then(|p| p.as_str()) {
+            Some(parent) => format!("{}..{}", parent, branch.name),
+            None => branch.name.clone(),
+        };
+        let summary = match repo.find_commit(branch.id) {
+            Some(commit) => commit.summary.to_str_lossy().into_owned(),
+            None => "(no longer exists)".to_owned(),
+        };
+        writeln!(
+            stdout,
+            "  {}: {}  # {}",
+            name,
+            repo.abbrev_id(branch.id),
+            summary
+        )?;
+    }
+
+    Ok(())
+}
+
+fn restore(
+    args: &crate::args::Args,
+    repo: &mut git_stack::git::GitRepo,
+    name: &str,
+    index: Option<usize>,
+    delete: bool,
+) -> proc_exit::ExitResult {
+    if repo.is_dirty() {
+        return Err(proc_exit::Code::USAGE_ERR.with_message("Working tree is dirty, aborting"));
+    }
+
+    let backups = git_stack::stash::Stack::new(name, repo);
+    let path = find_snapshot(&backups, index)?;
+    let snapshot = git_stack::stash::Snapshot::load(&path).with_code(proc_exit::Code::FAILURE)?;
+    let changes = crate::undo::changes(repo, &snapshot).with_code(proc_exit::Code::FAILURE)?;
+    {
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        for change in changes.iter() {
+            writeln!(stdout, "{}", change)?;
+        }
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+
+    if changes.is_empty() {
+        log::info!("Branches already match the snapshot");
+    } else {
+        let current =
+            git_stack::stash::Snapshot::from_repo(repo).with_code(proc_exit::Code::FAILURE)?;
+        crate::undo::restore(repo, &snapshot, &current).with_code(proc_exit::Code::FAILURE)?;
+        log::info!("Restored {} branch(es)", changes.len());
+    }
+    if delete {
+        let _ = std::fs::remove_file(&path);
+    }
+
+    Ok(())
+}
+
+fn prune(
+    args: &crate::args::Args,
+    repo: &git_stack::git::GitRepo,
+    repo_config: &git_stack::config::RepoConfig,
+    name: &str,
+) -> proc_exit::ExitResult {
+    let backups = git_stack::stash::Stack::new(name, repo);
+    let paths: Vec<_> = backups.iter().collect();
+    let over_capacity = repo_config
+        .capacity()
+        .map(|capacity| paths.len().saturating_sub(capacity))
+        .unwrap_or(0);
+
+    let mut pruned = 0;
+    for (i, path) in paths.iter().enumerate() {
+        let reason = if i < over_capacity {
+            "it is over `branch-stash.capacity`"
+        } else {
+            match git_stack::stash::Snapshot::load(path) {
+                Ok(snapshot)
+                    if snapshot
+                        .branches
+                        .iter()
+                        .any(|b| repo.raw().find_commit(b.id).is_err()) =>
+                {
+                    "it refers to commits that no longer exist"
+                }
+                Ok(_) => continue,
+                Err(_) => "it can't be read",
+            }
+        };
+        let index = snapshot_index(path).unwrap_or_default();
+        if args.dry_run {
+            log::info!("Would delete snapshot {}, {}", index, reason);
+        } else {
+            log::info!("Deleting snapshot {}, {}", index, reason);
+            std::fs::remove_file(path).with_code(proc_exit::Code::FAILURE)?;
+        }
+        pruned += 1;
+    }
+    if pruned == 0 {
+        log::info!("Nothing to prune in `{}`", name);
+    }
+
+    Ok(())
+}
+
+/// Snapshots are numbered by their file name, which `list` shows
+fn snapshot_index(path: &std::path::Path) -> Option<usize> {
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+fn find_snapshot(
+    backups: &git_stack::stash::Stack,
+    index: Option<usize>,
+) -> Result<std::path::PathBuf, proc_exit::Exit> {
+    let path = match index {
+        Some(index) => backups
+            .iter()
+            .find(|path| snapshot_index(path) == Some(index)),
+        None => backups.iter().last(),
+    };
+    path.ok_or_else(|| {
+        let message = match index {
+            Some(index) => format!("No snapshot {} in `{}`", index, backups.name),
+            None => format!("No backups in `{}`", backups.name),
+        };
+        proc_exit::Code::USAGE_ERR.with_message(message)
+    })
+}
@@ -0,0 +1,190 @@
+use std::io::Write;
+
+use proc_exit::WithCodeResultExt;
+use structopt::StructOpt;
+
+use crate::args::Completion;
+
+/// List the candidates for `kind` starting with `prefix`, one per line, for shell completions
+pub fn complete(kind: Completion, prefix: &str) -> proc_exit::ExitResult {
+    let mut candidates = match kind {
+        Completion::Stack => git_stack::config::Stack::variants()
+            .iter()
+            .map(|v| v.to_lowercase())
+            .collect(),
+        Completion::Branch => {
+            let repo = match open() {
+                Some(repo) => repo,
+                None => return Ok(()),
+            };
+            repo.local_branches().map(|b| b.name).collect()
+        }
+        Completion::Backup => {
+            let repo = match open() {
+                Some(repo) => repo,
+                None => return Ok(()),
+            };
+            git_stack::stash::Stack::all(&repo)
+                .map(|s| s.name)
+                .collect::<Vec<_>>()
+        }
+    };
+    candidates.retain(|c| c.starts_with(prefix));
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    for candidate in candidates {
+        writeln!(stdout, "{}", candidate).with_code(proc_exit::Code::FAILURE)?;
+    }
+    Ok(())
+}
+
+/// Write the completion script for `shell`, hooking in `complete` for branch names
+pub fn completions(shell: structopt::clap::Shell) -> proc_exit::ExitResult {
+    let mut script = Vec::new();
+    crate::args::Args::clap().gen_completions_to(BIN_NAME, shell, &mut script);
+    let script = String::from_utf8(script).with_code(proc_exit::Code::FAILURE)?;
+    let script = match shell {
+        structopt::clap::Shell::Bash => dynamic_bash(&script),
+        structopt::clap::Shell::Zsh => dynamic_zsh(&script),
+        structopt::clap::Shell::Fish => dynamic_fish(&script),
+        // No hook for these yet, they only get the static options and subcommands
+        _ => script,
+    };
+
+    std::io::stdout()
+        .write_all(script.as_bytes())
+        .with_code(proc_exit::Code::FAILURE)?;
+    Ok(())
+}
+
+const BIN_NAME: &str = "git-stack";
+
+/// Options that take a branch name
+const BRANCH_OPTIONS: &[&str] = &["base", "onto", "branch", "set"];
+
+/// `--stack` is a backup stack for `backup` but a fixed list of values everywhere else
+const BACKUP_OPTION: &str = "stack";
+
+fn dynamic_bash(script: &str) -> String {
+    let mut dynamic = String::with_capacity(script.len());
+    let mut option = None;
+    let mut in_commands = false;
+    for line in script.lines() {
+        let trimmed = line.trim();
+        if trimmed == r#"case "${cmd}" in"# {
+            in_commands = true;
+        }
+        if let Some(name) = trimmed
+            .strip_prefix("--")
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            option = Some(name.to_owned());
+        }
+        let kind = match option.as_deref() {
+            Some(name) if BRANCH_OPTIONS.contains(&name) => Some("branch"),
+            Some(BACKUP_OPTION) => Some("backup"),
+            _ => None,
+        };
+        match kind {
+            // clap spells subcommand paths with `__` for `-` but not the binary's own name, so
+            // no subcommand of `git-stack` would ever match
+            _ if trimmed == r#"cmd="git-stack""# || in_commands && trimmed == "git-stack)" => {
+                dynamic.push_str(&line.replace("git-stack", "git__stack"));
+            }
+            Some(kind) if trimmed == r#"COMPREPLY=($(compgen -f "${cur}"))"# => {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                dynamic.push_str(&format!(
+                    r#"{}COMPREPLY=($({} complete-candidates {} "${{cur}}" 2>/dev/null))"#,
+                    indent, BIN_NAME, kind
+                ));
+            }
+            _ => dynamic.push_str(line),
+        }
+        dynamic.push('\n');
+    }
+    // `git stack` completes through git's own script, which calls `_git_stack`
+    dynamic.push_str(
+        r#"
+_git_stack() {
+    local COMP_WORDS=(git-stack "${COMP_WORDS[@]:2}")
+    local COMP_CWORD=$((COMP_CWORD - 1))
+    _git-stack
+}
+"#,
+    );
+    dynamic
+}
+
+fn dynamic_zsh(script: &str) -> String {
+    let mut dynamic = String::with_capacity(script.len());
+    for line in script.lines() {
+        let kind = line
+            .strip_prefix("'--")
+            .and_then(|rest| rest.split('=').next())
+            .and_then(|name| {
+                if BRANCH_OPTIONS.contains(&name) {
+                    Some("branches")
+                } else if name == BACKUP_OPTION {
+                    Some("backups")
+                } else {
+                    None
+                }
+            });
+        match kind {
+            // Options with fixed values already end in `: :(...)'`
+            Some(kind) if line.ends_with("]' \\") => {
+                dynamic.push_str(&line[..line.len() - "' \\".len()]);
+                dynamic.push_str(&format!(": :_git_stack_{}' \\", kind));
+            }
+            _ if line == r#"_git-stack "$@""# => {
+                dynamic.push_str(&format!(
+                    r#"_git_stack_branches() {{
+    local -a candidates
+    candidates=(${{(f)"$({bin} complete-candidates branch 2>/dev/null)"}})
+    _describe -t branches 'branch' candidates
+}}
+
+_git_stack_backups() {{
+    local -a candidates
+    candidates=(${{(f)"$({bin} complete-candidates backup 2>/dev/null)"}})
+    _describe -t backups 'backup stack' candidates
+}}
+
+"#,
+                    bin = BIN_NAME
+                ));
+                dynamic.push_str(line);
+            }
+            _ => dynamic.push_str(line),
+        }
+        dynamic.push('\n');
+    }
+    dynamic
+}
+
+fn dynamic_fish(script: &str) -> String {
+    let mut dynamic = script.to_owned();
+    for option in BRANCH_OPTIONS {
+        dynamic.push_str(&format!(
+            "complete -c {bin} -l {option} -x -a \"({bin} complete-candidates branch (commandline -ct))\"\n",
+            bin = BIN_NAME,
+            option = option
+        ));
+    }
+    dynamic.push_str(&format!(
+        "complete -c {bin} -n \"__fish_seen_subcommand_from backup\" -l {option} -x -a \"({bin} complete-candidates backup (commandline -ct))\"\n",
+        bin = BIN_NAME,
+        option = BACKUP_OPTION
+    ));
+    dynamic
+}
+
+/// Completions are best-effort, so outside of a repo there is simply nothing to offer
+fn open() -> Option<git_stack::git::GitRepo> {
+    let cwd = std::env::current_dir().ok()?;
+    let repo = git2::Repository::discover(&cwd).ok()?;
+    Some(git_stack::git::GitRepo::new(repo))
+}
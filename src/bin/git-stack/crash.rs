@@ -0,0 +1,299 @@
+use std::io::Write;
+
+use itertools::Itertools;
+
+/// Config values that may hold commands, credentials, or who the user is
+const REDACTED_KEYS: &[&str] = &["exec", "audit-log", "allowed-email"];
+
+/// Most commits to describe in the graph's shape
+const MAX_COMMITS: usize = 1000;
+
+/// With `stack.crash-report`, write a diagnostic bundle when panicking, before `human_panic`'s
+/// report
+///
+/// Returns the log to keep for the bundle.
+pub fn install(args: &crate::args::Args) -> Option<git_stack::log::RecentLog> {
+    let cwd = std::env::current_dir().ok()?;
+    let repo = git2::Repository::discover(&cwd).ok()?;
+    if !git_stack::config::RepoConfig::crash_report_enabled(&repo) {
+        return None;
+    }
+    let repo_config = git_stack::config::RepoConfig::from_all(&repo)
+        .ok()?
+        .update(args.to_config());
+
+    let recent = git_stack::log::RecentLog::new();
+    let report = Report {
+        git_dir: repo.path().to_owned(),
+        protected_branches: repo_config.protected_branches().to_vec(),
+        config: redact(&repo_config.to_string()),
+        recent: recent.clone(),
+    };
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let written = report.write(info);
+        previous(info);
+        match written {
+            Ok(path) => {
+                let _ = writeln!(
+                    std::io::stderr(),
+                    "A diagnostic bundle was written to {}\n\
+                     Please look it over before attaching it to the report.",
+                    path.display()
+                );
+            }
+            Err(err) => {
+                let _ = writeln!(
+                    std::io::stderr(),
+                    "Could not write a diagnostic bundle: {}",
+                    err
+                );
+            }
+        }
+    }));
+    Some(recent)
+}
+
+struct Report {
+    git_dir: std::path::PathBuf,
+    protected_branches: Vec<String>,
+    config: String,
+    recent: git_stack::log::RecentLog,
+}
+
+impl Report {
+    fn write(&self, info: &dyn std::fmt::Display) -> std::io::Result<std::path::PathBuf> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let path = std::env::temp_dir().join(format!(
+            "git-stack-crash-{}-{}.txt",
+            now.as_secs(),
+            std::process::id()
+        ));
+        let mut file = std::fs::File::create(&path)?;
+
+        writeln!(
+            file,
+            "{} {}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        )?;
+        writeln!(
+            file,
+            "platform: {}-{}",
+            std::env::consts::ARCH,
+            std::env::consts::OS
+        )?;
+        writeln!(file, "{}", info)?;
+
+        writeln!(file)?;
+        writeln!(file, "# Graph")?;
+        let labels = match self.graph_shape() {
+            Ok((shape, labels)) => {
+                write!(file, "{}", shape)?;
+                Some(labels)
+            }
+            Err(err) => {
+                writeln!(file, "Could not read the branches: {}", err)?;
+                None
+            }
+        };
+
+        writeln!(file)?;
+        writeln!(file, "# Config")?;
+        write!(file, "{}", self.config)?;
+
+        writeln!(file)?;
+        writeln!(file, "# Log")?;
+        match labels {
+            Some(labels) => {
+                for record in self.recent.records() {
+                    writeln!(file, "{}", labels.redact(&record))?;
+                }
+            }
+            None => writeln!(file, "Left out, without the branches to redact it by")?,
+        }
+
+        Ok(path)
+    }
+
+    /// The commits in the stacks and how they connect, with every name and id replaced by a label
+    fn graph_shape(&self) -> Result<(String, Labels), eyre::Error> {
+        let repo = git2::Repository::open(&self.git_dir)?;
+        let protected = git_stack::git::ProtectedBranches::new(
+            self.protected_branches.iter().map(|s| s.as_str()),
+        )?;
+
+        let mut branches = Vec::new();
+        for branch in repo.branches(Some(git2::BranchType::Local))? {
+            let (branch, _) = branch?;
+            let name = match branch.name() {
+                Ok(Some(name)) => name.to_owned(),
+                _ => continue,
+            };
+            if let Some(id) = branch.get().target() {
+                let is_protected = protected.is_protected(&name);
+                branches.push((name, is_protected, id));
+            }
+        }
+
+        let mut labels = std::collections::HashMap::new();
+        let mut names = Vec::new();
+        let (mut protected_count, mut branch_count) = (0, 0);
+        for (name, is_protected, id) in branches.iter() {
+            let label = if *is_protected {
+                protected_count += 1;
+                format!("protected-{}", protected_count)
+            } else {
+                branch_count += 1;
+                format!("branch-{}", branch_count)
+            };
+            names.push((name.clone(), label.clone()));
+            labels.entry(*id).or_insert_with(Vec::new).push(label);
+        }
+        let branches: Vec<_> = branches
+            .into_iter()
+            .map(|(_, is_protected, id)| (is_protected, id))
+            .collect();
+
+        let mut revwalk = repo.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL)?;
+        for (is_protected, id) in branches.iter().copied() {
+            if is_protected {
+                revwalk.hide(id)?;
+            } else {
+                revwalk.push(id)?;
+            }
+        }
+        let ids = revwalk.take(MAX_COMMITS).collect::<Result<Vec<_>, _>>()?;
+        let mut commits: std::collections::HashMap<_, _> = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (*id, format!("c{}", i)))
+            .collect();
+
+        let mut shape = String::new();
+        let mut bases = Vec::new();
+        for id in ids.iter() {
+            let commit = repo.find_commit(*id)?;
+            let parents = commit
+                .parent_ids()
+                .map(|parent_id| {
+                    commits
+                        .entry(parent_id)
+                        .or_insert_with(|| {
+                            bases.push(parent_id);
+                            format!("b{}", bases.len() - 1)
+                        })
+                        .clone()
+                })
+                .join(" ");
+            shape.push_str(&format!("{} <- {}", commits[id], parents));
+            if let Some(labels) = labels.get(id) {
+                shape.push_str(&format!("  [{}]", labels.join(", ")));
+            }
+            shape.push('\n');
+        }
+        if ids.len() == MAX_COMMITS {
+            shape.push_str(&format!("... after {} commits\n", MAX_COMMITS));
+        }
+        for id in bases.iter() {
+            shape.push_str(&commits[id]);
+            if let Some(labels) = labels.get(id) {
+                shape.push_str(&format!("  [{}]", labels.join(", ")));
+            }
+            shape.push('\n');
+        }
+        Ok((shape, Labels::new(commits, names)))
+    }
+}
+
+/// What the graph's shape calls each commit and branch, to redact the log by
+struct Labels {
+    commits: std::collections::HashMap<git2::Oid, String>,
+    name_labels: std::collections::HashMap<String, String>,
+    names: Option<regex::Regex>,
+    ids: regex::Regex,
+    commands: regex::Regex,
+    summaries: regex::Regex,
+    userinfo: regex::Regex,
+}
+
+impl Labels {
+    fn new(
+        commits: std::collections::HashMap<git2::Oid, String>,
+        mut names: Vec<(String, String)>,
+    ) -> Self {
+        // Longest first, so `feature` doesn't match within `feature-2`
+        names.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+        let names_re = if names.is_empty() {
+            None
+        } else {
+            let pattern = names.iter().map(|(name, _)| regex::escape(name)).join("|");
+            Some(regex::Regex::new(&pattern).expect("escaped names are a valid pattern"))
+        };
+        Self {
+            commits,
+            name_labels: names.into_iter().collect(),
+            names: names_re,
+            ids: regex::Regex::new(r"\b[0-9a-f]{7,40}\b").unwrap(),
+            commands: regex::Regex::new(r#"(sh -c|exec) .*|Exec\(".*?"\)"#).unwrap(),
+            summaries: regex::Regex::new(r#"  # .*|summary: ".*?""#).unwrap(),
+            userinfo: regex::Regex::new(r"([a-zA-Z][a-zA-Z0-9+.-]*://)[^/@\s]+@").unwrap(),
+        }
+    }
+
+    /// Replace names and ids by their labels, dropping commands, commit summaries, and credentials
+    fn redact(&self, record: &str) -> String {
+        let record = self.commands.replace_all(record, "<command>");
+        let record = self.summaries.replace_all(&record, "<summary>");
+        let record = self.userinfo.replace_all(&record, "$1");
+        let record = self.ids.replace_all(&record, |caps: &regex::Captures<'_>| {
+            let id = &caps[0];
+            self.commits
+                .iter()
+                .find(|(oid, _)| oid.to_string().starts_with(id))
+                .map(|(_, label)| label.clone())
+                .unwrap_or_else(|| "<id>".to_owned())
+        });
+        let names = match self.names.as_ref() {
+            Some(names) => names,
+            None => return record.into_owned(),
+        };
+
+        let mut redacted = String::new();
+        let mut last = 0;
+        for found in names.find_iter(&record) {
+            // Only whole names, like `feature` but not within `my-feature` or `feature/2`
+            let before = record[..found.start()].chars().next_back();
+            let after = record[found.end()..].chars().next();
+            let is_whole = !matches!(before, Some(c) if is_name_char(c))
+                && !matches!(after, Some(c) if is_name_char(c) || c == '/');
+            if is_whole {
+                redacted.push_str(&record[last..found.start()]);
+                redacted.push_str(&self.name_labels[found.as_str()]);
+                last = found.end();
+            }
+        }
+        redacted.push_str(&record[last..]);
+        redacted
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.' || c == '-'
+}
+
+/// Blank out the values of `REDACTED_KEYS` in `git config`-style `config`
+fn redact(config: &str) -> String {
+    config
+        .lines()
+        .map(|line| match line.trim_start().split_once('=') {
+            Some((key, _)) if REDACTED_KEYS.contains(&key) => {
+                format!("\t{}=<redacted>\n", key)
+            }
+            _ => format!("{}\n", line),
+        })
+        .collect()
+}
@@ -0,0 +1,77 @@
+use std::io::Write;
+
+use proc_exit::WithCodeResultExt;
+
+pub fn describe(
+    args: &crate::args::Args,
+    describe_args: &crate::args::DescribeArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let mut repo = git_stack::git::GitRepo::new(repo);
+
+    let branch = match describe_args.branch.as_deref() {
+        Some(name) => repo.find_local_branch(name).ok_or_else(|| {
+            proc_exit::Code::USAGE_ERR.with_message(format!("could not find branch `{}`", name))
+        })?,
+        None => repo.head_branch().ok_or_else(|| {
+            proc_exit::Code::USAGE_ERR.with_message("`HEAD` is not on a branch, name one")
+        })?,
+    };
+    let current = repo.branch_description(&branch.name);
+
+    if describe_args.show {
+        match current {
+            Some(description) => writeln!(std::io::stdout(), "{}", description.trim_end())?,
+            None => log::info!("`{}` has no description", branch.name),
+        }
+        return Ok(());
+    }
+
+    let description = if describe_args.unset {
+        None
+    } else {
+        let edited = match describe_args.message.as_deref() {
+            Some(message) => message.to_owned(),
+            None => edit(&repo, &branch.name, current.as_deref().unwrap_or(""))
+                .with_code(proc_exit::Code::FAILURE)?,
+        };
+        // Like git, an empty description removes it
+        Some(git_stack::git::cleanup_message(&edited)).filter(|d| !d.is_empty())
+    };
+
+    if description == current {
+        log::info!("Description of `{}` unchanged", branch.name);
+        return Ok(());
+    }
+    if args.dry_run {
+        match description {
+            Some(_) => log::info!("Would describe `{}`", branch.name),
+            None => log::info!("Would forget the description of `{}`", branch.name),
+        }
+        return Ok(());
+    }
+    repo.set_branch_description(&branch.name, description.as_deref())
+        .with_code(proc_exit::Code::FAILURE)?;
+    match description {
+        Some(_) => log::info!("Described `{}`", branch.name),
+        None => log::info!("Forgot the description of `{}`", branch.name),
+    }
+
+    Ok(())
+}
+
+fn edit(repo: &git_stack::git::GitRepo, name: &str, current: &str) -> eyre::Result<String> {
+    let config = repo.raw().config()?;
+    let editor = git_stack::git::Editor::commit_editor(&config);
+    let dir = repo.raw().path().join("git-stack");
+    let template = format!(
+        "{}\n# Please edit the description for the branch\n#   {}\n# Lines starting with '#' will be stripped.\n",
+        current.trim_end(),
+        name
+    );
+    editor.edit_text(&dir, DESCRIPTION_FILE, &template)
+}
+
+const DESCRIPTION_FILE: &str = "EDIT_DESCRIPTION";
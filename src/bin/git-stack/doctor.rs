@@ -0,0 +1,240 @@
+use std::io::Write;
+
+use itertools::Itertools;
+use proc_exit::WithCodeResultExt;
+
+/// A problem `doctor` found, with how to fix it
+struct Problem {
+    message: String,
+    fix: String,
+}
+
+pub fn doctor(args: &crate::args::Args) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+    let repo_config = git_stack::config::RepoConfig::from_all(&repo)
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    let protected = git_stack::git::ProtectedBranches::new(
+        repo_config.protected_branches().iter().map(|s| s.as_str()),
+    )
+    .with_code(proc_exit::Code::CONFIG_ERR)?;
+
+    let mut repo = git_stack::git::GitRepo::new(repo);
+    repo.set_push_remote(repo_config.push_remote());
+    repo.set_pull_remote(repo_config.pull_remote());
+    let branches = git_stack::git::Branches::new(repo.local_branches());
+    let protected_branches = branches.protected(&protected);
+
+    let mut problems = Vec::new();
+    check_protected(&repo_config, &protected_branches, &mut problems);
+    check_remotes(&repo, &repo_config, &mut problems);
+    check_diverged(&repo, &protected_branches, &mut problems);
+    check_head(&repo, &mut problems);
+    check_backups(&repo, &repo_config, &mut problems);
+    check_worktrees(&repo, &branches, &mut problems);
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    if problems.is_empty() {
+        writeln!(stdout, "No problems found")?;
+        return Ok(());
+    }
+    for problem in problems.iter() {
+        writeln!(stdout, "warning: {}", problem.message)?;
+        writeln!(stdout, "  fix: {}", problem.fix)?;
+    }
+    proc_exit::Code::FAILURE.ok()
+}
+
+fn check_protected(
+    repo_config: &git_stack::config::RepoConfig,
+    protected_branches: &git_stack::git::Branches,
+    problems: &mut Vec<Problem>,
+) {
+    if protected_branches.is_empty() {
+        problems.push(Problem {
+            message: format!(
+                "no local branch matches `stack.protected-branch` ({})",
+                repo_config.protected_branches().iter().unique().join(", ")
+            ),
+            fix: "run `git stack --protect <branch>` for the branch your stacks are based on"
+                .to_owned(),
+        });
+    }
+}
+
+fn check_remotes(
+    repo: &git_stack::git::GitRepo,
+    repo_config: &git_stack::config::RepoConfig,
+    problems: &mut Vec<Problem>,
+) {
+    let remotes = repo.raw().remotes().ok();
+    let remotes: Vec<_> = remotes.iter().flat_map(|r| r.iter().flatten()).collect();
+    let mut checked = Vec::new();
+    for (field, remote) in [
+        ("stack.push-remote", repo_config.push_remote()),
+        ("stack.pull-remote", repo_config.pull_remote()),
+    ]
+    .iter()
+    {
+        if remotes.contains(remote) || checked.contains(remote) {
+            continue;
+        }
+        checked.push(*remote);
+        let fix = match remotes.as_slice() {
+            [] => format!("run `git remote add {} <url>`", remote),
+            remotes => format!(
+                "run `git config {} <remote>` with one of {}",
+                field,
+                remotes.join(", ")
+            ),
+        };
+        problems.push(Problem {
+            message: format!("`{}` is `{}`, which is not a remote", field, remote),
+            fix,
+        });
+    }
+}
+
+fn check_diverged(
+    repo: &git_stack::git::GitRepo,
+    protected_branches: &git_stack::git::Branches,
+    problems: &mut Vec<Problem>,
+) {
+    for branch in protected_branches.iter().flat_map(|(_, b)| b.iter()) {
+        let pull_id = match branch.pull_id {
+            Some(pull_id) => pull_id,
+            None => continue,
+        };
+        let merge_base = repo.merge_base(branch.id, pull_id);
+        if merge_base != Some(branch.id) && merge_base != Some(pull_id) {
+            let upstream = format!("{}/{}", repo.pull_remote(), branch.name);
+            problems.push(Problem {
+                message: format!("`{}` has diverged from `{}`", branch.name, upstream),
+                fix: format!(
+                    "move any commits you need off of `{}`, then run `git branch -f {} {}`",
+                    branch.name, branch.name, upstream
+                ),
+            });
+        }
+    }
+}
+
+fn check_head(repo: &git_stack::git::GitRepo, problems: &mut Vec<Problem>) {
+    if repo.raw().head_detached().unwrap_or(false) {
+        problems.push(Problem {
+            message: "HEAD is detached, `git stack` won't know which stack you are on".to_owned(),
+            fix: "run `git switch <branch>`".to_owned(),
+        });
+    }
+}
+
+fn check_backups(
+    repo: &git_stack::git::GitRepo,
+    repo_config: &git_stack::config::RepoConfig,
+    problems: &mut Vec<Problem>,
+) {
+    for stack in git_stack::stash::Stack::all(repo) {
+        let snapshots: Vec<_> = stack.iter().collect();
+        if let Some(capacity) = repo_config.capacity() {
+            if capacity < snapshots.len() {
+                problems.push(Problem {
+                    message: format!(
+                        "backup stack `{}` has {} snapshots, more than `branch-stash.capacity` ({})",
+                        stack.name,
+                        snapshots.len(),
+                        capacity
+                    ),
+                    fix: format!("run `git stack backup prune --stack {}`", stack.name),
+                });
+            }
+        }
+
+        let unrestorable = snapshots
+            .iter()
+            .filter_map(|path| git_stack::stash::Snapshot::load(path).ok())
+            .filter(|snapshot| {
+                snapshot
+                    .branches
+                    .iter()
+                    .any(|b| repo.raw().find_commit(b.id).is_err())
+            })
+            .count();
+        if unrestorable != 0 {
+            problems.push(Problem {
+                message: format!(
+                    "backup stack `{}` has {} snapshot(s) referring to commits that no longer exist",
+                    stack.name, unrestorable
+                ),
+                fix: format!("run `git stack backup prune --stack {}`", stack.name),
+            });
+        }
+    }
+}
+
+fn check_worktrees(
+    repo: &git_stack::git::GitRepo,
+    branches: &git_stack::git::Branches,
+    problems: &mut Vec<Problem>,
+) {
+    let worktrees = match repo.raw().worktrees() {
+        Ok(worktrees) => worktrees,
+        Err(err) => {
+            log::debug!("Could not list worktrees: {}", err);
+            return;
+        }
+    };
+    for name in worktrees.iter().flatten() {
+        let worktree = match repo.raw().find_worktree(name) {
+            Ok(worktree) => worktree,
+            Err(err) => {
+                log::debug!("Could not open worktree `{}`: {}", name, err);
+                continue;
+            }
+        };
+        if is_same_path(Some(worktree.path()), repo.raw().workdir()) {
+            continue;
+        }
+        let head = git2::Repository::open_from_worktree(&worktree)
+            .ok()
+            .and_then(|r| {
+                r.head()
+                    .ok()
+                    .and_then(|h| h.shorthand().map(|s| s.to_owned()))
+            });
+        let head = match head {
+            Some(head) => head,
+            None => continue,
+        };
+        if branches
+            .iter()
+            .flat_map(|(_, b)| b.iter())
+            .any(|b| b.name == head)
+        {
+            problems.push(Problem {
+                message: format!(
+                    "`{}` is checked out in {}, rewriting it here will leave that worktree out of date",
+                    head,
+                    worktree.path().display()
+                ),
+                fix: format!(
+                    "run `git switch --detach` in {}, or restack from there",
+                    worktree.path().display()
+                ),
+            });
+        }
+    }
+}
+
+fn is_same_path(one: Option<&std::path::Path>, two: Option<&std::path::Path>) -> bool {
+    match (one, two) {
+        (Some(one), Some(two)) => match (one.canonicalize(), two.canonicalize()) {
+            (Ok(one), Ok(two)) => one == two,
+            _ => one == two,
+        },
+        _ => false,
+    }
+}
@@ -0,0 +1,35 @@
+//! Which forge's CLI to talk to about a remote
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+}
+
+impl Forge {
+    /// The forge `url` is hosted on, by its host rather than anything else in the URL
+    ///
+    /// `gitlab_hosts` are self-hosted GitLab instances, see `stack.gitlab-host`.
+    pub fn from_url(url: &str, gitlab_hosts: &[String]) -> Option<Self> {
+        let host = git_stack::git::url_host(url)?;
+        if host.eq_ignore_ascii_case("github.com") {
+            Some(Self::GitHub)
+        } else if host.eq_ignore_ascii_case("gitlab.com")
+            || gitlab_hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+        {
+            Some(Self::GitLab)
+        } else {
+            None
+        }
+    }
+
+    /// The forge `remote` is pushed to
+    pub fn from_remote(
+        repo: &git_stack::git::GitRepo,
+        remote: &str,
+        gitlab_hosts: &[String],
+    ) -> Option<Self> {
+        let url = git_stack::git::remote_url(repo.raw(), remote, git2::Direction::Push)?;
+        Self::from_url(&url, gitlab_hosts)
+    }
+}
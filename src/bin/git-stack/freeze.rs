@@ -0,0 +1,82 @@
+use proc_exit::WithCodeResultExt;
+
+pub fn freeze(
+    args: &crate::args::Args,
+    freeze_args: &crate::args::FreezeArgs,
+) -> proc_exit::ExitResult {
+    let repo = open_repo()?;
+    let mut config = repo.raw().config().with_code(proc_exit::Code::CONFIG_ERR)?;
+
+    for branch in find_branches(&repo, &freeze_args.branches)? {
+        let key = frozen_key(&branch.name);
+        if config.get_bool(&key).unwrap_or(false) {
+            log::info!("`{}` is already frozen", branch.name);
+            continue;
+        }
+        if args.dry_run {
+            log::info!("Would freeze `{}`", branch.name);
+            continue;
+        }
+        config
+            .set_bool(&key, true)
+            .with_code(proc_exit::Code::FAILURE)?;
+        log::info!("Froze `{}`", branch.name);
+    }
+
+    Ok(())
+}
+
+pub fn unfreeze(
+    args: &crate::args::Args,
+    freeze_args: &crate::args::FreezeArgs,
+) -> proc_exit::ExitResult {
+    let repo = open_repo()?;
+    let mut config = repo.raw().config().with_code(proc_exit::Code::CONFIG_ERR)?;
+
+    for branch in find_branches(&repo, &freeze_args.branches)? {
+        let key = frozen_key(&branch.name);
+        if !config.get_bool(&key).unwrap_or(false) {
+            log::info!("`{}` is not frozen", branch.name);
+            continue;
+        }
+        if args.dry_run {
+            log::info!("Would unfreeze `{}`", branch.name);
+            continue;
+        }
+        config.remove(&key).with_code(proc_exit::Code::FAILURE)?;
+        log::info!("Unfroze `{}`", branch.name);
+    }
+
+    Ok(())
+}
+
+fn open_repo() -> Result<git_stack::git::GitRepo, proc_exit::Exit> {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    Ok(git_stack::git::GitRepo::new(repo))
+}
+
+fn find_branches(
+    repo: &git_stack::git::GitRepo,
+    names: &[String],
+) -> Result<Vec<git_stack::git::Branch>, proc_exit::Exit> {
+    if names.is_empty() {
+        let branch = repo.head_branch().ok_or_else(|| {
+            proc_exit::Code::USAGE_ERR.with_message("`HEAD` is not on a branch, pass a branch name")
+        })?;
+        return Ok(vec![branch]);
+    }
+    names
+        .iter()
+        .map(|name| {
+            repo.find_local_branch(name).ok_or_else(|| {
+                proc_exit::Code::USAGE_ERR.with_message(format!("could not find branch `{}`", name))
+            })
+        })
+        .collect()
+}
+
+fn frozen_key(name: &str) -> String {
+    format!("branch.{}.{}", name, crate::stack::FROZEN_KEY)
+}
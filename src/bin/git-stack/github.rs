@@ -0,0 +1,176 @@
+//! Manage pull requests through the GitHub CLI (`gh`)
+
+use eyre::WrapErr;
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PullRequest {
+    pub number: u64,
+    pub base_ref_name: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PullRequestStatus {
+    pub number: u64,
+    pub head_ref_name: String,
+    /// `OPEN`, `CLOSED`, or `MERGED`
+    pub state: String,
+    /// `APPROVED`, `CHANGES_REQUESTED`, `REVIEW_REQUIRED`, or empty without required reviews
+    #[serde(default)]
+    pub review_decision: String,
+    pub is_draft: bool,
+    /// Checks run on the PR's head commit, like CI
+    #[serde(default)]
+    pub status_check_rollup: Vec<Check>,
+}
+
+impl PullRequestStatus {
+    pub fn is_open(&self) -> bool {
+        self.state == "OPEN"
+    }
+
+    /// Overall result of the checks, if any ran
+    pub fn ci_status(&self) -> Option<CiStatus> {
+        if self.status_check_rollup.is_empty() {
+            return None;
+        }
+        let mut status = CiStatus::Passing;
+        for check in self.status_check_rollup.iter() {
+            status = status.max(check.ci_status());
+        }
+        Some(status)
+    }
+}
+
+/// A check run or commit status
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+    /// Check runs: `QUEUED`, `IN_PROGRESS`, or `COMPLETED`
+    #[serde(default)]
+    pub status: Option<String>,
+    /// Completed check runs: `SUCCESS`, `FAILURE`, `NEUTRAL`, `SKIPPED`, `CANCELLED`, etc
+    #[serde(default)]
+    pub conclusion: Option<String>,
+    /// Commit statuses: `SUCCESS`, `PENDING`, `EXPECTED`, `FAILURE`, or `ERROR`
+    #[serde(default)]
+    pub state: Option<String>,
+}
+
+impl Check {
+    fn ci_status(&self) -> CiStatus {
+        let result = self
+            .conclusion
+            .as_deref()
+            .filter(|c| !c.is_empty())
+            .or(self.state.as_deref());
+        match result {
+            Some("SUCCESS") | Some("NEUTRAL") | Some("SKIPPED") => CiStatus::Passing,
+            Some("PENDING") | Some("EXPECTED") | None => CiStatus::Pending,
+            Some(_) => CiStatus::Failing,
+        }
+    }
+}
+
+/// Ordered from best to worst
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CiStatus {
+    Passing,
+    Pending,
+    Failing,
+}
+
+/// The latest PR for each branch, preferring open ones
+pub fn list_prs() -> eyre::Result<std::collections::HashMap<String, PullRequestStatus>> {
+    let stdout = gh(&[
+        "pr",
+        "list",
+        "--state",
+        "all",
+        "--limit",
+        "200",
+        "--json",
+        "number,headRefName,state,reviewDecision,isDraft,statusCheckRollup",
+    ])?;
+    let prs: Vec<PullRequestStatus> =
+        serde_json::from_slice(&stdout).wrap_err("could not parse PRs")?;
+
+    let mut by_branch = std::collections::HashMap::<String, PullRequestStatus>::new();
+    // Newest first
+    for pr in prs {
+        let replace = match by_branch.get(&pr.head_ref_name) {
+            Some(existing) => !existing.is_open() && pr.is_open(),
+            None => true,
+        };
+        if replace {
+            by_branch.insert(pr.head_ref_name.clone(), pr);
+        }
+    }
+    Ok(by_branch)
+}
+
+/// Find the open PR for `head`, if any
+pub fn find_pr(head: &str) -> eyre::Result<Option<PullRequest>> {
+    let stdout = gh(&[
+        "pr",
+        "list",
+        "--head",
+        head,
+        "--state",
+        "open",
+        "--json",
+        "number,baseRefName",
+    ])?;
+    let prs: Vec<PullRequest> = serde_json::from_slice(&stdout)
+        .wrap_err_with(|| format!("could not parse PRs for `{}`", head))?;
+    Ok(prs.into_iter().next())
+}
+
+/// Open a PR for `head` onto `base`, filling in the title and body from its commits
+///
+/// A branch `description` is used as the body instead.
+///
+/// Returns the PR's URL
+pub fn create_pr(
+    head: &str,
+    base: &str,
+    description: Option<&str>,
+    draft: bool,
+) -> eyre::Result<String> {
+    let mut args = vec!["pr", "create", "--head", head, "--base", base, "--fill"];
+    if let Some(description) = description {
+        args.push("--body");
+        args.push(description);
+    }
+    if draft {
+        args.push("--draft");
+    }
+    let stdout = gh(&args)?;
+    Ok(String::from_utf8_lossy(&stdout).trim().to_owned())
+}
+
+/// Change the branch `number` will be merged into
+pub fn retarget_pr(number: u64, base: &str) -> eyre::Result<()> {
+    gh(&["pr", "edit", &number.to_string(), "--base", base])?;
+    Ok(())
+}
+
+/// Merge the open PR for `head` with a merge commit
+pub fn merge_pr(head: &str) -> eyre::Result<()> {
+    gh(&["pr", "merge", head, "--merge"])?;
+    Ok(())
+}
+
+fn gh(args: &[&str]) -> eyre::Result<Vec<u8>> {
+    log::trace!("gh {}", args.join(" "));
+    let output = std::process::Command::new("gh")
+        .args(args)
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .wrap_err("could not run `gh`, is the GitHub CLI installed?")?;
+    if !output.status.success() {
+        eyre::bail!("`gh {}` failed", args.join(" "));
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,90 @@
+//! Look up merge requests through the GitLab CLI (`glab`)
+
+use eyre::WrapErr;
+
+use crate::github::PullRequestStatus;
+
+#[derive(Clone, Debug, serde::Deserialize)]
+struct MergeRequest {
+    iid: u64,
+    source_branch: String,
+    /// `opened`, `closed`, `locked`, or `merged`
+    state: String,
+    #[serde(default)]
+    draft: bool,
+    /// `not_approved` while waiting on required approvals
+    #[serde(default)]
+    detailed_merge_status: String,
+}
+
+impl MergeRequest {
+    /// In the shape `gh` reports PRs in
+    ///
+    /// `glab mr list` doesn't report pipelines, so there are no checks.
+    fn into_status(self) -> PullRequestStatus {
+        let state = match self.state.as_str() {
+            "opened" => "OPEN",
+            "merged" => "MERGED",
+            _ => "CLOSED",
+        };
+        let review_decision = match self.detailed_merge_status.as_str() {
+            "not_approved" => "REVIEW_REQUIRED",
+            _ => "",
+        };
+        PullRequestStatus {
+            number: self.iid,
+            head_ref_name: self.source_branch,
+            state: state.to_owned(),
+            review_decision: review_decision.to_owned(),
+            is_draft: self.draft,
+            status_check_rollup: Vec::new(),
+        }
+    }
+}
+
+/// The latest MR for each branch, preferring open ones, like `github::list_prs`
+pub fn list_mrs() -> eyre::Result<std::collections::HashMap<String, PullRequestStatus>> {
+    let stdout = glab(&[
+        "mr",
+        "list",
+        "--all",
+        "--per-page",
+        "200",
+        "--output",
+        "json",
+    ])?;
+    let mrs: Vec<MergeRequest> = serde_json::from_slice(&stdout).wrap_err("could not parse MRs")?;
+
+    let mut by_branch = std::collections::HashMap::<String, PullRequestStatus>::new();
+    // Newest first
+    for mr in mrs {
+        let mr = mr.into_status();
+        let replace = match by_branch.get(&mr.head_ref_name) {
+            Some(existing) => !existing.is_open() && mr.is_open(),
+            None => true,
+        };
+        if replace {
+            by_branch.insert(mr.head_ref_name.clone(), mr);
+        }
+    }
+    Ok(by_branch)
+}
+
+/// Merge the open MR for `head`, like `github::merge_pr`
+pub fn merge_mr(head: &str) -> eyre::Result<()> {
+    glab(&["mr", "merge", head, "--yes"])?;
+    Ok(())
+}
+
+fn glab(args: &[&str]) -> eyre::Result<Vec<u8>> {
+    log::trace!("glab {}", args.join(" "));
+    let output = std::process::Command::new("glab")
+        .args(args)
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .wrap_err("could not run `glab`, is the GitLab CLI installed?")?;
+    if !output.status.success() {
+        eyre::bail!("`glab {}` failed", args.join(" "));
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,25 @@
+use eyre::WrapErr;
+
+/// Read the JSON in `path`, if it was ever saved
+pub fn load_json<T: serde::de::DeserializeOwned>(
+    path: &std::path::Path,
+) -> eyre::Result<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file = std::fs::File::open(path)?;
+    let reader = std::io::BufReader::new(file);
+    let value = serde_json::from_reader(reader)
+        .wrap_err_with(|| format!("could not parse {}", path.display()))?;
+    Ok(Some(value))
+}
+
+/// Write `value` to `path` as JSON, creating its directory as needed
+pub fn save_json<T: serde::Serialize>(path: &std::path::Path, value: &T) -> eyre::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let s = serde_json::to_string_pretty(value)?;
+    std::fs::write(path, &s)?;
+    Ok(())
+}
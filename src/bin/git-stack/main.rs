@@ -2,13 +2,36 @@
 #![allow(clippy::let_and_return)]
 #![allow(clippy::if_same_then_else)]
 
+#[macro_use]
+extern crate clap;
+
 use std::io::Write;
 
 use structopt::StructOpt;
 
+mod archive;
 mod args;
+mod backup;
+mod complete;
 mod config;
+mod crash;
+mod describe;
+mod doctor;
+mod forge;
+mod freeze;
+mod github;
+mod gitlab;
+mod json;
+mod man;
+mod navigate;
+mod parent;
+mod quarantine;
+mod reorder;
+mod repair;
+mod simulate;
 mod stack;
+mod undo;
+mod version;
 
 fn main() {
     human_panic::setup_panic!();
@@ -23,6 +46,12 @@ fn run() -> proc_exit::ExitResult {
         Err(e) if e.use_stderr() => {
             return Err(proc_exit::Code::USAGE_ERR.with_message(e));
         }
+        Err(e)
+            if e.kind == structopt::clap::ErrorKind::VersionDisplayed && version::is_verbose() =>
+        {
+            version::verbose_version()?;
+            return proc_exit::Code::SUCCESS.ok();
+        }
         Err(e) => {
             writeln!(std::io::stdout(), "{}", e)?;
             return proc_exit::Code::SUCCESS.ok();
@@ -33,9 +62,54 @@ fn run() -> proc_exit::ExitResult {
     let colored_stdout = concolor_control::get(concolor_control::Stream::Stdout).ansi_color();
     let colored_stderr = concolor_control::get(concolor_control::Stream::Stderr).ansi_color();
 
-    git_stack::log::init_logging(args.verbose.clone(), colored_stderr);
+    let recent = crash::install(&args);
+    git_stack::log::init_logging_with(args.verbose.clone(), colored_stderr, recent);
 
-    if let Some(output_path) = args.dump_config.as_deref() {
+    if let Some(subcommand) = args.subcommand.as_ref() {
+        match subcommand {
+            args::Subcommand::Next => navigate::next()?,
+            args::Subcommand::Prev => navigate::prev()?,
+            args::Subcommand::Doctor => doctor::doctor(&args)?,
+            args::Subcommand::Undo(undo_args) => undo::undo(&args, undo_args)?,
+            args::Subcommand::Redo(undo_args) => undo::redo(&args, undo_args)?,
+            args::Subcommand::Backup(command) => backup::backup(&args, command)?,
+            args::Subcommand::Quarantine(command) => quarantine::quarantine(&args, command)?,
+            args::Subcommand::Parent(parent_args) => parent::parent(&args, parent_args)?,
+            args::Subcommand::Describe(describe_args) => describe::describe(&args, describe_args)?,
+            args::Subcommand::Freeze(freeze_args) => freeze::freeze(&args, freeze_args)?,
+            args::Subcommand::Unfreeze(freeze_args) => freeze::unfreeze(&args, freeze_args)?,
+            args::Subcommand::Completions(completions) => complete::completions(completions.shell)?,
+            args::Subcommand::Complete(complete) => {
+                complete::complete(complete.kind, &complete.prefix)?
+            }
+            args::Subcommand::Man(man) => man::man(&man.dir)?,
+            args::Subcommand::Simulate(simulate_args) => simulate::simulate(simulate_args)?,
+            args::Subcommand::Plan(_)
+            | args::Subcommand::Apply(_)
+            | args::Subcommand::Reword(_)
+            | args::Subcommand::Absorb(_)
+            | args::Subcommand::Move(_)
+            | args::Subcommand::Split(_)
+            | args::Subcommand::Drop(_)
+            | args::Subcommand::Squash(_)
+            | args::Subcommand::Reorder(_)
+            | args::Subcommand::Sync
+            | args::Subcommand::Submit(_)
+            | args::Subcommand::ExportBundle(_)
+            | args::Subcommand::ImportBundle(_)
+            | args::Subcommand::FormatPatch(_)
+            | args::Subcommand::DeleteMerged
+            | args::Subcommand::Archive(_)
+            | args::Subcommand::Rename(_)
+            | args::Subcommand::Create(_)
+            | args::Subcommand::Repair
+            | args::Subcommand::Adopt(_)
+            | args::Subcommand::Status(_)
+            | args::Subcommand::Fix(_)
+            | args::Subcommand::Land(_)
+            | args::Subcommand::Run(_) => stack::stack(&args, colored_stdout)?,
+        }
+    } else if let Some(output_path) = args.dump_config.as_deref() {
         config::dump_config(&args, output_path)?;
     } else if let Some(ignore) = args.protect.as_deref() {
         config::protect(&args, ignore)?;
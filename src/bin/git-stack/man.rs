@@ -0,0 +1,225 @@
+use std::fmt::Write as _;
+
+use eyre::WrapErr;
+use proc_exit::WithCodeResultExt;
+use structopt::clap::{AppSettings, ArgSettings};
+use structopt::StructOpt;
+
+/// Write roff man pages for `git-stack` and each of its subcommands into `dir`
+pub fn man(dir: &std::path::Path) -> proc_exit::ExitResult {
+    std::fs::create_dir_all(dir).with_code(proc_exit::Code::CANT_CREAT)?;
+
+    // clap 2 has no man page generator, so this walks the same parser its completion generators
+    // do
+    let app = crate::args::Args::clap();
+    let version = app.p.meta.version.unwrap_or_default();
+    let mut pages = Vec::new();
+    collect_pages(&app, BIN_NAME, "git stack", version, &mut pages);
+
+    for (name, page) in pages {
+        let path = dir.join(format!("{}.1", name));
+        log::debug!("Writing {}", path.display());
+        std::fs::write(&path, page)
+            .wrap_err_with(|| format!("could not write {}", path.display()))
+            .with_code(proc_exit::Code::IO_ERR)?;
+    }
+
+    Ok(())
+}
+
+const BIN_NAME: &str = "git-stack";
+
+fn collect_pages(
+    app: &structopt::clap::App,
+    name: &str,
+    command: &str,
+    version: &str,
+    pages: &mut Vec<(String, String)>,
+) {
+    let subcommands: Vec<_> = app
+        .p
+        .subcommands
+        .iter()
+        .filter(|sub| !sub.p.is_set(AppSettings::Hidden) && sub.p.meta.name != "help")
+        .collect();
+    pages.push((
+        name.to_owned(),
+        render(app, name, command, version, &subcommands),
+    ));
+    for sub in subcommands {
+        let name = format!("{}-{}", name, sub.p.meta.name);
+        let command = format!("{} {}", command, sub.p.meta.name);
+        collect_pages(sub, &name, &command, version, pages);
+    }
+}
+
+fn render(
+    app: &structopt::clap::App,
+    name: &str,
+    command: &str,
+    version: &str,
+    subcommands: &[&structopt::clap::App],
+) -> String {
+    let meta = &app.p.meta;
+    // The flattened color arguments' doc comment takes over the top-level `about`
+    let about = if name == BIN_NAME {
+        env!("CARGO_PKG_DESCRIPTION")
+    } else {
+        meta.about.unwrap_or_default()
+    };
+    let mut page = String::new();
+    let _ = writeln!(
+        page,
+        ".TH {} 1 \"\" \"{} {}\" \"Git Manual\"",
+        name.to_uppercase(),
+        BIN_NAME,
+        version
+    );
+
+    let _ = writeln!(page, ".SH NAME");
+    let _ = writeln!(page, "{} \\- {}", escape(name), escape(about));
+
+    let _ = writeln!(page, ".SH SYNOPSIS");
+    let mut synopsis = format!("\\fB{}\\fR [\\fIOPTIONS\\fR]", escape(command));
+    for positional in app.p.positionals.values() {
+        if positional.b.settings.is_set(ArgSettings::Hidden) {
+            continue;
+        }
+        let value = value_name(
+            positional.b.name,
+            first_name(positional.v.val_names.as_ref().map(|n| n.values())),
+        );
+        if positional.b.settings.is_set(ArgSettings::Required) {
+            let _ = write!(synopsis, " \\fI{}\\fR", value);
+        } else {
+            let _ = write!(synopsis, " [\\fI{}\\fR]", value);
+        }
+    }
+    if !subcommands.is_empty() {
+        synopsis.push_str(" [\\fISUBCOMMAND\\fR]");
+    }
+    let _ = writeln!(page, "{}", synopsis);
+
+    if let Some(about) = meta.long_about {
+        let _ = writeln!(page, ".SH DESCRIPTION");
+        let _ = writeln!(page, "{}", paragraph(about));
+    }
+
+    let _ = writeln!(page, ".SH OPTIONS");
+    for flag in app.p.flags.iter() {
+        if flag.b.settings.is_set(ArgSettings::Hidden) {
+            continue;
+        }
+        let _ = writeln!(page, ".TP");
+        let _ = writeln!(page, "{}", switches(flag.s.short, flag.s.long));
+        if let Some(help) = flag.b.long_help.or(flag.b.help) {
+            let _ = writeln!(page, "{}", paragraph(help));
+        }
+    }
+    for opt in app.p.opts.iter() {
+        if opt.b.settings.is_set(ArgSettings::Hidden) {
+            continue;
+        }
+        let _ = writeln!(page, ".TP");
+        let _ = writeln!(
+            page,
+            "{} \\fI{}\\fR",
+            switches(opt.s.short, opt.s.long),
+            value_name(
+                opt.b.name,
+                first_name(opt.v.val_names.as_ref().map(|n| n.values()))
+            )
+        );
+        if let Some(help) = opt.b.long_help.or(opt.b.help) {
+            let _ = writeln!(page, "{}", paragraph(help));
+        }
+        if let Some(possible) = opt.v.possible_vals.as_ref() {
+            let _ = writeln!(page, ".br");
+            let _ = writeln!(page, "Possible values: {}", escape(&possible.join(", ")));
+        }
+    }
+    for positional in app.p.positionals.values() {
+        if positional.b.settings.is_set(ArgSettings::Hidden) {
+            continue;
+        }
+        let _ = writeln!(page, ".TP");
+        let _ = writeln!(
+            page,
+            "\\fI{}\\fR",
+            value_name(
+                positional.b.name,
+                first_name(positional.v.val_names.as_ref().map(|n| n.values()))
+            )
+        );
+        if let Some(help) = positional.b.long_help.or(positional.b.help) {
+            let _ = writeln!(page, "{}", paragraph(help));
+        }
+    }
+    if name != BIN_NAME {
+        let _ = writeln!(page, ".PP");
+        let _ = writeln!(
+            page,
+            "The options of \\fB{}\\fR(1) apply as well.",
+            escape(BIN_NAME)
+        );
+    }
+
+    if !subcommands.is_empty() {
+        let _ = writeln!(page, ".SH SUBCOMMANDS");
+        for sub in subcommands {
+            let _ = writeln!(page, ".TP");
+            let _ = writeln!(
+                page,
+                "\\fB{}\\fR(1)",
+                escape(&format!("{}-{}", name, sub.p.meta.name))
+            );
+            if let Some(about) = sub.p.meta.about {
+                let _ = writeln!(page, "{}", paragraph(about));
+            }
+        }
+    }
+
+    let _ = writeln!(page, ".SH SEE ALSO");
+    if name == BIN_NAME {
+        let _ = writeln!(page, "\\fBgit\\fR(1)");
+    } else {
+        let _ = writeln!(page, "\\fB{}\\fR(1)", escape(BIN_NAME));
+    }
+
+    page
+}
+
+fn switches(short: Option<char>, long: Option<&str>) -> String {
+    let short = short.map(|s| format!("\\fB\\-{}\\fR", escape(&s.to_string())));
+    let long = long.map(|l| format!("\\fB\\-\\-{}\\fR", escape(l)));
+    short.into_iter().chain(long).collect::<Vec<_>>().join(", ")
+}
+
+fn first_name<'a>(names: Option<impl Iterator<Item = &'a &'a str>>) -> Option<&'a str> {
+    names.and_then(|mut names| names.next().copied())
+}
+
+fn value_name(name: &str, val_name: Option<&str>) -> String {
+    format!("<{}>", escape(val_name.unwrap_or(name)))
+}
+
+/// Escape text for roff, keeping lines from being read as requests
+fn paragraph(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = escape(line.trim());
+            if line.is_empty() {
+                ".PP".to_owned()
+            } else if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}", line)
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('-', "\\-")
+}
@@ -0,0 +1,85 @@
+use bstr::ByteSlice;
+use proc_exit::WithCodeResultExt;
+
+pub fn next() -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let mut repo = open()?;
+
+    let branches = git_stack::git::Branches::new(repo.local_branches());
+    let head_commit = repo.head_commit();
+    let next = git_stack::git::find_next(&repo, &branches, head_commit.id);
+    let name = choose(&next, "below", &describe_head(&repo))?;
+    switch(&mut repo, &name)
+}
+
+pub fn prev() -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let mut repo = open()?;
+
+    let branches = git_stack::git::Branches::new(repo.local_branches());
+    let head_commit = repo.head_commit();
+    let prev: Vec<_> = git_stack::git::find_prev(&repo, &branches, head_commit.id)
+        .iter()
+        .collect();
+    let name = choose(&prev, "above", &describe_head(&repo))?;
+    switch(&mut repo, &name)
+}
+
+fn open() -> Result<git_stack::git::GitRepo, proc_exit::Exit> {
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo_config =
+        git_stack::config::RepoConfig::from_all(&repo).with_code(proc_exit::Code::CONFIG_ERR)?;
+    let mut repo = git_stack::git::GitRepo::new(repo);
+    repo.set_dirty_check(repo_config.dirty_check());
+    if repo.is_dirty() {
+        return Err(proc_exit::Code::USAGE_ERR.with_message("Working tree is dirty, aborting"));
+    }
+    Ok(repo)
+}
+
+fn choose(
+    candidates: &[&git_stack::git::Branch],
+    direction: &str,
+    head: &str,
+) -> Result<String, proc_exit::Exit> {
+    match candidates {
+        [] => {
+            Err(proc_exit::Code::USAGE_ERR
+                .with_message(format!("No branches {} {}", direction, head)))
+        }
+        [branch] => Ok(branch.name.clone()),
+        _ => {
+            let choices = candidates
+                .iter()
+                .map(|b| format!("  {}", b.name))
+                .collect::<Vec<_>>()
+                .join("\n");
+            Err(proc_exit::Code::USAGE_ERR.with_message(format!(
+                "Multiple branches {} {}, switch to one of:\n{}",
+                direction, head, choices
+            )))
+        }
+    }
+}
+
+fn describe_head(repo: &git_stack::git::GitRepo) -> String {
+    match repo.head_branch() {
+        Some(branch) => format!("`{}`", branch.name),
+        None => {
+            let head_commit = repo.head_commit();
+            format!(
+                "{} ({})",
+                head_commit.id,
+                head_commit.summary.to_str_lossy()
+            )
+        }
+    }
+}
+
+fn switch(repo: &mut git_stack::git::GitRepo, name: &str) -> proc_exit::ExitResult {
+    log::trace!("git switch {}", name);
+    repo.switch(name).with_code(proc_exit::Code::FAILURE)?;
+    log::info!("Switched to `{}`", name);
+    Ok(())
+}
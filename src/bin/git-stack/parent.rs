@@ -0,0 +1,78 @@
+use std::io::Write;
+
+use proc_exit::WithCodeResultExt;
+
+pub fn parent(
+    args: &crate::args::Args,
+    parent_args: &crate::args::ParentArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+
+    let branch = match parent_args.branch.as_deref() {
+        Some(name) => repo.find_local_branch(name).ok_or_else(|| {
+            proc_exit::Code::USAGE_ERR.with_message(format!("could not find branch `{}`", name))
+        })?,
+        None => repo.head_branch().ok_or_else(|| {
+            proc_exit::Code::USAGE_ERR.with_message("`HEAD` is not on a branch, pass `--branch`")
+        })?,
+    };
+    let key = format!("branch.{}.{}", branch.name, crate::stack::PARENT_KEY);
+    let mut config = repo.raw().config().with_code(proc_exit::Code::CONFIG_ERR)?;
+
+    if let Some(parent_name) = parent_args.set.as_deref() {
+        if parent_name == branch.name {
+            return Err(proc_exit::Code::USAGE_ERR
+                .with_message(format!("`{}` can't be its own parent", branch.name)));
+        }
+        let parent = repo.find_local_branch(parent_name).ok_or_else(|| {
+            proc_exit::Code::USAGE_ERR
+                .with_message(format!("could not find branch `{}`", parent_name))
+        })?;
+        if args.dry_run {
+            log::info!(
+                "Would record `{}` as the parent of `{}`",
+                parent.name,
+                branch.name
+            );
+            return Ok(());
+        }
+        config
+            .set_str(&key, &parent.name)
+            .with_code(proc_exit::Code::FAILURE)?;
+        log::info!(
+            "Recorded `{}` as the parent of `{}`",
+            parent.name,
+            branch.name
+        );
+        if repo.merge_base(parent.id, branch.id) != Some(parent.id) {
+            log::info!(
+                "To restack `{}` onto it, run `git stack --rebase`",
+                branch.name
+            );
+        }
+    } else if parent_args.unset {
+        if config.get_string(&key).is_err() {
+            log::info!("`{}` has no recorded parent", branch.name);
+            return Ok(());
+        }
+        if args.dry_run {
+            log::info!("Would forget the parent of `{}`", branch.name);
+            return Ok(());
+        }
+        config.remove(&key).with_code(proc_exit::Code::FAILURE)?;
+        log::info!("Forgot the parent of `{}`", branch.name);
+    } else {
+        match config.get_string(&key) {
+            Ok(parent_name) => writeln!(std::io::stdout(), "{}", parent_name)?,
+            Err(_) => log::info!(
+                "`{}` has no recorded parent, it is guessed from the history",
+                branch.name
+            ),
+        }
+    }
+
+    Ok(())
+}
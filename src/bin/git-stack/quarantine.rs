@@ -0,0 +1,186 @@
+use std::io::Write;
+
+use bstr::ByteSlice;
+use proc_exit::WithCodeResultExt;
+
+use crate::args::QuarantineCommand;
+
+pub fn quarantine(args: &crate::args::Args, command: &QuarantineCommand) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo_config = git_stack::config::RepoConfig::from_all(&repo)
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    let mut repo = git_stack::git::GitRepo::new(repo);
+    let mut quarantine =
+        git_stack::stash::Quarantine::load(&repo).with_code(proc_exit::Code::FAILURE)?;
+
+    match command {
+        QuarantineCommand::List => list(&repo, &repo_config, &quarantine),
+        QuarantineCommand::Restore(restore_args) => {
+            restore(args, &mut repo, &mut quarantine, &restore_args.branch)
+        }
+        QuarantineCommand::Purge(purge_args) => {
+            purge(args, &repo, &repo_config, &mut quarantine, purge_args.all)
+        }
+    }
+}
+
+fn list(
+    repo: &git_stack::git::GitRepo,
+    repo_config: &git_stack::config::RepoConfig,
+    quarantine: &git_stack::stash::Quarantine,
+) -> proc_exit::ExitResult {
+    let now = now();
+    let mut branches: Vec<_> = quarantine.branches.iter().collect();
+    branches.sort_by_key(|b| b.deleted);
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    for branch in branches.iter() {
+        let summary = match repo.find_commit(branch.id) {
+            Some(commit) => commit.summary.to_str_lossy().into_owned(),
+            None => "(no longer exists)".to_owned(),
+        };
+        let kept = age(now, branch.deleted);
+        let status = match repo_config.quarantine_age() {
+            Some(max_age) if kept < max_age => format!(
+                "deleted {} ago, purged in {}",
+                crate::stack::format_age(kept),
+                crate::stack::format_age(max_age - kept)
+            ),
+            Some(_) => format!(
+                "deleted {} ago, due to be purged",
+                crate::stack::format_age(kept)
+            ),
+            None => format!("deleted {} ago", crate::stack::format_age(kept)),
+        };
+        writeln!(
+            stdout,
+            "{}: {}  # {}  ({})",
+            branch.name,
+            repo.abbrev_id(branch.id),
+            summary,
+            status
+        )?;
+    }
+    if branches.is_empty() {
+        log::info!("No deleted branches in quarantine");
+    }
+
+    Ok(())
+}
+
+fn restore(
+    args: &crate::args::Args,
+    repo: &mut git_stack::git::GitRepo,
+    quarantine: &mut git_stack::stash::Quarantine,
+    name: &str,
+) -> proc_exit::ExitResult {
+    let branch = quarantine.get(name).cloned().ok_or_else(|| {
+        proc_exit::Code::USAGE_ERR.with_message(format!("`{}` is not in quarantine", name))
+    })?;
+    if repo.find_local_branch(name).is_some() {
+        return Err(proc_exit::Code::USAGE_ERR.with_message(format!(
+            "`{}` already exists, rename it before restoring the deleted one",
+            name
+        )));
+    }
+    if repo.find_commit(branch.id).is_none() {
+        return Err(proc_exit::Code::FAILURE.with_message(format!(
+            "`{}` can't be restored, {} no longer exists",
+            name, branch.id
+        )));
+    }
+
+    if args.dry_run {
+        log::info!("Would restore `{}` at {}", name, repo.abbrev_id(branch.id));
+        return Ok(());
+    }
+    repo.branch(name, branch.id, "restore from quarantine")
+        .with_code(proc_exit::Code::FAILURE)?;
+    quarantine
+        .remove(repo, name)
+        .with_code(proc_exit::Code::FAILURE)?;
+    quarantine.save(repo).with_code(proc_exit::Code::FAILURE)?;
+    log::info!("Restored `{}` at {}", name, repo.abbrev_id(branch.id));
+
+    Ok(())
+}
+
+fn purge(
+    args: &crate::args::Args,
+    repo: &git_stack::git::GitRepo,
+    repo_config: &git_stack::config::RepoConfig,
+    quarantine: &mut git_stack::stash::Quarantine,
+    all: bool,
+) -> proc_exit::ExitResult {
+    let now = now();
+    // With quarantining off, there is nothing left worth keeping
+    let max_age = match repo_config.quarantine_age() {
+        Some(max_age) if !all => max_age,
+        _ => std::time::Duration::from_secs(0),
+    };
+
+    if args.dry_run {
+        let expired: Vec<_> = quarantine
+            .branches
+            .iter()
+            .filter(|b| max_age <= age(now, b.deleted))
+            .collect();
+        for branch in expired.iter() {
+            log::info!(
+                "Would purge `{}` ({})",
+                branch.name,
+                repo.abbrev_id(branch.id)
+            );
+        }
+        if expired.is_empty() {
+            log::info!("Nothing to purge");
+        }
+        return Ok(());
+    }
+
+    let purged =
+        purge_expired(repo, quarantine, max_age, now).with_code(proc_exit::Code::FAILURE)?;
+    quarantine.save(repo).with_code(proc_exit::Code::FAILURE)?;
+    if purged == 0 {
+        log::info!("Nothing to purge");
+    }
+
+    Ok(())
+}
+
+/// Finally remove the branches kept longer than `max_age`, returning how many
+pub(crate) fn purge_expired(
+    repo: &git_stack::git::GitRepo,
+    quarantine: &mut git_stack::stash::Quarantine,
+    max_age: std::time::Duration,
+    now: i64,
+) -> Result<usize, git2::Error> {
+    // Inclusive, so a zero age purges what was just deleted
+    let cutoff = now - max_age.as_secs() as i64 + 1;
+    let purged = quarantine.purge(repo, cutoff)?;
+    for branch in purged.iter() {
+        log::info!(
+            "Purged `{}` ({}), deleted {} ago",
+            branch.name,
+            repo.abbrev_id(branch.id),
+            crate::stack::format_age(age(now, branch.deleted))
+        );
+    }
+    Ok(purged.len())
+}
+
+/// Seconds since the epoch, as quarantine entries are timestamped
+pub(crate) fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+fn age(now: i64, then: i64) -> std::time::Duration {
+    std::time::Duration::from_secs(now.saturating_sub(then).max(0) as u64)
+}
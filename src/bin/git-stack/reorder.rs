@@ -0,0 +1,106 @@
+/// What to reorder with `git stack reorder`
+#[derive(Clone, Debug)]
+pub(crate) struct ReorderOptions {
+    /// Defaults to the current branch
+    pub(crate) branch: Option<String>,
+}
+
+const REORDER_FILE: &str = "REORDER_EDITMSG";
+
+/// Reorder `branch_name`'s commits in the editor, returning the script to restack them and what
+/// is on top of them, or `None` if the order is unchanged
+pub(crate) fn plan_reorder(
+    state: &crate::stack::State,
+    branch_name: &str,
+) -> eyre::Result<Option<git_stack::git::Script>> {
+    let branch = state
+        .repo
+        .find_local_branch(branch_name)
+        .ok_or_else(|| eyre::eyre!("could not find branch `{}`", branch_name))?;
+    if state.protected_branches.contains_oid(branch.id) {
+        eyre::bail!("`{}` is protected, refusing to reorder it", branch.name);
+    }
+    let start_id = crate::stack::branch_start(state, &branch)?;
+    let mut commits: Vec<_> = state
+        .repo
+        .commits_from(branch.id)
+        .take_while(|c| c.id != start_id)
+        .collect();
+    commits.reverse();
+    if commits.len() < 2 {
+        eyre::bail!("nothing to reorder, `{}` has one commit", branch.name);
+    }
+    let ids: Vec<_> = commits.iter().map(|c| c.id).collect();
+
+    let mut template = String::new();
+    for commit in commits.iter() {
+        template.push_str(&format!(
+            "pick {} # {}\n",
+            state
+                .repo
+                .raw()
+                .find_object(commit.id, None)?
+                .short_id()?
+                .as_str()
+                .unwrap_or_default(),
+            commit.summary
+        ));
+    }
+    template.push_str(&format!(
+        "\n# Reorder the commits of `{}`, oldest first.\n\
+         # Every commit must stay; use `git stack drop` or `git stack squash` for the others.\n",
+        branch.name
+    ));
+    let config = state.repo.raw().config()?;
+    let editor = git_stack::git::Editor::sequence_editor(&config);
+    let dir = state.repo.raw().path().join("git-stack");
+    let edited = editor.edit_text(&dir, REORDER_FILE, &template)?;
+
+    let mut order = Vec::new();
+    for line in edited.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let rev = match words.next() {
+            Some("pick") | Some("p") => words.next().unwrap_or_default(),
+            Some(rev) => rev,
+            None => continue,
+        };
+        let id = state
+            .repo
+            .resolve(rev)
+            .map(|c| c.id)
+            .filter(|id| ids.contains(id))
+            .ok_or_else(|| eyre::eyre!("`{}` is not one of `{}`'s commits", rev, branch.name))?;
+        if order.contains(&id) {
+            eyre::bail!("`{}` is listed more than once", rev);
+        }
+        order.push(id);
+    }
+    if order.len() != ids.len() {
+        eyre::bail!(
+            "{} of `{}`'s commits are missing, every commit must stay",
+            ids.len() - order.len(),
+            branch.name
+        );
+    }
+    if order == ids {
+        return Ok(None);
+    }
+
+    let oldest = state
+        .repo
+        .find_commit(ids[0])
+        .expect("branch commits exist");
+    let mut root = crate::stack::graph_from_parent(state, &oldest, start_id)?;
+    if !git_stack::graph::reorder(&mut root, &ids, &order) {
+        eyre::bail!(
+            "cannot reorder `{}`, other branches are on top of its commits",
+            branch.name
+        );
+    }
+
+    Ok(Some(git_stack::graph::to_script(&root)))
+}
@@ -0,0 +1,63 @@
+/// Restack the branches on old versions of other branches onto where those branches are now,
+/// returning `None` if none are
+pub(crate) fn plan_repair(
+    state: &crate::stack::State,
+) -> eyre::Result<Option<git_stack::git::Script>> {
+    let drifted = find_drifted(state);
+    if drifted.is_empty() {
+        return Ok(None);
+    }
+
+    let mut base_id = drifted[0].branch.id;
+    for drift in drifted.iter() {
+        for id in [drift.branch.id, drift.parent.id] {
+            base_id = state.repo.merge_base(base_id, id).ok_or_else(|| {
+                eyre::eyre!("`{}` shares no history with the rest", drift.branch.name)
+            })?;
+        }
+    }
+    let base = state
+        .repo
+        .find_commit(base_id)
+        .expect("merge base of existing commits exists");
+    let mut graphed_branches = state.branches.descendants(&state.repo, base_id);
+    let mut root = git_stack::graph::Node::new(base, &mut graphed_branches);
+    root = root.extend_branches(&state.repo, graphed_branches)?;
+    crate::stack::protect(state, &mut root, &state.protected_branches);
+    crate::stack::leave_frozen(state, &mut root);
+    for drift in drifted.iter() {
+        if git_stack::graph::reparent(&mut root, drift.branch.id, drift.fork_id, drift.parent.id) {
+            log::info!(
+                "Restacking `{}` onto `{}`, it is on an old version of it",
+                drift.branch.name,
+                drift.parent.name
+            );
+        } else {
+            log::warn!(
+                "Leaving `{}` in place, it can't be moved onto `{}`",
+                drift.branch.name,
+                drift.parent.name
+            );
+        }
+    }
+
+    Ok(Some(git_stack::graph::to_script(&root)))
+}
+
+/// Find branches built on a commit another branch's reflog says it used to point at, that it no
+/// longer contains
+fn find_drifted(state: &crate::stack::State) -> Vec<git_stack::git::Drifted> {
+    let branches: Vec<_> = state
+        .branches
+        .iter()
+        .flat_map(|(_, branches)| branches.iter())
+        .filter(|branch| !state.protected_branches.contains_oid(branch.id))
+        .map(|branch| {
+            let past_ids =
+                git_stack::git::past_ids(state.repo.raw(), &state.repo.branch_ref(&branch.name));
+            (branch.clone(), past_ids)
+        })
+        .collect();
+    let protected_ids: Vec<_> = state.protected_branches.oids().collect();
+    git_stack::git::find_drifted(state.repo.raw(), &branches, &protected_ids)
+}
@@ -0,0 +1,419 @@
+use eyre::WrapErr;
+use proc_exit::WithCodeResultExt;
+
+/// Restack randomly generated stacks and undo it, checking nothing is lost along the way
+///
+/// Each run generates its history from its own seed, `seed + run`, so a failure is reproduced by
+/// passing that seed with `--runs 1`.
+pub fn simulate(args: &crate::args::SimulateArgs) -> proc_exit::ExitResult {
+    let seed = args.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64
+    });
+    let exe = std::env::current_exe().with_code(proc_exit::Code::FAILURE)?;
+
+    for run in 0..args.runs {
+        let run_seed = seed.wrapping_add(run);
+        let dir = std::env::temp_dir().join(format!("git-stack-simulate-{}", run_seed));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).with_code(proc_exit::Code::IO_ERR)?;
+        }
+        let result = History::generate(&dir, run_seed)
+            .and_then(|history| history.check(&exe).map(|()| history));
+        match result {
+            Ok(history) => {
+                log::info!(
+                    "Run {} (seed {}): ok, {} {} branch(es) over {} commit(s)",
+                    run + 1,
+                    run_seed,
+                    if history.conflicted {
+                        "stopped on the conflict and aborted restacking"
+                    } else {
+                        "restacked"
+                    },
+                    history.branches.len() - 1,
+                    history.commits
+                );
+                if !args.keep {
+                    std::fs::remove_dir_all(&dir).with_code(proc_exit::Code::IO_ERR)?;
+                }
+            }
+            Err(err) => {
+                return Err(proc_exit::Code::FAILURE.with_message(format!(
+                    "Run {} (seed {}) failed: {:#}\n\
+                     The repository is kept at {}, reproduce it with `git stack simulate --seed {} --runs 1`",
+                    run + 1,
+                    run_seed,
+                    err,
+                    dir.display(),
+                    run_seed
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+const PROTECTED_BRANCH: &str = "main";
+
+/// Every commit edits a line of one of these, so restacking has changes to merge
+const FILES: [&str; 3] = ["a.txt", "b.txt", "c.txt"];
+const LINES: usize = 12;
+
+/// A generated repository and the branches as they were before restacking
+struct History {
+    dir: std::path::PathBuf,
+    branches: Vec<(String, git2::Oid)>,
+    head: String,
+    commits: usize,
+    /// Restacking is expected to stop on a conflict
+    conflicted: bool,
+    /// There are merge commits to recreate with `--rebase-merges`
+    merges: bool,
+}
+
+impl History {
+    /// Build `main` with stacks based on its older commits, and on top of each other
+    ///
+    /// `main` edits every fourth line of `FILES` and the branches the lines two after them, so the
+    /// edits merge cleanly, unless a branch is made to edit a line `main` changes later on.
+    fn generate(dir: &std::path::Path, seed: u64) -> eyre::Result<Self> {
+        let mut rng = Rng::new(seed);
+        let repo = git2::Repository::init(dir)?;
+        {
+            let mut config = repo.config()?;
+            config.set_str("user.name", "Simulator")?;
+            config.set_str("user.email", "simulator@example.com")?;
+        }
+        let mut commits = 0;
+
+        let mut main_ids = vec![initial_commit(&repo)?];
+        // Which line each `main` commit edited
+        let mut main_edits = vec![None];
+        for i in 1..=1 + rng.below(3) {
+            let parent = *main_ids.last().unwrap();
+            let edit = (rng.below(FILES.len()), 4 * rng.below(LINES / 4));
+            let message = format!("main-{}", i);
+            main_ids.push(edit_line(&repo, parent, edit, &message, &message)?);
+            main_edits.push(Some(edit));
+        }
+        commits += main_ids.len();
+
+        let mut conflicted = false;
+        let mut want_conflict = rng.below(4) == 0;
+        let mut merges = false;
+        let mut stacked: Vec<Vec<git2::Oid>> = Vec::new();
+        // Squashing or moving a fixup changes the commits up to it, which branches can't be on
+        let mut unstackable = std::collections::HashSet::new();
+        let branch_count = 1 + rng.below(4);
+        let mut branches = Vec::new();
+        for b in 0..branch_count {
+            // Based on an old `main` to restack, or on part of an earlier branch
+            let stackable: Vec<_> = stacked
+                .iter()
+                .flatten()
+                .copied()
+                .filter(|id| !unstackable.contains(id))
+                .collect();
+            let (mut parent, fork) = if stackable.is_empty() || rng.below(2) == 0 {
+                let fork = rng.below(main_ids.len() - 1);
+                (main_ids[fork], Some(fork))
+            } else {
+                (stackable[rng.below(stackable.len())], None)
+            };
+            let mut ids: Vec<git2::Oid> = Vec::new();
+            // The last commit's summary, if a `fixup!` can target it
+            let mut target: Option<String> = None;
+            for c in 0..1 + rng.below(3) {
+                let message = format!("branch-{}-{}", b, c);
+                let edit = (rng.below(FILES.len()), 4 * rng.below(LINES / 4) + 2);
+                let later_main_edit =
+                    fork.and_then(|fork| main_edits[fork + 1..].iter().flatten().next());
+                let roll = rng.below(8);
+                let (id, summary) = match later_main_edit {
+                    Some(main_edit) if want_conflict => {
+                        want_conflict = false;
+                        conflicted = true;
+                        let id = edit_line(&repo, parent, *main_edit, &message, &message)?;
+                        (id, Some(message))
+                    }
+                    _ if roll == 0 && target.is_some() => {
+                        let fixup = format!("fixup! {}", target.take().unwrap());
+                        unstackable.insert(parent);
+                        (edit_line(&repo, parent, edit, &message, &fixup)?, None)
+                    }
+                    _ if roll == 1 => {
+                        let side_message = format!("{}-side", message);
+                        let side_edit = (edit.0, (edit.1 + 4) % LINES);
+                        let side =
+                            edit_line(&repo, parent, side_edit, &side_message, &side_message)?;
+                        let ours = edit_line(&repo, parent, edit, &message, &message)?;
+                        merges = true;
+                        commits += 2;
+                        let id = merge(&repo, ours, side, &format!("Merge {}", side_message))?;
+                        (id, None)
+                    }
+                    _ => (
+                        edit_line(&repo, parent, edit, &message, &message)?,
+                        Some(message),
+                    ),
+                };
+                parent = id;
+                target = summary;
+                ids.push(parent);
+                commits += 1;
+            }
+            branches.push((format!("branch-{}", b), parent));
+            stacked.push(ids);
+        }
+
+        let main_id = *main_ids.last().unwrap();
+        repo.branch(PROTECTED_BRANCH, &repo.find_commit(main_id)?, true)?;
+        for (name, id) in branches.iter() {
+            repo.branch(name, &repo.find_commit(*id)?, true)?;
+        }
+        let head = branches[rng.below(branches.len())].0.clone();
+        repo.set_head(&format!("refs/heads/{}", head))?;
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
+
+        branches.push((PROTECTED_BRANCH.to_owned(), main_id));
+        Ok(Self {
+            dir: dir.to_owned(),
+            branches,
+            head,
+            commits,
+            conflicted,
+            merges,
+        })
+    }
+
+    /// Restack, check every branch kept its changes on top of `main`, then undo, checking every
+    /// branch is back
+    ///
+    /// When a conflict is expected, restacking has to stop and `--abort` put everything back
+    /// instead.
+    fn check(&self, exe: &std::path::Path) -> eyre::Result<()> {
+        let mut args = vec!["--rebase", "--stack", "all", "--fixup", "squash"];
+        if self.merges {
+            args.push("--rebase-merges");
+        }
+        self.run(exe, &args)?;
+        let repo = git2::Repository::open(&self.dir)?;
+        // Stopping to resolve a conflict isn't a failure
+        let stopped = repo.index()?.has_conflicts();
+        match (self.conflicted, stopped) {
+            (true, true) => {
+                self.run(exe, &["--abort"])?;
+                return self.check_restored(&repo, "--abort");
+            }
+            (true, false) => eyre::bail!("restacking was expected to stop on a conflict"),
+            (false, true) => eyre::bail!("restacking stopped on an unexpected conflict"),
+            (false, false) => {}
+        }
+
+        let main_id = self.branch_id(&repo, PROTECTED_BRANCH)?;
+        for (name, old_id) in self.branches.iter() {
+            if name == PROTECTED_BRANCH {
+                continue;
+            }
+            let new_id = self.branch_id(&repo, name)?;
+            if repo.merge_base(new_id, main_id)? != main_id {
+                eyre::bail!("`{}` was not restacked onto `{}`", name, PROTECTED_BRANCH);
+            }
+            // Each line is what the branch changed it to, or else what `main` has
+            let base_id = repo.merge_base(*old_id, main_id)?;
+            for file in FILES.iter() {
+                let base = read_lines(&repo, base_id, file)?;
+                let old = read_lines(&repo, *old_id, file)?;
+                let main = read_lines(&repo, main_id, file)?;
+                let new = read_lines(&repo, new_id, file)?;
+                for (i, new_line) in new.iter().enumerate() {
+                    let expected = if old[i] != base[i] { &old[i] } else { &main[i] };
+                    if new_line != expected {
+                        eyre::bail!(
+                            "`{}` has `{}` on line {} of `{}` when restacked, rather than `{}`",
+                            name,
+                            new_line,
+                            i + 1,
+                            file,
+                            expected
+                        );
+                    }
+                }
+            }
+        }
+        self.check_head(&repo)?;
+
+        self.run(exe, &["undo", "--yes"])?;
+        self.check_restored(&repo, "undo")?;
+
+        Ok(())
+    }
+
+    fn check_restored(&self, repo: &git2::Repository, how: &str) -> eyre::Result<()> {
+        for (name, old_id) in self.branches.iter() {
+            if self.branch_id(repo, name)? != *old_id {
+                eyre::bail!("`{}` did not restore `{}`", how, name);
+            }
+        }
+        self.check_head(repo)
+    }
+
+    fn check_head(&self, repo: &git2::Repository) -> eyre::Result<()> {
+        let head = repo.head()?;
+        if head.shorthand() != Some(self.head.as_str()) {
+            eyre::bail!(
+                "`HEAD` moved from `{}` to `{}`",
+                self.head,
+                head.shorthand().unwrap_or("a detached commit")
+            );
+        }
+        let mut options = git2::StatusOptions::new();
+        options.include_untracked(true);
+        if !repo.statuses(Some(&mut options))?.is_empty() {
+            eyre::bail!("the working tree was left dirty");
+        }
+        Ok(())
+    }
+
+    fn branch_id(&self, repo: &git2::Repository, name: &str) -> eyre::Result<git2::Oid> {
+        let branch = repo
+            .find_branch(name, git2::BranchType::Local)
+            .wrap_err_with(|| format!("`{}` is gone", name))?;
+        branch
+            .get()
+            .target()
+            .ok_or_else(|| eyre::eyre!("`{}` is not a commit", name))
+    }
+
+    /// Run `git-stack` in the repository, away from the user's config
+    fn run(&self, exe: &std::path::Path, args: &[&str]) -> eyre::Result<()> {
+        log::trace!("git-stack {}", args.join(" "));
+        let output = std::process::Command::new(exe)
+            .args(args)
+            .current_dir(&self.dir)
+            .env("HOME", &self.dir)
+            .env("XDG_CONFIG_HOME", &self.dir)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env_remove("GIT_DIR")
+            .env_remove("GIT_WORK_TREE")
+            .output()
+            .wrap_err("could not run `git-stack`")?;
+        if !output.status.success() {
+            eyre::bail!(
+                "`git-stack {}` failed with {}: {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The commit every history starts from, with `FILES` numbered line by line
+fn initial_commit(repo: &git2::Repository) -> eyre::Result<git2::Oid> {
+    let signature = repo.signature()?;
+    let mut builder = repo.treebuilder(None)?;
+    for file in FILES.iter() {
+        let content: String = (0..LINES).map(|i| format!("{}\n", i + 1)).collect();
+        builder.insert(file, repo.blob(content.as_bytes())?, 0o100644)?;
+    }
+    let tree = repo.find_tree(builder.write()?)?;
+    let id = repo.commit(None, &signature, &signature, "initial", &tree, &[])?;
+    Ok(id)
+}
+
+/// Commit `content` over a line of one of `FILES`, on top of `parent`
+fn edit_line(
+    repo: &git2::Repository,
+    parent: git2::Oid,
+    (file, line): (usize, usize),
+    content: &str,
+    message: &str,
+) -> eyre::Result<git2::Oid> {
+    let signature = repo.signature()?;
+    let parent = repo.find_commit(parent)?;
+    let mut lines = read_lines(repo, parent.id(), FILES[file])?;
+    lines[line] = content.to_owned();
+    let content: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    let base_tree = parent.tree()?;
+    let mut builder = repo.treebuilder(Some(&base_tree))?;
+    builder.insert(FILES[file], repo.blob(content.as_bytes())?, 0o100644)?;
+    let tree = repo.find_tree(builder.write()?)?;
+    let id = repo.commit(None, &signature, &signature, message, &tree, &[&parent])?;
+    Ok(id)
+}
+
+/// Merge `side` into `parent`, which edited different lines
+fn merge(
+    repo: &git2::Repository,
+    parent: git2::Oid,
+    side: git2::Oid,
+    message: &str,
+) -> eyre::Result<git2::Oid> {
+    let signature = repo.signature()?;
+    let parent = repo.find_commit(parent)?;
+    let side = repo.find_commit(side)?;
+    let mut index = repo.merge_commits(&parent, &side, None)?;
+    if index.has_conflicts() {
+        eyre::bail!("generated a conflicting merge");
+    }
+    let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+    let id = repo.commit(
+        None,
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &[&parent, &side],
+    )?;
+    Ok(id)
+}
+
+fn read_lines(repo: &git2::Repository, id: git2::Oid, file: &str) -> eyre::Result<Vec<String>> {
+    let tree = repo.find_commit(id)?.tree()?;
+    let entry = tree
+        .get_name(file)
+        .ok_or_else(|| eyre::eyre!("`{}` is missing from {}", file, id))?;
+    let blob = repo.find_blob(entry.id())?;
+    let lines: Vec<_> = String::from_utf8_lossy(blob.content())
+        .lines()
+        .map(ToOwned::to_owned)
+        .collect();
+    if lines.len() != LINES {
+        eyre::bail!(
+            "`{}` has {} lines in {}, not {}",
+            file,
+            lines.len(),
+            id,
+            LINES
+        );
+    }
+    Ok(lines)
+}
+
+/// xorshift64*, plenty for shuffling histories without another dependency
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero
+        Self((seed ^ 0x9E37_79B9_7F4A_7C15).max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A number in `0..n`
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
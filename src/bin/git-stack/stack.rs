@@ -6,22 +6,86 @@ use eyre::WrapErr;
 use itertools::Itertools;
 use proc_exit::WithCodeResultExt;
 
-struct State {
-    repo: git_stack::git::GitRepo,
-    branches: git_stack::git::Branches,
-    protected_branches: git_stack::git::Branches,
+pub(crate) struct State {
+    pub(crate) repo: git_stack::git::GitRepo,
+    pub(crate) branches: git_stack::git::Branches,
+    pub(crate) protected_branches: git_stack::git::Branches,
+    /// Commits tagged per `stack.protected-tag`
+    protected_tags: Vec<git2::Oid>,
     head_commit: std::rc::Rc<git_stack::git::Commit>,
     stacks: Vec<StackState>,
+    selected_branches: Option<git_stack::git::BranchGlobs>,
+    /// Branches kept out of rebasing, pushing, and deleting, per `git stack freeze`
+    pub(crate) frozen_branches: HashSet<String>,
 
     rebase: bool,
+    /// Per `--sandbox`, even under `--dry-run` which leaves the sandbox refs alone
+    sandbox: bool,
+    interactive: bool,
+    resume: bool,
+    abort: bool,
+    step: bool,
+    stage_only: bool,
+    commit_plan: bool,
+    plan_out: Option<std::path::PathBuf>,
+    apply_plan: Option<std::path::PathBuf>,
+    reword: Option<RewordOptions>,
+    fix: Option<FixOptions>,
+    absorb: bool,
+    move_branch: Option<(String, String)>,
+    repair: bool,
+    split: Option<(String, Vec<std::path::PathBuf>)>,
+    drop_commit: Option<String>,
+    squash_commits: Option<SquashRange>,
+    reorder: Option<crate::reorder::ReorderOptions>,
+    sync: bool,
+    submit: Option<SubmitOptions>,
+    export_bundle: Option<std::path::PathBuf>,
+    import_bundle: Option<ImportBundle>,
+    format_patch: Option<FormatPatch>,
+    delete_merged: bool,
+    archive: Option<std::time::Duration>,
+    rename: Option<RenameBranch>,
+    create: Option<CreateBranch>,
+    adopt: Option<AdoptCommits>,
+    land: Option<LandBranch>,
+    run: Option<RunOptions>,
     pull: bool,
     push: bool,
     fixup: git_stack::config::Fixup,
-    dry_run: bool,
+    dedup_cherry_picks: bool,
+    drop_empty: bool,
+    exec: Option<String>,
+    push_policy: git_stack::config::PushPolicy,
+    push_wip: git_stack::config::PushWip,
+    verify: bool,
+    pub(crate) dry_run: bool,
+    expect_plan: Option<String>,
+    on_conflict: git_stack::config::OnConflict,
+    risk_gate: git_stack::config::RiskGate,
+    quarantine_age: Option<std::time::Duration>,
+    force: bool,
     snapshot_capacity: Option<usize>,
+    audit_log: Option<git_stack::audit::AuditLog>,
+    maintenance: git_stack::config::Maintenance,
+    maintenance_threshold: usize,
+    rewritten: usize,
+    /// Whether a rebase stopped for `git stack --continue`
+    stopped: bool,
 
     show_format: git_stack::config::Format,
     show_stacked: bool,
+    porcelain: bool,
+    show_full_hashes: bool,
+    show_prs: bool,
+    pr_cache_age: Option<std::time::Duration>,
+    gitlab_hosts: Vec<String>,
+    show_prefetch: bool,
+    offline: bool,
+    allowed_emails: Vec<String>,
+    truncate: bool,
+    palette: Palette,
+    glyphs: Glyphs,
 }
 
 impl State {
@@ -34,14 +98,231 @@ impl State {
             .update(args.to_config());
 
         let mut rebase = args.rebase;
-        let pull = args.pull;
+        let sync = matches!(args.subcommand, Some(crate::args::Subcommand::Sync));
+        let delete_merged = matches!(args.subcommand, Some(crate::args::Subcommand::DeleteMerged));
+        let repair = matches!(args.subcommand, Some(crate::args::Subcommand::Repair));
+        let pull = args.pull || sync;
         if pull {
             log::trace!("`--pull` implies `--rebase`");
             rebase = true;
         }
+        let sandbox = args.sandbox;
+        if sandbox {
+            log::trace!("`--sandbox` implies `--rebase`");
+            rebase = true;
+        }
+        let stage_only = args.stage_only;
+        if stage_only {
+            log::trace!("`--stage-only` implies `--rebase`");
+            rebase = true;
+        }
+        let commit_plan = args.commit_plan;
+        let step = args.step;
+        if step {
+            log::trace!("`--step` implies `--rebase`");
+            rebase = true;
+        }
+        let resume = args.resume;
+        if resume {
+            log::trace!("`--continue` implies `--rebase`");
+            rebase = true;
+        }
+        let abort = args.abort;
+        let interactive = args.interactive;
+        if interactive {
+            log::trace!("`--interactive` implies `--rebase`");
+            rebase = true;
+        }
+        let expect_plan = args.expect_plan.clone();
+        if expect_plan.is_some() {
+            log::trace!("`--expect-plan` implies `--rebase`");
+            rebase = true;
+        }
+        if args.onto.is_some() {
+            log::trace!("`--onto` implies `--rebase`");
+            rebase = true;
+        }
+        let (plan_out, apply_plan) = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Plan(plan)) => (Some(plan.out.clone()), None),
+            Some(crate::args::Subcommand::Apply(apply)) => (None, Some(apply.plan.clone())),
+            _ => (None, None),
+        };
+        if plan_out.is_some() || apply_plan.is_some() {
+            rebase = true;
+        }
+        let reword = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Reword(reword)) => Some(RewordOptions {
+                rev: if reword.all {
+                    None
+                } else {
+                    Some(reword.rev.clone().unwrap_or_else(|| "HEAD".to_owned()))
+                },
+                replace: reword.replace.clone(),
+            }),
+            _ => None,
+        };
+        let fix = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Fix(fix)) => {
+                if !fix.sign_off && !fix.reset_author {
+                    return Err(proc_exit::Code::USAGE_ERR
+                        .with_message("Nothing to fix, pass `--sign-off` or `--reset-author`"));
+                }
+                Some(FixOptions {
+                    sign_off: fix.sign_off,
+                    reset_author: fix.reset_author,
+                    reset_date: fix.reset_date,
+                })
+            }
+            _ => None,
+        };
+        let move_branch = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Move(move_args)) => {
+                Some((move_args.branch.clone(), move_args.onto.clone()))
+            }
+            _ => None,
+        };
+        let split = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Split(split)) => {
+                Some((split.rev.clone(), split.paths.clone()))
+            }
+            _ => None,
+        };
+        let drop_commit = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Drop(drop_args)) => Some(drop_args.rev.clone()),
+            _ => None,
+        };
+        let squash_commits = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Squash(squash_args)) => {
+                match (squash_args.range.as_ref(), squash_args.branch.as_ref()) {
+                    (Some(range), _) => Some(SquashRange::Range(range.clone())),
+                    (None, Some(branch)) => Some(SquashRange::Branch(branch.clone())),
+                    (None, None) => unreachable!("clap requires one"),
+                }
+            }
+            _ => None,
+        };
+        let reorder = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Reorder(reorder_args)) => {
+                Some(crate::reorder::ReorderOptions {
+                    branch: reorder_args.branch.clone(),
+                })
+            }
+            _ => None,
+        };
+        let submit = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Submit(submit_args)) => Some(SubmitOptions {
+                draft: submit_args.draft,
+            }),
+            _ => None,
+        };
+        let rename = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Rename(rename_args)) => Some(RenameBranch {
+                old: rename_args.old.clone(),
+                new: rename_args.new.clone(),
+                remote: rename_args.remote,
+            }),
+            _ => None,
+        };
+        let create = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Create(create_args)) => Some(CreateBranch {
+                name: expand_branch_template(
+                    repo_config.branch_template(),
+                    &create_args.name,
+                    create_args.ticket.as_deref(),
+                    repo.raw(),
+                )
+                .with_code(proc_exit::Code::USAGE_ERR)?,
+                at: create_args.at.clone(),
+            }),
+            _ => None,
+        };
+        let archive = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Archive(archive_args)) => {
+                let max_age = match archive_args.older_than.as_deref() {
+                    Some(age) => Some(
+                        git_stack::config::parse_age(age)
+                            .filter(|age| *age != std::time::Duration::from_secs(0))
+                            .ok_or_else(|| {
+                                proc_exit::Code::USAGE_ERR
+                                    .with_message(format!("`{}` is not an age, like `90d`", age))
+                            })?,
+                    ),
+                    None => repo_config.all_max_age(),
+                };
+                let max_age = max_age.ok_or_else(|| {
+                    proc_exit::Code::USAGE_ERR
+                        .with_message("`stack.all-max-age` is off, pass `--older-than <age>`")
+                })?;
+                Some(max_age)
+            }
+            _ => None,
+        };
+        let adopt = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Adopt(adopt_args)) => Some(AdoptCommits {
+                rev: adopt_args.rev.clone(),
+                branch: adopt_args.branch.clone(),
+            }),
+            _ => None,
+        };
+        let land = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Land(land_args)) => Some(LandBranch {
+                branch: land_args.branch.clone(),
+                forge: land_args.forge,
+            }),
+            _ => None,
+        };
+        if land.is_some() {
+            log::trace!("`land` implies `--rebase`");
+            rebase = true;
+        }
+        let export_bundle = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::ExportBundle(export)) => Some(export.out.clone()),
+            _ => None,
+        };
+        let import_bundle = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::ImportBundle(import)) => Some(ImportBundle {
+                bundle: import.bundle.clone(),
+                namespace: import
+                    .namespace
+                    .clone()
+                    .unwrap_or_else(|| repo_config.import_namespace().to_owned()),
+            }),
+            _ => None,
+        };
+        let format_patch = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::FormatPatch(format_args)) => Some(FormatPatch {
+                out: format_args.out.clone(),
+                per_branch: format_args.per_branch,
+                send: format_args.send,
+                to: format_args.to.clone(),
+                cc: format_args.cc.clone(),
+            }),
+            _ => None,
+        };
+        let run = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Run(run_args)) => {
+                if sandbox {
+                    return Err(proc_exit::Code::USAGE_ERR
+                        .with_message("`run` needs to check out branches, it can't be sandboxed"));
+                }
+                Some(RunOptions {
+                    command: run_args.command.clone(),
+                    fail_fast: run_args.fail_fast,
+                })
+            }
+            _ => None,
+        };
+        let (absorb, squash) = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Absorb(absorb)) => (true, absorb.squash),
+            _ => (false, false),
+        };
+        if squash {
+            log::trace!("`absorb --squash` implies `--rebase`");
+            rebase = true;
+        }
         let rebase = rebase;
 
-        let fixup = if args.fixup.is_some() || rebase {
+        let fixup = if args.fixup.is_some() || args.fix || rebase {
             repo_config.fixup()
         } else {
             // Assume the user is only wanting to show the tree and not modify it.
@@ -54,27 +335,132 @@ impl State {
             }
             no_op
         };
-        let push = args.push;
+        let fixup = if squash {
+            git_stack::config::Fixup::Squash
+        } else {
+            fixup
+        };
+        let push = args.push || sync;
         let protected = git_stack::git::ProtectedBranches::new(
             repo_config.protected_branches().iter().map(|s| s.as_str()),
         )
         .with_code(proc_exit::Code::CONFIG_ERR)?;
+        let selected_branches = if args.branches.is_empty() {
+            None
+        } else {
+            let selected_branches =
+                git_stack::git::BranchGlobs::new(args.branches.iter().map(|s| s.as_str()))
+                    .with_code(proc_exit::Code::USAGE_ERR)?;
+            Some(selected_branches)
+        };
         let dry_run = args.dry_run;
         let snapshot_capacity = repo_config.capacity();
+        let maintenance = repo_config.maintenance();
+        let maintenance_threshold = repo_config.maintenance_threshold();
+        // Neither a dry-run nor the sandbox rewrite any branches
+        let audit_log = if dry_run || sandbox {
+            None
+        } else {
+            repo_config
+                .audit_log()
+                .map(|path| git_stack::audit::AuditLog::new(path, repo.raw()))
+        };
 
         let show_format = repo_config.show_format();
         let show_stacked = repo_config.show_stacked();
+        let porcelain = matches!(
+            args.subcommand,
+            Some(crate::args::Subcommand::Status(crate::args::StatusArgs {
+                porcelain: true
+            }))
+        );
+        let show_full_hashes = repo_config.show_full_hashes();
+        let show_prs = repo_config.show_prs();
+        let show_prefetch = repo_config.show_prefetch() && !args.offline;
+        let allowed_emails = if repo_config.allowed_emails().is_empty() {
+            Vec::new()
+        } else {
+            let mut allowed_emails = repo_config.allowed_emails().to_vec();
+            if let Ok(email) = repo.raw().config().and_then(|c| c.get_string("user.email")) {
+                allowed_emails.push(email);
+            }
+            allowed_emails
+        };
+        let truncate = !args.no_truncate;
+        let palette = Palette::from_config(&repo_config);
+        let glyphs = Glyphs::new(repo_config.show_glyphs());
 
         repo.set_push_remote(repo_config.push_remote());
         repo.set_pull_remote(repo_config.pull_remote());
+        repo.set_rebase_merges(repo_config.rebase_merges());
+        repo.set_max_history(repo_config.max_history());
+        repo.set_dirty_check(repo_config.dirty_check());
+        repo.set_signer(signer(&repo).with_code(proc_exit::Code::CONFIG_ERR)?);
+        repo.set_committer_date(repo_config.committer_date());
+        repo.set_reset_author_date(args.reset_author_date);
 
-        let branches = git_stack::git::Branches::new(repo.local_branches());
+        let move_onto = match args.subcommand.as_ref() {
+            Some(crate::args::Subcommand::Move(move_args)) => Some(move_args.onto.as_str()),
+            _ => None,
+        };
+        let named_branches = args
+            .base
+            .as_deref()
+            .into_iter()
+            .chain(args.onto.as_deref())
+            .chain(move_onto);
+        if sandbox && !args.dry_run {
+            // Before anything is written, so it all lands under `refs/git-stack/sandbox/`
+            repo.enter_sandbox().with_code(proc_exit::Code::FAILURE)?;
+        }
+        track_remote_branches(&mut repo, named_branches, args.dry_run)
+            .with_code(proc_exit::Code::USAGE_ERR)?;
+
+        let mut branches = git_stack::git::Branches::new(repo.local_branches());
         let protected_branches = branches.protected(&protected);
+        if !args.exclude.is_empty() {
+            let excluded =
+                git_stack::git::BranchGlobs::new(args.exclude.iter().map(|s| s.as_str()))
+                    .with_code(proc_exit::Code::USAGE_ERR)?;
+            branches = git_stack::git::Branches::new(
+                branches
+                    .iter()
+                    .flat_map(|(_, b)| b.iter())
+                    .filter(|b| {
+                        // The bases stacks are found from can't be left out
+                        let is_protected = protected_branches
+                            .get(b.id)
+                            .into_iter()
+                            .flatten()
+                            .any(|p| p.name == b.name);
+                        let is_excluded = excluded.matches(&b.name) && !is_protected;
+                        if is_excluded {
+                            log::debug!("Skipping `{}`, excluded by `--exclude`", b.name);
+                        }
+                        !is_excluded
+                    })
+                    .cloned(),
+            );
+        }
+        let frozen_branches = frozen_branches(repo.raw());
+        let protected_tags = if repo_config.protected_tags().is_empty() {
+            Vec::new()
+        } else {
+            let protected_tag_names = git_stack::git::ProtectedBranches::new(
+                repo_config.protected_tags().iter().map(|s| s.as_str()),
+            )
+            .with_code(proc_exit::Code::CONFIG_ERR)?;
+            repo.tags()
+                .filter(|(name, _)| protected_tag_names.is_protected(name))
+                .map(|(_, id)| id)
+                .unique()
+                .collect()
+        };
         let head_commit = repo.head_commit();
         let base = args
             .base
             .as_deref()
-            .map(|name| resolve_explicit_base(&repo, name))
+            .map(|name| resolve_base_commit(&repo, name))
             .transpose()
             .with_code(proc_exit::Code::USAGE_ERR)?;
         let onto = args
@@ -83,26 +469,86 @@ impl State {
             .map(|name| resolve_explicit_base(&repo, name))
             .transpose()
             .with_code(proc_exit::Code::USAGE_ERR)?;
-        let stacks = match (base, onto, repo_config.stack()) {
-            (Some(base), None, git_stack::config::Stack::All) => {
-                let onto = base.clone();
-                vec![StackState {
-                    base,
-                    onto,
-                    branches: branches.all(),
-                }]
+        let stack = if sync {
+            log::trace!("`sync` includes every stack");
+            git_stack::config::Stack::All
+        } else if delete_merged {
+            log::trace!("`delete-merged` includes every stack");
+            git_stack::config::Stack::All
+        } else if archive.is_some() {
+            log::trace!("`archive` includes every stack");
+            git_stack::config::Stack::All
+        } else if import_bundle.is_some() {
+            log::trace!("`import-bundle` includes every stack");
+            git_stack::config::Stack::All
+        } else {
+            repo_config.stack()
+        };
+        let stack = match (&base, &onto, stack) {
+            (_, Some(_), git_stack::config::Stack::All) => {
+                log::trace!("`--onto` moves the current stack");
+                git_stack::config::Stack::Current
             }
-            (None, Some(onto), git_stack::config::Stack::All) => {
-                let base = onto.clone();
+            (_, _, stack) => stack,
+        };
+        let stacks = match (base, onto, stack) {
+            (Some((base, detached)), None, git_stack::config::Stack::All) => {
+                let onto = base.clone();
+                let branches = branches.related(&repo, base.id);
                 vec![StackState {
                     base,
                     onto,
-                    branches: branches.all(),
+                    branches,
+                    base_detached: detached,
+                    onto_detached: detached,
                 }]
             }
             (None, None, git_stack::config::Stack::All) => {
+                let cutoff = if sync
+                    || delete_merged
+                    || archive.is_some()
+                    || import_bundle.is_some()
+                    || args.everything
+                {
+                    None
+                } else {
+                    repo_config.all_max_age().map(|age| {
+                        let now = std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .unwrap_or_default();
+                        now.as_secs().saturating_sub(age.as_secs()) as i64
+                    })
+                };
+                let mut stale = 0;
                 let mut stack_branches = std::collections::BTreeMap::new();
                 for (branch_id, branch) in branches.iter() {
+                    if let Some(cutoff) = cutoff {
+                        if branch_id != head_commit.id
+                            && !protected_branches.contains_oid(branch_id)
+                            && committed_before(&repo, branch_id, cutoff)
+                        {
+                            for branch in branch.iter() {
+                                log::debug!(
+                                    "Skipping `{}`, it is older than `stack.all-max-age`",
+                                    branch.name
+                                );
+                            }
+                            stale += branch.len();
+                            continue;
+                        }
+                    }
+                    // Like `gh-pages`, with a history of its own
+                    if git_stack::git::find_protected_base(&repo, &protected_branches, branch_id)
+                        .is_none()
+                    {
+                        for branch in branch.iter() {
+                            log::info!(
+                                "Skipping `{}`, it shares no history with a protected branch",
+                                branch.name
+                            );
+                        }
+                        continue;
+                    }
                     let base_branch =
                         resolve_implicit_base(&repo, branch_id, &branches, &protected_branches)
                             .with_code(proc_exit::Code::USAGE_ERR)?;
@@ -111,6 +557,12 @@ impl State {
                         .or_insert_with(git_stack::git::Branches::default)
                         .extend(branch.iter().cloned());
                 }
+                if 0 < stale {
+                    log::debug!(
+                        "Skipped {} branch(es) older than `stack.all-max-age`, use `--everything` to include them",
+                        stale
+                    );
+                }
                 stack_branches
                     .into_iter()
                     .map(|(base, branches)| {
@@ -119,17 +571,21 @@ impl State {
                             base,
                             onto,
                             branches,
+                            base_detached: false,
+                            onto_detached: false,
                         }
                     })
                     .collect()
             }
             (base, onto, stack) => {
-                let base = base
+                let (base, base_detached) = base
                     .map(Result::Ok)
                     .unwrap_or_else(|| {
                         resolve_implicit_base(&repo, head_commit.id, &branches, &protected_branches)
+                            .map(|base| (base, false))
                     })
                     .with_code(proc_exit::Code::USAGE_ERR)?;
+                let onto_detached = onto.is_none() && base_detached;
                 let onto = onto.unwrap_or_else(|| base.clone());
                 let merge_base_oid = repo
                     .merge_base(base.id, head_commit.id)
@@ -157,6 +613,8 @@ impl State {
                     base,
                     onto,
                     branches: stack_branches,
+                    base_detached,
+                    onto_detached,
                 }]
             }
         };
@@ -165,22 +623,82 @@ impl State {
             repo,
             branches,
             protected_branches,
+            protected_tags,
             head_commit,
             stacks,
+            selected_branches,
+            frozen_branches,
 
             rebase,
+            sandbox,
+            interactive,
+            resume,
+            abort,
+            step,
+            stage_only,
+            commit_plan,
+            plan_out,
+            apply_plan,
+            reword,
+            fix,
+            absorb,
+            move_branch,
+            repair,
+            split,
+            drop_commit,
+            squash_commits,
+            reorder,
+            sync,
+            submit,
+            export_bundle,
+            import_bundle,
+            format_patch,
+            delete_merged,
+            archive,
+            rename,
+            create,
+            adopt,
+            land,
+            run,
             pull,
             push,
             fixup,
+            dedup_cherry_picks: repo_config.dedup_cherry_picks(),
+            drop_empty: repo_config.drop_empty(),
+            exec: repo_config.exec().map(ToOwned::to_owned),
+            push_policy: repo_config.push_policy(),
+            push_wip: repo_config.push_wip(),
+            verify: args.verify,
             dry_run,
+            expect_plan,
+            on_conflict: repo_config.on_conflict(),
+            risk_gate: repo_config.risk_gate(),
+            quarantine_age: repo_config.quarantine_age(),
+            force: args.force,
             snapshot_capacity,
+            audit_log,
+            maintenance,
+            maintenance_threshold,
+            rewritten: 0,
+            stopped: false,
 
             show_format,
             show_stacked,
+            porcelain,
+            show_full_hashes,
+            show_prs,
+            pr_cache_age: repo_config.pr_cache_age(),
+            gitlab_hosts: repo_config.gitlab_hosts().to_vec(),
+            show_prefetch,
+            offline: args.offline,
+            allowed_emails,
+            truncate,
+            palette,
+            glyphs,
         })
     }
 
-    fn update(&mut self) -> eyre::Result<()> {
+    pub(crate) fn update(&mut self) -> eyre::Result<()> {
         self.head_commit = self.repo.head_commit();
         self.branches.update(&self.repo);
         self.protected_branches.update(&self.repo);
@@ -197,26 +715,53 @@ struct StackState {
     base: git_stack::git::Branch,
     onto: git_stack::git::Branch,
     branches: git_stack::git::Branches,
+    /// `base` is a commit, like a release tag, named by its abbreviated id rather than a branch
+    base_detached: bool,
+    /// `onto` is `base`'s commit, rather than a branch
+    onto_detached: bool,
 }
 
 impl StackState {
     fn update(&mut self, repo: &dyn git_stack::git::Repo) -> eyre::Result<()> {
-        self.base = repo
-            .find_local_branch(self.base.name.as_str())
-            .ok_or_else(|| eyre::eyre!("can no longer find branch {}", self.base.name))?;
-        self.onto = repo
-            .find_local_branch(self.onto.name.as_str())
-            .ok_or_else(|| eyre::eyre!("can no longer find branch {}", self.onto.name))?;
+        // A detached base is a fixed commit, there is no branch to follow
+        if !self.base_detached {
+            self.base = repo
+                .find_local_branch(self.base.name.as_str())
+                .ok_or_else(|| eyre::eyre!("can no longer find branch {}", self.base.name))?;
+        }
+        if !self.onto_detached {
+            self.onto = repo
+                .find_local_branch(self.onto.name.as_str())
+                .ok_or_else(|| eyre::eyre!("can no longer find branch {}", self.onto.name))?;
+        }
         self.branches.update(repo);
         Ok(())
     }
 
+    /// Protected branches, including `onto` so the stack can land on any branch
+    fn protected_branches(&self, state: &State) -> git_stack::git::Branches {
+        let mut protected_branches = state.protected_branches.clone();
+        if !self.onto_detached && !protected_branches.contains_oid(self.onto.id) {
+            protected_branches.insert(self.onto.clone());
+        }
+        protected_branches
+    }
+
+    /// Mark the commits in `root` that must not be rewritten, including a detached `onto`
+    fn protect(&self, state: &State, root: &mut git_stack::graph::Node) {
+        protect(state, root, &self.protected_branches(state));
+        if self.onto_detached {
+            git_stack::graph::protect_commits(root, &state.repo, &[self.onto.id]);
+        }
+    }
+
     fn graphed_branches(&self) -> git_stack::git::Branches {
         let mut graphed_branches = self.branches.clone();
-        if !graphed_branches.contains_oid(self.base.id) {
+        // A detached base is still the root of the graph, just without a branch to label it
+        if !graphed_branches.contains_oid(self.base.id) && !self.base_detached {
             graphed_branches.insert(self.base.clone());
         }
-        if !graphed_branches.contains_oid(self.onto.id) {
+        if !graphed_branches.contains_oid(self.onto.id) && !self.onto_detached {
             graphed_branches.insert(self.onto.clone());
         }
         graphed_branches
@@ -230,6 +775,13 @@ pub fn stack(args: &crate::args::Args, colored_stdout: bool) -> proc_exit::ExitR
     let repo = git_stack::git::GitRepo::new(repo);
     let mut state = State::new(repo, args)?;
 
+    let initial_branches = state.branches.clone();
+    let initial_stacks: Vec<_> = state
+        .stacks
+        .iter()
+        .map(|stack| (stack.onto.clone(), stack.branches.clone()))
+        .collect();
+
     if state.pull {
         if state.repo.is_dirty() {
             return Err(proc_exit::Code::USAGE_ERR.with_message("Working tree is dirty, aborting"));
@@ -246,7 +798,12 @@ pub fn stack(args: &crate::args::Args, colored_stdout: bool) -> proc_exit::ExitR
         let mut pulled_ids = HashSet::new();
         for stack in state.stacks.iter() {
             let mut stack_pulled_ids = HashSet::new();
-            if state.protected_branches.contains_oid(stack.onto.id) {
+            if stack.onto_detached {
+                log::warn!(
+                    "Skipping pull of `{}`, it is a commit, not a branch",
+                    stack.onto.name
+                );
+            } else if state.protected_branches.contains_oid(stack.onto.id) {
                 match git_pull(&mut state.repo, stack.onto.name.as_str(), state.dry_run) {
                     Ok(pull_range) => {
                         stack_pulled_ids.extend(
@@ -274,9 +831,14 @@ pub fn stack(args: &crate::args::Args, colored_stdout: bool) -> proc_exit::ExitR
                     &stack.onto.name,
                     &state.branches,
                     &state.protected_branches,
+                    &state.frozen_branches,
                     state.dry_run,
                 ) {
-                    Ok(()) => {}
+                    Ok(deleted) => {
+                        if let Err(err) = quarantine_branches(&state, &deleted) {
+                            log::warn!("Could not quarantine branches obsoleted by pull: {}", err);
+                        }
+                    }
                     Err(err) => {
                         log::warn!("Could not remove branches obsoleted by pull: {}", err);
                     }
@@ -284,121 +846,3839 @@ pub fn stack(args: &crate::args::Args, colored_stdout: bool) -> proc_exit::ExitR
                 pulled_ids.extend(stack_pulled_ids);
             }
         }
-        if !pulled_ids.is_empty() {
-            state.update().with_code(proc_exit::Code::FAILURE)?;
-        }
+        if !pulled_ids.is_empty() {
+            state.update().with_code(proc_exit::Code::FAILURE)?;
+        }
+    }
+
+    let mut success = true;
+    let mut backed_up = false;
+    let stopped_path = state.repo.raw().path().join("git-stack").join(STOPPED_FILE);
+    let staged_path = state.repo.raw().path().join("git-stack").join(STAGED_FILE);
+
+    if state.abort {
+        let stop = StoppedRebase::load(&stopped_path)
+            .with_code(proc_exit::Code::USAGE_ERR)?
+            .ok_or_else(|| eyre::eyre!("There is no stopped rebase to abort"))
+            .with_code(proc_exit::Code::USAGE_ERR)?;
+        abort_rebase(&mut state, &stop).with_code(proc_exit::Code::FAILURE)?;
+        std::fs::remove_file(&stopped_path).with_code(proc_exit::Code::FAILURE)?;
+        state.update().with_code(proc_exit::Code::FAILURE)?;
+        show(&state, colored_stdout).with_code(proc_exit::Code::FAILURE)?;
+        return Ok(());
+    }
+    if let Some(out) = state.export_bundle.clone() {
+        export_bundle(&state, &out).with_code(proc_exit::Code::FAILURE)?;
+        return Ok(());
+    }
+    if let Some(options) = state.format_patch.clone() {
+        format_patch(&state, &options).with_code(proc_exit::Code::FAILURE)?;
+        return Ok(());
+    }
+    if let Some(import) = state.import_bundle.clone() {
+        backed_up |= import_bundle(&mut state, &import).with_code(proc_exit::Code::FAILURE)?;
+        // The stacks were picked before the imported branches existed
+        let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+        state = State::new(git_stack::git::GitRepo::new(repo), args)?;
+    }
+    if let Some(rename) = state.rename.clone() {
+        check_not_stopped(&stopped_path, "renaming")?;
+        rename_branch(&mut state, &rename).with_code(proc_exit::Code::USAGE_ERR)?;
+        if !state.dry_run {
+            backed_up = true;
+        }
+    }
+    if let Some(create) = state.create.clone() {
+        create_branch(&mut state, &create).with_code(proc_exit::Code::USAGE_ERR)?;
+    }
+    if let Some(adopt) = state.adopt.clone() {
+        match adopt.rev.as_deref() {
+            Some(rev) => {
+                check_not_stopped(&stopped_path, "adopting")?;
+                backed_up |= adopt_commits(&mut state, rev, adopt.branch.as_deref())
+                    .with_code(proc_exit::Code::USAGE_ERR)?;
+            }
+            None => {
+                list_orphans(&state, colored_stdout).with_code(proc_exit::Code::FAILURE)?;
+                return Ok(());
+            }
+        }
+    }
+    if state.delete_merged {
+        check_idle(&state, &stopped_path, "deleting branches")?;
+
+        let merged = merged_branches(&state);
+        if merged.is_empty() {
+            log::info!("No merged branches to delete");
+        } else {
+            backed_up |= backup_unless_dry_run(&state)?;
+            for (onto, branches) in merged {
+                for branch in branches.iter() {
+                    log::info!("Deleting `{}`, merged into `{}`", branch.name, onto);
+                }
+                let deleted = drop_branches(
+                    &mut state.repo,
+                    branches.iter().map(|b| b.id),
+                    &onto,
+                    &state.branches,
+                    &state.protected_branches,
+                    &state.frozen_branches,
+                    state.dry_run,
+                )
+                .with_code(proc_exit::Code::FAILURE)?;
+                quarantine_branches(&state, &deleted).with_code(proc_exit::Code::FAILURE)?;
+            }
+            state.update().with_code(proc_exit::Code::FAILURE)?;
+        }
+    }
+    if let Some(max_age) = state.archive {
+        check_not_stopped(&stopped_path, "archiving branches")?;
+
+        backed_up |= crate::archive::archive_branches(&mut state, max_age)
+            .with_code(proc_exit::Code::FAILURE)?;
+    }
+    if let Some(land) = state.land.clone() {
+        check_idle(&state, &stopped_path, "landing")?;
+        let forge = landing_forge(&state, &land).with_code(proc_exit::Code::USAGE_ERR)?;
+        backed_up |= backup_unless_dry_run(&state)?;
+        land_branch(&mut state, &land, forge).with_code(proc_exit::Code::FAILURE)?;
+    }
+    if state.absorb {
+        check_not_stopped(&stopped_path, "absorbing")?;
+        let absorbed = absorb(&mut state).with_code(proc_exit::Code::FAILURE)?;
+        if absorbed {
+            backed_up = true;
+            if state.rebase && state.repo.is_dirty() {
+                log::warn!(
+                    "Leaving the fixups in place as the working tree is dirty, run `git stack --fix` to squash them"
+                );
+                state.rebase = false;
+            }
+        } else {
+            state.rebase = false;
+        }
+    }
+    if state.commit_plan {
+        if state.repo.is_dirty() {
+            return Err(proc_exit::Code::USAGE_ERR.with_message("Working tree is dirty, aborting"));
+        }
+
+        let staged = StagedPlan::load(&staged_path)
+            .with_code(proc_exit::Code::USAGE_ERR)?
+            .ok_or_else(|| eyre::eyre!("Nothing is staged, run `git stack --stage-only` first"))
+            .with_code(proc_exit::Code::USAGE_ERR)?;
+        backed_up |= backup_unless_dry_run(&state)?;
+        staged
+            .apply(&mut state.repo, state.dry_run)
+            .with_code(proc_exit::Code::FAILURE)?;
+        if !state.dry_run {
+            std::fs::remove_file(&staged_path).with_code(proc_exit::Code::FAILURE)?;
+        }
+        state.update().with_code(proc_exit::Code::FAILURE)?;
+    } else if let Some(reword) = state.reword.clone() {
+        let head_branch = preflight(&state, &stopped_path, "rewording")?;
+
+        match (reword.rev.as_deref(), reword.replace.as_ref()) {
+            (Some(rev), replace) => {
+                let script =
+                    plan_reword(&mut state, rev, replace).with_code(proc_exit::Code::USAGE_ERR)?;
+                if let Some(script) = script {
+                    backed_up |= backup_unless_dry_run(&state)?;
+                    success &= execute(&mut state, std::slice::from_ref(&script), &head_branch)?;
+                } else {
+                    log::info!("Message unchanged, nothing to reword");
+                }
+            }
+            (None, Some(replace)) => {
+                let (scripts, plan_id) =
+                    plan_reword_all(&mut state, replace).with_code(proc_exit::Code::USAGE_ERR)?;
+                if scripts.is_empty() {
+                    log::info!("No commit messages match, nothing to reword");
+                } else {
+                    expect_plan(&state, plan_id)?;
+                    backed_up |= backup_unless_dry_run(&state)?;
+                    success &= execute(&mut state, &scripts, &head_branch)?;
+                }
+            }
+            (None, None) => unreachable!("`--all` requires `--replace`"),
+        }
+    } else if let Some(fix) = state.fix {
+        let head_branch = preflight(&state, &stopped_path, "fixing")?;
+
+        let scripts = plan_fix(&mut state, fix).with_code(proc_exit::Code::USAGE_ERR)?;
+        if scripts.is_empty() {
+            log::info!("Nothing to fix");
+        } else {
+            backed_up |= backup_unless_dry_run(&state)?;
+            success &= execute(&mut state, &scripts, &head_branch)?;
+        }
+    } else if let Some((branch, onto)) = state.move_branch.clone() {
+        let head_branch = preflight(&state, &stopped_path, "moving")?;
+
+        let script = plan_move(&state, &branch, &onto).with_code(proc_exit::Code::USAGE_ERR)?;
+        backed_up |= backup_unless_dry_run(&state)?;
+        success &= execute(&mut state, std::slice::from_ref(&script), &head_branch)?;
+    } else if state.repair {
+        let head_branch = preflight(&state, &stopped_path, "repairing")?;
+
+        match crate::repair::plan_repair(&state).with_code(proc_exit::Code::USAGE_ERR)? {
+            Some(script) => {
+                backed_up |= backup_unless_dry_run(&state)?;
+                success &= execute(&mut state, std::slice::from_ref(&script), &head_branch)?;
+            }
+            None => {
+                log::info!("Nothing to repair, no branch is on an old version of another");
+            }
+        }
+    } else if let Some((rev, paths)) = state.split.clone() {
+        let head_branch = preflight(&state, &stopped_path, "splitting")?;
+
+        let paths = repo_paths(&state.repo, &paths).with_code(proc_exit::Code::USAGE_ERR)?;
+        let (script, parent_id) =
+            plan_split(&mut state, &rev, &paths).with_code(proc_exit::Code::USAGE_ERR)?;
+        backed_up |= backup_unless_dry_run(&state)?;
+        success &= execute(&mut state, std::slice::from_ref(&script), &head_branch)?;
+        if paths.is_empty() && state.stopped {
+            let stop = StoppedRebase::load(&stopped_path)
+                .with_code(proc_exit::Code::FAILURE)?
+                .expect("stop was just saved");
+            if stop.continuation.conflict().is_none() {
+                // Leave the changes for the user to commit in pieces
+                let parent = state
+                    .repo
+                    .raw()
+                    .find_object(parent_id, None)
+                    .with_code(proc_exit::Code::FAILURE)?;
+                state
+                    .repo
+                    .raw()
+                    .reset(&parent, git2::ResetType::Mixed, None)
+                    .with_code(proc_exit::Code::FAILURE)?;
+                log::info!("Its changes are unstaged, commit them in pieces");
+            }
+        }
+    } else if let Some(rev) = state.drop_commit.clone() {
+        let head_branch = preflight(&state, &stopped_path, "dropping")?;
+
+        let script = plan_drop(&state, &rev).with_code(proc_exit::Code::USAGE_ERR)?;
+        backed_up |= backup_unless_dry_run(&state)?;
+        success &= execute(&mut state, std::slice::from_ref(&script), &head_branch)?;
+    } else if let Some(range) = state.squash_commits.clone() {
+        let head_branch = preflight(&state, &stopped_path, "squashing")?;
+
+        let script = plan_squash(&mut state, &range).with_code(proc_exit::Code::USAGE_ERR)?;
+        backed_up |= backup_unless_dry_run(&state)?;
+        success &= execute(&mut state, std::slice::from_ref(&script), &head_branch)?;
+    } else if let Some(reorder) = state.reorder.clone() {
+        let head_branch = preflight(&state, &stopped_path, "reordering")?;
+
+        let branch = reorder.branch.as_deref().unwrap_or(&head_branch);
+        match crate::reorder::plan_reorder(&state, branch).with_code(proc_exit::Code::USAGE_ERR)? {
+            Some(script) => {
+                backed_up |= backup_unless_dry_run(&state)?;
+                success &= execute(&mut state, std::slice::from_ref(&script), &head_branch)?;
+            }
+            None => {
+                log::info!("Order unchanged, nothing to reorder");
+            }
+        }
+    } else if state.rebase {
+        // `--step` picks up where the last one left off
+        let resume = state.resume || (state.step && stopped_path.exists());
+        let resumed = if resume {
+            let resumed = StoppedRebase::load(&stopped_path)
+                .with_code(proc_exit::Code::USAGE_ERR)?
+                .ok_or_else(|| eyre::eyre!("There is no stopped rebase to continue"))
+                .with_code(proc_exit::Code::USAGE_ERR)?;
+            Some(resumed)
+        } else {
+            None
+        };
+        if let Some(conflict) = resumed.as_ref().and_then(|r| r.continuation.conflict()) {
+            if !state.dry_run {
+                state
+                    .repo
+                    .continue_cherry_pick(conflict)
+                    .map_err(|err| {
+                        eyre::eyre!("{}, then run `git stack --continue`", err.message())
+                    })
+                    .with_code(proc_exit::Code::USAGE_ERR)?;
+            }
+        }
+
+        // Neither the sandbox nor writing out a plan touch the working tree
+        if !state.sandbox && state.plan_out.is_none() && state.repo.is_dirty() {
+            return Err(proc_exit::Code::USAGE_ERR.with_message("Working tree is dirty, aborting"));
+        }
+
+        let (scripts, restore_branch, original, executor) = if let Some(resumed) = resumed {
+            let executor =
+                git_stack::git::Executor::resume(&state.repo, &resumed.continuation, state.dry_run);
+            (
+                resumed.continuation.scripts().to_vec(),
+                resumed.restore_branch,
+                resumed.original,
+                executor,
+            )
+        } else {
+            if stopped_path.exists() {
+                return Err(proc_exit::Code::USAGE_ERR.with_message(format!(
+                    "A rebase is stopped, run `git stack --continue` or remove {} to discard it",
+                    stopped_path.display()
+                )));
+            }
+
+            let (scripts, restore_branch) = if let Some(path) = state.apply_plan.as_deref() {
+                let plan = load_plan(path).with_code(proc_exit::Code::USAGE_ERR)?;
+                plan.validate(&state.repo)
+                    .with_code(proc_exit::Code::FAILURE)?;
+                (plan.scripts, plan.restore_branch)
+            } else {
+                let head_branch = state
+                    .repo
+                    .head_branch()
+                    .ok_or_else(|| eyre::eyre!("Must not be in a detached HEAD state."))
+                    .with_code(proc_exit::Code::USAGE_ERR)?
+                    .name;
+                let mut restore_branch = head_branch.clone();
+
+                let scripts: Result<Vec<_>, proc_exit::Exit> = state
+                    .stacks
+                    .iter()
+                    .map(|stack| {
+                        let script =
+                            plan_rebase(&state, stack).with_code(proc_exit::Code::FAILURE)?;
+                        if script.is_branch_deleted(&head_branch) && !stack.onto_detached {
+                            restore_branch = stack.onto.name.clone();
+                        }
+                        Ok(script)
+                    })
+                    .collect();
+                let mut scripts = scripts?;
+                // Leave the plan whole for editing, so it can still be reordered
+                if !state.interactive {
+                    let mut up_to_date = Vec::new();
+                    for script in scripts.iter_mut() {
+                        up_to_date.extend(script.prune_up_to_date(&state.repo));
+                    }
+                    scripts.retain(|s| !s.is_empty());
+                    up_to_date.sort();
+                    if scripts.is_empty() {
+                        log::info!("Nothing to do, branches are up to date");
+                    } else if !up_to_date.is_empty() {
+                        log::info!(
+                            "Skipping {} branch(es) already up to date: {}",
+                            up_to_date.len(),
+                            up_to_date.join(", ")
+                        );
+                    }
+                }
+                if let Some(exec) = state.exec.as_deref() {
+                    for script in scripts.iter_mut() {
+                        script.exec_after_branches(exec);
+                    }
+                }
+                if state.interactive {
+                    scripts = edit_plan(&state, &scripts).with_code(proc_exit::Code::FAILURE)?;
+                    if scripts.is_empty() {
+                        log::info!("Nothing to do, the plan is empty");
+                        return Ok(());
+                    }
+                }
+                if !scripts.iter().any(|s| s.is_branch_deleted(&head_branch)) {
+                    restore_branch = head_branch;
+                }
+                (scripts, restore_branch)
+            };
+
+            let plan_id = git_stack::git::plan_id(&scripts);
+            expect_plan(&state, plan_id)?;
+            if state.dry_run {
+                report_content_changes(&state, &scripts);
+                report_conflicts(&state, &scripts);
+            }
+
+            if let Some(path) = state.plan_out.as_deref() {
+                if !state.dry_run {
+                    state.repo.ensure_fingerprint();
+                }
+                let plan = git_stack::git::Plan::new(&state.repo, scripts, restore_branch);
+                save_plan(&plan, path).with_code(proc_exit::Code::FAILURE)?;
+                if path != std::path::Path::new("-") {
+                    log::info!(
+                        "Wrote plan {} to {}, run `git stack apply {}` to apply it",
+                        plan_id,
+                        path.display(),
+                        path.display()
+                    );
+                }
+                return Ok(());
+            }
+
+            if state.risk_gate != git_stack::config::RiskGate::Off {
+                let changes = assess_changes(&state, &scripts);
+                if !gate_risk(&state, &changes).with_code(proc_exit::Code::USAGE_ERR)? {
+                    log::info!("Leaving the branches as-is");
+                    return Ok(());
+                }
+            }
+
+            // Nothing to undo when nothing will change
+            if !backed_up
+                && !state.dry_run
+                && !state.sandbox
+                && !state.stage_only
+                && !scripts.is_empty()
+            {
+                backup(&state).with_code(proc_exit::Code::FAILURE)?;
+                backed_up = true;
+            }
+
+            let original = git_stack::stash::Snapshot::from_repo(&state.repo)
+                .with_code(proc_exit::Code::FAILURE)?;
+            let executor = git_stack::git::Executor::new(&state.repo, state.dry_run);
+            (scripts, restore_branch, Some(original), executor)
+        };
+        let verify_original = if state.verify { original.clone() } else { None };
+        success &= run_scripts(&mut state, executor, &scripts, restore_branch, original)?;
+
+        if !state.stopped && !state.stage_only && !state.dry_run {
+            if let Some(original) = verify_original {
+                success &= verify_restack(&state, &original).with_code(proc_exit::Code::FAILURE)?;
+            }
+        }
+    }
+
+    if let Some(audit_log) = state.audit_log.as_ref() {
+        audit_log
+            .record_updates(&initial_branches, &state.branches)
+            .wrap_err_with(|| format!("could not write to {}", audit_log.path().display()))
+            .with_code(proc_exit::Code::FAILURE)?;
+    }
+
+    let mut run_failed = false;
+    if let Some(options) = state.run.clone() {
+        if state.stopped {
+            log::warn!(
+                "Skipping `{}` until the rebase is finished",
+                options.command
+            );
+        } else {
+            let failed = run(&mut state, &options).with_code(proc_exit::Code::FAILURE)?;
+            if !failed.is_empty() {
+                log::error!(
+                    "`{}` failed on {}",
+                    options.command,
+                    failed.iter().map(|b| format!("`{}`", b)).join(", ")
+                );
+                run_failed = true;
+                success = false;
+            }
+        }
+    }
+
+    if state.push && state.stopped {
+        log::warn!("Skipping push until the rebase is finished");
+    } else if state.push && run_failed {
+        log::warn!(
+            "Skipping push until `{}` passes",
+            state.run.as_ref().unwrap().command
+        );
+    } else if state.push {
+        let pre_push_branches = state.branches.clone();
+        let pushed = push(&mut state);
+        state.update().with_code(proc_exit::Code::FAILURE)?;
+        // Record whatever made it out, even if some pushes failed
+        if let Some(audit_log) = state.audit_log.as_ref() {
+            audit_log
+                .record_pushes(&pre_push_branches, &state.branches)
+                .wrap_err_with(|| format!("could not write to {}", audit_log.path().display()))
+                .with_code(proc_exit::Code::FAILURE)?;
+        }
+        pushed.with_code(proc_exit::Code::FAILURE)?;
+    }
+
+    if let Some(options) = state.submit.clone() {
+        if state.stopped {
+            log::warn!("Skipping submit until the rebase is finished");
+        } else if run_failed {
+            log::warn!(
+                "Skipping submit until `{}` passes",
+                state.run.as_ref().unwrap().command
+            );
+        } else {
+            let pre_push_branches = state.branches.clone();
+            let submitted = submit(&mut state, &options);
+            state.update().with_code(proc_exit::Code::FAILURE)?;
+            if let Some(audit_log) = state.audit_log.as_ref() {
+                audit_log
+                    .record_pushes(&pre_push_branches, &state.branches)
+                    .wrap_err_with(|| format!("could not write to {}", audit_log.path().display()))
+                    .with_code(proc_exit::Code::FAILURE)?;
+            }
+            submitted.with_code(proc_exit::Code::FAILURE)?;
+        }
+    }
+
+    if state.sync && !state.stopped {
+        report_sync(&state, &initial_stacks);
+    }
+
+    if !state.stopped {
+        if state.porcelain {
+            show_porcelain(&state).with_code(proc_exit::Code::FAILURE)?;
+        } else {
+            if state.show_prefetch && !state.pull {
+                prefetch(&state.repo);
+            }
+            show(&state, colored_stdout).with_code(proc_exit::Code::FAILURE)?;
+        }
+    }
+
+    maintain(&state);
+    if backed_up {
+        log::info!("To undo, run `git stack undo`");
+    }
+    if state.repo.is_sandboxed() && !state.dry_run {
+        log::info!(
+            "Sandboxed branches are under `refs/git-stack/sandbox/`, your branches are untouched"
+        );
+    }
+
+    if !success {
+        return proc_exit::Code::FAILURE.ok();
+    }
+
+    Ok(())
+}
+
+pub(crate) const STASH_STACK_NAME: &str = "git-stack";
+/// Snapshots from before each `git stack undo`, for `git stack redo`
+pub(crate) const REDO_STACK_NAME: &str = "git-stack-redo";
+
+/// Keep the object database in shape after heavy rewrites, see `stack.maintenance`
+fn maintain(state: &State) {
+    if state.dry_run || state.rewritten == 0 || state.rewritten < state.maintenance_threshold {
+        return;
+    }
+
+    match state.maintenance {
+        git_stack::config::Maintenance::Ignore => {}
+        git_stack::config::Maintenance::Hint => {
+            let commands = MAINTENANCE_TASKS
+                .iter()
+                .map(|task| format!("`git maintenance run --task={}`", task))
+                .join(" and ");
+            log::info!(
+                "Rewrote {} commits, run {} to keep the repo fast",
+                state.rewritten,
+                commands
+            );
+        }
+        git_stack::config::Maintenance::Run => {
+            for task in MAINTENANCE_TASKS {
+                log::debug!("git maintenance run --task={}", task);
+                let status = std::process::Command::new("git")
+                    .arg("maintenance")
+                    .arg("run")
+                    .arg(format!("--task={}", task))
+                    .status();
+                match status {
+                    Ok(status) if status.success() => {}
+                    Ok(status) => {
+                        log::warn!(
+                            "`git maintenance run --task={}` failed with {}",
+                            task,
+                            status
+                        );
+                        break;
+                    }
+                    Err(err) => {
+                        log::warn!("Could not run `git maintenance`: {}", err);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Pack the loose objects a rewrite leaves behind, then consolidate the packs
+///
+/// These are run one at a time as `git maintenance` runs `incremental-repack` first, which fails
+/// without any packs.
+const MAINTENANCE_TASKS: [&str; 2] = ["loose-objects", "incremental-repack"];
+
+/// Sign rewritten commits like `git commit` would, per `commit.gpgsign`
+fn signer(repo: &git_stack::git::GitRepo) -> eyre::Result<Option<git_stack::git::Signer>> {
+    let config = repo.raw().config()?;
+    let ident = repo.raw().signature().ok().map(|sig| {
+        format!(
+            "{} <{}>",
+            sig.name().unwrap_or_default(),
+            sig.email().unwrap_or_default()
+        )
+    });
+    git_stack::git::Signer::from_config(&config, ident.as_deref()).map_err(|err| eyre::eyre!(err))
+}
+
+fn backup(state: &State) -> eyre::Result<()> {
+    backup_with(state, |_| {})
+}
+
+/// Back up the branches before rewriting them, returning whether there is a backup to undo to
+fn backup_unless_dry_run(state: &State) -> Result<bool, proc_exit::Exit> {
+    if state.dry_run {
+        return Ok(false);
+    }
+    backup(state).with_code(proc_exit::Code::FAILURE)?;
+    Ok(true)
+}
+
+/// Refuse to start `verb` on top of a stopped rebase
+fn check_not_stopped(stopped_path: &std::path::Path, verb: &str) -> Result<(), proc_exit::Exit> {
+    if stopped_path.exists() {
+        return Err(proc_exit::Code::USAGE_ERR.with_message(format!(
+            "A rebase is stopped, run `git stack --continue` before {}",
+            verb
+        )));
+    }
+    Ok(())
+}
+
+/// Refuse to start `verb` with a dirty working tree or on top of a stopped rebase
+fn check_idle(
+    state: &State,
+    stopped_path: &std::path::Path,
+    verb: &str,
+) -> Result<(), proc_exit::Exit> {
+    if state.repo.is_dirty() {
+        return Err(proc_exit::Code::USAGE_ERR.with_message("Working tree is dirty, aborting"));
+    }
+    check_not_stopped(stopped_path, verb)
+}
+
+/// Check that `verb` can rewrite the stack, returning the branch to come back to
+fn preflight(
+    state: &State,
+    stopped_path: &std::path::Path,
+    verb: &str,
+) -> Result<String, proc_exit::Exit> {
+    check_idle(state, stopped_path, verb)?;
+    let head_branch = state
+        .repo
+        .head_branch()
+        .ok_or_else(|| eyre::eyre!("Must not be in a detached HEAD state."))
+        .with_code(proc_exit::Code::USAGE_ERR)?;
+    Ok(head_branch.name)
+}
+
+/// Back up the branches, letting `annotate` add to the snapshot's metadata
+pub(crate) fn backup_with(
+    state: &State,
+    annotate: impl FnOnce(&mut git_stack::stash::Snapshot),
+) -> eyre::Result<()> {
+    let mut snapshots = git_stack::stash::Stack::new(STASH_STACK_NAME, &state.repo);
+    snapshots.capacity(state.snapshot_capacity);
+    state.repo.ensure_fingerprint();
+    let mut snapshot = git_stack::stash::Snapshot::from_repo(&state.repo)?;
+    snapshot.insert_parent(&state.repo, &state.branches, &state.protected_branches);
+    annotate(&mut snapshot);
+    snapshots.push(snapshot)?;
+    // Anything undone before is now on a different timeline
+    git_stack::stash::Stack::new(REDO_STACK_NAME, &state.repo).clear();
+    Ok(())
+}
+
+const STAGED_FILE: &str = "staged.json";
+
+/// Branch updates from `--stage-only`, waiting on `--commit-plan`
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StagedPlan {
+    restore_branch: String,
+    updates: Vec<StagedUpdate>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StagedUpdate {
+    branch: String,
+    #[serde(with = "git_stack::serde_oid::option")]
+    old_id: Option<git2::Oid>,
+    #[serde(with = "git_stack::serde_oid::option")]
+    new_id: Option<git2::Oid>,
+}
+
+impl StagedPlan {
+    fn new(
+        repo: &dyn git_stack::git::Repo,
+        restore_branch: String,
+        staged: &[(String, Option<git2::Oid>)],
+    ) -> Self {
+        let updates = staged
+            .iter()
+            .filter_map(|(branch, new_id)| {
+                let old_id = repo.find_local_branch(branch).map(|b| b.id);
+                (old_id != *new_id).then(|| StagedUpdate {
+                    branch: branch.clone(),
+                    old_id,
+                    new_id: *new_id,
+                })
+            })
+            .collect();
+        Self {
+            restore_branch,
+            updates,
+        }
+    }
+
+    fn load(path: &std::path::Path) -> eyre::Result<Option<Self>> {
+        crate::json::load_json(path)
+    }
+
+    fn save(&self, path: &std::path::Path) -> eyre::Result<()> {
+        crate::json::save_json(path, self)
+    }
+
+    fn apply(&self, repo: &mut dyn git_stack::git::Repo, dry_run: bool) -> eyre::Result<()> {
+        for update in self.updates.iter() {
+            let current_id = repo.find_local_branch(&update.branch).map(|b| b.id);
+            if current_id != update.old_id {
+                eyre::bail!(
+                    "`{}` changed since it was staged, re-run `git stack --stage-only`",
+                    update.branch
+                );
+            }
+        }
+
+        if !dry_run {
+            repo.detach()?;
+        }
+        for update in self.updates.iter() {
+            match update.new_id {
+                Some(new_id) => {
+                    log::trace!("git branch -f {} {}", update.branch, new_id);
+                    if !dry_run {
+                        repo.branch(&update.branch, new_id, "commit staged plan")?;
+                    }
+                }
+                None => {
+                    log::trace!("git branch -D {}", update.branch);
+                    if !dry_run {
+                        repo.delete_branch(&update.branch)?;
+                    }
+                }
+            }
+        }
+        log::trace!("git switch {}", self.restore_branch);
+        if !dry_run {
+            repo.switch(&self.restore_branch)?;
+        }
+        Ok(())
+    }
+}
+
+const STOPPED_FILE: &str = "stopped.json";
+
+/// A rebase waiting on the user, see `--continue`
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoppedRebase {
+    restore_branch: String,
+    continuation: git_stack::git::Continuation,
+    /// Branches from before the rebase, for `--abort`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    original: Option<git_stack::stash::Snapshot>,
+}
+
+impl StoppedRebase {
+    fn load(path: &std::path::Path) -> eyre::Result<Option<Self>> {
+        crate::json::load_json(path)
+    }
+
+    fn save(&self, path: &std::path::Path) -> eyre::Result<()> {
+        crate::json::save_json(path, self)
+    }
+}
+
+/// Throw away the stopped rebase's work, like `git rebase --abort`
+fn abort_rebase(state: &mut State, stop: &StoppedRebase) -> eyre::Result<()> {
+    {
+        let repo = state.repo.raw();
+        let head = repo.head()?.peel_to_commit()?;
+        repo.reset(head.as_object(), git2::ResetType::Hard, None)?;
+        repo.cleanup_state()?;
+    }
+    state.repo.rerere_clear()?;
+
+    match stop.original.as_ref() {
+        Some(original) => {
+            original.apply(&mut state.repo)?;
+        }
+        None => {
+            log::warn!("Branches were left as-is, run `git stack undo` to restore them");
+        }
+    }
+    state.repo.switch(&stop.restore_branch)?;
+    log::info!("Aborted, back on `{}`", stop.restore_branch);
+    Ok(())
+}
+
+fn next_step(scripts: &[git_stack::git::Script]) -> Option<&git_stack::git::Command> {
+    scripts.iter().find_map(|script| {
+        script
+            .commands
+            .iter()
+            .find(|c| c.is_step())
+            .or_else(|| next_step(&script.dependents))
+    })
+}
+
+fn describe_command(repo: &dyn git_stack::git::Repo, command: &git_stack::git::Command) -> String {
+    match command {
+        git_stack::git::Command::CherryPick(oid) | git_stack::git::Command::Squash(oid) => {
+            match repo.find_commit(*oid) {
+                Some(commit) => format!("{}  # {}", command, commit.summary.to_str_lossy()),
+                None => command.to_string(),
+            }
+        }
+        _ => command.to_string(),
+    }
+}
+
+/// Mark the commits in `root` that must not be rewritten, from protected branches and tags
+pub(crate) fn protect(
+    state: &State,
+    root: &mut git_stack::graph::Node,
+    protected_branches: &git_stack::git::Branches,
+) {
+    git_stack::graph::protect_branches(root, &state.repo, protected_branches);
+    git_stack::graph::protect_commits(root, &state.repo, &state.protected_tags);
+}
+
+/// Keep frozen branches, and what is stacked on them, from being moved
+pub(crate) fn leave_frozen(state: &State, root: &mut git_stack::graph::Node) {
+    if state.frozen_branches.is_empty() {
+        return;
+    }
+    let skipped =
+        git_stack::graph::retain_branches(root, &|b| !state.frozen_branches.contains(&b.name));
+    for branch in skipped {
+        if state.frozen_branches.contains(&branch.name) {
+            log::info!("Leaving `{}` in place, it is frozen", branch.name);
+        } else {
+            log::info!(
+                "Leaving `{}` in place, it is stacked on a frozen branch",
+                branch.name
+            );
+        }
+    }
+}
+
+/// Keep branches with octopus merges in place, as only `--rebase-merges` can recreate them
+fn leave_octopus_merges(state: &State, root: &mut git_stack::graph::Node) {
+    if state.repo.rebase_merges() {
+        return;
+    }
+    let octopus_branches = git_stack::graph::octopus_branches(root, &state.repo)
+        .into_iter()
+        .map(|b| b.name)
+        .collect::<std::collections::HashSet<_>>();
+    if octopus_branches.is_empty() {
+        return;
+    }
+    let skipped = git_stack::graph::retain_branches(root, &|b| !octopus_branches.contains(&b.name));
+    for branch in skipped {
+        if octopus_branches.contains(&branch.name) {
+            log::info!(
+                "Leaving `{}` in place, it has an octopus merge (recreate it with `--rebase-merges`)",
+                branch.name
+            );
+        } else {
+            log::info!(
+                "Leaving `{}` in place, it is stacked on an octopus merge",
+                branch.name
+            );
+        }
+    }
+}
+
+fn plan_rebase(state: &State, stack: &StackState) -> eyre::Result<git_stack::git::Script> {
+    let mut graphed_branches = stack.graphed_branches();
+    let base_commit = state
+        .repo
+        .find_commit(stack.base.id)
+        .expect("base branch is valid");
+    let mut root = git_stack::graph::Node::new(base_commit, &mut graphed_branches);
+    root = root.extend_branches(&state.repo, graphed_branches)?;
+    stack.protect(state, &mut root);
+    follow_recorded_parents(state, &mut root);
+    if let Some(selected_branches) = state.selected_branches.as_ref() {
+        let skipped =
+            git_stack::graph::retain_branches(&mut root, &|b| selected_branches.matches(&b.name));
+        for branch in skipped {
+            log::info!(
+                "Leaving `{}` in place, not covered by `--branch`",
+                branch.name
+            );
+        }
+    }
+    leave_frozen(state, &mut root);
+    leave_octopus_merges(state, &mut root);
+
+    git_stack::graph::rebase_branches_except(&mut root, stack.onto.id, &state.protected_tags);
+    git_stack::graph::drop_by_tree_id(&mut root);
+    git_stack::graph::drop_by_patch_id(&mut root, &state.repo);
+    if state.dedup_cherry_picks {
+        git_stack::graph::dedup_cherry_picks(&mut root, &state.repo);
+    }
+    git_stack::graph::fixup(&mut root, state.fixup)?;
+
+    let script = git_stack::graph::to_script(&root);
+
+    Ok(script)
+}
+
+/// Reword `rev`, returning the script to restack what is on top of it
+fn plan_reword(
+    state: &mut State,
+    rev: &str,
+    replace: Option<&git_stack::git::Substitution>,
+) -> eyre::Result<Option<git_stack::git::Script>> {
+    let target = rewritable_commit(state, rev, "reword")?;
+
+    let (message, parent_id) = {
+        let commit = state.repo.raw().find_commit(target.id)?;
+        if commit.parent_count() != 1 {
+            eyre::bail!("can only reword commits with one parent");
+        }
+        (
+            commit.message_bytes().to_str_lossy().into_owned(),
+            commit.parent_id(0)?,
+        )
+    };
+
+    let edited = if let Some(replace) = replace {
+        match replace.apply(&message) {
+            Some(edited) => edited,
+            None => return Ok(None),
+        }
+    } else {
+        let config = state.repo.raw().config()?;
+        let editor = git_stack::git::Editor::commit_editor(&config);
+        let dir = state.repo.raw().path().join("git-stack");
+        let template = format!(
+            "{}\n# Rewording {}. Lines starting with '#' will be ignored, and an\n# empty message aborts the reword.\n",
+            message.trim_end(),
+            target.id
+        );
+        editor.edit_text(&dir, REWORD_FILE, &template)?
+    };
+    let edited = git_stack::git::cleanup_message(&edited);
+    if edited.is_empty() {
+        eyre::bail!("Aborting reword due to empty commit message");
+    }
+    if edited == git_stack::git::cleanup_message(&message) {
+        return Ok(None);
+    }
+
+    // Everything containing the commit moves to the reworded copy
+    let mut root = graph_from_parent(state, &target, parent_id)?;
+    let reworded_id = state.repo.reword(target.id, &edited)?;
+    let reworded = state
+        .repo
+        .find_commit(reworded_id)
+        .expect("reworded commit was just created");
+    let found = git_stack::graph::reword(&mut root, target.id, reworded);
+    assert!(found, "target was inserted");
+
+    Ok(Some(git_stack::graph::to_script(&root)))
+}
+
+const REWORD_FILE: &str = "COMMIT_EDITMSG";
+
+/// Options for `git stack reword`
+#[derive(Clone, Debug)]
+struct RewordOptions {
+    /// `None` for every commit in the stacks
+    rev: Option<String>,
+    replace: Option<git_stack::git::Substitution>,
+}
+
+/// Apply `replace` to every commit message in the stacks, printing each change for approval
+///
+/// Returns the scripts to restack the changed commits and an id summarizing the changes, for
+/// `--expect-plan`, see `git_stack::git::reword_plan_id`.
+fn plan_reword_all(
+    state: &mut State,
+    replace: &git_stack::git::Substitution,
+) -> eyre::Result<(Vec<git_stack::git::Script>, git2::Oid)> {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+
+    let mut rewords = Vec::new();
+    let mut scripts = Vec::new();
+    for stack in state.stacks.iter() {
+        let mut graphed_branches = stack.graphed_branches();
+        let base_commit = state
+            .repo
+            .find_commit(stack.base.id)
+            .expect("base branch is valid");
+        let mut root = git_stack::graph::Node::new(base_commit, &mut graphed_branches);
+        root = root.extend_branches(&state.repo, graphed_branches)?;
+        stack.protect(state, &mut root);
+
+        let mut ids = Vec::new();
+        unprotected_commits(&root, &mut ids);
+        let mut reworded = 0;
+        for id in ids {
+            let message = {
+                let commit = state.repo.raw().find_commit(id)?;
+                if commit.parent_count() != 1 {
+                    log::debug!("Not rewording merge commit {}", id);
+                    continue;
+                }
+                commit.message_bytes().to_str_lossy().into_owned()
+            };
+            let edited = match replace.apply(&message) {
+                Some(edited) => git_stack::git::cleanup_message(&edited),
+                None => continue,
+            };
+            if edited.is_empty() || edited == git_stack::git::cleanup_message(&message) {
+                continue;
+            }
+
+            writeln!(stdout, "{}", state.repo.abbrev_id(id))?;
+            for line in message.lines().filter(|l| !edited.lines().any(|e| e == *l)) {
+                writeln!(stdout, "  - {}", line)?;
+            }
+            for line in edited.lines().filter(|e| !message.lines().any(|l| l == *e)) {
+                writeln!(stdout, "  + {}", line)?;
+            }
+
+            let reworded_id = state.repo.reword(id, &edited)?;
+            rewords.push((id, edited));
+            let reworded_commit = state
+                .repo
+                .find_commit(reworded_id)
+                .expect("reworded commit was just created");
+            let found = git_stack::graph::reword(&mut root, id, reworded_commit);
+            assert!(found, "commit came from the graph");
+            reworded += 1;
+        }
+        if reworded != 0 {
+            log::info!(
+                "Rewording {} commit(s) on top of `{}`",
+                reworded,
+                stack.base.name
+            );
+            scripts.push(git_stack::graph::to_script(&root));
+        }
+    }
+
+    let plan_id = git_stack::git::reword_plan_id(&rewords);
+    Ok((scripts, plan_id))
+}
+
+#[derive(Copy, Clone, Debug)]
+struct FixOptions {
+    sign_off: bool,
+    reset_author: bool,
+    reset_date: bool,
+}
+
+/// Fix up every commit in the stacks, returning the scripts to restack those that changed
+fn plan_fix(state: &mut State, fix: FixOptions) -> eyre::Result<Vec<git_stack::git::Script>> {
+    let signature = state
+        .repo
+        .raw()
+        .signature()
+        .wrap_err("set `user.name` and `user.email` to fix commits")?;
+    let name = signature.name().unwrap_or_default().to_owned();
+    let email = signature.email().unwrap_or_default().to_owned();
+    let identity = format!("{} <{}>", name, email);
+
+    let mut scripts = Vec::new();
+    for stack in state.stacks.iter() {
+        let mut graphed_branches = stack.graphed_branches();
+        let base_commit = state
+            .repo
+            .find_commit(stack.base.id)
+            .expect("base branch is valid");
+        let mut root = git_stack::graph::Node::new(base_commit, &mut graphed_branches);
+        root = root.extend_branches(&state.repo, graphed_branches)?;
+        stack.protect(state, &mut root);
+
+        let mut ids = Vec::new();
+        unprotected_commits(&root, &mut ids);
+        let mut fixed = 0;
+        for id in ids {
+            let (message, author, changed) = {
+                let commit = state.repo.raw().find_commit(id)?;
+                if commit.parent_count() != 1 {
+                    log::debug!("Not fixing merge commit {}", id);
+                    continue;
+                }
+                let mut changed = false;
+
+                let mut message = commit.message_bytes().to_str_lossy().into_owned();
+                if fix.sign_off {
+                    if let Some(signed) = git_stack::git::sign_off(&message, &identity) {
+                        message = signed;
+                        changed = true;
+                    }
+                }
+
+                let mut author = commit.author().to_owned();
+                if fix.reset_author {
+                    let committer = commit.committer();
+                    let same_identity = |sig: &git2::Signature<'_>| {
+                        sig.name_bytes() == name.as_bytes() && sig.email_bytes() == email.as_bytes()
+                    };
+                    if fix.reset_date || !same_identity(&author) || !same_identity(&committer) {
+                        let when = if fix.reset_date {
+                            signature.when()
+                        } else {
+                            author.when()
+                        };
+                        author = git2::Signature::new(&name, &email, &when)?;
+                        changed = true;
+                    }
+                }
+
+                (message, author, changed)
+            };
+            if !changed {
+                continue;
+            }
+            let fixed_id = state.repo.recommit(id, &message, &author)?;
+            let fixed_commit = state
+                .repo
+                .find_commit(fixed_id)
+                .expect("fixed commit was just created");
+            let found = git_stack::graph::reword(&mut root, id, fixed_commit);
+            assert!(found, "commit came from the graph");
+            fixed += 1;
+        }
+        if fixed != 0 {
+            log::info!("Fixing {} commit(s) on top of `{}`", fixed, stack.base.name);
+            scripts.push(git_stack::graph::to_script(&root));
+        }
+    }
+
+    Ok(scripts)
+}
+
+/// Resolve `rev`, making sure it isn't protected
+fn rewritable_commit(
+    state: &State,
+    rev: &str,
+    verb: &str,
+) -> eyre::Result<std::rc::Rc<git_stack::git::Commit>> {
+    let target = state
+        .repo
+        .resolve(rev)
+        .ok_or_else(|| eyre::eyre!("could not find commit `{}`", rev))?;
+    if let Some(protected) = state
+        .protected_branches
+        .iter()
+        .flat_map(|(_, b)| b.iter())
+        .find(|b| state.repo.merge_base(target.id, b.id) == Some(target.id))
+    {
+        eyre::bail!(
+            "{} is protected by `{}`, refusing to {} it",
+            target.id,
+            protected.name,
+            verb
+        );
+    }
+    Ok(target)
+}
+
+/// Graph `target` and every branch containing it, rooted at its parent
+pub(crate) fn graph_from_parent(
+    state: &State,
+    target: &std::rc::Rc<git_stack::git::Commit>,
+    parent_id: git2::Oid,
+) -> eyre::Result<git_stack::graph::Node> {
+    let mut graphed_branches = state.branches.descendants(&state.repo, target.id);
+    if graphed_branches.is_empty() {
+        eyre::bail!("{} is not on any branch", target.id);
+    }
+    let parent = state
+        .repo
+        .find_commit(parent_id)
+        .expect("parent of an existing commit exists");
+
+    let mut root = git_stack::graph::Node::new(parent, &mut graphed_branches);
+    root = root.insert_commit(&state.repo, target.clone(), &mut graphed_branches)?;
+    root = root.extend_branches(&state.repo, graphed_branches)?;
+    protect(state, &mut root, &state.protected_branches);
+    Ok(root)
+}
+
+/// Split `rev`, returning the script to restack what is on top of it
+///
+/// Without `paths`, the script stops at `rev` for the user to split it.
+fn plan_split(
+    state: &mut State,
+    rev: &str,
+    paths: &[std::path::PathBuf],
+) -> eyre::Result<(git_stack::git::Script, git2::Oid)> {
+    let target = rewritable_commit(state, rev, "split")?;
+    let parent_id = {
+        let commit = state.repo.raw().find_commit(target.id)?;
+        if commit.parent_count() != 1 {
+            eyre::bail!("can only split commits with one parent");
+        }
+        commit.parent_id(0)?
+    };
+
+    let mut root = graph_from_parent(state, &target, parent_id)?;
+    if paths.is_empty() {
+        let mut script = git_stack::graph::to_script(&root);
+        let found = insert_break_after(&mut script, target.id);
+        assert!(found, "target was inserted");
+        Ok((script, parent_id))
+    } else {
+        let (first_id, second_id) = state.repo.split(target.id, paths)?;
+        let commits = vec![
+            state
+                .repo
+                .find_commit(first_id)
+                .expect("split was just created"),
+            state
+                .repo
+                .find_commit(second_id)
+                .expect("split was just created"),
+        ];
+        let found = git_stack::graph::split(&mut root, target.id, commits);
+        assert!(found, "target was inserted");
+        Ok((git_stack::graph::to_script(&root), parent_id))
+    }
+}
+
+/// Drop `rev`, returning the script to restack what is on top of it onto its parent
+fn plan_drop(state: &State, rev: &str) -> eyre::Result<git_stack::git::Script> {
+    let target = rewritable_commit(state, rev, "drop")?;
+    let parent_id = {
+        let commit = state.repo.raw().find_commit(target.id)?;
+        if commit.parent_count() != 1 {
+            eyre::bail!("can only drop commits with one parent");
+        }
+        commit.parent_id(0)?
+    };
+
+    // Branches at the commit move to its parent, so the parent can't be the root
+    let branches = state.branches.get(target.id).unwrap_or_default();
+    let mut root = if branches.is_empty() {
+        graph_from_parent(state, &target, parent_id)?
+    } else {
+        let parent = state
+            .repo
+            .find_commit(parent_id)
+            .expect("parent of an existing commit exists");
+        let grandparent_id = state
+            .repo
+            .raw()
+            .find_commit(parent_id)?
+            .parent_ids()
+            .next()
+            .ok_or_else(|| eyre::eyre!("{} is the root commit", parent_id))?;
+        graph_from_parent(state, &parent, grandparent_id)?
+    };
+    if !git_stack::graph::drop_commit(&mut root, target.id) {
+        eyre::bail!(
+            "dropping {} would leave `{}` on a protected commit, delete the branch instead",
+            target.id,
+            branches.iter().map(|b| b.name.as_str()).join("`, `")
+        );
+    }
+
+    Ok(git_stack::graph::to_script(&root))
+}
+
+/// Branch for `git stack create`
+#[derive(Clone, Debug)]
+struct CreateBranch {
+    name: String,
+    at: String,
+}
+
+/// Git config, under `branch.<name>`, for the branch a branch was created on top of
+pub(crate) const PARENT_KEY: &str = "stack-parent";
+
+/// Each branch with a recorded parent, and that parent
+pub(crate) fn recorded_parents(repo: &git2::Repository) -> Vec<(String, String)> {
+    branch_config(repo, PARENT_KEY)
+}
+
+/// Git config, under `branch.<name>`, for keeping a branch out of bulk operations
+pub(crate) const FROZEN_KEY: &str = "stack-frozen";
+
+/// Branches frozen with `git stack freeze`
+fn frozen_branches(repo: &git2::Repository) -> HashSet<String> {
+    branch_config(repo, FROZEN_KEY)
+        .into_iter()
+        .filter(|(_, value)| git2::Config::parse_bool(value.as_str()).unwrap_or(false))
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Git config, under `branch.<name>`, for what a branch is for, shared with `git branch
+/// --edit-description`, `git format-patch`, and `git request-pull`
+const DESCRIPTION_KEY: &str = "description";
+
+/// Each branch with `key` set under `branch.<name>`, and its value
+fn branch_config(repo: &git2::Repository, key: &str) -> Vec<(String, String)> {
+    let config = match repo.config().and_then(|mut c| c.snapshot()) {
+        Ok(config) => config,
+        Err(err) => {
+            log::debug!("Could not read `branch.*.{}`: {}", key, err);
+            return Vec::new();
+        }
+    };
+    let entries = match config.entries(Some(&format!(r"branch\..*\.{}", key))) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::debug!("Could not read `branch.*.{}`: {}", key, err);
+            return Vec::new();
+        }
+    };
+    let mut values = Vec::new();
+    let entries_ref = &entries;
+    for entry in entries_ref.flat_map(|e| e.into_iter()) {
+        let name = entry.name().and_then(|n| {
+            n.strip_prefix("branch.")?
+                .strip_suffix(key)?
+                .strip_suffix('.')
+        });
+        if let (Some(name), Some(value)) = (name, entry.value()) {
+            values.push((name.to_owned(), value.to_owned()));
+        }
+    }
+    values
+}
+
+/// Restack branches onto their recorded parents, when the history no longer agrees
+fn follow_recorded_parents(state: &State, root: &mut git_stack::graph::Node) {
+    for (name, parent_name) in recorded_parents(state.repo.raw()) {
+        let (branch, parent) = match (
+            state.repo.find_local_branch(&name),
+            state.repo.find_local_branch(&parent_name),
+        ) {
+            (Some(branch), Some(parent)) => (branch, parent),
+            (Some(_), None) => {
+                log::debug!(
+                    "Ignoring the recorded parent of `{}`, `{}` doesn't exist",
+                    name,
+                    parent_name
+                );
+                continue;
+            }
+            (None, _) => continue,
+        };
+        if state.protected_branches.contains_oid(parent.id)
+            || state.repo.merge_base(parent.id, branch.id) == Some(parent.id)
+        {
+            continue;
+        }
+        let fork_id = match fork_point(&state.repo, &parent, branch.id) {
+            Some(fork_id) => fork_id,
+            None => continue,
+        };
+        if git_stack::graph::reparent(root, branch.id, fork_id, parent.id) {
+            log::debug!(
+                "Restacking `{}` onto its recorded parent `{}`",
+                name,
+                parent_name
+            );
+        }
+    }
+}
+
+/// Where `branch_id` started from `parent`, looking back through `parent`'s reflog for if it was
+/// rewritten since
+fn fork_point(
+    repo: &git_stack::git::GitRepo,
+    parent: &git_stack::git::Branch,
+    branch_id: git2::Oid,
+) -> Option<git2::Oid> {
+    let reflog = repo.raw().reflog(&repo.branch_ref(&parent.name)).ok();
+    reflog
+        .iter()
+        .flat_map(|reflog| reflog.iter())
+        .map(|entry| entry.id_new())
+        .find(|id| repo.merge_base(*id, branch_id) == Some(*id))
+        .or_else(|| repo.merge_base(parent.id, branch_id))
+}
+
+/// Fill in `stack.branch-template`
+fn expand_branch_template(
+    template: &str,
+    name: &str,
+    ticket: Option<&str>,
+    repo: &git2::Repository,
+) -> eyre::Result<String> {
+    let mut expanded = template.replace("{name}", name);
+    if expanded.contains("{user}") {
+        let user = repo
+            .config()
+            .and_then(|c| c.get_string("user.email"))
+            .ok()
+            .and_then(|email| email.split('@').next().map(|u| u.to_owned()))
+            .filter(|u| !u.is_empty())
+            .or_else(|| std::env::var("USER").ok())
+            .or_else(|| std::env::var("USERNAME").ok())
+            .ok_or_else(|| eyre::eyre!("could not find a `{{user}}`, set `user.email`"))?;
+        expanded = expanded.replace("{user}", &user);
+    }
+    match ticket {
+        Some(ticket) if expanded.contains("{ticket}") => {
+            expanded = expanded.replace("{ticket}", ticket);
+        }
+        Some(ticket) => {
+            log::warn!(
+                "Ignoring ticket `{}`, `stack.branch-template` has no `{{ticket}}`",
+                ticket
+            );
+        }
+        None if expanded.contains("{ticket}") => {
+            eyre::bail!("`stack.branch-template` needs a ticket, pass `--ticket <ID>`");
+        }
+        None => {}
+    }
+
+    if !git2::Reference::is_valid_name(&format!("refs/heads/{}", expanded)) {
+        eyre::bail!("`{}` is not a valid branch name", expanded);
+    }
+    Ok(expanded)
+}
+
+fn create_branch(state: &mut State, create: &CreateBranch) -> eyre::Result<()> {
+    if state.repo.find_local_branch(&create.name).is_some() {
+        eyre::bail!("branch `{}` already exists", create.name);
+    }
+    let at = state
+        .repo
+        .resolve(&create.at)
+        .ok_or_else(|| eyre::eyre!("could not resolve {}", create.at))?;
+    if at.id != state.head_commit.id && state.repo.is_dirty() {
+        eyre::bail!("Working tree is dirty, aborting");
+    }
+
+    // Prefer the branch we're on, then anything else at or below the new branch
+    let head_branch = state.repo.head_branch().filter(|b| b.id == at.id);
+    let parent = head_branch
+        .or_else(|| {
+            state
+                .branches
+                .get(at.id)
+                .into_iter()
+                .flatten()
+                .min_by_key(|b| b.name.as_str())
+                .cloned()
+        })
+        .or_else(|| git_stack::git::find_base(&state.repo, &state.branches, at.id).cloned())
+        .or_else(|| {
+            git_stack::git::find_protected_base(&state.repo, &state.protected_branches, at.id)
+                .cloned()
+        });
+
+    match parent.as_ref() {
+        Some(parent) => log::info!("Creating `{}` on top of `{}`", create.name, parent.name),
+        None => log::info!("Creating `{}` at {}", create.name, at.id),
+    }
+    if state.dry_run {
+        return Ok(());
+    }
+
+    let reason = match parent.as_ref() {
+        Some(parent) => format!("create on top of {}", parent.name),
+        None => "create".to_owned(),
+    };
+    state.repo.branch(&create.name, at.id, &reason)?;
+    if let Some(parent) = parent.as_ref() {
+        if !state.repo.is_sandboxed() {
+            let mut config = state.repo.raw().config()?;
+            config.set_str(
+                &format!("branch.{}.{}", create.name, PARENT_KEY),
+                &parent.name,
+            )?;
+        }
+    }
+    state.repo.switch(&create.name)?;
+
+    let new = state
+        .repo
+        .find_local_branch(&create.name)
+        .ok_or_else(|| eyre::eyre!("could not find branch `{}`", create.name))?;
+    state.branches.insert(new.clone());
+    for stack in state.stacks.iter_mut() {
+        let in_stack = match parent.as_ref() {
+            Some(parent) => {
+                stack.base.name == parent.name
+                    || stack.onto.name == parent.name
+                    || stack
+                        .branches
+                        .get(parent.id)
+                        .into_iter()
+                        .flatten()
+                        .any(|b| b.name == parent.name)
+            }
+            None => false,
+        };
+        if in_stack {
+            stack.branches.insert(new.clone());
+        }
+    }
+    state.update()?;
+
+    Ok(())
+}
+
+/// Commits for `git stack adopt`, listing the orphaned commits without `rev`
+#[derive(Clone, Debug)]
+struct AdoptCommits {
+    rev: Option<String>,
+    branch: Option<String>,
+}
+
+/// Commits recorded in the reflogs and backups, named by how to refer to them
+fn orphan_candidates(state: &State) -> Vec<(String, git2::Oid)> {
+    let refs = state
+        .branches
+        .iter()
+        .flat_map(|(_, b)| b.iter())
+        .map(|b| state.repo.branch_ref(&b.name));
+    let mut candidates = git_stack::git::reflog_candidates(state.repo.raw(), refs);
+    let backups = git_stack::stash::Stack::new(STASH_STACK_NAME, &state.repo);
+    for path in backups.iter().rev() {
+        if let Ok(snapshot) = git_stack::stash::Snapshot::load(&path) {
+            candidates.extend(
+                snapshot
+                    .branches
+                    .iter()
+                    .map(|b| (state.repo.abbrev_id(b.id), b.id)),
+            );
+        }
+    }
+    candidates
+}
+
+/// Commits no branch reaches, from the reflogs and backups
+fn find_orphans(state: &State) -> eyre::Result<Vec<git_stack::git::Orphan>> {
+    let candidates = orphan_candidates(state);
+    let branches: Vec<_> = state.branches.iter().flat_map(|(_, b)| b.iter()).collect();
+    let branch_ids: Vec<_> = branches.iter().map(|b| b.id).collect();
+    let protected_ids: Vec<_> = state.protected_branches.oids().collect();
+    let orphans =
+        git_stack::git::find_orphans(state.repo.raw(), &candidates, &branch_ids, &protected_ids)?;
+    Ok(orphans)
+}
+
+fn list_orphans(state: &State, colored_stdout: bool) -> eyre::Result<()> {
+    let orphans = find_orphans(state)?;
+    if orphans.is_empty() {
+        log::info!("No orphaned commits");
+        return Ok(());
+    }
+
+    for orphan in orphans.iter() {
+        let base_commit = match orphan.base_id.and_then(|id| state.repo.find_commit(id)) {
+            Some(base_commit) => base_commit,
+            None => {
+                log::debug!("Skipping {}, it has no parent to show it on", orphan.source);
+                continue;
+            }
+        };
+        let mut branches = git_stack::git::Branches::default();
+        for branch in state.branches.get(base_commit.id).into_iter().flatten() {
+            branches.insert(branch.clone());
+        }
+        // Label the orphan with how to refer to it
+        branches.insert(git_stack::git::Branch {
+            name: orphan.source.clone(),
+            id: orphan.tip_id(),
+            push_id: None,
+            pull_id: None,
+        });
+        let mut root = git_stack::graph::Node::new(base_commit, &mut branches);
+        root = root.extend_branches(&state.repo, branches)?;
+        protect(state, &mut root, &state.protected_branches);
+
+        writeln!(
+            std::io::stdout(),
+            "{}",
+            DisplayTree::new(&state.repo, &root)
+                .colored(colored_stdout, state.palette)
+                .show(state.show_format)
+                .stacked(state.show_stacked)
+                .full_hashes(state.show_full_hashes)
+                .max_width(if state.truncate {
+                    terminal_width()
+                } else {
+                    None
+                })
+                .glyphs(state.glyphs)
+                .protected_branches(&state.protected_branches)
+        )?;
+    }
+    log::info!("To adopt one, run `git stack adopt <rev>`, with `--branch <name>` to create a branch for it");
+
+    Ok(())
+}
+
+/// Point a branch at `rev`, fast-forwarding it or creating it
+///
+/// Returns whether the branches were backed up
+fn adopt_commits(state: &mut State, rev: &str, branch: Option<&str>) -> eyre::Result<bool> {
+    // libgit2 reads `HEAD@{n}` from the current branch's reflog, unlike git and our listing
+    let target = orphan_candidates(state)
+        .into_iter()
+        .find(|(name, _)| name == rev)
+        .and_then(|(_, id)| state.repo.find_commit(id))
+        .or_else(|| state.repo.resolve(rev))
+        .ok_or_else(|| eyre::eyre!("could not resolve {}", rev))?;
+    let (name, existing) = match branch {
+        Some(name) => (name.to_owned(), state.repo.find_local_branch(name)),
+        None => {
+            let base = git_stack::git::find_base(&state.repo, &state.branches, target.id)
+                .filter(|b| !state.protected_branches.contains_oid(b.id))
+                .cloned()
+                .ok_or_else(|| {
+                    eyre::eyre!(
+                        "{} isn't on top of a branch, pass `--branch <name>` to create one",
+                        rev
+                    )
+                })?;
+            (base.name.clone(), Some(base))
+        }
+    };
+    let reason = format!("adopt {}", rev);
+
+    let existing = match existing {
+        Some(existing) => existing,
+        None => {
+            log::info!("Creating `{}` at {}", name, state.repo.abbrev_id(target.id));
+            if !state.dry_run {
+                state.repo.branch(&name, target.id, &reason)?;
+                state.update()?;
+            }
+            return Ok(false);
+        }
+    };
+    if existing.id == target.id {
+        log::info!("`{}` is already at {}", name, rev);
+        return Ok(false);
+    }
+    if state
+        .protected_branches
+        .get(existing.id)
+        .into_iter()
+        .flatten()
+        .any(|b| b.name == name)
+    {
+        eyre::bail!("`{}` is protected", name);
+    }
+    if state.repo.merge_base(existing.id, target.id) != Some(existing.id) {
+        eyre::bail!(
+            "can't fast-forward `{}` to {}, it has commits that aren't there",
+            name,
+            rev
+        );
+    }
+
+    log::info!(
+        "Fast-forwarding `{}` to {}",
+        name,
+        state.repo.abbrev_id(target.id)
+    );
+    if state.dry_run {
+        return Ok(false);
+    }
+    let is_head = state.repo.head_branch().map(|b| b.name) == Some(name.clone());
+    if is_head && state.repo.is_dirty() {
+        eyre::bail!("Working tree is dirty, aborting");
+    }
+    backup(state)?;
+    if is_head {
+        state.repo.detach()?;
+        state.repo.branch(&name, target.id, &reason)?;
+        state.repo.switch(&name)?;
+    } else {
+        state.repo.branch(&name, target.id, &reason)?;
+    }
+    state.update()?;
+
+    Ok(true)
+}
+
+/// Branch for `git stack rename`
+#[derive(Clone, Debug)]
+struct RenameBranch {
+    old: String,
+    new: String,
+    /// Rename it on the push remote on the next push
+    remote: bool,
+}
+
+/// Git config, under `branch.<name>`, for the name a branch still has on the push remote
+const RENAMED_FROM_KEY: &str = "stack-renamed-from";
+
+fn rename_branch(state: &mut State, rename: &RenameBranch) -> eyre::Result<()> {
+    let old = state
+        .repo
+        .find_local_branch(&rename.old)
+        .ok_or_else(|| eyre::eyre!("could not find branch `{}`", rename.old))?;
+    if state.repo.find_local_branch(&rename.new).is_some() {
+        eyre::bail!("branch `{}` already exists", rename.new);
+    }
+    let protected = state
+        .protected_branches
+        .get(old.id)
+        .into_iter()
+        .flatten()
+        .any(|b| b.name == old.name);
+    if protected {
+        eyre::bail!("cannot rename `{}`, it is protected", old.name);
+    }
+
+    log::info!("Renaming `{}` to `{}`", rename.old, rename.new);
+    if state.dry_run {
+        return Ok(());
+    }
+
+    backup_with(state, |snapshot| {
+        snapshot.insert_message(&format!("rename {} {}", rename.old, rename.new));
+        snapshot.insert_rename(&rename.old, &rename.new);
+    })?;
+    state.repo.rename_branch(&rename.old, &rename.new)?;
+    if !state.repo.is_sandboxed() {
+        let mut config = state.repo.raw().config()?;
+        if rename.remote && old.push_id.is_some() {
+            config.set_str(
+                &format!("branch.{}.{}", rename.new, RENAMED_FROM_KEY),
+                &rename.old,
+            )?;
+        }
+
+        // Keep branches created on top of it pointing at it
+        reparent_children(&mut config, &rename.old, &rename.new)?;
+    }
+
+    let new = state
+        .repo
+        .find_local_branch(&rename.new)
+        .ok_or_else(|| eyre::eyre!("could not find branch `{}`", rename.new))?;
+    state.branches.insert(new.clone());
+    for stack in state.stacks.iter_mut() {
+        let in_stack = stack
+            .branches
+            .get(old.id)
+            .into_iter()
+            .flatten()
+            .any(|b| b.name == old.name);
+        if in_stack {
+            stack.branches.insert(new.clone());
+        }
+    }
+    state.update()?;
+
+    Ok(())
+}
+
+/// Point the branches created on top of `old` at `new`
+fn reparent_children(config: &mut git2::Config, old: &str, new: &str) -> eyre::Result<()> {
+    let mut children = Vec::new();
+    let snapshot = config.snapshot()?;
+    let entries = snapshot.entries(Some(&format!(r"branch\..*\.{}", PARENT_KEY)))?;
+    let entries_ref = &entries;
+    for entry in entries_ref.flat_map(|e| e.into_iter()) {
+        if entry.value() == Some(old) {
+            if let Some(name) = entry.name() {
+                children.push(name.to_owned());
+            }
+        }
+    }
+    for key in children {
+        config.set_str(&key, new)?;
+    }
+    Ok(())
+}
+
+/// Branch for `git stack land`
+#[derive(Clone, Debug)]
+struct LandBranch {
+    branch: Option<String>,
+    /// Merge through the forge rather than locally
+    forge: bool,
+}
+
+/// The forge `land --forge` merges through, checked before anything is changed
+fn landing_forge(state: &State, land: &LandBranch) -> eyre::Result<Option<crate::forge::Forge>> {
+    if !land.forge {
+        return Ok(None);
+    }
+    let forge = crate::forge::Forge::from_remote(
+        &state.repo,
+        state.repo.push_remote(),
+        &state.gitlab_hosts,
+    )
+    .ok_or_else(|| eyre::eyre!("`--forge` only supports GitHub/GitLab remotes"))?;
+    Ok(Some(forge))
+}
+
+fn land_branch(
+    state: &mut State,
+    land: &LandBranch,
+    forge: Option<crate::forge::Forge>,
+) -> eyre::Result<()> {
+    let (branch, onto) = find_landing(state, land.branch.as_deref())?;
+    let protected = state
+        .protected_branches
+        .get(onto.id)
+        .into_iter()
+        .flatten()
+        .any(|b| b.name == onto.name);
+    if !protected {
+        eyre::bail!(
+            "`{}` is stacked on `{}` which is not protected, land that first",
+            branch.name,
+            onto.name
+        );
+    }
+
+    log::info!("Landing `{}` in `{}`", branch.name, onto.name);
+    if state.dry_run {
+        return Ok(());
+    }
+
+    if let Some(forge) = forge {
+        match forge {
+            crate::forge::Forge::GitHub => crate::github::merge_pr(&branch.name)?,
+            crate::forge::Forge::GitLab => crate::gitlab::merge_mr(&branch.name)?,
+        }
+        git_pull(&mut state.repo, &onto.name, false)?;
+    } else {
+        let message = format!("Merge branch '{}' into {}\n", branch.name, onto.name);
+        let new_id = state
+            .repo
+            .merge(onto.id, branch.id, &message)
+            .wrap_err_with(|| {
+                format!(
+                    "could not land `{}`, run `git stack --rebase` to restack it onto `{}` first",
+                    branch.name, onto.name
+                )
+            })?;
+        let reason = format!("land {}", branch.name);
+        let head_branch = state.repo.head_branch();
+        if head_branch.as_ref().map(|b| b.name.as_str()) == Some(onto.name.as_str()) {
+            state.repo.detach()?;
+            state.repo.branch(&onto.name, new_id, &reason)?;
+            state.repo.switch(&onto.name)?;
+        } else {
+            state.repo.branch(&onto.name, new_id, &reason)?;
+        }
+    }
+
+    let head_branch = state.repo.head_branch();
+    if head_branch.as_ref().map(|b| b.name.as_str()) == Some(branch.name.as_str()) {
+        state.repo.switch(&onto.name)?;
+    }
+    quarantine_branches(state, std::slice::from_ref(&branch))?;
+    state.repo.delete_branch(&branch.name)?;
+    if !state.repo.is_sandboxed() {
+        let mut config = state.repo.raw().config()?;
+        reparent_children(&mut config, &branch.name, &onto.name)?;
+    }
+    state.update()?;
+
+    Ok(())
+}
+
+/// The branch `git stack land` merges and the branch it is merged into
+///
+/// Without a name, this is the bottom branch of the stack `HEAD` is in.
+fn find_landing(
+    state: &State,
+    name: Option<&str>,
+) -> eyre::Result<(git_stack::git::Branch, git_stack::git::Branch)> {
+    for stack in state.stacks.iter() {
+        let tip = match name {
+            Some(name) => {
+                let found = stack
+                    .branches
+                    .iter()
+                    .flat_map(|(_, b)| b.iter())
+                    .find(|b| b.name == name);
+                match found {
+                    Some(branch) => branch.id,
+                    None => continue,
+                }
+            }
+            None => state.head_commit.id,
+        };
+        let base_id = match state.repo.merge_base(stack.onto.id, tip) {
+            Some(base_id) => base_id,
+            None => continue,
+        };
+
+        let mut bottom = None;
+        for commit in state.repo.commits_from(tip).take_while(|c| c.id != base_id) {
+            let branch = stack
+                .branches
+                .get(commit.id)
+                .into_iter()
+                .flatten()
+                .filter(|b| {
+                    !state
+                        .protected_branches
+                        .get(b.id)
+                        .into_iter()
+                        .flatten()
+                        .any(|p| p.name == b.name)
+                })
+                .min_by_key(|b| b.name.as_str());
+            if let Some(branch) = branch {
+                bottom = Some(branch.clone());
+            }
+        }
+
+        match (name, bottom) {
+            (Some(name), Some(bottom)) if bottom.id != tip => {
+                eyre::bail!(
+                    "`{}` is stacked on `{}`, land that first",
+                    name,
+                    bottom.name
+                );
+            }
+            (Some(name), _) => {
+                let branch = state
+                    .repo
+                    .find_local_branch(name)
+                    .ok_or_else(|| eyre::eyre!("could not find branch `{}`", name))?;
+                if base_id == branch.id {
+                    eyre::bail!("`{}` has already landed in `{}`", name, stack.onto.name);
+                }
+                return Ok((branch, stack.onto.clone()));
+            }
+            (None, Some(bottom)) => return Ok((bottom, stack.onto.clone())),
+            (None, None) => continue,
+        }
+    }
+
+    match name {
+        Some(name) => eyre::bail!(
+            "could not find `{}` in the current stack, switch to it first",
+            name
+        ),
+        None => {
+            eyre::bail!("nothing to land, `HEAD` has no branches stacked on a protected branch")
+        }
+    }
+}
+
+/// Delete the branch `git stack rename --remote` left behind on the push remote
+fn finish_remote_rename(repo: &git_stack::git::GitRepo, branch_name: &str) {
+    let key = format!("branch.{}.{}", branch_name, RENAMED_FROM_KEY);
+    let mut config = match repo.raw().config() {
+        Ok(config) => config,
+        Err(_) => return,
+    };
+    let old = match config.get_string(&key) {
+        Ok(old) => old,
+        Err(_) => return,
+    };
+
+    let remote = repo.push_remote();
+    let on_remote = repo
+        .raw()
+        .find_branch(&format!("{}/{}", remote, old), git2::BranchType::Remote)
+        .is_ok();
+    if on_remote {
+        log::trace!("git push {} --delete {}", remote, old);
+        let status = std::process::Command::new("git")
+            .arg("push")
+            .arg(remote)
+            .arg("--delete")
+            .arg(&old)
+            .status();
+        if !status.map(|s| s.success()).unwrap_or(false) {
+            log::warn!(
+                "Could not delete `{}` from `{}`, the old name of `{}`",
+                old,
+                remote,
+                branch_name
+            );
+            return;
+        }
+    }
+    let _ = config.remove(&key);
+}
+
+/// Commits for `git stack squash`
+#[derive(Clone, Debug)]
+enum SquashRange {
+    /// `<from>..<to>`
+    Range(String),
+    /// Everything exclusive to the branch
+    Branch(String),
+}
+
+/// Squash `range` into one commit, returning the script to restack what is on top of it
+fn plan_squash(state: &mut State, range: &SquashRange) -> eyre::Result<git_stack::git::Script> {
+    let (from_id, to_id) = match range {
+        SquashRange::Range(range) => {
+            let (from, to) = range
+                .split_once("..")
+                .ok_or_else(|| eyre::eyre!("expected `<from>..<to>`, got `{}`", range))?;
+            let to = if to.is_empty() { "HEAD" } else { to };
+            let from_id = state
+                .repo
+                .resolve(from)
+                .ok_or_else(|| eyre::eyre!("could not find commit `{}`", from))?
+                .id;
+            let to_id = state
+                .repo
+                .resolve(to)
+                .ok_or_else(|| eyre::eyre!("could not find commit `{}`", to))?
+                .id;
+            if state.repo.merge_base(from_id, to_id) != Some(from_id) {
+                eyre::bail!("`{}` is not an ancestor of `{}`", from, to);
+            }
+            (from_id, to_id)
+        }
+        SquashRange::Branch(name) => {
+            let branch = state
+                .repo
+                .find_local_branch(name)
+                .ok_or_else(|| eyre::eyre!("could not find branch `{}`", name))?;
+            (branch_start(state, &branch)?, branch.id)
+        }
+    };
+
+    let mut ids: Vec<_> = state
+        .repo
+        .commits_from(to_id)
+        .take_while(|c| c.id != from_id)
+        .map(|c| c.id)
+        .collect();
+    ids.reverse();
+    if ids.len() < 2 {
+        eyre::bail!("nothing to squash, need at least two commits");
+    }
+
+    let oldest = rewritable_commit(state, &ids[0].to_string(), "squash")?;
+    let mut root = graph_from_parent(state, &oldest, from_id)?;
+    let squashed_id = state.repo.squash_commits(&ids)?;
+    let squashed = state
+        .repo
+        .find_commit(squashed_id)
+        .expect("squashed commit was just created");
+    if !git_stack::graph::squash_commits(&mut root, &ids, squashed) {
+        eyre::bail!(
+            "cannot squash {}..{}, commits in the middle have branches or other commits on top of them",
+            from_id,
+            to_id
+        );
+    }
+
+    Ok(git_stack::graph::to_script(&root))
+}
+
+/// Where `branch`'s own commits start, at another branch or a protected commit
+pub(crate) fn branch_start(
+    state: &State,
+    branch: &git_stack::git::Branch,
+) -> eyre::Result<git2::Oid> {
+    let start = state
+        .repo
+        .commits_from(branch.id)
+        .skip(1)
+        .find(|c| {
+            state.branches.get(c.id).is_some()
+                || state
+                    .protected_branches
+                    .iter()
+                    .flat_map(|(_, b)| b.iter())
+                    .any(|b| state.repo.merge_base(c.id, b.id) == Some(c.id))
+        })
+        .ok_or_else(|| eyre::eyre!("could not find where `{}` starts", branch.name))?;
+    Ok(start.id)
+}
+
+/// Check each moved branch makes the same changes against its new base as it did against its old
+/// one, returning whether they all do
+///
+/// Differences come from how conflicts were resolved or commits that were dropped for having
+/// landed upstream.
+fn verify_restack(state: &State, original: &git_stack::stash::Snapshot) -> eyre::Result<bool> {
+    let mut verified = true;
+    for stack in state.stacks.iter() {
+        let protected_branches = stack.protected_branches(state);
+        for branch in stack.branches.iter().flat_map(|(_, b)| b.iter()) {
+            if protected_branches.contains_oid(branch.id) {
+                continue;
+            }
+            let old_id = match original.branches.iter().find(|b| b.name == branch.name) {
+                Some(old) if old.id != branch.id => old.id,
+                _ => continue,
+            };
+            let (old_base, new_base) = match (
+                state.repo.merge_base(old_id, stack.base.id),
+                state.repo.merge_base(branch.id, stack.onto.id),
+            ) {
+                (Some(old_base), Some(new_base)) => (old_base, new_base),
+                _ => continue,
+            };
+            let old_patch = state.repo.range_patch_id(old_base, old_id)?;
+            let new_patch = state.repo.range_patch_id(new_base, branch.id)?;
+            if old_patch == new_patch {
+                log::trace!("`{}` is unchanged by restacking", branch.name);
+            } else {
+                log::error!(
+                    "Restacking changed the content of `{}`, compare with `git range-diff {}..{} {}..{}`",
+                    branch.name,
+                    state.repo.abbrev_id(old_base),
+                    state.repo.abbrev_id(old_id),
+                    state.repo.abbrev_id(new_base),
+                    state.repo.abbrev_id(branch.id),
+                );
+                verified = false;
+            }
+        }
+    }
+    if verified {
+        log::debug!("Verified every branch is unchanged by restacking");
+    }
+    Ok(verified)
+}
+
+/// Log whether restacking each branch is guaranteed to keep its changes the same
+///
+/// Only moving a branch to a new base with none of its files changed underneath it is guaranteed to
+/// preserve its content.  Conflicting regions are resolved and dropped commits go missing.
+fn report_content_changes(state: &State, scripts: &[git_stack::git::Script]) {
+    let changes = assess_changes(state, scripts);
+    if !changes.is_empty() {
+        log::info!("Restacking each branch:");
+        for change in changes {
+            log::info!(
+                "  {}: {} ({})",
+                change.branch,
+                change.description,
+                change.risk
+            );
+        }
+    }
+}
+
+/// A branch the plan will fail to restack
+struct PredictedConflict {
+    branch: String,
+    commit: git2::Oid,
+    paths: Vec<std::path::PathBuf>,
+    blocked: Vec<String>,
+}
+
+fn report_conflicts(state: &State, scripts: &[git_stack::git::Script]) {
+    let mut conflicts = Vec::new();
+    let mut marks = std::collections::HashMap::new();
+    for script in scripts {
+        predict_conflicts(&state.repo, script, None, &mut marks, &mut conflicts);
+    }
+    for conflict in conflicts {
+        let paths = conflict
+            .paths
+            .iter()
+            .map(|p| p.display().to_string())
+            .join(", ");
+        let summary = state
+            .repo
+            .find_commit(conflict.commit)
+            .map(|c| c.summary.to_str_lossy().into_owned())
+            .unwrap_or_default();
+        log::warn!(
+            "`{}` will conflict in {} (cherry-picking {}  # {})",
+            conflict.branch,
+            paths,
+            state.repo.abbrev_id(conflict.commit),
+            summary
+        );
+        if !conflict.blocked.is_empty() {
+            log::warn!("  Blocked dependents: {}", conflict.blocked.join(", "));
+        }
+    }
+}
+
+/// Cherry-pick each commit in memory, tracking trees instead of creating commits
+fn predict_conflicts(
+    repo: &git_stack::git::GitRepo,
+    script: &git_stack::git::Script,
+    mut tree_id: Option<git2::Oid>,
+    marks: &mut std::collections::HashMap<git2::Oid, git2::Oid>,
+    conflicts: &mut Vec<PredictedConflict>,
+) {
+    for (index, command) in script.commands.iter().enumerate() {
+        match command {
+            git_stack::git::Command::SwitchCommit(id) => {
+                tree_id = repo.find_commit(*id).map(|c| c.tree_id);
+            }
+            git_stack::git::Command::RegisterMark(mark) => {
+                if let Some(tree_id) = tree_id {
+                    marks.insert(*mark, tree_id);
+                }
+            }
+            git_stack::git::Command::SwitchMark(mark) => {
+                tree_id = marks.get(mark).copied();
+            }
+            git_stack::git::Command::CherryPick(id) | git_stack::git::Command::Squash(id) => {
+                // Without a starting point, there is nothing to predict from
+                let current = match tree_id {
+                    Some(current) => current,
+                    None => return,
+                };
+                match repo.predict_cherry_pick(current, *id) {
+                    Ok(git_stack::git::PickPrediction::Clean(next)) => {
+                        tree_id = Some(next);
+                    }
+                    Ok(git_stack::git::PickPrediction::Conflict(paths)) => {
+                        let branch = script.commands[index..]
+                            .iter()
+                            .find_map(|c| match c {
+                                git_stack::git::Command::CreateBranch(name) => Some(name.as_str()),
+                                _ => None,
+                            })
+                            .unwrap_or("detached");
+                        conflicts.push(PredictedConflict {
+                            branch: branch.to_owned(),
+                            commit: *id,
+                            paths,
+                            blocked: script
+                                .dependent_branches()
+                                .into_iter()
+                                .map(ToOwned::to_owned)
+                                .collect(),
+                        });
+                        return;
+                    }
+                    Err(err) => {
+                        log::debug!("Could not predict cherry-picking {}: {}", id, err.message());
+                        return;
+                    }
+                }
+            }
+            git_stack::git::Command::CreateBranch(_)
+            | git_stack::git::Command::DeleteBranch(_)
+            | git_stack::git::Command::Break
+            | git_stack::git::Command::Exec(_) => {}
+        }
+    }
+
+    for dependent in script.dependents.iter() {
+        predict_conflicts(repo, dependent, tree_id, marks, conflicts);
+    }
+}
+
+/// Ask about or refuse risky changes, per `stack.risk-gate`, returning whether to go ahead
+fn gate_risk(state: &State, changes: &[git_stack::git::BranchChange]) -> eyre::Result<bool> {
+    if state.force || state.dry_run {
+        return Ok(true);
+    }
+    let risk = match git_stack::git::Gate::for_changes(state.risk_gate, changes) {
+        git_stack::git::Gate::Apply => return Ok(true),
+        git_stack::git::Gate::Refuse => {
+            for change in changes
+                .iter()
+                .filter(|c| c.risk == git_stack::git::Risk::High)
+            {
+                log::error!(
+                    "  {}: {} ({})",
+                    change.branch,
+                    change.description,
+                    change.risk
+                );
+            }
+            eyre::bail!("refusing high risk changes, pass `--force` to apply them anyway");
+        }
+        git_stack::git::Gate::Ask(risk) => risk,
+    };
+    for change in changes
+        .iter()
+        .filter(|c| git_stack::git::Risk::Low < c.risk)
+    {
+        log::warn!(
+            "  {}: {} ({})",
+            change.branch,
+            change.description,
+            change.risk
+        );
+    }
+    if !atty::is(atty::Stream::Stdin) {
+        eyre::bail!(
+            "can't ask about {} changes without a terminal, pass `--force` to apply them",
+            risk
+        );
+    }
+    let stderr = std::io::stderr();
+    let mut stderr = stderr.lock();
+    write!(stderr, "Apply {} changes? [y/N] ", risk)?;
+    stderr.flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn assess_changes(
+    state: &State,
+    scripts: &[git_stack::git::Script],
+) -> Vec<git_stack::git::BranchChange> {
+    let mut planned = git_stack::git::PlannedChanges::new(scripts);
+    let mut changes = Vec::new();
+    for stack in state.stacks.iter() {
+        let protected_branches = stack.protected_branches(state);
+        let mut branches: Vec<_> = stack
+            .branches
+            .iter()
+            .flat_map(|(_, b)| b.iter())
+            .filter(|b| !protected_branches.contains_oid(b.id))
+            .collect();
+        branches.sort_by_key(|b| b.name.as_str());
+        changes.extend(planned.assess(&state.repo, stack.base.id, &stack.onto, &branches));
+    }
+    changes
+}
+
+/// Log what `sync` changed, stack by stack
+fn report_sync(
+    state: &State,
+    initial_stacks: &[(git_stack::git::Branch, git_stack::git::Branches)],
+) {
+    for (onto, branches) in initial_stacks {
+        let mut branches: Vec<_> = branches.iter().flat_map(|(_, b)| b.iter()).collect();
+        branches.sort_by_key(|b| b.name.as_str());
+
+        let mut changes = Vec::new();
+        for old in branches {
+            let new = state.repo.find_local_branch(&old.name);
+            let change = match new {
+                None => "deleted, merged upstream".to_owned(),
+                Some(new) => {
+                    let mut change = Vec::new();
+                    if new.id != old.id {
+                        change.push(format!(
+                            "{} -> {}",
+                            format_id(&state.repo, old.id, state.show_full_hashes),
+                            format_id(&state.repo, new.id, state.show_full_hashes)
+                        ));
+                    }
+                    if new.push_id == Some(new.id) && old.push_id != Some(new.id) {
+                        change.push("pushed".to_owned());
+                    }
+                    if change.is_empty() {
+                        continue;
+                    }
+                    change.join(", ")
+                }
+            };
+            changes.push(format!("  {}: {}", old.name, change));
+        }
+
+        if changes.is_empty() {
+            log::info!("Stack on `{}`: up-to-date", onto.name);
+        } else {
+            log::info!("Stack on `{}`:", onto.name);
+            for change in changes {
+                log::info!("{}", change);
+            }
+        }
+    }
+}
+
+fn format_id(repo: &git_stack::git::GitRepo, id: git2::Oid, full: bool) -> String {
+    if full {
+        id.to_string()
+    } else {
+        repo.abbrev_id(id)
+    }
+}
+
+/// Log the branches that couldn't be restacked, returning whether there were none
+fn report_failures(results: &[(git2::Error, &str, Vec<&str>)]) -> bool {
+    for (err, name, dependents) in results.iter() {
+        log::error!("Failed to re-stack branch `{}`: {}", name, err.message());
+        if !dependents.is_empty() {
+            log::error!("  Blocked dependents: {}", dependents.iter().join(", "));
+        }
+    }
+    results.is_empty()
+}
+
+/// Log the commits `Executor::drop_empty` dropped
+fn report_dropped(repo: &git_stack::git::GitRepo, dropped: &[git2::Oid]) {
+    for id in dropped {
+        let summary = repo
+            .find_commit(*id)
+            .map(|c| c.summary.to_str_lossy().into_owned())
+            .unwrap_or_default();
+        log::info!(
+            "Dropped {}  # {}, it became empty",
+            format_id(repo, *id, false),
+            summary
+        );
+    }
+}
+
+/// Make `paths`, relative to the current directory, relative to the root of `repo`
+fn repo_paths(
+    repo: &git_stack::git::GitRepo,
+    paths: &[std::path::PathBuf],
+) -> eyre::Result<Vec<std::path::PathBuf>> {
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let workdir = repo
+        .raw()
+        .workdir()
+        .ok_or_else(|| eyre::eyre!("cannot split by path in a bare repository"))?
+        .canonicalize()?;
+    let cwd = std::env::current_dir()?.canonicalize()?;
+    let prefix = cwd.strip_prefix(&workdir).unwrap_or_else(|_| "".as_ref());
+    Ok(paths.iter().map(|p| prefix.join(p)).collect())
+}
+
+/// Stop right after `id` is picked, before any of its branches are updated
+fn insert_break_after(script: &mut git_stack::git::Script, id: git2::Oid) -> bool {
+    let pick = git_stack::git::Command::CherryPick(id);
+    if let Some(index) = script.commands.iter().position(|c| *c == pick) {
+        script
+            .commands
+            .insert(index + 1, git_stack::git::Command::Break);
+        return true;
+    }
+    script
+        .dependents
+        .iter_mut()
+        .any(|dependent| insert_break_after(dependent, id))
+}
+
+/// Move `branch_name`'s commits onto `onto_name`, returning the script to restack them and their
+/// dependents
+fn plan_move(
+    state: &State,
+    branch_name: &str,
+    onto_name: &str,
+) -> eyre::Result<git_stack::git::Script> {
+    let branch = state
+        .repo
+        .find_local_branch(branch_name)
+        .ok_or_else(|| eyre::eyre!("could not find branch `{}`", branch_name))?;
+    let onto = find_branch_dwim(&state.repo, onto_name)?
+        .ok_or_else(|| eyre::eyre!("could not find branch `{}`", onto_name))?;
+    if state
+        .protected_branches
+        .iter()
+        .flat_map(|(_, b)| b.iter())
+        .any(|b| b.name == branch.name)
+    {
+        eyre::bail!("`{}` is protected, refusing to move it", branch.name);
+    }
+
+    let base_id = state
+        .repo
+        .merge_base(branch.id, onto.id)
+        .ok_or_else(|| eyre::eyre!("`{}` and `{}` share no history", branch.name, onto.name))?;
+    let base = state
+        .repo
+        .find_commit(base_id)
+        .expect("merge base of existing commits exists");
+    let mut graphed_branches = state.branches.descendants(&state.repo, base_id);
+    let mut root = git_stack::graph::Node::new(base, &mut graphed_branches);
+    root = root.extend_branches(&state.repo, graphed_branches)?;
+    protect(state, &mut root, &state.protected_branches);
+    if !git_stack::graph::move_branch(&mut root, branch.id, onto.id) {
+        eyre::bail!(
+            "cannot move `{}` onto `{}` which is on top of it",
+            branch.name,
+            onto.name
+        );
+    }
+
+    Ok(git_stack::graph::to_script(&root))
+}
+
+/// Abort unless the plan is the one `--expect-plan` approved, reporting its id for a `--dry-run`
+fn expect_plan(state: &State, plan_id: git2::Oid) -> Result<(), proc_exit::Exit> {
+    if let Some(expected) = state.expect_plan.as_deref() {
+        let matches = git_stack::git::matches_plan_id(plan_id, expected)
+            .with_code(proc_exit::Code::USAGE_ERR)?;
+        if !matches {
+            return Err(proc_exit::Code::FAILURE.with_message(format!(
+                "Plan changed (expected {}, found {}), aborting",
+                expected, plan_id
+            )));
+        }
+    } else if state.dry_run {
+        log::info!("Plan {} (apply with `--expect-plan {}`)", plan_id, plan_id);
+    }
+    Ok(())
+}
+
+/// Run one-off scripts, returning whether every branch was restacked
+fn execute(
+    state: &mut State,
+    scripts: &[git_stack::git::Script],
+    head_branch: &str,
+) -> Result<bool, proc_exit::Exit> {
+    let mut scripts = scripts.to_vec();
+    if let Some(exec) = state.exec.as_deref() {
+        for script in scripts.iter_mut() {
+            script.exec_after_branches(exec);
+        }
+    }
+    let original =
+        git_stack::stash::Snapshot::from_repo(&state.repo).with_code(proc_exit::Code::FAILURE)?;
+    let executor = git_stack::git::Executor::new(&state.repo, state.dry_run);
+    run_scripts(
+        state,
+        executor,
+        &scripts,
+        head_branch.to_owned(),
+        Some(original),
+    )
+}
+
+/// Run `scripts`, returning whether every branch was restacked
+///
+/// `--step`, `break`, and conflicts under `stack.on-conflict=stop` stop the rebase for
+/// `git stack --continue`, and `--stage-only` holds back the branch updates for
+/// `--commit-plan`.
+fn run_scripts(
+    state: &mut State,
+    mut executor: git_stack::git::Executor,
+    scripts: &[git_stack::git::Script],
+    restore_branch: String,
+    original: Option<git_stack::stash::Snapshot>,
+) -> Result<bool, proc_exit::Exit> {
+    let stopped_path = state.repo.raw().path().join("git-stack").join(STOPPED_FILE);
+    let staged_path = state.repo.raw().path().join("git-stack").join(STAGED_FILE);
+    executor.step(state.step);
+    executor.stage_only(state.stage_only);
+    executor.rebase_merges(state.repo.rebase_merges());
+    executor.drop_empty(state.drop_empty);
+    executor.stop_on_conflict(
+        !state.sandbox && state.on_conflict == git_stack::config::OnConflict::Stop,
+    );
+
+    let mut success = true;
+    for script in scripts.iter() {
+        let results = executor.run_script(&mut state.repo, script);
+        success &= report_failures(&results);
+    }
+    report_dropped(&state.repo, executor.dropped());
+    if let Some(continuation) = executor.continuation() {
+        let stop = StoppedRebase {
+            restore_branch,
+            continuation,
+            original,
+        };
+        stop.save(&stopped_path)
+            .with_code(proc_exit::Code::FAILURE)?;
+        state.stopped = true;
+        let head_commit = state.repo.head_commit();
+        log::info!(
+            "Stopped at {}  # {}",
+            head_commit.id,
+            head_commit.summary.to_str_lossy()
+        );
+        if let Some(conflict) = stop.continuation.conflict() {
+            let resolved = state
+                .repo
+                .cherry_pick_to_worktree(conflict)
+                .with_code(proc_exit::Code::FAILURE)?;
+            let conflict_commit = state.repo.find_commit(conflict).unwrap();
+            log::warn!(
+                "Could not apply {}  # {}",
+                conflict,
+                conflict_commit.summary.to_str_lossy()
+            );
+            if !resolved.is_empty() {
+                log::info!(
+                    "Resolved by rerere: {}",
+                    resolved.iter().map(|p| p.display()).join(", ")
+                );
+            }
+            if matches!(state.repo.raw().index(), Ok(index) if !index.has_conflicts()) {
+                log::info!(
+                    "Review the resolution, then run `git stack --continue` (or `git stack --abort` to give up)"
+                );
+            } else if !resolved.is_empty() {
+                log::info!(
+                    "Review the resolution and resolve any other conflicts, `git add` them, then run `git stack --continue` (or `git stack --abort` to give up)"
+                );
+            } else {
+                log::info!(
+                    "Resolve the conflicts and `git add` them, then run `git stack --continue` (or `git stack --abort` to give up)"
+                );
+            }
+        } else if state.step {
+            if let Some(next) = next_step(stop.continuation.scripts()) {
+                log::info!("Next: {}", describe_command(&state.repo, next));
+            }
+            log::info!(
+                "Run `git stack --step` for the next step, or `git stack --continue` to finish"
+            );
+        } else {
+            log::info!("Amend as needed, then run `git stack --continue`");
+        }
+    } else if state.stage_only {
+        let staged = StagedPlan::new(&state.repo, restore_branch, executor.staged());
+        if staged.updates.is_empty() {
+            log::info!("Nothing to stage, branches are up-to-date");
+        } else {
+            for update in staged.updates.iter() {
+                match update.new_id {
+                    Some(new_id) => log::info!("{}: {}", update.branch, new_id),
+                    None => log::info!("{}: deleted", update.branch),
+                }
+            }
+            if !state.dry_run {
+                staged
+                    .save(&staged_path)
+                    .with_code(proc_exit::Code::FAILURE)?;
+                log::info!(
+                    "Inspect with `git show <id>`, then run `git stack --commit-plan` to update the branches"
+                );
+            }
+        }
+    } else {
+        executor
+            .close(&mut state.repo, &restore_branch)
+            .with_code(proc_exit::Code::FAILURE)?;
+        if stopped_path.exists() {
+            std::fs::remove_file(&stopped_path).with_code(proc_exit::Code::FAILURE)?;
+        }
+    }
+    state.rewritten += executor.rewritten();
+    state.update().with_code(proc_exit::Code::FAILURE)?;
+    Ok(success)
+}
+
+/// Commit staged changes as `fixup!`s for the commits that last touched them
+///
+/// Returns whether any fixups were committed.
+fn absorb(state: &mut State) -> eyre::Result<bool> {
+    let head_branch = state
+        .repo
+        .head_branch()
+        .ok_or_else(|| eyre::eyre!("Must not be in a detached HEAD state."))?;
+    let head_id = state.head_commit.id;
+
+    // Only commits that aren't protected can be fixed up
+    let protected_bases: HashSet<_> = state
+        .protected_branches
+        .iter()
+        .flat_map(|(_, b)| b.iter())
+        .filter_map(|b| state.repo.merge_base(head_id, b.id))
+        .collect();
+    let candidates: Vec<_> = state
+        .repo
+        .commits_from(head_id)
+        .take_while(|c| !protected_bases.contains(&c.id))
+        .collect();
+    let candidate_ids: HashSet<_> = candidates.iter().map(|c| c.id).collect();
+
+    let attributed = git_stack::git::attribute_staged(state.repo.raw(), head_id, &candidate_ids)?;
+    if attributed.is_empty() {
+        log::info!("Nothing staged to absorb");
+        return Ok(false);
+    }
+    let mut fixups: Vec<(git2::Oid, Vec<git_stack::git::StagedHunk>)> = Vec::new();
+    for (hunk, attribution) in attributed {
+        match attribution {
+            git_stack::git::Attribution::Commit(id) => {
+                match fixups.iter_mut().find(|(target_id, _)| *target_id == id) {
+                    Some((_, hunks)) => hunks.push(hunk),
+                    None => fixups.push((id, vec![hunk])),
+                }
+            }
+            git_stack::git::Attribution::Skip(reason) => {
+                log::warn!("Leaving {} staged, {}", hunk, reason);
+            }
+        }
+    }
+    if fixups.is_empty() {
+        log::warn!("Could not absorb any staged changes");
+        return Ok(false);
+    }
+    // Oldest first, matching the order `--fixup` would squash them in
+    fixups.sort_by_key(|(id, _)| std::cmp::Reverse(candidates.iter().position(|c| c.id == *id)));
+    for (id, hunks) in fixups.iter() {
+        let target = candidates
+            .iter()
+            .find(|c| c.id == *id)
+            .expect("targets are candidates");
+        log::info!(
+            "Absorbing {} into {} ({})",
+            hunks.iter().join(", "),
+            target.id,
+            target.summary.to_str_lossy()
+        );
+    }
+    if state.dry_run {
+        return Ok(false);
+    }
+
+    backup(state)?;
+    let tip_id = git_stack::git::commit_fixups(state.repo.raw(), head_id, &fixups)?;
+    let tip_id = state.repo.sign_range(head_id, tip_id)?;
+    // Moving the branch doesn't touch the index or working tree
+    state.repo.branch(&head_branch.name, tip_id, "absorb")?;
+    state.update()?;
+
+    Ok(true)
+}
+
+fn load_plan(path: &std::path::Path) -> eyre::Result<git_stack::git::Plan> {
+    let plan = if path == std::path::Path::new("-") {
+        serde_json::from_reader(std::io::stdin())?
+    } else {
+        let file = std::fs::File::open(path)
+            .wrap_err_with(|| format!("could not open {}", path.display()))?;
+        let reader = std::io::BufReader::new(file);
+        serde_json::from_reader(reader)
+            .wrap_err_with(|| format!("could not parse {}", path.display()))?
+    };
+    Ok(plan)
+}
+
+fn save_plan(plan: &git_stack::git::Plan, path: &std::path::Path) -> eyre::Result<()> {
+    let mut s = serde_json::to_string_pretty(plan)?;
+    s.push('\n');
+    if path == std::path::Path::new("-") {
+        std::io::stdout().write_all(s.as_bytes())?;
+    } else {
+        std::fs::write(path, &s)?;
+    }
+    Ok(())
+}
+
+fn edit_plan(
+    state: &State,
+    scripts: &[git_stack::git::Script],
+) -> eyre::Result<Vec<git_stack::git::Script>> {
+    let config = state.repo.raw().config()?;
+    let editor = git_stack::git::Editor::sequence_editor(&config);
+    let todo = git_stack::git::to_todo(scripts, &state.repo);
+    let dir = state.repo.raw().path().join("git-stack");
+    git_stack::git::edit_todo(&editor, &dir, &todo, &state.repo)
+}
+
+fn push(state: &mut State) -> eyre::Result<()> {
+    let mut graphed_branches = git_stack::git::Branches::new(None.into_iter());
+    for stack in state.stacks.iter() {
+        let stack_graphed_branches = stack.graphed_branches();
+        graphed_branches.extend(stack_graphed_branches.into_iter().flat_map(|(_, b)| b));
+    }
+    let mut root = git_stack::graph::Node::new(state.head_commit.clone(), &mut graphed_branches);
+    root = root.extend_branches(&state.repo, graphed_branches)?;
+
+    protect(state, &mut root, &state.protected_branches);
+    follow_recorded_parents(state, &mut root);
+    mark_pushable(state, &mut root)?;
+
+    let mut changes = Vec::new();
+    diverged_branches(state, &root, &mut changes);
+    if state.dry_run {
+        for change in changes.iter() {
+            log::info!(
+                "Would force-push `{}`, it {} ({})",
+                change.branch,
+                change.description,
+                change.risk
+            );
+        }
+    } else if !gate_risk(state, &changes)? {
+        log::info!("Leaving the remote branches as-is");
+        return Ok(());
+    }
+
+    git_push(
+        &mut state.repo,
+        &root,
+        &state.frozen_branches,
+        state.dry_run,
+    )?;
+
+    Ok(())
+}
+
+/// Mark which branches can be pushed, asking about WIP commits per `stack.push-wip=prompt`
+fn mark_pushable(state: &State, root: &mut git_stack::graph::Node) -> eyre::Result<()> {
+    git_stack::graph::pushable(root, state.push_policy, state.push_wip);
+    if state.push_wip == git_stack::config::PushWip::Prompt {
+        let mut blocked = Vec::new();
+        wip_blocked_branches(root, &mut blocked);
+        if !blocked.is_empty() && confirm_wip_push(state, &blocked)? {
+            git_stack::graph::pushable(root, state.push_policy, git_stack::config::PushWip::Allow);
+        }
+    }
+    Ok(())
+}
+
+/// Branches whose push would discard commits that only exist on the remote
+fn diverged_branches(
+    state: &State,
+    node: &git_stack::graph::Node,
+    changes: &mut Vec<git_stack::git::BranchChange>,
+) {
+    if node.pushable {
+        for branch in node.branches.iter() {
+            if state.frozen_branches.contains(&branch.name) {
+                continue;
+            }
+            changes.extend(git_stack::git::assess_push(&state.repo, branch));
+        }
+    }
+    for child in node.children.values() {
+        diverged_branches(state, child, changes);
+    }
+}
+
+/// Branches held back from pushing only because of WIP commits
+fn wip_blocked_branches(node: &git_stack::graph::Node, blocked: &mut Vec<String>) {
+    if matches!(node.push_blocker, Some(b) if b.is_wip()) {
+        blocked.extend(node.branches.iter().map(|b| b.name.clone()));
+    }
+    for child in node.children.values() {
+        wip_blocked_branches(child, blocked);
+    }
+}
+
+/// Per `stack.push-wip=prompt`, ask whether to push branches with WIP commits
+fn confirm_wip_push(state: &State, blocked: &[String]) -> eyre::Result<bool> {
+    let names = blocked.iter().map(|n| format!("`{}`", n)).join(", ");
+    if state.dry_run {
+        log::info!("Would ask before pushing {}, they have WIP commits", names);
+        return Ok(false);
+    }
+    if !atty::is(atty::Stream::Stdin) {
+        log::info!(
+            "Skipping push of {}, they have WIP commits and there is no terminal to confirm",
+            names
+        );
+        return Ok(false);
+    }
+    let stderr = std::io::stderr();
+    let mut stderr = stderr.lock();
+    write!(stderr, "Push {} despite WIP commits? [y/N] ", names)?;
+    stderr.flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Options for `git stack run`
+#[derive(Clone, Debug)]
+struct RunOptions {
+    command: String,
+    fail_fast: bool,
+}
+
+/// Run the command on every unprotected branch, bottom-up, returning the branches it failed on
+fn run(state: &mut State, options: &RunOptions) -> eyre::Result<Vec<String>> {
+    if state.repo.is_dirty() {
+        eyre::bail!("Working tree is dirty, aborting");
+    }
+    let head_branch = state
+        .repo
+        .head_branch()
+        .ok_or_else(|| eyre::eyre!("Must not be in a detached HEAD state."))?
+        .name;
+
+    let mut branches = Vec::new();
+    for stack in state.stacks.iter() {
+        let mut graphed_branches = stack.graphed_branches();
+        let base_commit = state
+            .repo
+            .find_commit(stack.base.id)
+            .expect("base branch is valid");
+        let mut root = git_stack::graph::Node::new(base_commit, &mut graphed_branches);
+        root = root.extend_branches(&state.repo, graphed_branches)?;
+        stack.protect(state, &mut root);
+        unprotected_branches(&root, &mut branches);
+    }
+
+    let mut failed = Vec::new();
+    let mut result = Ok(());
+    for branch in branches {
+        if state.dry_run {
+            log::info!("Would run `{}` on `{}`", options.command, branch);
+            continue;
+        }
+        if let Err(err) = state.repo.switch(&branch) {
+            result = Err(eyre::eyre!("could not switch to `{}`: {}", branch, err));
+            break;
+        }
+        log::info!("Running `{}` on `{}`", options.command, branch);
+        let status = git_stack::git::shell_command(&options.command, &[]).status();
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                log::error!(
+                    "`{}` failed on `{}` with {}",
+                    options.command,
+                    branch,
+                    status
+                );
+                failed.push(branch.clone());
+            }
+            Err(err) => {
+                result = Err(eyre::eyre!("could not run `{}`: {}", options.command, err));
+                break;
+            }
+        }
+        if state.repo.is_dirty() {
+            // Switching branches would throw the changes away
+            eyre::bail!(
+                "`{}` left changes on `{}`, clean them up and switch back to `{}`",
+                options.command,
+                branch,
+                head_branch
+            );
+        }
+        if options.fail_fast && !failed.is_empty() {
+            break;
+        }
+    }
+
+    state
+        .repo
+        .switch(&head_branch)
+        .wrap_err_with(|| format!("could not switch back to `{}`", head_branch))?;
+    state.update()?;
+    result.map(|_| failed)
+}
+
+fn unprotected_branches(node: &git_stack::graph::Node, branches: &mut Vec<String>) {
+    if !node.action.is_protected() {
+        branches.extend(node.branches.iter().map(|b| b.name.clone()).sorted());
+    }
+    for child in node.children.values() {
+        unprotected_branches(child, branches);
+    }
+}
+
+/// Write the stacks' unprotected branches, and their commits beyond each base, to a bundle at `out`
+fn export_bundle(state: &State, out: &std::path::Path) -> eyre::Result<()> {
+    let mut branches = Vec::new();
+    let mut bases = Vec::new();
+    for stack in state.stacks.iter() {
+        let mut graphed_branches = stack.graphed_branches();
+        let base_commit = state
+            .repo
+            .find_commit(stack.base.id)
+            .expect("base branch is valid");
+        let mut root = git_stack::graph::Node::new(base_commit, &mut graphed_branches);
+        root = root.extend_branches(&state.repo, graphed_branches)?;
+        stack.protect(state, &mut root);
+        let mut stack_branches = Vec::new();
+        unprotected_branches(&root, &mut stack_branches);
+        if !stack_branches.is_empty() {
+            branches.extend(stack_branches);
+            bases.push(stack.base.clone());
+        }
+    }
+    branches.sort_unstable();
+    branches.dedup();
+    if branches.is_empty() {
+        eyre::bail!("No branches to bundle, they are all protected");
+    }
+    let bases = bases.into_iter().unique_by(|b| b.id).collect::<Vec<_>>();
+
+    let base_names = bases.iter().map(|b| format!("`{}`", b.name)).join(", ");
+    if state.dry_run {
+        log::info!(
+            "Would bundle {} on top of {} into {}",
+            branches.iter().map(|b| format!("`{}`", b)).join(", "),
+            base_names,
+            out.display()
+        );
+        return Ok(());
+    }
+
+    let output = std::process::Command::new("git")
+        .arg("bundle")
+        .arg("create")
+        .arg(out)
+        .args(branches.iter().map(|b| format!("refs/heads/{}", b)))
+        .args(bases.iter().map(|b| format!("^{}", b.id)))
+        .output()
+        .wrap_err("could not run `git bundle`")?;
+    if !output.status.success() {
+        eyre::bail!(
+            "could not bundle the stack: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    log::info!(
+        "Bundled {} branches into {}, needing {} to unbundle",
+        branches.len(),
+        out.display(),
+        base_names
+    );
+    log::info!(
+        "Import them with `git stack import-bundle {}`",
+        out.display()
+    );
+    Ok(())
+}
+
+/// Options for `git stack format-patch`
+#[derive(Clone, Debug)]
+struct FormatPatch {
+    out: std::path::PathBuf,
+    per_branch: bool,
+    send: bool,
+    to: Vec<String>,
+    cc: Vec<String>,
+}
+
+const SERIES_FILE: &str = "series.json";
+
+/// The last version of each series mailed by `git stack format-patch --send`, by name
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct SentSeries {
+    series: std::collections::BTreeMap<String, SentVersion>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SentVersion {
+    version: usize,
+    /// Cover letter's `Message-Id`, for the next version to reply to
+    message_id: String,
+}
+
+impl SentSeries {
+    fn load(path: &std::path::Path) -> eyre::Result<Self> {
+        Ok(crate::json::load_json(path)?.unwrap_or_default())
+    }
+
+    fn save(&self, path: &std::path::Path) -> eyre::Result<()> {
+        crate::json::save_json(path, self)
+    }
+}
+
+/// A run of commits to mail as one patch series
+#[derive(Clone, Debug)]
+struct PatchSeries {
+    name: String,
+    base: String,
+    from: git2::Oid,
+    to: git2::Oid,
+    /// Branches in the series, bottom-up, with how many commits each adds
+    branches: Vec<(String, usize)>,
+}
+
+/// Write each stack as patch series with cover letters, in the order they apply
+fn format_patch(state: &State, options: &FormatPatch) -> eyre::Result<()> {
+    let mut series = Vec::new();
+    for stack in state.stacks.iter() {
+        let mut graphed_branches = stack.graphed_branches();
+        let base_commit = state
+            .repo
+            .find_commit(stack.base.id)
+            .expect("base branch is valid");
+        let mut root = git_stack::graph::Node::new(base_commit, &mut graphed_branches);
+        root = root.extend_branches(&state.repo, graphed_branches)?;
+        stack.protect(state, &mut root);
+
+        let base = PatchSeries {
+            name: String::new(),
+            base: stack.base.name.clone(),
+            from: root.local_commit.id,
+            to: root.local_commit.id,
+            branches: Vec::new(),
+        };
+        patch_series(&root, base, 0, options.per_branch, &mut series);
+    }
+    if series.is_empty() {
+        eyre::bail!("No branches to format, they are all protected");
+    }
+
+    let sent_path = state.repo.raw().path().join("git-stack").join(SERIES_FILE);
+    let mut sent = SentSeries::load(&sent_path)?;
+    for (i, series) in series.iter().enumerate() {
+        let dir = options
+            .out
+            .join(format!("{:02}-{}", i + 1, series.name.replace('/', "-")));
+        let commits = series
+            .branches
+            .iter()
+            .map(|(_, count)| count)
+            .sum::<usize>();
+        let previous = sent.series.get(&series.name);
+        let version = previous.map(|p| p.version + 1).unwrap_or(1);
+        if state.dry_run {
+            log::info!(
+                "Would write {} patch(es) for `{}` v{}, on top of `{}`, to {}",
+                commits,
+                series.name,
+                version,
+                series.base,
+                dir.display()
+            );
+            continue;
+        }
+
+        let mut command = std::process::Command::new("git");
+        command
+            .arg("format-patch")
+            .arg("--cover-letter")
+            .arg("--thread=shallow")
+            .arg(format!("--base={}", series.from))
+            .arg("-o")
+            .arg(&dir);
+        if let Some(previous) = previous {
+            // Rerolls are threaded under the last version's cover letter
+            command
+                .arg(format!("--reroll-count={}", version))
+                .arg(format!("--in-reply-to={}", previous.message_id));
+        }
+        let output = command
+            .arg(format!("{}..{}", series.from, series.to))
+            .output()
+            .wrap_err("could not run `git format-patch`")?;
+        if !output.status.success() {
+            eyre::bail!(
+                "could not format `{}`: {}",
+                series.name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        // Fill in git's placeholders, unless it found a branch description to use instead
+        let patches: Vec<_> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(std::path::PathBuf::from)
+            .collect();
+        let cover_path = patches
+            .first()
+            .ok_or_else(|| eyre::eyre!("`git format-patch` wrote nothing for `{}`", series.name))?;
+        distinguish_message_ids(&patches, &format!("{}v{}", i + 1, version))?;
+        let cover = std::fs::read_to_string(cover_path)
+            .wrap_err_with(|| format!("could not read {}", cover_path.display()))?;
+        let mut blurb = format!("Based on `{}`.\n", series.base);
+        if 1 < series.branches.len() {
+            blurb.push_str("\nBranches, bottom-up:\n");
+            for (branch, count) in series.branches.iter() {
+                blurb.push_str(&format!("  {} ({} commit(s))\n", branch, count));
+            }
+        }
+        let cover = cover
+            .replace("*** SUBJECT HERE ***", &series.name)
+            .replace("*** BLURB HERE ***\n", &blurb);
+        std::fs::write(cover_path, &cover)
+            .wrap_err_with(|| format!("could not write {}", cover_path.display()))?;
+
+        log::info!(
+            "Wrote {} patch(es) for `{}` v{}, on top of `{}`, to {}",
+            commits,
+            series.name,
+            version,
+            series.base,
+            dir.display()
+        );
+
+        if options.send {
+            let message_id = cover
+                .lines()
+                .take_while(|line| !line.is_empty())
+                .find_map(|line| line.strip_prefix("Message-Id: "))
+                .ok_or_else(|| eyre::eyre!("{} has no `Message-Id`", cover_path.display()))?
+                .to_owned();
+            send_email(&series.name, &patches, options)?;
+            log::info!("Sent `{}` v{}", series.name, version);
+            sent.series.insert(
+                series.name.clone(),
+                SentVersion {
+                    version,
+                    message_id,
+                },
+            );
+            // Recorded as soon as it's sent, in case a later series fails
+            sent.save(&sent_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Make the `Message-Id`s of a series unique to `tag`
+///
+/// `git format-patch` only varies them by the commit and the second it ran in, so series written
+/// together, or sharing commits, would otherwise collide.
+fn distinguish_message_ids(patches: &[std::path::PathBuf], tag: &str) -> eyre::Result<()> {
+    let mut suffix = None;
+    for path in patches {
+        let patch = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("could not read {}", path.display()))?;
+        let suffix = match suffix.as_ref() {
+            Some(suffix) => suffix,
+            None => {
+                // `<cover.<time>.git.<email>>`, with only the first part varying within a series
+                let id = patch
+                    .lines()
+                    .take_while(|line| !line.is_empty())
+                    .find_map(|line| line.strip_prefix("Message-Id: "))
+                    .ok_or_else(|| eyre::eyre!("{} has no `Message-Id`", path.display()))?;
+                let start = id
+                    .find('.')
+                    .ok_or_else(|| eyre::eyre!("unexpected `Message-Id` {}", id))?;
+                suffix.insert(id[start..].to_owned())
+            }
+        };
+        let unique = suffix.replacen(".git.", &format!(".{}.git.", tag), 1);
+        // Only the headers, which end at the first blank line, refer to other patches
+        let headers_end = patch.find("\n\n").unwrap_or(patch.len());
+        let patch = format!(
+            "{}{}",
+            patch[..headers_end].replace(suffix.as_str(), &unique),
+            &patch[headers_end..]
+        );
+        std::fs::write(path, patch)
+            .wrap_err_with(|| format!("could not write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Mail `patches`, as already threaded by `git format-patch`
+fn send_email(
+    name: &str,
+    patches: &[std::path::PathBuf],
+    options: &FormatPatch,
+) -> eyre::Result<()> {
+    let mut command = std::process::Command::new("git");
+    command.arg("send-email").arg("--no-thread");
+    for to in options.to.iter() {
+        command.arg(format!("--to={}", to));
+    }
+    for cc in options.cc.iter() {
+        command.arg(format!("--cc={}", cc));
+    }
+    // Left attached to the terminal for `git send-email` to confirm with the user
+    let status = command
+        .arg("--")
+        .args(patches)
+        .status()
+        .wrap_err("could not run `git send-email`")?;
+    if !status.success() {
+        eyre::bail!(
+            "could not send `{}`, `git send-email` failed with {}",
+            name,
+            status
+        );
+    }
+    Ok(())
+}
+
+/// Collect the series on top of `node`, where `current` is the series so far and `count` the
+/// commits since its last branch
+fn patch_series(
+    node: &git_stack::graph::Node,
+    mut current: PatchSeries,
+    mut count: usize,
+    per_branch: bool,
+    series: &mut Vec<PatchSeries>,
+) {
+    if node.action.is_protected() {
+        // A protected branch within the stack restarts the series on top of it
+        if let Some(name) = node.branches.iter().map(|b| b.name.as_str()).min() {
+            current.base = name.to_owned();
+        }
+        current.from = node.local_commit.id;
+        current.to = node.local_commit.id;
+        current.branches.clear();
+        count = 0;
+    } else {
+        count += 1;
+        if !node.branches.is_empty() {
+            let mut branches: Vec<_> = node.branches.iter().map(|b| b.name.clone()).collect();
+            branches.sort_unstable();
+            current.name = branches[0].clone();
+            current.to = node.local_commit.id;
+            current.branches.push((branches.join(", "), count));
+            count = 0;
+            if per_branch {
+                series.push(current.clone());
+                current.base = current.name.clone();
+                current.from = current.to;
+                current.branches.clear();
+            }
+        }
+    }
+
+    // Commits past the last branch are left out, there's nothing to name them by
+    if node.children.is_empty()
+        && !per_branch
+        && !current.branches.is_empty()
+        && !series.iter().any(|s| s.to == current.to)
+    {
+        series.push(current.clone());
+    }
+    for child in node.children.values() {
+        patch_series(child, current.clone(), count, per_branch, series);
+    }
+}
+
+/// Options for `git stack import-bundle`
+#[derive(Clone, Debug)]
+struct ImportBundle {
+    bundle: std::path::PathBuf,
+    namespace: String,
+}
+
+/// Fetch the branches in a bundle from `export-bundle` into `import.namespace`, returning whether
+/// any branches changed
+fn import_bundle(state: &mut State, import: &ImportBundle) -> eyre::Result<bool> {
+    let output = std::process::Command::new("git")
+        .arg("bundle")
+        .arg("verify")
+        .arg(&import.bundle)
+        .output()
+        .wrap_err("could not run `git bundle`")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let missing = stderr
+            .lines()
+            .filter_map(|line| line.strip_prefix("error: "))
+            .map(|line| line.trim())
+            .filter(|line| git2::Oid::from_str(line).is_ok())
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            eyre::bail!(
+                "could not import {}: {}",
+                import.bundle.display(),
+                stderr.trim()
+            );
+        }
+        eyre::bail!(
+            "could not import {}, this repository lacks {}, fetch what it is based on first",
+            import.bundle.display(),
+            missing.join(", ")
+        );
+    }
+
+    let output = std::process::Command::new("git")
+        .arg("bundle")
+        .arg("list-heads")
+        .arg(&import.bundle)
+        .output()
+        .wrap_err("could not run `git bundle`")?;
+    if !output.status.success() {
+        eyre::bail!(
+            "could not read {}: {}",
+            import.bundle.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let mut heads = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let (id, name) = match line.split_once(' ') {
+            Some(head) => head,
+            None => continue,
+        };
+        if let Some(branch) = name.strip_prefix("refs/heads/") {
+            heads.push((git2::Oid::from_str(id)?, branch.to_owned()));
+        }
+    }
+    if heads.is_empty() {
+        eyre::bail!("{} has no branches to import", import.bundle.display());
+    }
+
+    if state.dry_run {
+        for (_, branch) in heads.iter() {
+            log::info!("Would import `{}` under `{}`", branch, import.namespace);
+        }
+        return Ok(false);
+    }
+
+    // Only into `FETCH_HEAD`, the branches are created below
+    let status = std::process::Command::new("git")
+        .arg("fetch")
+        .arg("--quiet")
+        .arg("--no-tags")
+        .arg(&import.bundle)
+        .args(
+            heads
+                .iter()
+                .map(|(_, branch)| format!("refs/heads/{}", branch)),
+        )
+        .status()
+        .wrap_err("could not run `git fetch`")?;
+    if !status.success() {
+        eyre::bail!("could not fetch from {}", import.bundle.display());
+    }
+
+    let mut imports = Vec::new();
+    for (id, branch) in heads {
+        let mut namespace = import.namespace.clone();
+        if namespace.contains("{user}") {
+            let user = state
+                .repo
+                .raw()
+                .find_commit(id)?
+                .author()
+                .email()
+                .and_then(|email| email.split('@').next())
+                .filter(|user| !user.is_empty())
+                .map(|user| user.to_owned())
+                .ok_or_else(|| {
+                    eyre::eyre!(
+                        "could not find a `{{user}}` for `{}`, pass --namespace",
+                        branch
+                    )
+                })?;
+            namespace = namespace.replace("{user}", &user);
+        }
+        let name = format!("{}/{}", namespace.trim_end_matches('/'), branch);
+        if !git2::Reference::is_valid_name(&format!("refs/heads/{}", name)) {
+            eyre::bail!("`{}` is not a valid branch name", name);
+        }
+        match state.repo.find_local_branch(&name) {
+            Some(existing) if existing.id == id => {
+                log::info!("`{}` is already up to date", name);
+            }
+            existing => imports.push((id, name, existing.is_some())),
+        }
+    }
+    if imports.is_empty() {
+        return Ok(false);
+    }
+
+    let names = imports.iter().map(|(_, name, _)| name.as_str()).join(" ");
+    backup_with(state, |snapshot| {
+        snapshot.insert_message(&format!("import-bundle {}", names));
+    })?;
+    for (id, name, existing) in imports.iter() {
+        state.repo.branch(name, *id, "import-bundle")?;
+        if *existing {
+            log::info!("Updated `{}`", name);
+        } else {
+            log::info!("Imported `{}`", name);
+        }
+    }
+    state.update()?;
+    Ok(true)
+}
+
+/// Options for `git stack submit`
+#[derive(Clone, Debug)]
+struct SubmitOptions {
+    draft: bool,
+}
+
+/// Push every branch in the stacks and point its PR at the branch beneath it
+fn submit(state: &mut State, options: &SubmitOptions) -> eyre::Result<()> {
+    let mut roots = Vec::new();
+    for stack in state.stacks.iter() {
+        let mut graphed_branches = stack.graphed_branches();
+        let base_commit = state
+            .repo
+            .find_commit(stack.base.id)
+            .expect("base branch is valid");
+        let mut root = git_stack::graph::Node::new(base_commit, &mut graphed_branches);
+        root = root.extend_branches(&state.repo, graphed_branches)?;
+        protect(state, &mut root, &state.protected_branches);
+        follow_recorded_parents(state, &mut root);
+        let skipped = git_stack::graph::retain_branches(&mut root, &|b| {
+            !state.frozen_branches.contains(&b.name)
+        });
+        for branch in skipped {
+            if state.frozen_branches.contains(&branch.name) {
+                log::info!("Skipping submit of `{}`, it is frozen", branch.name);
+            } else {
+                log::info!(
+                    "Skipping submit of `{}`, it is stacked on a frozen branch",
+                    branch.name
+                );
+            }
+        }
+
+        mark_pushable(state, &mut root)?;
+        roots.push((stack.base.name.clone(), root));
+    }
+
+    let mut changes = Vec::new();
+    for (_, root) in roots.iter() {
+        diverged_submits(state, root, &mut changes);
+    }
+    if state.dry_run {
+        for change in changes.iter() {
+            log::info!(
+                "Would force-push `{}`, it {} ({})",
+                change.branch,
+                change.description,
+                change.risk
+            );
+        }
+    } else if !gate_risk(state, &changes)? {
+        log::info!("Leaving the remote branches as-is");
+        return Ok(());
+    }
+
+    let mut failed = Vec::new();
+    for (base, root) in roots.iter() {
+        submit_node(
+            &mut state.repo,
+            root,
+            base,
+            options,
+            state.dry_run,
+            &mut failed,
+        );
+    }
+
+    if state.show_prs && !state.dry_run {
+        // Opened and retargeted PRs would otherwise be missing until the cache expires
+        pull_requests(state, true);
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        eyre::bail!("could not submit {}", failed.into_iter().join(", "));
+    }
+}
+
+/// Branches whose submit would discard commits that only exist on the remote
+fn diverged_submits(
+    state: &State,
+    node: &git_stack::graph::Node,
+    changes: &mut Vec<git_stack::git::BranchChange>,
+) {
+    if node.push_blocker.is_some() {
+        return;
+    }
+    if !node.action.is_protected() {
+        for branch in node.branches.iter() {
+            changes.extend(git_stack::git::assess_push(&state.repo, branch));
+        }
+    }
+    for child in node.children.values() {
+        diverged_submits(state, child, changes);
+    }
+}
+
+fn submit_node(
+    repo: &mut git_stack::git::GitRepo,
+    node: &git_stack::graph::Node,
+    base: &str,
+    options: &SubmitOptions,
+    dry_run: bool,
+    failed: &mut Vec<String>,
+) {
+    let mut base = base;
+    if node.action.is_protected() {
+        if let Some(branch) = node.branches.iter().min_by_key(|b| b.name.as_str()) {
+            base = branch.name.as_str();
+        }
+    } else if let Some(blocker) = node.push_blocker {
+        for branch in node.branches.iter() {
+            log::info!("Skipping submit of `{}`, {}", branch.name, blocker);
+        }
+        return;
+    } else if !node.branches.is_empty() {
+        let mut branches: Vec<_> = node.branches.iter().collect();
+        branches.sort_by_key(|b| b.name.as_str());
+        let prior_failures = failed.len();
+        for branch in branches.iter() {
+            if let Err(err) = submit_branch(repo, branch, base, options, dry_run) {
+                log::error!("Failed to submit `{}`: {}", branch.name, err);
+                failed.push(branch.name.clone());
+            }
+        }
+        if prior_failures != failed.len() {
+            // Dependents' PRs would be based on a branch that doesn't exist upstream
+            return;
+        }
+        base = branches[0].name.as_str();
+    }
+
+    for child in node.children.values() {
+        submit_node(repo, child, base, options, dry_run, failed);
+    }
+}
+
+fn submit_branch(
+    repo: &mut git_stack::git::GitRepo,
+    branch: &git_stack::git::Branch,
+    base: &str,
+    options: &SubmitOptions,
+    dry_run: bool,
+) -> eyre::Result<()> {
+    if branch.push_id != Some(branch.id) {
+        git_push_branch(repo, &branch.name, dry_run)?;
+    }
+
+    match crate::github::find_pr(&branch.name)? {
+        Some(pr) if pr.base_ref_name == base => {
+            log::debug!("PR #{} for `{}` is up-to-date", pr.number, branch.name);
+        }
+        Some(pr) => {
+            log::info!(
+                "Retargeting PR #{} for `{}` from `{}` onto `{}`",
+                pr.number,
+                branch.name,
+                pr.base_ref_name,
+                base
+            );
+            if !dry_run {
+                crate::github::retarget_pr(pr.number, base)?;
+            }
+        }
+        None => {
+            if dry_run {
+                log::info!("Would open a PR for `{}` onto `{}`", branch.name, base);
+            } else {
+                let description = repo.branch_description(&branch.name);
+                let url = crate::github::create_pr(
+                    &branch.name,
+                    base,
+                    description.as_deref(),
+                    options.draft,
+                )?;
+                log::info!("Opened {} for `{}` onto `{}`", url, branch.name, base);
+            }
+        }
     }
 
-    const STASH_STACK_NAME: &str = "git-stack";
-    let mut success = true;
-    let mut backed_up = false;
-    if state.rebase {
-        if state.repo.is_dirty() {
-            return Err(proc_exit::Code::USAGE_ERR.with_message("Working tree is dirty, aborting"));
-        }
+    Ok(())
+}
 
-        let mut snapshots = git_stack::stash::Stack::new(STASH_STACK_NAME, &state.repo);
-        snapshots.capacity(state.snapshot_capacity);
-        let mut snapshot = git_stack::stash::Snapshot::from_repo(&state.repo)
-            .with_code(proc_exit::Code::FAILURE)?;
-        snapshot.insert_parent(&state.repo, &state.branches, &state.protected_branches);
-        if !state.dry_run {
-            snapshots.push(snapshot)?;
-            backed_up = true;
-        }
+fn show(state: &State, colored_stdout: bool) -> eyre::Result<()> {
+    let root = show_graph(state)?;
 
-        let mut head_branch = state
-            .repo
-            .head_branch()
-            .ok_or_else(|| eyre::eyre!("Must not be in a detached HEAD state."))
-            .with_code(proc_exit::Code::USAGE_ERR)?
-            .name;
+    let renders_tree = matches!(
+        state.show_format,
+        git_stack::config::Format::Branches
+            | git_stack::config::Format::BranchCommits
+            | git_stack::config::Format::Commits
+    );
+    let prs = if state.show_prs && renders_tree {
+        pull_requests(state, state.pull)
+    } else {
+        Default::default()
+    };
 
-        let scripts: Result<Vec<_>, proc_exit::Exit> = state
-            .stacks
-            .iter()
-            .map(|stack| {
-                let script = plan_rebase(&state, stack).with_code(proc_exit::Code::FAILURE)?;
-                if script.is_branch_deleted(&head_branch) {
-                    head_branch = stack.onto.name.clone();
-                }
-                Ok(script)
-            })
-            .collect();
-        let scripts = scripts?;
+    let mut unexpected = std::collections::BTreeMap::new();
+    if !state.allowed_emails.is_empty() {
+        unexpected_emails(&state.repo, &root, &state.allowed_emails, &mut unexpected);
+    }
+    let descriptions = branch_config(state.repo.raw(), DESCRIPTION_KEY)
+        .into_iter()
+        .collect();
 
-        let mut executor = git_stack::git::Executor::new(&state.repo, state.dry_run);
-        for script in scripts {
-            let results = executor.run_script(&mut state.repo, &script);
-            for (err, name, dependents) in results.iter() {
-                success = false;
-                log::error!("Failed to re-stack branch `{}`: {}", name, err);
-                if !dependents.is_empty() {
-                    log::error!("  Blocked dependents: {}", dependents.iter().join(", "));
-                }
-            }
+    match state.show_format {
+        git_stack::config::Format::Silent => (),
+        git_stack::config::Format::Branches
+        | git_stack::config::Format::BranchCommits
+        | git_stack::config::Format::Commits => {
+            writeln!(
+                std::io::stdout(),
+                "{}",
+                DisplayTree::new(&state.repo, &root)
+                    .colored(colored_stdout, state.palette)
+                    .show(state.show_format)
+                    .stacked(state.show_stacked)
+                    .full_hashes(state.show_full_hashes)
+                    .max_width(if state.truncate {
+                        terminal_width()
+                    } else {
+                        None
+                    })
+                    .glyphs(state.glyphs)
+                    .protected_branches(&state.protected_branches)
+                    .prs(&prs)
+                    .unexpected_emails(&unexpected)
+                    .frozen(&state.frozen_branches)
+                    .descriptions(&descriptions)
+            )?;
+            let palette = if colored_stdout {
+                state.palette
+            } else {
+                Palette::plain()
+            };
+            write_fetch_ages(&mut std::io::stdout(), &state.repo, palette)?;
+        }
+        git_stack::config::Format::Debug => {
+            writeln!(std::io::stdout(), "{:#?}", root)?;
         }
-        executor
-            .close(&mut state.repo, &head_branch)
-            .with_code(proc_exit::Code::FAILURE)?;
-        state.update().with_code(proc_exit::Code::FAILURE)?;
     }
 
-    if state.push {
-        push(&mut state).with_code(proc_exit::Code::FAILURE)?;
-        state.update().with_code(proc_exit::Code::FAILURE)?;
+    if !unexpected.is_empty() {
+        let emails: std::collections::BTreeSet<_> = unexpected.values().collect();
+        log::warn!(
+            "{} commit(s) committed as {}, not an address in `user.email` or `stack.allowed-email`",
+            unexpected.len(),
+            emails.into_iter().join(", ")
+        );
+        log::warn!(
+            "  Fix them with `git rebase --exec 'git commit --amend --no-edit --reset-author' <base>`"
+        );
     }
 
-    show(&state, colored_stdout).with_code(proc_exit::Code::FAILURE)?;
+    Ok(())
+}
 
-    if backed_up {
-        log::info!("To undo, run `git branch-stash pop {}`", STASH_STACK_NAME);
-    }
+/// Where the PRs last looked up are kept, for `stack.pr-cache-age` and `--offline`
+const PRS_FILE: &str = "prs.json";
 
-    if !success {
-        return proc_exit::Code::FAILURE.ok();
+/// Look up the PRs, unless they were within `stack.pr-cache-age` and not `refresh`ing, falling
+/// back to the last ones looked up when offline
+fn pull_requests(state: &State, refresh: bool) -> PullRequests {
+    let path = state.repo.raw().path().join("git-stack").join(PRS_FILE);
+    let cached = match state.pr_cache_age {
+        Some(max_age) if !refresh => std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .filter(|age| *age < max_age)
+            .is_some(),
+        _ => false,
+    };
+    if cached {
+        log::trace!("Using the PRs cached in {}", path.display());
+    }
+    if !state.offline && !cached {
+        let forge = crate::forge::Forge::from_remote(
+            &state.repo,
+            state.repo.push_remote(),
+            &state.gitlab_hosts,
+        );
+        // Anything not known to be on GitLab is left to `gh`, which knows about GitHub Enterprise
+        let prs = if forge == Some(crate::forge::Forge::GitLab) {
+            crate::gitlab::list_mrs()
+        } else {
+            crate::github::list_prs()
+        };
+        match prs {
+            Ok(prs) => {
+                if let Err(err) = crate::json::save_json(&path, &prs) {
+                    log::debug!("Could not save PRs to {}: {}", path.display(), err);
+                }
+                return prs;
+            }
+            Err(err) => {
+                log::warn!("Could not look up PRs: {}", err);
+            }
+        }
+    }
+    match crate::json::load_json(&path) {
+        Ok(prs) => prs.unwrap_or_default(),
+        Err(err) => {
+            log::debug!("Could not load PRs: {}", err);
+            Default::default()
+        }
     }
-
-    Ok(())
 }
 
-fn plan_rebase(state: &State, stack: &StackState) -> eyre::Result<git_stack::git::Script> {
-    let mut graphed_branches = stack.graphed_branches();
-    let base_commit = state
-        .repo
-        .find_commit(stack.base.id)
-        .expect("base branch is valid");
-    let mut root = git_stack::graph::Node::new(base_commit, &mut graphed_branches);
-    root = root.extend_branches(&state.repo, graphed_branches)?;
-    git_stack::graph::protect_branches(&mut root, &state.repo, &state.protected_branches);
-
-    git_stack::graph::rebase_branches(&mut root, stack.onto.id);
-    git_stack::graph::drop_by_tree_id(&mut root);
-    git_stack::graph::fixup(&mut root, state.fixup);
+/// Print one line per branch for scripts, see `git stack status --porcelain`
+fn show_porcelain(state: &State) -> eyre::Result<()> {
+    let root = show_graph(state)?;
+    let mut behind = std::collections::HashMap::new();
+    let mut restack = std::collections::HashMap::new();
+    behind_base(
+        &state.repo,
+        &state.protected_branches,
+        &root,
+        true,
+        &mut behind,
+        &mut restack,
+    );
 
-    let script = git_stack::graph::to_script(&root);
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    writeln!(stdout, "# git-stack status v1")?;
+    let status = BranchStatus {
+        base: "-".to_owned(),
+        wip: false,
+        fixup: false,
+        conflict: false,
+    };
+    write_porcelain(&mut stdout, state, &root, status, &behind, &restack)?;
+    Ok(())
+}
 
-    Ok(script)
+/// What a branch has picked up from the commits beneath it, down to its base
+#[derive(Clone)]
+struct BranchStatus {
+    base: String,
+    wip: bool,
+    fixup: bool,
+    conflict: bool,
 }
 
-fn push(state: &mut State) -> eyre::Result<()> {
-    let mut graphed_branches = git_stack::git::Branches::new(None.into_iter());
-    for stack in state.stacks.iter() {
-        let stack_graphed_branches = stack.graphed_branches();
-        graphed_branches.extend(stack_graphed_branches.into_iter().flat_map(|(_, b)| b));
+fn write_porcelain(
+    out: &mut dyn std::io::Write,
+    state: &State,
+    node: &git_stack::graph::Node,
+    mut status: BranchStatus,
+    behind: &std::collections::HashMap<git2::Oid, BehindBase>,
+    restack: &std::collections::HashMap<git2::Oid, Restack>,
+) -> eyre::Result<()> {
+    let protected = node.action.is_protected();
+    if !protected {
+        status.wip |= node.local_commit.wip_summary().is_some();
+        status.fixup |= node.local_commit.fixup_summary().is_some();
+        status.conflict |= behind.get(&node.local_commit.id) == Some(&BehindBase::Conflict);
     }
-    let mut root = git_stack::graph::Node::new(state.head_commit.clone(), &mut graphed_branches);
-    root = root.extend_branches(&state.repo, graphed_branches)?;
 
-    git_stack::graph::protect_branches(&mut root, &state.repo, &state.protected_branches);
-    git_stack::graph::pushable(&mut root);
-
-    git_push(&mut state.repo, &root, state.dry_run)?;
+    let mut branches: Vec<_> = node.branches.iter().collect();
+    branches.sort_by_key(|b| b.name.as_str());
+    if !protected {
+        for branch in branches.iter() {
+            let (ahead, behind_remote) =
+                match commit_relation(&state.repo, branch.id, branch.push_id) {
+                    Some((ahead, behind_remote)) => (ahead.to_string(), behind_remote.to_string()),
+                    None => ("-".to_owned(), "-".to_owned()),
+                };
+            let behind_base = restack
+                .get(&node.local_commit.id)
+                .map(|r| r.commits)
+                .unwrap_or(0);
+            writeln!(
+                out,
+                "branch {} base={} ahead={} behind={} behind-base={} pushable={} wip={} fixup={} conflict={}",
+                branch.name,
+                status.base,
+                ahead,
+                behind_remote,
+                behind_base,
+                node.pushable,
+                status.wip,
+                status.fixup,
+                status.conflict,
+            )?;
+        }
+    }
+    if let Some(branch) = branches.first() {
+        // Conflicts with upstream carry up the stack
+        status = BranchStatus {
+            base: branch.name.clone(),
+            wip: false,
+            fixup: false,
+            conflict: status.conflict,
+        };
+    }
 
+    for child in node.children.values() {
+        write_porcelain(out, state, child, status.clone(), behind, restack)?;
+    }
     Ok(())
 }
 
-fn show(state: &State, colored_stdout: bool) -> eyre::Result<()> {
+/// Graph every stack for showing, as-if the mutations were performed for `--dry-run`
+fn show_graph(state: &State) -> eyre::Result<git_stack::graph::Node> {
     let mut roots = state
         .stacks
         .iter()
@@ -410,13 +4690,34 @@ fn show(state: &State, colored_stdout: bool) -> eyre::Result<()> {
                 .expect("base branch is valid");
             let mut root = git_stack::graph::Node::new(base_commit, &mut graphed_branches);
             root = root.extend_branches(&state.repo, graphed_branches)?;
-            git_stack::graph::protect_branches(&mut root, &state.repo, &state.protected_branches);
+            if state.dry_run {
+                stack.protect(state, &mut root);
+            } else {
+                protect(state, &mut root, &state.protected_branches);
+            }
+            follow_recorded_parents(state, &mut root);
 
             if state.dry_run {
                 // Show as-if we performed all mutations
-                git_stack::graph::rebase_branches(&mut root, stack.onto.id);
+                if let Some(selected_branches) = state.selected_branches.as_ref() {
+                    git_stack::graph::retain_branches(&mut root, &|b| {
+                        selected_branches.matches(&b.name)
+                    });
+                }
+                git_stack::graph::retain_branches(&mut root, &|b| {
+                    !state.frozen_branches.contains(&b.name)
+                });
+                git_stack::graph::rebase_branches_except(
+                    &mut root,
+                    stack.onto.id,
+                    &state.protected_tags,
+                );
                 git_stack::graph::drop_by_tree_id(&mut root);
-                git_stack::graph::fixup(&mut root, state.fixup);
+                git_stack::graph::drop_by_patch_id(&mut root, &state.repo);
+                if state.dedup_cherry_picks {
+                    git_stack::graph::dedup_cherry_picks(&mut root, &state.repo);
+                }
+                git_stack::graph::fixup(&mut root, state.fixup)?;
             }
 
             eyre::Result::Ok(root)
@@ -430,37 +4731,131 @@ fn show(state: &State, colored_stdout: bool) -> eyre::Result<()> {
         root = root.extend(&state.repo, other?)?;
     }
 
-    git_stack::graph::pushable(&mut root);
+    git_stack::graph::pushable(&mut root, state.push_policy, state.push_wip);
 
-    match state.show_format {
-        git_stack::config::Format::Silent => (),
-        git_stack::config::Format::Branches
-        | git_stack::config::Format::BranchCommits
-        | git_stack::config::Format::Commits => {
-            writeln!(
-                std::io::stdout(),
-                "{}",
-                DisplayTree::new(&state.repo, &root)
-                    .colored(colored_stdout)
-                    .show(state.show_format)
-                    .stacked(state.show_stacked)
-                    .protected_branches(&state.protected_branches)
-            )?;
-        }
-        git_stack::config::Format::Debug => {
-            writeln!(std::io::stdout(), "{:#?}", root)?;
+    Ok(root)
+}
+
+/// Commits in the stacks whose committer isn't one of `allowed`
+fn unexpected_emails(
+    repo: &git_stack::git::GitRepo,
+    node: &git_stack::graph::Node,
+    allowed: &[String],
+    unexpected: &mut UnexpectedEmails,
+) {
+    if !node.action.is_protected() {
+        let email = repo
+            .raw()
+            .find_commit(node.local_commit.id)
+            .ok()
+            .and_then(|c| c.committer().email().map(|e| e.to_owned()));
+        if let Some(email) = email {
+            if !is_allowed_email(&email, allowed) {
+                unexpected.insert(node.local_commit.id, email);
+            }
         }
     }
+    for child in node.children.values() {
+        unexpected_emails(repo, child, allowed, unexpected);
+    }
+}
 
-    Ok(())
+fn is_allowed_email(email: &str, allowed: &[String]) -> bool {
+    allowed.iter().any(|allowed| {
+        if allowed.starts_with('@') {
+            email.to_lowercase().ends_with(&allowed.to_lowercase())
+        } else {
+            email.eq_ignore_ascii_case(allowed)
+        }
+    })
 }
 
+type UnexpectedEmails = std::collections::BTreeMap<git2::Oid, String>;
+
 fn resolve_explicit_base(
-    repo: &dyn git_stack::git::Repo,
+    repo: &git_stack::git::GitRepo,
     base: &str,
 ) -> eyre::Result<git_stack::git::Branch> {
-    repo.find_local_branch(base)
-        .ok_or_else(|| eyre::eyre!("could not find branch {:?}", base))
+    find_branch_dwim(repo, base)?.ok_or_else(|| eyre::eyre!("could not find branch {:?}", base))
+}
+
+/// Find a local branch, or the remote branch `git switch <name>` would create it from
+fn find_branch_dwim(
+    repo: &git_stack::git::GitRepo,
+    name: &str,
+) -> eyre::Result<Option<git_stack::git::Branch>> {
+    if let Some(branch) = repo.find_local_branch(name) {
+        return Ok(Some(branch));
+    }
+    let branch = repo
+        .find_remote_branch_dwim(name)
+        .map_err(|err| eyre::eyre!("{}", err.message()))?
+        .map(|(_, id)| git_stack::git::Branch {
+            name: name.to_owned(),
+            id,
+            push_id: None,
+            pull_id: None,
+        });
+    Ok(branch)
+}
+
+/// Create the named branches that only exist on a remote, tracking it like `git switch <name>`
+fn track_remote_branches<'n>(
+    repo: &mut git_stack::git::GitRepo,
+    names: impl Iterator<Item = &'n str>,
+    dry_run: bool,
+) -> eyre::Result<()> {
+    for name in names {
+        if repo.find_local_branch(name).is_some() {
+            continue;
+        }
+        let found = repo
+            .find_remote_branch_dwim(name)
+            .map_err(|err| eyre::eyre!("{}", err.message()))?;
+        let (remote, id) = match found {
+            Some(found) => found,
+            None => continue,
+        };
+        if dry_run {
+            log::info!("Would create `{}` tracking `{}/{}`", name, remote, name);
+            continue;
+        }
+        repo.track_remote_branch(name, &remote, id)?;
+        log::info!("Created `{}` tracking `{}/{}`", name, remote, name);
+    }
+    Ok(())
+}
+
+/// Like `resolve_explicit_base` but also accepting any commit, like a release tag, as a detached
+/// base named by its abbreviated id
+///
+/// Returns whether the base is detached
+fn resolve_base_commit(
+    repo: &git_stack::git::GitRepo,
+    base: &str,
+) -> eyre::Result<(git_stack::git::Branch, bool)> {
+    if let Ok(branch) = resolve_explicit_base(repo, base) {
+        return Ok((branch, false));
+    }
+    let commit = repo
+        .resolve(base)
+        .ok_or_else(|| eyre::eyre!("could not find branch or commit {:?}", base))?;
+    let branch = git_stack::git::Branch {
+        name: repo.abbrev_id(commit.id),
+        id: commit.id,
+        push_id: None,
+        pull_id: None,
+    };
+    log::debug!("Using commit {} ({}) as a detached base", branch.name, base);
+    Ok((branch, true))
+}
+
+/// Whether the commit was last committed to before `cutoff`, in seconds since the epoch
+pub(crate) fn committed_before(repo: &git_stack::git::GitRepo, id: git2::Oid, cutoff: i64) -> bool {
+    repo.raw()
+        .find_commit(id)
+        .map(|commit| commit.committer().when().seconds() < cutoff)
+        .unwrap_or(false)
 }
 
 fn resolve_implicit_base(
@@ -506,6 +4901,118 @@ fn git_fetch(repo: &mut git_stack::git::GitRepo) -> eyre::Result<()> {
     Ok(())
 }
 
+/// Refresh the remote-tracking branches for the next run, without waiting on the network
+fn prefetch(repo: &git_stack::git::GitRepo) {
+    const MIN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+    let remote = repo.push_remote();
+    let age = fetch_age(repo, remote);
+    if age.map(|age| age < MIN_INTERVAL).unwrap_or(false) {
+        log::trace!("Skipping prefetch of `{}`, it was just fetched", remote);
+        return;
+    }
+
+    log::debug!("git fetch --quiet {} &", remote);
+    // Left to finish on its own; `git` takes care of locking against other fetches
+    let child = std::process::Command::new("git")
+        .arg("fetch")
+        .arg("--quiet")
+        .arg(remote)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    if let Err(err) = child {
+        log::debug!("Could not prefetch `{}`: {}", remote, err);
+        return;
+    }
+
+    match age {
+        Some(age) => log::info!(
+            "Fetching `{}` in the background, remote data is {} old",
+            remote,
+            format_age(age)
+        ),
+        None => log::info!("Fetching `{}` in the background", remote),
+    }
+}
+
+/// How long ago `remote` was last fetched
+///
+/// This goes by `FETCH_HEAD`, if that was from `remote`, and the last update to its
+/// remote-tracking branches.
+fn fetch_age(repo: &git_stack::git::GitRepo, remote: &str) -> Option<std::time::Duration> {
+    let mut fetched: Option<std::time::SystemTime> = None;
+
+    let fetch_head = repo.raw().path().join("FETCH_HEAD");
+    // `FETCH_HEAD` records the URL after any `insteadOf` rewrite, without a `.git` suffix
+    let url = git_stack::git::remote_url(repo.raw(), remote, git2::Direction::Fetch);
+    if let (Some(url), Ok(content)) = (url, std::fs::read_to_string(&fetch_head)) {
+        let url = url.trim_end_matches('/');
+        let url = url.strip_suffix(".git").unwrap_or(url);
+        if content.lines().any(|line| line.trim_end().ends_with(url)) {
+            fetched = std::fs::metadata(&fetch_head)
+                .and_then(|m| m.modified())
+                .ok();
+        }
+    }
+
+    let tracking = format!("refs/remotes/{}/*", remote);
+    if let Ok(mut references) = repo.raw().references_glob(&tracking) {
+        for name in references.names().flatten() {
+            let updated = repo.raw().reflog(name).ok().and_then(|reflog| {
+                let seconds = reflog.get(0)?.committer().when().seconds();
+                Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds.max(0) as u64))
+            });
+            fetched = fetched.max(updated);
+        }
+    }
+
+    fetched.map(|fetched| fetched.elapsed().unwrap_or_default())
+}
+
+pub(crate) fn format_age(age: std::time::Duration) -> String {
+    let minutes = age.as_secs() / 60;
+    if minutes < 1 {
+        "<1m".to_owned()
+    } else if minutes < 120 {
+        format!("{}m", minutes)
+    } else if minutes < 48 * 60 {
+        format!("{}h", minutes / 60)
+    } else {
+        format!("{}d", minutes / (24 * 60))
+    }
+}
+
+/// Say how old the ahead / behind counts are, so stale ones aren't trusted
+fn write_fetch_ages(
+    out: &mut dyn std::io::Write,
+    repo: &git_stack::git::GitRepo,
+    palette: Palette,
+) -> std::io::Result<()> {
+    const STALE: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+    let mut remotes = vec![repo.push_remote()];
+    if repo.pull_remote() != repo.push_remote() {
+        remotes.push(repo.pull_remote());
+    }
+    for remote in remotes {
+        let age = match fetch_age(repo, remote) {
+            Some(age) => age,
+            None => continue,
+        };
+        let footer = format!("{} info from {} ago", remote, format_age(age));
+        let style = if STALE <= age {
+            palette.warn
+        } else {
+            palette.hint
+        };
+        writeln!(out, "{}", style.paint(footer))?;
+    }
+    Ok(())
+}
+
 fn git_pull(
     repo: &mut git_stack::git::GitRepo,
     branch_name: &str,
@@ -535,13 +5042,9 @@ fn git_pull(
         }
 
         let local_branch = repo
-            .raw()
-            .find_branch(branch_name, git2::BranchType::Local)
-            .wrap_err_with(|| eyre::eyre!("local branch `{}` doesn't exist", branch_name))?;
-        let local_branch_annotated = {
-            repo.raw()
-                .reference_to_annotated_commit(local_branch.get())?
-        };
+            .find_local_branch(branch_name)
+            .ok_or_else(|| eyre::eyre!("local branch `{}` doesn't exist", branch_name))?;
+        let local_branch_annotated = repo.raw().find_annotated_commit(local_branch.id)?;
         log::trace!(
             "rebase local {}={}",
             branch_name,
@@ -624,7 +5127,7 @@ fn git_pull(
                 );
             }
 
-            let sig = repo.raw().signature().unwrap();
+            let sig = repo.committer(&repo.raw().find_commit(op.id())?)?;
             let commit_id = rebase
                 .commit(None, &sig, None)
                 .map_err(|e| {
@@ -648,8 +5151,10 @@ fn git_pull(
                 remote_branch_name
             )
         })?;
+        tip_id = repo.sign_range(end_id, tip_id)?;
     }
 
+    let reason = format!("pull {} from {}", branch_name, remote_branch_name);
     let head_branch = repo.head_branch();
     let head_branch_name = head_branch.as_ref().map(|b| b.name.as_str());
     if head_branch_name == Some(branch_name) {
@@ -661,13 +5166,14 @@ fn git_pull(
                 remote_branch_name
             )
         })?;
-        repo.branch(branch_name, tip_id).wrap_err_with(|| {
-            eyre::eyre!(
-                "failed to update `{}` to `{}`",
-                branch_name,
-                remote_branch_name
-            )
-        })?;
+        repo.branch(branch_name, tip_id, &reason)
+            .wrap_err_with(|| {
+                eyre::eyre!(
+                    "failed to update `{}` to `{}`",
+                    branch_name,
+                    remote_branch_name
+                )
+            })?;
         repo.switch(branch_name).wrap_err_with(|| {
             eyre::eyre!(
                 "failed to update `{}` to `{}`",
@@ -677,29 +5183,67 @@ fn git_pull(
         })?;
     } else {
         log::trace!("Updating {}", branch_name);
-        repo.branch(branch_name, tip_id).wrap_err_with(|| {
-            eyre::eyre!(
-                "failed to update `{}` to `{}`",
-                branch_name,
-                remote_branch_name
-            )
-        })?;
+        repo.branch(branch_name, tip_id, &reason)
+            .wrap_err_with(|| {
+                eyre::eyre!(
+                    "failed to update `{}` to `{}`",
+                    branch_name,
+                    remote_branch_name
+                )
+            })?;
     }
 
     Ok(pulled_range)
 }
 
+/// Unprotected branches whose commits are all in the branch they are stacked onto, either as-is or
+/// as patch-identical copies, grouped by that branch
+fn merged_branches(state: &State) -> Vec<(String, Vec<git_stack::git::Branch>)> {
+    let mut merged = Vec::new();
+    for stack in state.stacks.iter() {
+        let mut stack_merged = Vec::new();
+        let mut branches: Vec<_> = stack.branches.iter().flat_map(|(_, b)| b.iter()).collect();
+        branches.sort_by_key(|b| b.name.as_str());
+        for branch in branches {
+            let protected = state
+                .protected_branches
+                .get(branch.id)
+                .into_iter()
+                .flatten()
+                .any(|b| b.name == branch.name);
+            if protected {
+                continue;
+            }
+            if state.frozen_branches.contains(&branch.name) {
+                log::debug!("Keeping `{}`, it is frozen", branch.name);
+                continue;
+            }
+
+            if git_stack::git::is_merged(&state.repo, &stack.onto, branch) {
+                stack_merged.push(branch.clone());
+            }
+        }
+        if !stack_merged.is_empty() {
+            merged.push((stack.onto.name.clone(), stack_merged));
+        }
+    }
+
+    merged
+}
+
 fn drop_branches(
     repo: &mut git_stack::git::GitRepo,
     commit_ids: impl Iterator<Item = git2::Oid>,
     potential_head: &str,
     branches: &git_stack::git::Branches,
     protected_branches: &git_stack::git::Branches,
+    frozen: &HashSet<String>,
     dry_run: bool,
-) -> eyre::Result<()> {
+) -> eyre::Result<Vec<git_stack::git::Branch>> {
     let head_branch = repo.head_branch();
     let head_branch_name = head_branch.as_ref().map(|b| b.name.as_str());
 
+    let mut deleted = Vec::new();
     for commit_id in commit_ids {
         let commit_branches: HashSet<_> = branches.get(commit_id).into_iter().flatten().collect();
         let commit_protected_branches: HashSet<_> = protected_branches
@@ -714,6 +5258,8 @@ fn drop_branches(
         for branch in commit_unprotected {
             if branch.name == potential_head {
                 continue;
+            } else if frozen.contains(&branch.name) {
+                log::info!("Keeping `{}`, it is frozen", branch.name);
             } else if head_branch_name == Some(branch.name.as_str()) {
                 // Don't leave HEAD detached but instead switch to the branch we pulled
                 log::trace!("git switch {}", potential_head);
@@ -724,23 +5270,51 @@ fn drop_branches(
                 if !dry_run {
                     repo.delete_branch(&branch.name)?;
                 }
+                deleted.push((*branch).clone());
             } else {
                 log::trace!("git branch -D {}", branch.name);
                 if !dry_run {
                     repo.delete_branch(&branch.name)?;
                 }
+                deleted.push((*branch).clone());
             }
         }
     }
+    Ok(deleted)
+}
+
+/// Keep deleted branches restorable for `stack.quarantine-age`, purging those kept long enough
+fn quarantine_branches(state: &State, branches: &[git_stack::git::Branch]) -> eyre::Result<()> {
+    let max_age = match state.quarantine_age {
+        Some(max_age) => max_age,
+        None => return Ok(()),
+    };
+    if branches.is_empty() || state.dry_run || state.repo.is_sandboxed() {
+        return Ok(());
+    }
+
+    let now = crate::quarantine::now();
+    let mut quarantine = git_stack::stash::Quarantine::load(&state.repo)?;
+    for branch in branches {
+        quarantine.insert(&state.repo, &branch.name, branch.id, now)?;
+    }
+    crate::quarantine::purge_expired(&state.repo, &mut quarantine, max_age, now)?;
+    quarantine.save(&state.repo)?;
+    log::info!(
+        "To restore a deleted branch within {}, run `git stack quarantine restore <name>`",
+        format_age(max_age)
+    );
+
     Ok(())
 }
 
 fn git_push(
     repo: &mut git_stack::git::GitRepo,
     node: &git_stack::graph::Node,
+    frozen: &HashSet<String>,
     dry_run: bool,
 ) -> eyre::Result<()> {
-    let failed = git_push_internal(repo, node, dry_run);
+    let failed = git_push_internal(repo, node, frozen, dry_run);
     if failed.is_empty() {
         Ok(())
     } else {
@@ -751,36 +5325,17 @@ fn git_push(
 fn git_push_internal(
     repo: &mut git_stack::git::GitRepo,
     node: &git_stack::graph::Node,
+    frozen: &HashSet<String>,
     dry_run: bool,
 ) -> Vec<String> {
     let mut failed = Vec::new();
     for branch in node.branches.iter() {
-        if node.pushable {
-            let remote = repo.push_remote();
-            log::trace!(
-                "git push --force-with-lease --set-upstream {} {}",
-                remote,
-                branch.name
-            );
-            if !dry_run {
-                let status = std::process::Command::new("git")
-                    .arg("push")
-                    .arg("--force-with-lease")
-                    .arg("--set-upstream")
-                    .arg(repo.push_remote())
-                    .arg(&branch.name)
-                    .status();
-                match status {
-                    Ok(status) => {
-                        if !status.success() {
-                            failed.push(branch.name.clone());
-                        }
-                    }
-                    Err(err) => {
-                        log::debug!("`git push` failed with {}", err);
-                        failed.push(branch.name.clone());
-                    }
-                }
+        if frozen.contains(&branch.name) {
+            log::debug!("Skipping push of `{}`, frozen", branch.name);
+        } else if node.pushable {
+            if let Err(err) = git_push_branch(repo, &branch.name, dry_run) {
+                log::debug!("{}", err);
+                failed.push(branch.name.clone());
             }
         } else if node.action.is_protected() {
             log::debug!("Skipping push of `{}`, protected", branch.name);
@@ -791,20 +5346,58 @@ fn git_push_internal(
 
     if failed.is_empty() {
         for child in node.children.values() {
-            failed.extend(git_push_internal(repo, child, dry_run));
+            failed.extend(git_push_internal(repo, child, frozen, dry_run));
         }
     }
 
     failed
 }
 
+fn git_push_branch(
+    repo: &mut git_stack::git::GitRepo,
+    branch_name: &str,
+    dry_run: bool,
+) -> eyre::Result<()> {
+    let remote = repo.push_remote();
+    if let Some(url) = git_stack::git::remote_url(repo.raw(), remote, git2::Direction::Push) {
+        log::debug!("Pushing `{}` to {}", branch_name, url);
+    }
+    log::trace!(
+        "git push --force-with-lease --set-upstream {} {}",
+        remote,
+        branch_name
+    );
+    if !dry_run {
+        let status = std::process::Command::new("git")
+            .arg("push")
+            .arg("--force-with-lease")
+            .arg("--set-upstream")
+            .arg(remote)
+            .arg(branch_name)
+            .status()
+            .wrap_err("`git push` failed")?;
+        if !status.success() {
+            eyre::bail!("`git push {} {}` failed", remote, branch_name);
+        }
+        finish_remote_rename(repo, branch_name);
+    }
+    Ok(())
+}
+
 struct DisplayTree<'r> {
     repo: &'r git_stack::git::GitRepo,
     root: &'r git_stack::graph::Node,
     protected_branches: git_stack::git::Branches,
     palette: Palette,
+    glyphs: Glyphs,
     show: git_stack::config::Format,
     stacked: bool,
+    full_hashes: bool,
+    max_width: Option<usize>,
+    prs: Option<&'r PullRequests>,
+    unexpected_emails: Option<&'r UnexpectedEmails>,
+    frozen: Option<&'r HashSet<String>>,
+    descriptions: Option<&'r std::collections::HashMap<String, String>>,
 }
 
 impl<'r> DisplayTree<'r> {
@@ -814,14 +5407,21 @@ impl<'r> DisplayTree<'r> {
             root,
             protected_branches: Default::default(),
             palette: Palette::plain(),
+            glyphs: Glyphs::plain(),
             show: Default::default(),
             stacked: Default::default(),
+            full_hashes: false,
+            max_width: None,
+            prs: None,
+            unexpected_emails: None,
+            frozen: None,
+            descriptions: None,
         }
     }
 
-    pub fn colored(mut self, yes: bool) -> Self {
+    pub fn colored(mut self, yes: bool, palette: Palette) -> Self {
         if yes {
-            self.palette = Palette::colored()
+            self.palette = palette
         } else {
             self.palette = Palette::plain()
         }
@@ -838,22 +5438,79 @@ impl<'r> DisplayTree<'r> {
         self
     }
 
+    pub fn full_hashes(mut self, full_hashes: bool) -> Self {
+        self.full_hashes = full_hashes;
+        self
+    }
+
+    pub fn max_width(mut self, max_width: Option<usize>) -> Self {
+        self.max_width = max_width;
+        self
+    }
+
+    pub fn glyphs(mut self, glyphs: Glyphs) -> Self {
+        self.glyphs = glyphs;
+        self
+    }
+
     pub fn protected_branches(mut self, protected_branches: &git_stack::git::Branches) -> Self {
         self.protected_branches = protected_branches.clone();
         self
     }
+
+    pub fn prs(mut self, prs: &'r PullRequests) -> Self {
+        self.prs = Some(prs);
+        self
+    }
+
+    pub fn unexpected_emails(mut self, unexpected_emails: &'r UnexpectedEmails) -> Self {
+        self.unexpected_emails = Some(unexpected_emails);
+        self
+    }
+
+    pub fn frozen(mut self, frozen: &'r HashSet<String>) -> Self {
+        self.frozen = Some(frozen);
+        self
+    }
+
+    pub fn descriptions(
+        mut self,
+        descriptions: &'r std::collections::HashMap<String, String>,
+    ) -> Self {
+        self.descriptions = Some(descriptions);
+        self
+    }
 }
 
 impl<'r> std::fmt::Display for DisplayTree<'r> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         let head_branch = self.repo.head_branch().unwrap();
-        let mut tree = to_tree(
+        let mut behind = std::collections::HashMap::new();
+        let mut restack = std::collections::HashMap::new();
+        behind_base(
             self.repo,
-            &head_branch,
             &self.protected_branches,
             self.root,
-            &self.palette,
+            !self.glyphs.conflict.is_empty(),
+            &mut behind,
+            &mut restack,
         );
+        let template = RenderNode {
+            repo: self.repo,
+            head_branch: &head_branch,
+            protected_branches: &self.protected_branches,
+            node: None,
+            palette: &self.palette,
+            glyphs: &self.glyphs,
+            behind: &behind,
+            restack: &restack,
+            full_hashes: self.full_hashes,
+            prs: self.prs,
+            unexpected_emails: self.unexpected_emails,
+            frozen: self.frozen,
+            descriptions: self.descriptions,
+        };
+        let mut tree = to_tree(template, self.root);
         if self.stacked {
             tree.linearize();
         } else {
@@ -881,21 +5538,144 @@ impl<'r> std::fmt::Display for DisplayTree<'r> {
             }),
             git_stack::config::Format::Debug => tree.skip(|_| false),
         }
-        let tree = tree.into_display();
-        tree.fmt(f)
+        let tree = tree.into_display().to_string();
+        f.write_str(&align_columns(&tree, self.max_width))
     }
 }
 
-fn to_tree<'r>(
-    repo: &'r git_stack::git::GitRepo,
-    head_branch: &'r git_stack::git::Branch,
-    protected_branches: &'r git_stack::git::Branches,
-    node: &'r git_stack::graph::Node,
-    palette: &'r Palette,
-) -> Tree<'r> {
+/// Separates the branch names, annotations, and summary of a rendered node
+const COLUMN_SEP: char = '\u{1f}';
+
+/// Pad each `COLUMN_SEP`-delimited column to the widest entry, accounting for ANSI escapes
+///
+/// The last column is cut off to keep lines within `max_width`
+fn align_columns(rendered: &str, max_width: Option<usize>) -> String {
+    let rows: Vec<Vec<&str>> = rendered
+        .lines()
+        .map(|line| line.split(COLUMN_SEP).collect())
+        .collect();
+
+    let mut widths = Vec::new();
+    for row in rows.iter().filter(|row| 1 < row.len()) {
+        let (_, columns) = row.split_last().expect("at least one column");
+        widths.resize(widths.len().max(columns.len()), 0);
+        for (width, column) in widths.iter_mut().zip(columns) {
+            *width = display_width(column).max(*width);
+        }
+    }
+
+    let mut aligned = String::with_capacity(rendered.len());
+    for row in rows {
+        let (last, columns) = row.split_last().expect("split always has one");
+        let line_start = aligned.len();
+        for (width, column) in widths.iter().zip(columns) {
+            if *width == 0 {
+                continue;
+            }
+            aligned.push_str(column);
+            let padding = width - display_width(column) + 1;
+            aligned.push_str(&" ".repeat(padding));
+        }
+        match max_width {
+            Some(max_width) => {
+                let used = display_width(&aligned[line_start..]);
+                aligned.push_str(&truncate(last, max_width.saturating_sub(used)));
+            }
+            None => aligned.push_str(last),
+        }
+        aligned.push('\n');
+    }
+    aligned
+}
+
+/// Cut `s` off with `…` to fit within `max_width` terminal columns, preserving ANSI escapes
+fn truncate(s: &str, max_width: usize) -> std::borrow::Cow<'_, str> {
+    if display_width(s) <= max_width {
+        return std::borrow::Cow::Borrowed(s);
+    }
+
+    let ellipsis = '…';
+    let mut budget = max_width.saturating_sub(1);
+    let mut truncated = String::with_capacity(s.len());
+    let mut styled = false;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Keep escapes so styles are still turned on and off
+            truncated.push(c);
+            styled = true;
+            if let Some(c) = chars.next() {
+                truncated.push(c);
+                if c == '[' {
+                    for c in chars.by_ref() {
+                        truncated.push(c);
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+        } else if budget != 0 {
+            let width = unicode_width::UnicodeWidthChar::width(c).unwrap_or(0);
+            if budget < width {
+                // Don't split a wide character
+                budget = 0;
+            } else {
+                truncated.push(c);
+                budget -= width;
+            }
+        }
+    }
+    if 0 < max_width {
+        truncated.push(ellipsis);
+    }
+    if styled {
+        truncated.push_str("\x1b[0m");
+    }
+    std::borrow::Cow::Owned(truncated)
+}
+
+/// Columns in the terminal stdout is connected to, if any
+fn terminal_width() -> Option<usize> {
+    if !atty::is(atty::Stream::Stdout) {
+        return None;
+    }
+
+    if let Some((terminal_size::Width(width), _)) = terminal_size::terminal_size() {
+        if width != 0 {
+            return Some(width as usize);
+        }
+    }
+
+    std::env::var("COLUMNS").ok()?.parse().ok()
+}
+
+/// Terminal columns taken up by `s`, ignoring ANSI escape sequences
+fn display_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end with a byte in `@`..=`~`
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else {
+            width += unicode_width::UnicodeWidthChar::width(c).unwrap_or(0);
+        }
+    }
+    width
+}
+
+/// Build the tree under `node`, with `template` carrying what every node is rendered with
+fn to_tree<'r>(template: RenderNode<'r>, node: &'r git_stack::graph::Node) -> Tree<'r> {
     let mut weight = if node.action.is_protected() {
         Weight::Protected(0)
-    } else if node.local_commit.id == head_branch.id {
+    } else if node.local_commit.id == template.head_branch.id {
         Weight::Head(0)
     } else {
         Weight::Commit(0)
@@ -903,18 +5683,15 @@ fn to_tree<'r>(
 
     let mut stacks = Vec::new();
     for child in node.children.values() {
-        let child_tree = to_tree(repo, head_branch, protected_branches, child, palette);
+        let child_tree = to_tree(template, child);
         weight = weight.max(child_tree.weight);
         stacks.push(vec![child_tree]);
     }
 
     let tree = Tree {
         root: RenderNode {
-            repo,
-            head_branch,
-            protected_branches,
             node: Some(node),
-            palette,
+            ..template
         },
         weight,
         stacks,
@@ -1026,6 +5803,14 @@ struct RenderNode<'r> {
     protected_branches: &'r git_stack::git::Branches,
     node: Option<&'r git_stack::graph::Node>,
     palette: &'r Palette,
+    glyphs: &'r Glyphs,
+    behind: &'r std::collections::HashMap<git2::Oid, BehindBase>,
+    restack: &'r std::collections::HashMap<git2::Oid, Restack>,
+    full_hashes: bool,
+    prs: Option<&'r PullRequests>,
+    unexpected_emails: Option<&'r UnexpectedEmails>,
+    frozen: Option<&'r HashSet<String>>,
+    descriptions: Option<&'r std::collections::HashMap<String, String>>,
 }
 
 impl<'r> RenderNode<'r> {
@@ -1036,6 +5821,14 @@ impl<'r> RenderNode<'r> {
             protected_branches: self.protected_branches,
             node: None,
             palette: self.palette,
+            glyphs: self.glyphs,
+            behind: self.behind,
+            restack: self.restack,
+            full_hashes: self.full_hashes,
+            prs: self.prs,
+            unexpected_emails: self.unexpected_emails,
+            frozen: self.frozen,
+            descriptions: self.descriptions,
         }
     }
 }
@@ -1046,14 +5839,9 @@ impl<'r> RenderNode<'r> {
 impl<'r> std::fmt::Display for RenderNode<'r> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         if let Some(node) = self.node.as_ref() {
+            let mut annotations = Vec::new();
             if node.branches.is_empty() {
-                let abbrev_id = self
-                    .repo
-                    .raw()
-                    .find_object(node.local_commit.id, None)
-                    .unwrap()
-                    .short_id()
-                    .unwrap();
+                let abbrev_id = format_id(self.repo, node.local_commit.id, self.full_hashes);
                 let style = if self.head_branch.id == node.local_commit.id {
                     self.palette.highlight
                 } else if node.action.is_protected() {
@@ -1064,7 +5852,7 @@ impl<'r> std::fmt::Display for RenderNode<'r> {
                 } else {
                     self.palette.hint
                 };
-                write!(f, "{}", style.paint(abbrev_id.as_str().unwrap()))?;
+                write!(f, "{}", style.paint(abbrev_id))?;
             } else {
                 let mut branches: Vec<_> = node.branches.iter().collect();
                 branches.sort_by_key(|b| {
@@ -1076,29 +5864,78 @@ impl<'r> std::fmt::Display for RenderNode<'r> {
                     f,
                     "{}",
                     branches
-                        .into_iter()
+                        .iter()
                         .map(|b| {
-                            format!(
-                                "{}{}",
-                                format_branch_name(
-                                    b,
-                                    node,
-                                    self.head_branch,
-                                    self.protected_branches,
-                                    self.palette
-                                ),
-                                format_branch_status(b, self.repo, node, self.palette),
+                            format_branch_name(
+                                b,
+                                node,
+                                self.head_branch,
+                                self.protected_branches,
+                                self.palette,
                             )
                         })
                         .join(", ")
                 )?;
+                for b in branches.iter() {
+                    let status =
+                        format_branch_status(b, self.repo, node, self.palette, self.glyphs);
+                    if !status.is_empty() && !annotations.contains(&status) {
+                        annotations.push(status);
+                    }
+                }
+                if let Some(frozen) = self.frozen {
+                    if branches.iter().any(|b| frozen.contains(&b.name)) {
+                        annotations.push(self.palette.hint.paint("(frozen)").to_string());
+                    }
+                }
+                if let Some(blocker) = node.push_blocker {
+                    annotations.push(
+                        self.palette
+                            .hint
+                            .paint(format!("(not pushable, {})", blocker))
+                            .to_string(),
+                    );
+                }
+                if let Some(prs) = self.prs {
+                    for b in branches.iter() {
+                        if let Some(pr) = prs.get(b.name.as_str()) {
+                            annotations.push(format_pr_status(pr, self.palette, self.glyphs));
+                        }
+                    }
+                }
             }
 
-            write!(
-                f,
-                "{} ",
-                format_commit_status(self.repo, node, self.palette)
-            )?;
+            let mut restack_shown = false;
+            if !node.branches.is_empty() {
+                if let Some(restack) = self.restack.get(&node.local_commit.id) {
+                    annotations.push(
+                        self.palette
+                            .warn
+                            .paint(format!("({} behind {})", restack.commits, restack.onto))
+                            .to_string(),
+                    );
+                    restack_shown = true;
+                }
+            }
+            let redundant =
+                restack_shown && self.behind.get(&node.local_commit.id) == Some(&BehindBase::Clean);
+            let status =
+                format_commit_status(self.repo, node, self.palette, self.glyphs, self.behind);
+            if !status.is_empty() && !redundant {
+                annotations.push(status);
+            }
+            if let Some(email) = self
+                .unexpected_emails
+                .and_then(|u| u.get(&node.local_commit.id))
+            {
+                annotations.push(
+                    self.palette
+                        .warn
+                        .paint(format!("(committed as {})", email))
+                        .to_string(),
+                );
+            }
+            write!(f, "{}{}{}", COLUMN_SEP, annotations.join(" "), COLUMN_SEP)?;
 
             let summary = String::from_utf8_lossy(&node.local_commit.summary);
             if node.action.is_protected() {
@@ -1108,10 +5945,26 @@ impl<'r> std::fmt::Display for RenderNode<'r> {
                 write!(f, "{}", self.palette.warn.paint(summary))?;
             } else if node.local_commit.wip_summary().is_some() {
                 // Not for pushing implicitly
+                if !self.glyphs.wip.is_empty() {
+                    write!(f, "{} ", self.glyphs.wip)?;
+                }
                 write!(f, "{}", self.palette.error.paint(summary))?;
             } else {
                 write!(f, "{}", summary)?;
             }
+
+            // Only the first line fits in the tree, `git stack describe` shows the rest
+            let description = self.descriptions.and_then(|descriptions| {
+                node.branches
+                    .iter()
+                    .filter_map(|b| descriptions.get(&b.name))
+                    .next()
+            });
+            if let Some(line) =
+                description.and_then(|d| d.lines().map(str::trim).find(|l| !l.is_empty()))
+            {
+                write!(f, "  {}", self.palette.hint.paint(format!("# {}", line)))?;
+            }
         } else {
             write!(f, "o")?;
         }
@@ -1141,32 +5994,90 @@ fn format_branch_name<'d>(
     }
 }
 
+type PullRequests = std::collections::HashMap<String, crate::github::PullRequestStatus>;
+
+fn format_pr_status(
+    pr: &crate::github::PullRequestStatus,
+    palette: &Palette,
+    glyphs: &Glyphs,
+) -> String {
+    let review = format_pr_review(pr, palette, glyphs);
+    if !pr.is_open() {
+        return review;
+    }
+    match pr.ci_status() {
+        Some(crate::github::CiStatus::Passing) => {
+            format!("{} {}", review, palette.good.paint("ci-passing"))
+        }
+        Some(crate::github::CiStatus::Pending) => {
+            format!("{} {}", review, palette.hint.paint("ci-pending"))
+        }
+        Some(crate::github::CiStatus::Failing) => {
+            format!("{} {}", review, palette.error.paint("ci-failing"))
+        }
+        None => review,
+    }
+}
+
+fn format_pr_review(
+    pr: &crate::github::PullRequestStatus,
+    palette: &Palette,
+    glyphs: &Glyphs,
+) -> String {
+    let number = format!("#{}", pr.number);
+    match pr.state.as_str() {
+        "MERGED" => format!("{}", palette.hint.paint(format!("{} merged", number))),
+        "CLOSED" => format!("{}", palette.hint.paint(format!("{} closed", number))),
+        _ if pr.is_draft => format!("{}", palette.hint.paint(format!("{} draft", number))),
+        _ => match pr.review_decision.as_str() {
+            "APPROVED" => {
+                let approved = if glyphs.approved.is_empty() {
+                    "approved".to_owned()
+                } else {
+                    format!("{}approved", glyphs.approved)
+                };
+                format!("{}", palette.good.paint(format!("{} {}", number, approved)))
+            }
+            "CHANGES_REQUESTED" => format!(
+                "{}",
+                palette.error.paint(format!("{} changes-requested", number))
+            ),
+            "REVIEW_REQUIRED" => format!(
+                "{}",
+                palette.info.paint(format!("{} review-required", number))
+            ),
+            _ => format!("{}", palette.info.paint(number)),
+        },
+    }
+}
+
 fn format_branch_status<'d>(
     branch: &'d git_stack::git::Branch,
     repo: &'d git_stack::git::GitRepo,
     node: &'d git_stack::graph::Node,
     palette: &'d Palette,
+    glyphs: &'d Glyphs,
 ) -> String {
     // See format_commit_status
     if node.action.is_protected() {
         match commit_relation(repo, branch.id, branch.pull_id) {
             Some((0, 0)) => format!(""),
             Some((local, 0)) => {
-                format!(" {}", palette.warn.paint(format!("({} ahead)", local)))
+                format!("{}", palette.warn.paint(format!("({} ahead)", local)))
             }
             Some((0, remote)) => {
-                format!(" {}", palette.warn.paint(format!("({} behind)", remote)))
+                format!("{}", palette.warn.paint(format!("({} behind)", remote)))
             }
             Some((local, remote)) => {
                 format!(
-                    " {}",
+                    "{}",
                     palette
                         .warn
                         .paint(format!("({} ahead, {} behind)", local, remote)),
                 )
             }
             None => {
-                format!(" {}", palette.warn.paint("(no remote)"))
+                format!("{}", palette.warn.paint("(no remote)"))
             }
         }
     } else if node.action.is_delete() {
@@ -1185,17 +6096,17 @@ fn format_branch_status<'d>(
             let branch = &node.branches[0];
             match commit_relation(repo, branch.id, branch.push_id) {
                 Some((0, 0)) => {
-                    format!(" {}", palette.good.paint("(pushed)"))
+                    format!("{}", palette.good.paint(glyphs.pushed))
                 }
                 Some((local, 0)) => {
-                    format!(" {}", palette.info.paint(format!("({} ahead)", local)))
+                    format!("{}", palette.info.paint(format!("({} ahead)", local)))
                 }
                 Some((0, remote)) => {
-                    format!(" {}", palette.warn.paint(format!("({} behind)", remote)))
+                    format!("{}", palette.warn.paint(format!("({} behind)", remote)))
                 }
                 Some((local, remote)) => {
                     format!(
-                        " {}",
+                        "{}",
                         palette
                             .warn
                             .paint(format!("({} ahead, {} behind)", local, remote)),
@@ -1203,7 +6114,7 @@ fn format_branch_status<'d>(
                 }
                 None => {
                     if node.pushable {
-                        format!(" {}", palette.info.paint("(ready)"))
+                        format!("{}", palette.info.paint("(ready)"))
                     } else {
                         format!("")
                     }
@@ -1217,24 +6128,195 @@ fn format_commit_status<'d>(
     repo: &'d git_stack::git::GitRepo,
     node: &'d git_stack::graph::Node,
     palette: &'d Palette,
+    glyphs: &'d Glyphs,
+    behind: &'d std::collections::HashMap<git2::Oid, BehindBase>,
 ) -> String {
     // See format_branch_status
     if node.action.is_protected() {
         format!("")
+    } else if let Some(status) = behind
+        .get(&node.local_commit.id)
+        .filter(|_| !glyphs.needs_rebase.is_empty())
+    {
+        match status {
+            BehindBase::Clean => format!("{}", palette.warn.paint(glyphs.needs_rebase)),
+            BehindBase::Conflict => format!("{}", palette.error.paint(glyphs.conflict)),
+        }
     } else if node.action.is_delete() {
-        format!(" {}", palette.error.paint("(drop)"))
+        format!("{}", palette.error.paint("(drop)"))
     } else if 1 < repo
         .raw()
         .find_commit(node.local_commit.id)
         .unwrap()
         .parent_count()
     {
-        format!(" {}", palette.error.paint("(merge commit)"))
+        if repo.rebase_merges() {
+            format!("{}", palette.hint.paint("(merge)"))
+        } else if 2 < repo
+            .raw()
+            .find_commit(node.local_commit.id)
+            .unwrap()
+            .parent_count()
+        {
+            format!("{}", palette.warn.paint("(octopus merge)"))
+        } else {
+            format!("{}", palette.error.paint("(merge commit)"))
+        }
     } else {
         format!("")
     }
 }
 
+/// How a stack relates to the protected branch it is based on
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum BehindBase {
+    Clean,
+    /// The stack and upstream changed the same files
+    Conflict,
+}
+
+/// How far a stack needs to be restacked to catch up with its protected base
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Restack {
+    onto: String,
+    commits: usize,
+}
+
+/// Find stacks whose protected base has moved on
+///
+/// `behind` is by the id of each stack's first commit while `restack` has every commit in the
+/// stack.  Conflicts are only predicted with `predict`.
+fn behind_base(
+    repo: &git_stack::git::GitRepo,
+    protected_branches: &git_stack::git::Branches,
+    node: &git_stack::graph::Node,
+    predict: bool,
+    behind: &mut std::collections::HashMap<git2::Oid, BehindBase>,
+    restack: &mut std::collections::HashMap<git2::Oid, Restack>,
+) {
+    if !node.action.is_protected() {
+        return;
+    }
+
+    for child in node.children.values() {
+        if child.action.is_protected() {
+            continue;
+        }
+        let (onto, upstream_id) = upstream(repo, protected_branches, node, child);
+        if upstream_id == node.local_commit.id {
+            continue;
+        }
+        let commits = repo
+            .commits_from(upstream_id)
+            .take_while(|c| c.id != node.local_commit.id)
+            .count();
+        let conflict = predict
+            && repo
+                .find_commit(upstream_id)
+                .map(|upstream| predicts_conflict(repo, child, upstream.tree_id))
+                .unwrap_or(false);
+        let status = if conflict {
+            BehindBase::Conflict
+        } else {
+            BehindBase::Clean
+        };
+        behind.insert(child.local_commit.id, status);
+
+        let mut stack_ids = Vec::new();
+        stack_commits(child, &mut stack_ids);
+        for id in stack_ids {
+            restack.insert(
+                id,
+                Restack {
+                    onto: onto.clone(),
+                    commits,
+                },
+            );
+        }
+    }
+
+    for child in node.children.values() {
+        behind_base(repo, protected_branches, child, predict, behind, restack);
+    }
+}
+
+/// The name and id of the protected branch the stack at `child` would be restacked onto
+///
+/// Prefers the branch `find_protected_base` picks, falling back to the newest protected commit
+/// in the graph.
+fn upstream(
+    repo: &git_stack::git::GitRepo,
+    protected_branches: &git_stack::git::Branches,
+    node: &git_stack::graph::Node,
+    child: &git_stack::graph::Node,
+) -> (String, git2::Oid) {
+    let base = git_stack::git::find_protected_base(repo, protected_branches, child.local_commit.id)
+        .filter(|base| {
+            repo.merge_base(base.id, node.local_commit.id) == Some(node.local_commit.id)
+        });
+    if let Some(base) = base {
+        return (base.name.clone(), base.id);
+    }
+    let upstream = protected_tip(node);
+    let upstream_id = upstream.local_commit.id;
+    let onto = upstream
+        .branches
+        .iter()
+        .map(|b| b.name.clone())
+        .min()
+        .unwrap_or_else(|| repo.abbrev_id(upstream_id));
+    (onto, upstream_id)
+}
+
+/// Whether cherry-picking the stack at `node` onto the tree `tree_id` would conflict
+fn predicts_conflict(
+    repo: &git_stack::git::GitRepo,
+    node: &git_stack::graph::Node,
+    tree_id: git2::Oid,
+) -> bool {
+    match repo.predict_cherry_pick(tree_id, node.local_commit.id) {
+        Ok(git_stack::git::PickPrediction::Clean(next)) => node
+            .children
+            .values()
+            .filter(|child| !child.action.is_protected())
+            .any(|child| predicts_conflict(repo, child, next)),
+        Ok(git_stack::git::PickPrediction::Conflict(_)) => true,
+        Err(err) => {
+            log::debug!(
+                "Could not predict cherry-picking {}: {}",
+                node.local_commit.id,
+                err.message()
+            );
+            false
+        }
+    }
+}
+
+fn protected_tip(node: &git_stack::graph::Node) -> &git_stack::graph::Node {
+    node.children
+        .values()
+        .rev()
+        .find(|c| c.action.is_protected())
+        .map(protected_tip)
+        .unwrap_or(node)
+}
+
+fn stack_commits(node: &git_stack::graph::Node, ids: &mut Vec<git2::Oid>) {
+    ids.push(node.local_commit.id);
+    for child in node.children.values() {
+        stack_commits(child, ids);
+    }
+}
+
+fn unprotected_commits(node: &git_stack::graph::Node, ids: &mut Vec<git2::Oid>) {
+    if !node.action.is_protected() {
+        ids.push(node.local_commit.id);
+    }
+    for child in node.children.values() {
+        unprotected_commits(child, ids);
+    }
+}
+
 fn commit_relation(
     repo: &git_stack::git::GitRepo,
     local: git2::Oid,
@@ -1279,6 +6361,18 @@ impl Palette {
         }
     }
 
+    /// Avoids relying on red vs green, for deuteranopia and protanopia
+    pub fn deuteranopia() -> Self {
+        Self {
+            error: yansi::Style::new(yansi::Color::Magenta).bold(),
+            warn: yansi::Style::new(yansi::Color::Yellow).bold(),
+            info: yansi::Style::new(yansi::Color::Blue).bold(),
+            good: yansi::Style::new(yansi::Color::Cyan).bold(),
+            highlight: yansi::Style::new(yansi::Color::Unset).bold().underline(),
+            hint: yansi::Style::new(yansi::Color::Unset).dimmed(),
+        }
+    }
+
     pub fn plain() -> Self {
         Self {
             error: yansi::Style::default(),
@@ -1289,4 +6383,173 @@ impl Palette {
             hint: yansi::Style::default(),
         }
     }
+
+    /// The configured theme with any per-role overrides applied
+    pub fn from_config(config: &git_stack::config::RepoConfig) -> Self {
+        let mut palette = match config.theme() {
+            git_stack::config::Theme::Default => Self::colored(),
+            git_stack::config::Theme::Deuteranopia => Self::deuteranopia(),
+        };
+        for (role, color) in config.colors() {
+            let style = match parse_style(color) {
+                Ok(style) => style,
+                Err(err) => {
+                    log::warn!("Ignoring `stack.color.{}`: {}", role, err);
+                    continue;
+                }
+            };
+            match role {
+                "error" => palette.error = style,
+                "warn" => palette.warn = style,
+                "info" => palette.info = style,
+                "good" => palette.good = style,
+                "highlight" => palette.highlight = style,
+                "hint" => palette.hint = style,
+                _ => log::warn!("Ignoring unsupported `stack.color.{}`", role),
+            }
+        }
+        palette
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Glyphs {
+    pushed: &'static str,
+    needs_rebase: &'static str,
+    wip: &'static str,
+    conflict: &'static str,
+    approved: &'static str,
+}
+
+impl Glyphs {
+    /// Emoji if requested and the terminal can show them, falling back to text
+    pub fn new(requested: bool) -> Self {
+        if !requested {
+            Self::plain()
+        } else if supports_unicode() {
+            Self::emoji()
+        } else {
+            Self::text()
+        }
+    }
+
+    /// Only the statuses shown without `stack.show-glyphs`
+    pub fn plain() -> Self {
+        Self {
+            pushed: "(pushed)",
+            needs_rebase: "",
+            wip: "",
+            conflict: "",
+            approved: "",
+        }
+    }
+
+    pub fn emoji() -> Self {
+        Self {
+            pushed: "✅",
+            needs_rebase: "🔄",
+            wip: "🚧",
+            conflict: "⚠",
+            approved: "✔",
+        }
+    }
+
+    pub fn text() -> Self {
+        Self {
+            pushed: "(pushed)",
+            needs_rebase: "(needs rebase)",
+            wip: "",
+            conflict: "(conflict predicted)",
+            approved: "",
+        }
+    }
+}
+
+fn supports_unicode() -> bool {
+    if std::env::var_os("TERM")
+        .map(|t| t == "dumb")
+        .unwrap_or(false)
+    {
+        return false;
+    }
+    if cfg!(windows) {
+        // Windows Terminal, unlike the legacy console
+        return std::env::var_os("WT_SESSION").is_some();
+    }
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .map(|locale| {
+            let locale = locale.to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+        .unwrap_or(false)
+}
+
+/// Parse `git config` color syntax, like `bold red` or `#ff8700 ul`
+fn parse_style(spec: &str) -> Result<yansi::Style, String> {
+    let mut style = yansi::Style::default();
+    let mut colors = 0;
+    for word in spec.split_whitespace() {
+        let word = word.to_ascii_lowercase();
+        if let Some(color) = parse_color(&word)? {
+            style = match colors {
+                0 => style.fg(color),
+                1 => style.bg(color),
+                _ => return Err(format!("too many colors in `{}`", spec)),
+            };
+            colors += 1;
+        } else {
+            style = match word.as_str() {
+                "bold" => style.bold(),
+                "dim" => style.dimmed(),
+                "italic" => style.italic(),
+                "ul" => style.underline(),
+                "blink" => style.blink(),
+                "reverse" => style.invert(),
+                "strike" => style.strikethrough(),
+                _ => return Err(format!("unknown color or attribute `{}`", word)),
+            };
+        }
+    }
+    Ok(style)
+}
+
+fn parse_color(word: &str) -> Result<Option<yansi::Color>, String> {
+    let basic = [
+        ("black", yansi::Color::Black),
+        ("red", yansi::Color::Red),
+        ("green", yansi::Color::Green),
+        ("yellow", yansi::Color::Yellow),
+        ("blue", yansi::Color::Blue),
+        ("magenta", yansi::Color::Magenta),
+        ("cyan", yansi::Color::Cyan),
+        ("white", yansi::Color::White),
+    ];
+    let color = if word == "normal" || word == "default" {
+        yansi::Color::Unset
+    } else if let Some((_, color)) = basic.iter().find(|(name, _)| *name == word) {
+        *color
+    } else if let Some(index) = word
+        .strip_prefix("bright")
+        .and_then(|w| basic.iter().position(|(name, _)| *name == w))
+    {
+        yansi::Color::Fixed(8 + index as u8)
+    } else if let Some(hex) = word.strip_prefix('#') {
+        let rgb = u32::from_str_radix(hex, 16)
+            .ok()
+            .filter(|_| hex.len() == 6)
+            .ok_or_else(|| format!("invalid color `{}`", word))?;
+        yansi::Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+    } else if let Ok(index) = word.parse::<i16>() {
+        match index {
+            -1 => yansi::Color::Unset,
+            0..=255 => yansi::Color::Fixed(index as u8),
+            _ => return Err(format!("invalid color `{}`", word)),
+        }
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(color))
 }
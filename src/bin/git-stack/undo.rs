@@ -0,0 +1,171 @@
+use std::io::Write;
+
+use bstr::ByteSlice;
+use eyre::WrapErr;
+use proc_exit::WithCodeResultExt;
+
+pub fn undo(args: &crate::args::Args, undo_args: &crate::args::UndoArgs) -> proc_exit::ExitResult {
+    restore_from(
+        args,
+        undo_args,
+        crate::stack::STASH_STACK_NAME,
+        crate::stack::REDO_STACK_NAME,
+        "undo",
+    )
+}
+
+pub fn redo(args: &crate::args::Args, undo_args: &crate::args::UndoArgs) -> proc_exit::ExitResult {
+    restore_from(
+        args,
+        undo_args,
+        crate::stack::REDO_STACK_NAME,
+        crate::stack::STASH_STACK_NAME,
+        "redo",
+    )
+}
+
+/// Restore the latest snapshot in the `from` stack, saving the branches as they were to `to`
+fn restore_from(
+    args: &crate::args::Args,
+    undo_args: &crate::args::UndoArgs,
+    from: &str,
+    to: &str,
+    verb: &str,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo_config = git_stack::config::RepoConfig::from_all(&repo)
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    let mut repo = git_stack::git::GitRepo::new(repo);
+    repo.set_dirty_check(repo_config.dirty_check());
+    if repo.is_dirty() {
+        return Err(proc_exit::Code::USAGE_ERR.with_message("Working tree is dirty, aborting"));
+    }
+
+    let backups = git_stack::stash::Stack::new(from, &repo);
+    // Snapshots matching the branches as they are now have nothing to restore
+    let mut skipped = Vec::new();
+    let mut found = None;
+    for path in backups.iter().rev() {
+        let snapshot =
+            git_stack::stash::Snapshot::load(&path).with_code(proc_exit::Code::FAILURE)?;
+        let changes = changes(&repo, &snapshot).with_code(proc_exit::Code::FAILURE)?;
+        if changes.is_empty() {
+            log::debug!("Skipping {}, the branches already match it", path.display());
+            skipped.push(path);
+        } else {
+            found = Some((path, snapshot, changes));
+            break;
+        }
+    }
+    let (last, snapshot, changes) = match found {
+        Some(found) => found,
+        None => {
+            log::warn!("Nothing to {}", verb);
+            return Ok(());
+        }
+    };
+
+    {
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        for change in changes.iter() {
+            writeln!(stdout, "{}", change)?;
+        }
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+    if !undo_args.yes
+        && !confirm("Restore these branches?").with_code(proc_exit::Code::USAGE_ERR)?
+    {
+        log::info!("Branches were left as-is");
+        return Ok(());
+    }
+
+    repo.ensure_fingerprint();
+    let current =
+        git_stack::stash::Snapshot::from_repo(&repo).with_code(proc_exit::Code::FAILURE)?;
+    restore(&mut repo, &snapshot, &current).with_code(proc_exit::Code::FAILURE)?;
+    git_stack::stash::Stack::new(to, &repo)
+        .push(current)
+        .with_code(proc_exit::Code::FAILURE)?;
+    for path in skipped.iter().chain(std::iter::once(&last)) {
+        let _ = std::fs::remove_file(path);
+    }
+    log::info!("Restored {} branch(es)", changes.len());
+
+    Ok(())
+}
+
+/// Describe how restoring `snapshot` would move each branch, making sure it can
+pub(crate) fn changes(
+    repo: &git_stack::git::GitRepo,
+    snapshot: &git_stack::stash::Snapshot,
+) -> eyre::Result<Vec<String>> {
+    if let (Some(expected), Some(actual)) = (snapshot.fingerprint(), repo.fingerprint()) {
+        if expected != actual {
+            eyre::bail!(
+                "backup is for another repository (`stack.repo-id` {}, not {})",
+                expected,
+                actual
+            );
+        }
+    }
+
+    let mut changes = Vec::new();
+    for branch in snapshot.branches.iter() {
+        let current = repo.find_local_branch(&branch.name).map(|b| b.id);
+        if current == Some(branch.id) {
+            continue;
+        }
+        let commit = repo.find_commit(branch.id).ok_or_else(|| {
+            eyre::eyre!(
+                "backup has `{}` at {}, which no longer exists",
+                branch.name,
+                branch.id
+            )
+        })?;
+        let current = current
+            .map(|id| repo.abbrev_id(id))
+            .unwrap_or_else(|| "(deleted)".to_owned());
+        changes.push(format!(
+            "  {}: {} -> {}  # {}",
+            branch.name,
+            current,
+            repo.abbrev_id(branch.id),
+            commit.summary.to_str_lossy()
+        ));
+    }
+    Ok(changes)
+}
+
+/// Restore `snapshot`, putting the branches back as they were if any can't be
+pub(crate) fn restore(
+    repo: &mut git_stack::git::GitRepo,
+    snapshot: &git_stack::stash::Snapshot,
+    current: &git_stack::stash::Snapshot,
+) -> eyre::Result<()> {
+    if let Err(err) = snapshot.apply(repo) {
+        current
+            .apply(repo)
+            .wrap_err_with(|| format!("could not restore ({}) nor put the branches back", err))?;
+        eyre::bail!("could not restore, branches were left as-is: {}", err);
+    }
+    Ok(())
+}
+
+fn confirm(prompt: &str) -> eyre::Result<bool> {
+    if !atty::is(atty::Stream::Stdin) {
+        eyre::bail!("Can't ask for confirmation without a terminal, pass `--yes`");
+    }
+    let stderr = std::io::stderr();
+    let mut stderr = stderr.lock();
+    write!(stderr, "{} [y/N] ", prompt)?;
+    stderr.flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
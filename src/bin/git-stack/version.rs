@@ -0,0 +1,84 @@
+use std::io::Write;
+
+/// Whether `--verbose` came with `--version`, which exits before the rest of the arguments are
+/// parsed
+pub fn is_verbose() -> bool {
+    std::env::args_os()
+        .skip(1)
+        .take_while(|arg| arg != "--")
+        .any(|arg| {
+            let arg = arg.to_string_lossy();
+            arg == "--verbose"
+                || (arg.starts_with('-')
+                    && !arg.starts_with("--")
+                    && arg.contains('v')
+                    && arg[1..].chars().all(|c| SWITCHES.contains(c)))
+        })
+}
+
+/// Short flags that take no value, so can be grouped like `-Vv`
+const SWITCHES: &str = "hinqrvV";
+
+/// What is needed to reproduce a bug report: the libraries, the `git` on `PATH`, and the config
+/// in use
+pub fn verbose_version() -> proc_exit::ExitResult {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    // clap already printed the name and version, without a newline
+    writeln!(stdout)?;
+    writeln!(
+        stdout,
+        "platform: {}-{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS
+    )?;
+    writeln!(stdout, "libgit2: {}", libgit2_version())?;
+    writeln!(stdout, "git: {}", git_version())?;
+
+    let cwd = std::env::current_dir()?;
+    let repo = git2::Repository::discover(&cwd).ok();
+    writeln!(stdout, "config:")?;
+    for (scope, path) in git_stack::config::RepoConfig::sources(repo.as_ref()) {
+        let missing = if path.exists() { "" } else { " (missing)" };
+        writeln!(stdout, "  {}: {}{}", scope, path.display(), missing)?;
+    }
+    if repo.is_none() {
+        writeln!(stdout, "  (not in a repository)")?;
+    }
+
+    Ok(())
+}
+
+fn libgit2_version() -> String {
+    let version = git2::Version::get();
+    let (major, minor, rev) = version.libgit2_version();
+    let features = [
+        (version.threads(), "threads"),
+        (version.https(), "https"),
+        (version.ssh(), "ssh"),
+        (version.nsec(), "nsec"),
+    ]
+    .iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, name)| *name)
+    .collect::<Vec<_>>();
+    if features.is_empty() {
+        format!("{}.{}.{}", major, minor, rev)
+    } else {
+        format!("{}.{}.{} ({})", major, minor, rev, features.join(", "))
+    }
+}
+
+fn git_version() -> String {
+    match std::process::Command::new("git").arg("--version").output() {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .trim_start_matches("git version ")
+            .to_owned(),
+        Ok(output) => format!(
+            "`git --version` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(err) => format!("not found ({})", err),
+    }
+}
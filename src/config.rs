@@ -4,27 +4,93 @@ use std::str::FromStr;
 #[serde(rename_all = "kebab-case")]
 pub struct RepoConfig {
     pub protected_branches: Option<Vec<String>>,
+    pub protected_tags: Option<Vec<String>>,
     pub stack: Option<Stack>,
+    pub all_max_age: Option<String>,
     pub push_remote: Option<String>,
     pub pull_remote: Option<String>,
+    pub push_policy: Option<PushPolicy>,
+    pub push_wip: Option<PushWip>,
     pub show_format: Option<Format>,
     pub show_stacked: Option<bool>,
+    pub show_glyphs: Option<bool>,
+    pub show_full_hashes: Option<bool>,
+    pub show_prs: Option<bool>,
+    pub show_prefetch: Option<bool>,
     pub fixup: Option<Fixup>,
+    pub rebase_merges: Option<bool>,
+    pub dedup_cherry_picks: Option<bool>,
+    pub drop_empty: Option<bool>,
+    pub exec: Option<String>,
+    pub on_conflict: Option<OnConflict>,
+    pub risk_gate: Option<RiskGate>,
+    pub quarantine_age: Option<String>,
+    pub pr_cache_age: Option<String>,
+    pub committer_date: Option<CommitterDate>,
+    pub audit_log: Option<std::path::PathBuf>,
+    pub maintenance: Option<Maintenance>,
+    pub maintenance_threshold: Option<usize>,
+    pub max_history: Option<usize>,
+    pub dirty_check: Option<DirtyCheck>,
+    pub theme: Option<Theme>,
+    pub colors: Option<std::collections::BTreeMap<String, String>>,
+    pub branch_template: Option<String>,
+    pub import_namespace: Option<String>,
+    pub allowed_emails: Option<Vec<String>>,
+    pub gitlab_hosts: Option<Vec<String>>,
+    pub crash_report: Option<bool>,
 
     pub capacity: Option<usize>,
 }
 
 static PROTECTED_STACK_FIELD: &str = "stack.protected-branch";
+static PROTECTED_TAG_FIELD: &str = "stack.protected-tag";
 static STACK_FIELD: &str = "stack.stack";
+static ALL_MAX_AGE_FIELD: &str = "stack.all-max-age";
 static PUSH_REMOTE_FIELD: &str = "stack.push-remote";
 static PULL_REMOTE_FIELD: &str = "stack.pull-remote";
+static PUSH_POLICY_FIELD: &str = "stack.push-policy";
+static PUSH_WIP_FIELD: &str = "stack.push-wip";
 static FORMAT_FIELD: &str = "stack.show-format";
 static STACKED_FIELD: &str = "stack.show-stacked";
+static GLYPHS_FIELD: &str = "stack.show-glyphs";
+static FULL_HASHES_FIELD: &str = "stack.show-full-hashes";
+static PRS_FIELD: &str = "stack.show-prs";
+static PREFETCH_FIELD: &str = "stack.show-prefetch";
 static FIXUP_FIELD: &str = "stack.fixup";
+static REBASE_MERGES_FIELD: &str = "stack.rebase-merges";
+static DEDUP_CHERRY_PICKS_FIELD: &str = "stack.dedup-cherry-picks";
+static DROP_EMPTY_FIELD: &str = "stack.drop-empty";
+static EXEC_FIELD: &str = "stack.exec";
+static ON_CONFLICT_FIELD: &str = "stack.on-conflict";
+static RISK_GATE_FIELD: &str = "stack.risk-gate";
+static QUARANTINE_AGE_FIELD: &str = "stack.quarantine-age";
+static PR_CACHE_AGE_FIELD: &str = "stack.pr-cache-age";
+static COMMITTER_DATE_FIELD: &str = "stack.committer-date";
+static AUDIT_LOG_FIELD: &str = "stack.audit-log";
+static MAINTENANCE_FIELD: &str = "stack.maintenance";
+static MAINTENANCE_THRESHOLD_FIELD: &str = "stack.maintenance-threshold";
+static MAX_HISTORY_FIELD: &str = "stack.max-history";
+static DIRTY_CHECK_FIELD: &str = "stack.dirty-check";
+static THEME_FIELD: &str = "stack.theme";
+static COLOR_PREFIX: &str = "stack.color.";
+static BRANCH_TEMPLATE_FIELD: &str = "stack.branch-template";
+static IMPORT_NAMESPACE_FIELD: &str = "stack.import-namespace";
+static ALLOWED_EMAIL_FIELD: &str = "stack.allowed-email";
+static GITLAB_HOST_FIELD: &str = "stack.gitlab-host";
+static CRASH_REPORT_FIELD: &str = "stack.crash-report";
 static BACKUP_CAPACITY_FIELD: &str = "branch-stash.capacity";
 
 static DEFAULT_PROTECTED_BRANCHES: [&str; 4] = ["main", "master", "dev", "stable"];
 const DEFAULT_CAPACITY: usize = 30;
+const DEFAULT_MAINTENANCE_THRESHOLD: usize = 500;
+const DEFAULT_ALL_MAX_AGE: &str = "90d";
+const DEFAULT_QUARANTINE_AGE: &str = "14d";
+const DEFAULT_PR_CACHE_AGE: &str = "5m";
+const DEFAULT_IMPORT_NAMESPACE: &str = "review/{user}";
+
+/// Roles in the palette that `stack.color.<role>` can override
+pub static COLOR_ROLES: [&str; 6] = ["error", "warn", "info", "good", "highlight", "hint"];
 
 impl RepoConfig {
     pub fn from_all(repo: &git2::Repository) -> eyre::Result<Self> {
@@ -48,6 +114,27 @@ impl RepoConfig {
         Ok(config)
     }
 
+    /// The files `from_all` reads, from lowest to highest precedence, labeled by scope
+    pub fn sources(repo: Option<&git2::Repository>) -> Vec<(&'static str, std::path::PathBuf)> {
+        let mut sources = Vec::new();
+        for (scope, path) in [
+            ("system", git2::Config::find_system()),
+            ("xdg", git2::Config::find_xdg()),
+            ("global", git2::Config::find_global()),
+        ] {
+            if let Ok(path) = path {
+                sources.push((scope, path));
+            }
+        }
+        if let Some(repo) = repo {
+            if let Some(workdir) = repo.workdir() {
+                sources.push(("workdir", workdir.join(".gitconfig")));
+            }
+            sources.push(("repo", git_dir_config(repo)));
+        }
+        sources
+    }
+
     pub fn from_repo(repo: &git2::Repository) -> eyre::Result<Self> {
         let config_path = git_dir_config(repo);
         log::trace!("Loading {}", config_path.display());
@@ -111,10 +198,21 @@ impl RepoConfig {
                         .get_or_insert_with(Vec::new)
                         .push(value.into_owned());
                 }
+            } else if key == PROTECTED_TAG_FIELD {
+                if let Some(value) = value {
+                    config
+                        .protected_tags
+                        .get_or_insert_with(Vec::new)
+                        .push(value.into_owned());
+                }
             } else if key == STACK_FIELD {
                 if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
                     config.stack = Some(value);
                 }
+            } else if key == ALL_MAX_AGE_FIELD {
+                config.all_max_age = value
+                    .filter(|v| parse_age(v).is_some())
+                    .map(|v| v.into_owned());
             } else if key == PUSH_REMOTE_FIELD {
                 if let Some(value) = value {
                     config.push_remote = Some(value.into_owned());
@@ -129,10 +227,109 @@ impl RepoConfig {
                 }
             } else if key == STACKED_FIELD {
                 config.show_stacked = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == GLYPHS_FIELD {
+                config.show_glyphs = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == FULL_HASHES_FIELD {
+                config.show_full_hashes = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == PRS_FIELD {
+                config.show_prs = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == PREFETCH_FIELD {
+                config.show_prefetch = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == PUSH_POLICY_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.push_policy = Some(value);
+                }
+            } else if key == PUSH_WIP_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.push_wip = Some(value);
+                }
             } else if key == FIXUP_FIELD {
                 if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
                     config.fixup = Some(value);
                 }
+            } else if key == REBASE_MERGES_FIELD {
+                config.rebase_merges = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == DEDUP_CHERRY_PICKS_FIELD {
+                config.dedup_cherry_picks =
+                    Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == DROP_EMPTY_FIELD {
+                config.drop_empty = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == EXEC_FIELD {
+                if let Some(value) = value {
+                    config.exec = Some(value.into_owned());
+                }
+            } else if key == ON_CONFLICT_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.on_conflict = Some(value);
+                }
+            } else if key == RISK_GATE_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.risk_gate = Some(value);
+                }
+            } else if key == QUARANTINE_AGE_FIELD {
+                config.quarantine_age = value
+                    .filter(|v| parse_age(v).is_some())
+                    .map(|v| v.into_owned());
+            } else if key == PR_CACHE_AGE_FIELD {
+                config.pr_cache_age = value
+                    .filter(|v| parse_age(v).is_some())
+                    .map(|v| v.into_owned());
+            } else if key == COMMITTER_DATE_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.committer_date = Some(value);
+                }
+            } else if key == AUDIT_LOG_FIELD {
+                if let Some(value) = value {
+                    config.audit_log = Some(std::path::PathBuf::from(value.into_owned()));
+                }
+            } else if key == MAINTENANCE_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.maintenance = Some(value);
+                }
+            } else if key == MAINTENANCE_THRESHOLD_FIELD {
+                config.maintenance_threshold =
+                    value.as_deref().and_then(|s| s.parse::<usize>().ok());
+            } else if key == MAX_HISTORY_FIELD {
+                config.max_history = value.as_deref().and_then(|s| s.parse::<usize>().ok());
+            } else if key == DIRTY_CHECK_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.dirty_check = Some(value);
+                }
+            } else if key == THEME_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.theme = Some(value);
+                }
+            } else if let Some(role) = color_role(&key) {
+                if let Some(value) = value {
+                    config
+                        .colors
+                        .get_or_insert_with(Default::default)
+                        .insert(role.to_owned(), value.into_owned());
+                }
+            } else if key == BRANCH_TEMPLATE_FIELD {
+                if let Some(value) = value {
+                    config.branch_template = Some(value.into_owned());
+                }
+            } else if key == IMPORT_NAMESPACE_FIELD {
+                if let Some(value) = value {
+                    config.import_namespace = Some(value.into_owned());
+                }
+            } else if key == CRASH_REPORT_FIELD {
+                config.crash_report = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == ALLOWED_EMAIL_FIELD {
+                if let Some(value) = value {
+                    config
+                        .allowed_emails
+                        .get_or_insert_with(Vec::new)
+                        .push(value.into_owned());
+                }
+            } else if key == GITLAB_HOST_FIELD {
+                if let Some(value) = value {
+                    config
+                        .gitlab_hosts
+                        .get_or_insert_with(Vec::new)
+                        .push(value.into_owned());
+                }
             } else if key == BACKUP_CAPACITY_FIELD {
                 config.capacity = value.as_deref().and_then(|s| s.parse::<usize>().ok());
             } else {
@@ -200,6 +397,22 @@ impl RepoConfig {
             })
             .unwrap_or(None);
 
+        let protected_tags = config
+            .multivar(PROTECTED_TAG_FIELD, None)
+            .map(|entries| {
+                let entries_ref = &entries;
+                let protected_tags: Vec<_> = entries_ref
+                    .flat_map(|e| e.into_iter())
+                    .filter_map(|e| e.value().map(|v| v.to_owned()))
+                    .collect();
+                if protected_tags.is_empty() {
+                    None
+                } else {
+                    Some(protected_tags)
+                }
+            })
+            .unwrap_or(None);
+
         let push_remote = config.get_string(PUSH_REMOTE_FIELD).ok();
         let pull_remote = config.get_string(PULL_REMOTE_FIELD).ok();
 
@@ -208,17 +421,132 @@ impl RepoConfig {
             .ok()
             .and_then(|s| FromStr::from_str(s).ok());
 
+        let all_max_age = config
+            .get_string(ALL_MAX_AGE_FIELD)
+            .ok()
+            .filter(|s| parse_age(s).is_some());
+
         let show_format = config
             .get_str(FORMAT_FIELD)
             .ok()
             .and_then(|s| FromStr::from_str(s).ok());
 
         let show_stacked = config.get_bool(STACKED_FIELD).ok();
+        let show_glyphs = config.get_bool(GLYPHS_FIELD).ok();
+        let show_full_hashes = config.get_bool(FULL_HASHES_FIELD).ok();
+        let show_prs = config.get_bool(PRS_FIELD).ok();
+        let show_prefetch = config.get_bool(PREFETCH_FIELD).ok();
+
+        let push_policy = config
+            .get_string(PUSH_POLICY_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+        let push_wip = config
+            .get_string(PUSH_WIP_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
 
         let fixup = config
             .get_str(FIXUP_FIELD)
             .ok()
             .and_then(|s| FromStr::from_str(s).ok());
+        let rebase_merges = config.get_bool(REBASE_MERGES_FIELD).ok();
+        let dedup_cherry_picks = config.get_bool(DEDUP_CHERRY_PICKS_FIELD).ok();
+        let drop_empty = config.get_bool(DROP_EMPTY_FIELD).ok();
+        let exec = config.get_string(EXEC_FIELD).ok();
+        let on_conflict = config
+            .get_string(ON_CONFLICT_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+        let risk_gate = config
+            .get_string(RISK_GATE_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+        let quarantine_age = config
+            .get_string(QUARANTINE_AGE_FIELD)
+            .ok()
+            .filter(|s| parse_age(s).is_some());
+        let pr_cache_age = config
+            .get_string(PR_CACHE_AGE_FIELD)
+            .ok()
+            .filter(|s| parse_age(s).is_some());
+        let committer_date = config
+            .get_string(COMMITTER_DATE_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+
+        let audit_log = config.get_path(AUDIT_LOG_FIELD).ok();
+
+        let maintenance = config
+            .get_string(MAINTENANCE_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+        let maintenance_threshold = config
+            .get_i64(MAINTENANCE_THRESHOLD_FIELD)
+            .map(|i| i as usize)
+            .ok();
+        let max_history = config.get_i64(MAX_HISTORY_FIELD).map(|i| i as usize).ok();
+        let dirty_check = config
+            .get_string(DIRTY_CHECK_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+
+        let theme = config
+            .get_string(THEME_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+        let colors = config
+            .entries(Some(r"stack\.color\..*"))
+            .map(|entries| {
+                let mut colors = std::collections::BTreeMap::new();
+                let entries_ref = &entries;
+                for entry in entries_ref.flat_map(|e| e.into_iter()) {
+                    if let (Some(role), Some(value)) =
+                        (entry.name().and_then(color_role), entry.value())
+                    {
+                        colors.insert(role.to_owned(), value.to_owned());
+                    }
+                }
+                colors
+            })
+            .ok()
+            .filter(|colors| !colors.is_empty());
+
+        let branch_template = config.get_string(BRANCH_TEMPLATE_FIELD).ok();
+        let import_namespace = config.get_string(IMPORT_NAMESPACE_FIELD).ok();
+        let crash_report = config.get_bool(CRASH_REPORT_FIELD).ok();
+
+        let allowed_emails = config
+            .multivar(ALLOWED_EMAIL_FIELD, None)
+            .map(|entries| {
+                let entries_ref = &entries;
+                let allowed_emails: Vec<_> = entries_ref
+                    .flat_map(|e| e.into_iter())
+                    .filter_map(|e| e.value().map(|v| v.to_owned()))
+                    .collect();
+                if allowed_emails.is_empty() {
+                    None
+                } else {
+                    Some(allowed_emails)
+                }
+            })
+            .unwrap_or(None);
+
+        let gitlab_hosts = config
+            .multivar(GITLAB_HOST_FIELD, None)
+            .map(|entries| {
+                let entries_ref = &entries;
+                let gitlab_hosts: Vec<_> = entries_ref
+                    .flat_map(|e| e.into_iter())
+                    .filter_map(|e| e.value().map(|v| v.to_owned()))
+                    .collect();
+                if gitlab_hosts.is_empty() {
+                    None
+                } else {
+                    Some(gitlab_hosts)
+                }
+            })
+            .unwrap_or(None);
 
         let capacity = config
             .get_i64(BACKUP_CAPACITY_FIELD)
@@ -227,12 +555,41 @@ impl RepoConfig {
 
         Self {
             protected_branches,
+            protected_tags,
             push_remote,
             pull_remote,
             stack,
+            all_max_age,
             show_format,
             show_stacked,
+            show_glyphs,
+            show_full_hashes,
+            show_prs,
+            show_prefetch,
+            push_policy,
+            push_wip,
             fixup,
+            rebase_merges,
+            dedup_cherry_picks,
+            drop_empty,
+            exec,
+            on_conflict,
+            risk_gate,
+            quarantine_age,
+            pr_cache_age,
+            committer_date,
+            audit_log,
+            maintenance,
+            maintenance_threshold,
+            max_history,
+            dirty_check,
+            theme,
+            colors,
+            branch_template,
+            import_namespace,
+            allowed_emails,
+            gitlab_hosts,
+            crash_report,
 
             capacity,
         }
@@ -264,12 +621,58 @@ impl RepoConfig {
             (None, Some(rhs)) => self.protected_branches = Some(rhs),
             (_, _) => (),
         }
+        match (&mut self.protected_tags, other.protected_tags) {
+            (Some(lhs), Some(rhs)) => lhs.extend(rhs),
+            (None, Some(rhs)) => self.protected_tags = Some(rhs),
+            (_, _) => (),
+        }
 
         self.push_remote = other.push_remote.or(self.push_remote);
         self.pull_remote = other.pull_remote.or(self.pull_remote);
         self.stack = other.stack.or(self.stack);
+        self.all_max_age = other.all_max_age.or(self.all_max_age);
         self.show_format = other.show_format.or(self.show_format);
         self.show_stacked = other.show_stacked.or(self.show_stacked);
+        self.show_glyphs = other.show_glyphs.or(self.show_glyphs);
+        self.show_full_hashes = other.show_full_hashes.or(self.show_full_hashes);
+        self.show_prs = other.show_prs.or(self.show_prs);
+        self.show_prefetch = other.show_prefetch.or(self.show_prefetch);
+        self.push_policy = other.push_policy.or(self.push_policy);
+        self.push_wip = other.push_wip.or(self.push_wip);
+        self.fixup = other.fixup.or(self.fixup);
+        self.rebase_merges = other.rebase_merges.or(self.rebase_merges);
+        self.dedup_cherry_picks = other.dedup_cherry_picks.or(self.dedup_cherry_picks);
+        self.drop_empty = other.drop_empty.or(self.drop_empty);
+        self.exec = other.exec.or(self.exec);
+        self.on_conflict = other.on_conflict.or(self.on_conflict);
+        self.risk_gate = other.risk_gate.or(self.risk_gate);
+        self.quarantine_age = other.quarantine_age.or(self.quarantine_age);
+        self.pr_cache_age = other.pr_cache_age.or(self.pr_cache_age);
+        self.committer_date = other.committer_date.or(self.committer_date);
+        self.audit_log = other.audit_log.or(self.audit_log);
+        self.maintenance = other.maintenance.or(self.maintenance);
+        self.maintenance_threshold = other.maintenance_threshold.or(self.maintenance_threshold);
+        self.max_history = other.max_history.or(self.max_history);
+        self.dirty_check = other.dirty_check.or(self.dirty_check);
+        self.theme = other.theme.or(self.theme);
+        match (&mut self.colors, other.colors) {
+            (Some(lhs), Some(rhs)) => lhs.extend(rhs),
+            (None, Some(rhs)) => self.colors = Some(rhs),
+            (_, _) => (),
+        }
+        self.branch_template = other.branch_template.or(self.branch_template);
+        self.import_namespace = other.import_namespace.or(self.import_namespace);
+        self.crash_report = other.crash_report.or(self.crash_report);
+        match (&mut self.allowed_emails, other.allowed_emails) {
+            (Some(lhs), Some(rhs)) => lhs.extend(rhs),
+            (None, Some(rhs)) => self.allowed_emails = Some(rhs),
+            (_, _) => (),
+        }
+        match (&mut self.gitlab_hosts, other.gitlab_hosts) {
+            (Some(lhs), Some(rhs)) => lhs.extend(rhs),
+            (None, Some(rhs)) => self.gitlab_hosts = Some(rhs),
+            (_, _) => (),
+        }
         self.capacity = other.capacity.or(self.capacity);
 
         self
@@ -279,6 +682,10 @@ impl RepoConfig {
         self.protected_branches.as_deref().unwrap_or(&[])
     }
 
+    pub fn protected_tags(&self) -> &[String] {
+        self.protected_tags.as_deref().unwrap_or(&[])
+    }
+
     pub fn push_remote(&self) -> &str {
         self.push_remote.as_deref().unwrap_or("origin")
     }
@@ -301,10 +708,177 @@ impl RepoConfig {
         self.show_stacked.unwrap_or(true)
     }
 
+    pub fn show_glyphs(&self) -> bool {
+        self.show_glyphs.unwrap_or(false)
+    }
+
+    pub fn show_full_hashes(&self) -> bool {
+        self.show_full_hashes.unwrap_or(false)
+    }
+
+    pub fn show_prs(&self) -> bool {
+        self.show_prs.unwrap_or(false)
+    }
+
+    pub fn show_prefetch(&self) -> bool {
+        self.show_prefetch.unwrap_or(false)
+    }
+
+    pub fn push_policy(&self) -> PushPolicy {
+        self.push_policy.unwrap_or(PushPolicy::Bottom)
+    }
+
+    pub fn push_wip(&self) -> PushWip {
+        self.push_wip.unwrap_or(PushWip::Never)
+    }
+
     pub fn fixup(&self) -> Fixup {
         self.fixup.unwrap_or_else(Default::default)
     }
 
+    /// Recreate merge commits on top of the rewritten stack, rather than flattening them
+    pub fn rebase_merges(&self) -> bool {
+        self.rebase_merges.unwrap_or(false)
+    }
+
+    /// Share one copy of a commit cherry-picked onto sibling branches when restacking
+    pub fn dedup_cherry_picks(&self) -> bool {
+        self.dedup_cherry_picks.unwrap_or(true)
+    }
+
+    /// Drop commits that become empty when restacked, like `git rebase`
+    pub fn drop_empty(&self) -> bool {
+        self.drop_empty.unwrap_or(true)
+    }
+
+    /// Command to run on each restacked branch, like `git rebase --exec`
+    pub fn exec(&self) -> Option<&str> {
+        self.exec
+            .as_deref()
+            .filter(|command| !command.trim().is_empty())
+    }
+
+    /// What to do when a cherry-pick conflicts
+    pub fn on_conflict(&self) -> OnConflict {
+        self.on_conflict.unwrap_or(OnConflict::Stop)
+    }
+
+    /// Which risky changes to ask about or refuse, before applying them
+    pub fn risk_gate(&self) -> RiskGate {
+        self.risk_gate.unwrap_or(RiskGate::Off)
+    }
+
+    /// How long deleted branches stay restorable before being purged, if they are kept at all
+    pub fn quarantine_age(&self) -> Option<std::time::Duration> {
+        let age = self
+            .quarantine_age
+            .as_deref()
+            .unwrap_or(DEFAULT_QUARANTINE_AGE);
+        parse_age(age).filter(|age| *age != std::time::Duration::from_secs(0))
+    }
+
+    /// How long the PRs looked up for `stack.show-prs` are reused before looking them up again, if
+    /// at all
+    pub fn pr_cache_age(&self) -> Option<std::time::Duration> {
+        let age = self.pr_cache_age.as_deref().unwrap_or(DEFAULT_PR_CACHE_AGE);
+        parse_age(age).filter(|age| *age != std::time::Duration::from_secs(0))
+    }
+
+    /// What committer date to give rewritten commits
+    pub fn committer_date(&self) -> CommitterDate {
+        self.committer_date.unwrap_or(CommitterDate::Now)
+    }
+
+    pub fn audit_log(&self) -> Option<&std::path::Path> {
+        self.audit_log.as_deref()
+    }
+
+    /// How recent a branch's commit must be to include it in `stack.stack = all`, if limited
+    pub fn all_max_age(&self) -> Option<std::time::Duration> {
+        let age = self.all_max_age.as_deref().unwrap_or(DEFAULT_ALL_MAX_AGE);
+        parse_age(age).filter(|age| *age != std::time::Duration::from_secs(0))
+    }
+
+    pub fn maintenance(&self) -> Maintenance {
+        self.maintenance.unwrap_or(Maintenance::Hint)
+    }
+
+    pub fn maintenance_threshold(&self) -> usize {
+        self.maintenance_threshold
+            .unwrap_or(DEFAULT_MAINTENANCE_THRESHOLD)
+    }
+
+    /// Most commits to graph per branch, `None` (`0`) for all of them
+    pub fn max_history(&self) -> Option<usize> {
+        self.max_history.filter(|max| *max != 0)
+    }
+
+    pub fn dirty_check(&self) -> DirtyCheck {
+        self.dirty_check.unwrap_or(DirtyCheck::TrackedOnly)
+    }
+
+    pub fn theme(&self) -> Theme {
+        self.theme.unwrap_or(Theme::Default)
+    }
+
+    /// Per-role overrides of the theme, in `git config` color syntax
+    pub fn colors(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.colors
+            .iter()
+            .flat_map(|c| c.iter())
+            .map(|(role, color)| (role.as_str(), color.as_str()))
+    }
+
+    /// Template for `git stack create`, with `{name}`, `{user}`, and `{ticket}` placeholders
+    pub fn branch_template(&self) -> &str {
+        self.branch_template.as_deref().unwrap_or("{name}")
+    }
+
+    /// Where `git stack import-bundle` puts branches, with a `{user}` placeholder for their author
+    pub fn import_namespace(&self) -> &str {
+        self.import_namespace
+            .as_deref()
+            .unwrap_or(DEFAULT_IMPORT_NAMESPACE)
+    }
+
+    pub fn allowed_emails(&self) -> &[String] {
+        self.allowed_emails.as_deref().unwrap_or(&[])
+    }
+
+    /// Self-hosted GitLab instances, on top of `gitlab.com`
+    pub fn gitlab_hosts(&self) -> &[String] {
+        self.gitlab_hosts.as_deref().unwrap_or(&[])
+    }
+
+    /// Write a diagnostic bundle when `git stack` panics
+    pub fn crash_report(&self) -> bool {
+        self.crash_report.unwrap_or(false)
+    }
+
+    /// Just `stack.crash-report`, without loading the rest of the config
+    pub fn crash_report_enabled(repo: &git2::Repository) -> bool {
+        if let Some(enabled) = Self::from_env().crash_report {
+            return enabled;
+        }
+        // Lowest to highest precedence, like `from_all`
+        let default_config = git2::Config::open_default().ok();
+        let workdir_config = repo
+            .workdir()
+            .map(|workdir| workdir.join(".gitconfig"))
+            .filter(|path| path.exists())
+            .and_then(|path| git2::Config::open(&path).ok());
+        let repo_config = repo
+            .config()
+            .and_then(|config| config.open_level(git2::ConfigLevel::Local))
+            .ok();
+        [default_config, workdir_config, repo_config]
+            .iter()
+            .rev()
+            .flatten()
+            .find_map(|config| config.get_bool(CRASH_REPORT_FIELD).ok())
+            .unwrap_or(false)
+    }
+
     pub fn capacity(&self) -> Option<usize> {
         let capacity = self.capacity.unwrap_or(DEFAULT_CAPACITY);
         (capacity != 0).then(|| capacity)
@@ -322,12 +896,26 @@ impl std::fmt::Display for RepoConfig {
                 branch
             )?;
         }
+        for tag in self.protected_tags() {
+            writeln!(
+                f,
+                "\t{}={}",
+                PROTECTED_TAG_FIELD.split_once(".").unwrap().1,
+                tag
+            )?;
+        }
         writeln!(
             f,
             "\t{}={}",
             STACK_FIELD.split_once(".").unwrap().1,
             self.stack()
         )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            ALL_MAX_AGE_FIELD.split_once(".").unwrap().1,
+            self.all_max_age.as_deref().unwrap_or(DEFAULT_ALL_MAX_AGE)
+        )?;
         writeln!(
             f,
             "\t{}={}",
@@ -352,12 +940,180 @@ impl std::fmt::Display for RepoConfig {
             STACKED_FIELD.split_once(".").unwrap().1,
             self.show_stacked()
         )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            GLYPHS_FIELD.split_once(".").unwrap().1,
+            self.show_glyphs()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            FULL_HASHES_FIELD.split_once(".").unwrap().1,
+            self.show_full_hashes()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            PRS_FIELD.split_once(".").unwrap().1,
+            self.show_prs()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            PREFETCH_FIELD.split_once(".").unwrap().1,
+            self.show_prefetch()
+        )?;
         writeln!(
             f,
             "\t{}={}",
             FIXUP_FIELD.split_once(".").unwrap().1,
             self.fixup()
         )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            REBASE_MERGES_FIELD.split_once(".").unwrap().1,
+            self.rebase_merges()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            DEDUP_CHERRY_PICKS_FIELD.split_once(".").unwrap().1,
+            self.dedup_cherry_picks()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            DROP_EMPTY_FIELD.split_once(".").unwrap().1,
+            self.drop_empty()
+        )?;
+        if let Some(exec) = self.exec() {
+            writeln!(f, "\t{}={}", EXEC_FIELD.split_once(".").unwrap().1, exec)?;
+        }
+        writeln!(
+            f,
+            "\t{}={}",
+            ON_CONFLICT_FIELD.split_once(".").unwrap().1,
+            self.on_conflict()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            RISK_GATE_FIELD.split_once(".").unwrap().1,
+            self.risk_gate()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            QUARANTINE_AGE_FIELD.split_once(".").unwrap().1,
+            self.quarantine_age
+                .as_deref()
+                .unwrap_or(DEFAULT_QUARANTINE_AGE)
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            PR_CACHE_AGE_FIELD.split_once(".").unwrap().1,
+            self.pr_cache_age.as_deref().unwrap_or(DEFAULT_PR_CACHE_AGE)
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            COMMITTER_DATE_FIELD.split_once(".").unwrap().1,
+            self.committer_date()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            PUSH_POLICY_FIELD.split_once(".").unwrap().1,
+            self.push_policy()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            PUSH_WIP_FIELD.split_once(".").unwrap().1,
+            self.push_wip()
+        )?;
+        if let Some(audit_log) = self.audit_log() {
+            writeln!(
+                f,
+                "\t{}={}",
+                AUDIT_LOG_FIELD.split_once(".").unwrap().1,
+                audit_log.display()
+            )?;
+        }
+        writeln!(
+            f,
+            "\t{}={}",
+            MAINTENANCE_FIELD.split_once(".").unwrap().1,
+            self.maintenance()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            MAINTENANCE_THRESHOLD_FIELD.split_once(".").unwrap().1,
+            self.maintenance_threshold()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            MAX_HISTORY_FIELD.split_once(".").unwrap().1,
+            self.max_history().unwrap_or(0)
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            DIRTY_CHECK_FIELD.split_once(".").unwrap().1,
+            self.dirty_check()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            THEME_FIELD.split_once(".").unwrap().1,
+            self.theme()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            BRANCH_TEMPLATE_FIELD.split_once(".").unwrap().1,
+            self.branch_template()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            IMPORT_NAMESPACE_FIELD.split_once(".").unwrap().1,
+            self.import_namespace()
+        )?;
+        for email in self.allowed_emails() {
+            writeln!(
+                f,
+                "\t{}={}",
+                ALLOWED_EMAIL_FIELD.split_once(".").unwrap().1,
+                email
+            )?;
+        }
+        for host in self.gitlab_hosts() {
+            writeln!(
+                f,
+                "\t{}={}",
+                GITLAB_HOST_FIELD.split_once(".").unwrap().1,
+                host
+            )?;
+        }
+        writeln!(
+            f,
+            "\t{}={}",
+            CRASH_REPORT_FIELD.split_once(".").unwrap().1,
+            self.crash_report()
+        )?;
+        if self.colors().next().is_some() {
+            let (section, subsection) = COLOR_PREFIX.trim_end_matches('.').split_once(".").unwrap();
+            writeln!(f, "[{} \"{}\"]", section, subsection)?;
+            for (role, color) in self.colors() {
+                writeln!(f, "\t{}={}", role, color)?;
+            }
+        }
         writeln!(f, "[{}]", BACKUP_CAPACITY_FIELD.split_once(".").unwrap().0)?;
         writeln!(
             f,
@@ -369,6 +1125,34 @@ impl std::fmt::Display for RepoConfig {
     }
 }
 
+fn color_role(key: &str) -> Option<&str> {
+    let role = key.strip_prefix(COLOR_PREFIX)?;
+    if COLOR_ROLES.contains(&role) {
+        Some(role)
+    } else {
+        log::warn!("Unsupported color role: {}", key);
+        None
+    }
+}
+
+/// Parse an age like `90d`, in `s`econds, `m`inutes, `h`ours, `d`ays, or `w`eeks
+pub fn parse_age(age: &str) -> Option<std::time::Duration> {
+    let age = age.trim();
+    let digits = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let (count, unit) = age.split_at(digits);
+    let count = count.parse::<u64>().ok()?;
+    let seconds = match unit.trim() {
+        "" if count == 0 => 0,
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(std::time::Duration::from_secs(count * seconds))
+}
+
 fn git_dir_config(repo: &git2::Repository) -> std::path::PathBuf {
     repo.path().join("config")
 }
@@ -427,3 +1211,119 @@ impl Default for Fixup {
         Fixup::Move
     }
 }
+
+arg_enum! {
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum PushPolicy {
+        Bottom,
+        Ready,
+    }
+}
+
+arg_enum! {
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum PushWip {
+        Never,
+        Allow,
+        Prompt,
+    }
+}
+
+arg_enum! {
+    /// What to do when a cherry-pick conflicts
+    ///
+    /// - `Stop`: check the conflict out into the working tree to resolve and `--continue`
+    /// - `Abort`: fail the branch and its dependents, restacking the rest
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum OnConflict {
+        Stop,
+        Abort,
+    }
+}
+
+arg_enum! {
+    /// What to do before applying changes, by how risky they are
+    ///
+    /// - `Off`: apply everything
+    /// - `Prompt`: apply low risk changes, asking about medium and high risk ones
+    /// - `Strict`: apply low risk changes, asking about medium risk ones and refusing high risk
+    ///   ones
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum RiskGate {
+        Off,
+        Prompt,
+        Strict,
+    }
+}
+
+arg_enum! {
+    /// What committer date to give rewritten commits
+    ///
+    /// - `Now`: the current time, like `git rebase`
+    /// - `Keep`: the commit's original committer date
+    /// - `Author`: the commit's author date, like `git rebase --committer-date-is-author-date`
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum CommitterDate {
+        Now,
+        Keep,
+        Author,
+    }
+}
+
+arg_enum! {
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum Maintenance {
+        Ignore,
+        Hint,
+        Run,
+    }
+}
+
+arg_enum! {
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum Theme {
+        Default,
+        Deuteranopia,
+    }
+}
+
+/// What changes keep the working tree from being rewritten
+///
+/// - `TrackedOnly`: modified or staged files, leaving untracked files be
+/// - `All`: untracked files too
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirtyCheck {
+    TrackedOnly,
+    All,
+}
+
+impl FromStr for DirtyCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("tracked-only") || s.eq_ignore_ascii_case("trackedonly") {
+            Ok(DirtyCheck::TrackedOnly)
+        } else if s.eq_ignore_ascii_case("all") {
+            Ok(DirtyCheck::All)
+        } else {
+            Err("valid values: tracked-only, all".to_owned())
+        }
+    }
+}
+
+impl std::fmt::Display for DirtyCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DirtyCheck::TrackedOnly => "tracked-only".fmt(f),
+            DirtyCheck::All => "all".fmt(f),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Attribute staged changes to the commits that last touched them, like `hg absorb`
+
+/// A staged change, with 0 lines of context
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StagedHunk {
+    pub path: std::path::PathBuf,
+    pub old_start: u32,
+    pub old_lines: u32,
+    pub new_start: u32,
+    pub new_lines: u32,
+}
+
+impl StagedHunk {
+    /// Identity that is stable as other hunks are applied, since the index doesn't change
+    fn key(&self) -> (&std::path::Path, u32, u32) {
+        (self.path.as_path(), self.new_start, self.new_lines)
+    }
+}
+
+impl std::fmt::Display for StagedHunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}",
+            self.path.display(),
+            self.new_start.max(self.old_start)
+        )
+    }
+}
+
+/// Where a staged hunk should be absorbed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Attribution {
+    Commit(git2::Oid),
+    /// The hunk can't be absorbed, with the reason why
+    Skip(String),
+}
+
+/// Find which of `candidates` last touched each staged hunk
+///
+/// Only hunks whose surrounding lines all come from a single candidate are attributed.
+pub fn attribute_staged(
+    repo: &git2::Repository,
+    head_id: git2::Oid,
+    candidates: &std::collections::HashSet<git2::Oid>,
+) -> Result<Vec<(StagedHunk, Attribution)>, git2::Error> {
+    let head_tree = repo.find_commit(head_id)?.tree()?;
+    let index = repo.index()?;
+    let diff = staged_diff(repo, &head_tree, &index)?;
+
+    let mut attributed = Vec::new();
+    for delta_index in 0..diff.deltas().len() {
+        let patch = git2::Patch::from_diff(&diff, delta_index)?;
+        let delta = diff.get_delta(delta_index).expect("index is in bounds");
+        let path = match delta.new_file().path().or_else(|| delta.old_file().path()) {
+            Some(path) => path.to_owned(),
+            None => continue,
+        };
+
+        let patch = match patch {
+            Some(patch) if !delta.flags().is_binary() => patch,
+            _ => {
+                let hunk = StagedHunk {
+                    path,
+                    old_start: 0,
+                    old_lines: 0,
+                    new_start: 0,
+                    new_lines: 0,
+                };
+                attributed.push((hunk, Attribution::Skip("binary file".to_owned())));
+                continue;
+            }
+        };
+        let blame = if delta.status() == git2::Delta::Added {
+            None
+        } else {
+            let mut opts = git2::BlameOptions::new();
+            opts.newest_commit(head_id);
+            Some(repo.blame_file(&path, Some(&mut opts))?)
+        };
+
+        for hunk_index in 0..patch.num_hunks() {
+            let (hunk, _) = patch.hunk(hunk_index)?;
+            let hunk = StagedHunk {
+                path: path.clone(),
+                old_start: hunk.old_start(),
+                old_lines: hunk.old_lines(),
+                new_start: hunk.new_start(),
+                new_lines: hunk.new_lines(),
+            };
+            let attribution = match blame.as_ref() {
+                Some(blame) => attribute_hunk(blame, &hunk, candidates),
+                None => Attribution::Skip("new file".to_owned()),
+            };
+            attributed.push((hunk, attribution));
+        }
+    }
+
+    Ok(attributed)
+}
+
+fn attribute_hunk(
+    blame: &git2::Blame<'_>,
+    hunk: &StagedHunk,
+    candidates: &std::collections::HashSet<git2::Oid>,
+) -> Attribution {
+    let lines: Vec<usize> = if hunk.old_lines == 0 {
+        // Pure insertion, so go by its neighbors (`old_start` is the line it follows)
+        let above = hunk.old_start as usize;
+        let below = above + 1;
+        [above, below]
+            .iter()
+            .copied()
+            .filter(|line| 0 < *line && *line <= blame_len(blame))
+            .collect()
+    } else {
+        let start = hunk.old_start as usize;
+        (start..start + hunk.old_lines as usize).collect()
+    };
+
+    let mut targets = lines
+        .into_iter()
+        .filter_map(|line| blame.get_line(line).map(|b| b.final_commit_id()))
+        .collect::<Vec<_>>();
+    targets.sort_unstable();
+    targets.dedup();
+    match targets.as_slice() {
+        [] => Attribution::Skip("no lines to attribute".to_owned()),
+        [target] if candidates.contains(target) => Attribution::Commit(*target),
+        [_] => Attribution::Skip("last changed outside of the stack".to_owned()),
+        _ => Attribution::Skip("changes lines from multiple commits".to_owned()),
+    }
+}
+
+fn blame_len(blame: &git2::Blame<'_>) -> usize {
+    blame
+        .iter()
+        .map(|h| h.final_start_line() + h.lines_in_hunk() - 1)
+        .max()
+        .unwrap_or(0)
+}
+
+fn staged_diff<'r>(
+    repo: &'r git2::Repository,
+    tree: &git2::Tree<'_>,
+    index: &git2::Index,
+) -> Result<git2::Diff<'r>, git2::Error> {
+    let mut opts = git2::DiffOptions::new();
+    // Without context, hunks only merge when they touch, keeping attribution precise
+    opts.context_lines(0);
+    repo.diff_tree_to_index(Some(tree), Some(index), Some(&mut opts))
+}
+
+/// Commit each target's hunks as a `fixup!` for it, on top of `head_id`
+///
+/// The index is left alone, so anything not absorbed stays staged.  Returns the new tip.
+pub fn commit_fixups(
+    repo: &git2::Repository,
+    head_id: git2::Oid,
+    fixups: &[(git2::Oid, Vec<StagedHunk>)],
+) -> Result<git2::Oid, git2::Error> {
+    let index = repo.index()?;
+    let signature = repo.signature()?;
+    let mut tip_id = head_id;
+    for (target_id, hunks) in fixups {
+        let target = repo.find_commit(*target_id)?;
+        let tip = repo.find_commit(tip_id)?;
+        let tip_tree = tip.tree()?;
+
+        // Re-diff against the tip so hunks already absorbed drop out
+        let diff = staged_diff(repo, &tip_tree, &index)?;
+        let keys: std::collections::HashSet<_> = hunks.iter().map(|h| h.key()).collect();
+        let current_path = std::cell::RefCell::new(None::<std::path::PathBuf>);
+        let mut opts = git2::ApplyOptions::new();
+        opts.delta_callback(|delta| {
+            *current_path.borrow_mut() = delta.and_then(|d| {
+                d.new_file()
+                    .path()
+                    .or_else(|| d.old_file().path())
+                    .map(ToOwned::to_owned)
+            });
+            true
+        });
+        opts.hunk_callback(|hunk| {
+            let path = current_path.borrow();
+            match (path.as_deref(), hunk) {
+                (Some(path), Some(hunk)) => {
+                    keys.contains(&(path, hunk.new_start(), hunk.new_lines()))
+                }
+                _ => false,
+            }
+        });
+        let mut fixup_index = repo.apply_to_tree(&tip_tree, &diff, Some(&mut opts))?;
+        drop(opts);
+        let fixup_tree_id = fixup_index.write_tree_to(repo)?;
+        let fixup_tree = repo.find_tree(fixup_tree_id)?;
+
+        let message = format!("fixup! {}", target.summary().unwrap_or_default());
+        tip_id = repo.commit(None, &signature, &signature, &message, &fixup_tree, &[&tip])?;
+    }
+    Ok(tip_id)
+}
@@ -70,6 +70,36 @@ impl Branches {
         self.clone()
     }
 
+    /// The branches sharing any history with `base_oid`, leaving out unrelated ones like
+    /// `gh-pages`
+    pub fn related(&self, repo: &dyn crate::git::Repo, base_oid: git2::Oid) -> Self {
+        let branches = self
+            .branches
+            .iter()
+            .filter(|(branch_oid, branch)| {
+                if repo.merge_base(**branch_oid, base_oid).is_some() {
+                    true
+                } else {
+                    let branch_name = &branch
+                        .first()
+                        .expect("we always have at least one branch")
+                        .name;
+                    log::info!(
+                        "Skipping `{}`, it shares no history with {}",
+                        branch_name,
+                        base_oid
+                    );
+                    false
+                }
+            })
+            .map(|(oid, branches)| {
+                let branches: Vec<_> = branches.to_vec();
+                (*oid, branches)
+            })
+            .collect();
+        Self { branches }
+    }
+
     pub fn descendants(&self, repo: &dyn crate::git::Repo, base_oid: git2::Oid) -> Self {
         let branches = self
             .branches
@@ -286,3 +316,39 @@ pub fn find_base<'b>(
         })
         .next()
 }
+
+/// The closest branches below `head_oid`, one per fork
+pub fn find_next<'b>(
+    repo: &dyn crate::git::Repo,
+    branches: &'b Branches,
+    head_oid: git2::Oid,
+) -> Vec<&'b crate::git::Branch> {
+    let descendant_oids: Vec<_> = branches
+        .oids()
+        .filter(|oid| *oid != head_oid && repo.merge_base(*oid, head_oid) == Some(head_oid))
+        .collect();
+    let mut next: Vec<_> = descendant_oids
+        .iter()
+        .filter(|oid| {
+            // Skip anything stacked on top of another candidate
+            !descendant_oids
+                .iter()
+                .any(|other| other != *oid && repo.merge_base(*other, **oid) == Some(*other))
+        })
+        .flat_map(|oid| branches.get(*oid).expect("oid is known to exist"))
+        .collect();
+    next.sort_by_key(|b| &b.name);
+    next
+}
+
+/// The closest branches above `head_oid`
+pub fn find_prev<'b>(
+    repo: &dyn crate::git::Repo,
+    branches: &'b Branches,
+    head_oid: git2::Oid,
+) -> &'b [crate::git::Branch] {
+    repo.commits_from(head_oid)
+        .filter(|c| c.id != head_oid)
+        .find_map(|commit| branches.get(commit.id))
+        .unwrap_or_default()
+}
@@ -1,6 +1,10 @@
-#[derive(Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(
+    Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
 pub struct Script {
     pub commands: Vec<Command>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub dependents: Vec<Script>,
 }
 
@@ -49,46 +53,536 @@ impl Script {
 
         false
     }
+
+    /// Run `command` on each branch as it is moved, like `git rebase --exec`
+    ///
+    /// Branches created at the same commit share one run.
+    pub fn exec_after_branches(&mut self, command: &str) {
+        let mut commands = Vec::with_capacity(self.commands.len());
+        for current in self.commands.drain(..) {
+            if let Command::CreateBranch(_) = current {
+                if let [.., Command::CreateBranch(_), Command::Exec(_)] = commands.as_slice() {
+                    commands.pop();
+                }
+                commands.push(current);
+                commands.push(Command::Exec(command.to_owned()));
+            } else {
+                commands.push(current);
+            }
+        }
+        self.commands = commands;
+        for dependent in self.dependents.iter_mut() {
+            dependent.exec_after_branches(command);
+        }
+    }
+
+    /// Drop the dependents that would only recreate commits where they already are, returning
+    /// the branches left as-is
+    ///
+    /// A script that changes nothing is emptied.
+    pub fn prune_up_to_date(&mut self, repo: &dyn crate::git::Repo) -> Vec<String> {
+        let mut up_to_date = Vec::new();
+        let mut marks = std::collections::HashMap::new();
+        if self.prune(repo, &mut marks, &mut up_to_date) {
+            self.commands.clear();
+        }
+        up_to_date
+    }
+
+    /// Returns whether this script and all of its dependents change nothing
+    fn prune(
+        &mut self,
+        repo: &dyn crate::git::Repo,
+        marks: &mut std::collections::HashMap<git2::Oid, Option<git2::Oid>>,
+        up_to_date: &mut Vec<String>,
+    ) -> bool {
+        // Where `HEAD` would be, if every commit so far is already in place
+        let mut head = None;
+        let mut unchanged = true;
+        for command in self.commands.iter() {
+            match command {
+                Command::SwitchCommit(id) => {
+                    head = Some(*id);
+                }
+                Command::RegisterMark(mark) => {
+                    marks.insert(*mark, head);
+                }
+                Command::SwitchMark(mark) => {
+                    head = marks.get(mark).copied().flatten();
+                }
+                Command::CherryPick(id) => {
+                    let parent_id = repo.parent_ids(*id).ok().and_then(|p| p.first().copied());
+                    head = Some(*id).filter(|_| head.is_some() && parent_id == head);
+                }
+                Command::CreateBranch(name) => {
+                    unchanged &=
+                        head.is_some() && repo.find_local_branch(name).map(|b| b.id) == head;
+                }
+                Command::Exec(_) => {}
+                Command::Squash(_) | Command::DeleteBranch(_) | Command::Break => {
+                    head = None;
+                }
+            }
+            unchanged &= head.is_some();
+        }
+
+        let mut dependents = Vec::with_capacity(self.dependents.len());
+        for mut dependent in self.dependents.drain(..) {
+            if !dependent.prune(repo, marks, up_to_date) {
+                unchanged = false;
+                dependents.push(dependent);
+            }
+        }
+        self.dependents = dependents;
+
+        if unchanged {
+            for command in self.commands.iter() {
+                if let Command::CreateBranch(name) = command {
+                    up_to_date.push(name.clone());
+                }
+            }
+        }
+        unchanged
+    }
+
+    /// Visit every command, including those of the dependents
+    pub fn walk(&self, visit: &mut dyn FnMut(&Command)) {
+        for command in self.commands.iter() {
+            visit(command);
+        }
+        for dependent in self.dependents.iter() {
+            dependent.walk(visit);
+        }
+    }
+
+    fn write_indented(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        indent: usize,
+    ) -> Result<(), std::fmt::Error> {
+        for command in self.commands.iter() {
+            writeln!(f, "{:indent$}{}", "", command, indent = indent)?;
+        }
+        for dependent in self.dependents.iter() {
+            writeln!(f, "{:indent$}dependent", "", indent = indent)?;
+            dependent.write_indented(f, indent + 4)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Script {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        self.write_indented(f, 0)
+    }
+}
+
+/// Content-addressed id for a set of scripts
+///
+/// The id only changes when the plan does, so a reviewed plan (e.g. from `--dry-run`) can be
+/// verified before being applied.
+pub fn plan_id(scripts: &[Script]) -> git2::Oid {
+    let plan = scripts.iter().map(|s| s.to_string()).collect::<String>();
+    git2::Oid::hash_object(git2::ObjectType::Blob, plan.as_bytes())
+        .expect("hashing in-memory content is infallible")
+}
+
+/// Id for approving message rewrites with `--expect-plan`, from each commit and its new message
+///
+/// Unlike `plan_id`, this doesn't hash the scripts, as they pick the reworded commits whose ids
+/// change with the committer date on every run.  Both kinds of id are checked with
+/// `matches_plan_id`.
+pub fn reword_plan_id(rewords: &[(git2::Oid, String)]) -> git2::Oid {
+    let plan = rewords
+        .iter()
+        .map(|(id, message)| format!("{}\n{}\0", id, message))
+        .collect::<String>();
+    git2::Oid::hash_object(git2::ObjectType::Blob, plan.as_bytes())
+        .expect("hashing in-memory content is infallible")
+}
+
+/// Shortest abbreviation of a plan id `matches_plan_id` accepts, like git's default `core.abbrev`
+pub const MIN_PLAN_ID_LEN: usize = 7;
+
+/// Whether `expected`, the id from a `--dry-run`, is `id` or an abbreviation of it
+///
+/// An empty or too short `expected` would approve nearly any plan, so it is an error.
+pub fn matches_plan_id(id: git2::Oid, expected: &str) -> eyre::Result<bool> {
+    if expected.len() < MIN_PLAN_ID_LEN || !expected.chars().all(|c| c.is_ascii_hexdigit()) {
+        eyre::bail!(
+            "expected a plan id of at least {} hex digits, got {:?}",
+            MIN_PLAN_ID_LEN,
+            expected
+        );
+    }
+    Ok(id.to_string().starts_with(&expected.to_ascii_lowercase()))
+}
+
+/// Rebase plan that can be saved and applied later
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Plan {
+    #[serde(with = "crate::serde_oid")]
+    pub id: git2::Oid,
+    /// The `Repo::fingerprint` of the repository the plan was made in
+    #[serde(default, rename = "repo_id", with = "crate::serde_oid::option")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<git2::Oid>,
+    pub restore_branch: String,
+    /// Where each branch the plan touches pointed when it was made
+    pub branches: Vec<PlannedBranch>,
+    pub scripts: Vec<Script>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PlannedBranch {
+    pub name: String,
+    #[serde(default, with = "crate::serde_oid::option")]
+    pub id: Option<git2::Oid>,
+}
+
+impl Plan {
+    pub fn new(repo: &dyn crate::git::Repo, scripts: Vec<Script>, restore_branch: String) -> Self {
+        let id = plan_id(&scripts);
+        let mut names = std::collections::BTreeSet::new();
+        for script in scripts.iter() {
+            script.walk(&mut |command| match command {
+                Command::CreateBranch(name) | Command::DeleteBranch(name) => {
+                    names.insert(name.clone());
+                }
+                _ => {}
+            });
+        }
+        let branches = names
+            .into_iter()
+            .map(|name| {
+                let id = repo.find_local_branch(&name).map(|b| b.id);
+                PlannedBranch { name, id }
+            })
+            .collect();
+        Self {
+            id,
+            repo: repo.fingerprint(),
+            restore_branch,
+            branches,
+            scripts,
+        }
+    }
+
+    /// Check that `repo` is still in the state the plan was made against
+    pub fn validate(&self, repo: &dyn crate::git::Repo) -> eyre::Result<()> {
+        if let (Some(expected), Some(actual)) = (self.repo, repo.fingerprint()) {
+            if expected != actual {
+                eyre::bail!(
+                    "plan {} is for another repository (`stack.repo-id` {}, not {})",
+                    self.id,
+                    expected,
+                    actual
+                );
+            }
+        }
+
+        let mut problems = Vec::new();
+
+        let actual_id = plan_id(&self.scripts);
+        if actual_id != self.id {
+            problems.push(format!("plan {} was modified (now {})", self.id, actual_id));
+        }
+
+        for branch in self.branches.iter() {
+            let current_id = repo.find_local_branch(&branch.name).map(|b| b.id);
+            if current_id != branch.id {
+                problems.push(format!(
+                    "`{}` is at {}, expected {}",
+                    branch.name,
+                    describe_id(current_id),
+                    describe_id(branch.id)
+                ));
+            }
+        }
+
+        let mut missing = std::collections::BTreeSet::new();
+        for script in self.scripts.iter() {
+            script.walk(&mut |command| match command {
+                Command::SwitchCommit(id) | Command::CherryPick(id) | Command::Squash(id)
+                    if repo.find_commit(*id).is_none() =>
+                {
+                    missing.insert(*id);
+                }
+                _ => {}
+            });
+        }
+        for id in missing {
+            problems.push(format!("commit {} is missing", id));
+        }
+
+        if !problems.is_empty() {
+            eyre::bail!(
+                "plan {} no longer applies:\n  {}",
+                self.id,
+                problems.join("\n  ")
+            );
+        }
+        Ok(())
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+fn describe_id(id: Option<git2::Oid>) -> String {
+    id.map(|id| id.to_string())
+        .unwrap_or_else(|| "nothing".to_owned())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Command {
     /// Switch to an existing commit
-    SwitchCommit(git2::Oid),
+    SwitchCommit(#[serde(with = "crate::serde_oid")] git2::Oid),
     /// Mark the current commit with an `Oid` for future reference
-    RegisterMark(git2::Oid),
+    RegisterMark(#[serde(with = "crate::serde_oid")] git2::Oid),
     /// Switch to a previously registered marked commit
-    SwitchMark(git2::Oid),
+    SwitchMark(#[serde(with = "crate::serde_oid")] git2::Oid),
     /// Cherry-pick an existing commit
-    CherryPick(git2::Oid),
+    CherryPick(#[serde(with = "crate::serde_oid")] git2::Oid),
     /// Squash a commit into prior commit.
-    Squash(git2::Oid),
+    Squash(#[serde(with = "crate::serde_oid")] git2::Oid),
     /// Mark a branch for creation at the current commit
     CreateBranch(String),
     /// Mark a branch for deletion
     DeleteBranch(String),
+    /// Check out the current commit and stop for the user to amend it
+    Break,
+    /// Run a shell command with the current commit checked out, failing the script if it fails
+    Exec(String),
+}
+
+impl Command {
+    /// Whether `--step` stops after this command
+    pub fn is_step(&self) -> bool {
+        matches!(
+            self,
+            Command::CherryPick(_)
+                | Command::Squash(_)
+                | Command::CreateBranch(_)
+                | Command::DeleteBranch(_)
+        )
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Command::SwitchCommit(oid) => write!(f, "switch-commit {}", oid),
+            Command::RegisterMark(oid) => write!(f, "register-mark {}", oid),
+            Command::SwitchMark(oid) => write!(f, "switch-mark {}", oid),
+            Command::CherryPick(oid) => write!(f, "cherry-pick {}", oid),
+            Command::Squash(oid) => write!(f, "squash {}", oid),
+            Command::CreateBranch(name) => write!(f, "create-branch {}", name),
+            Command::DeleteBranch(name) => write!(f, "delete-branch {}", name),
+            Command::Break => write!(f, "break"),
+            Command::Exec(command) => write!(f, "exec {}", command),
+        }
+    }
+}
+
+/// Work remaining after the `Executor` stopped for the user
+#[derive(Clone, Default, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Continuation {
+    #[serde(serialize_with = "serialize_marks")]
+    #[serde(deserialize_with = "deserialize_marks")]
+    marks: std::collections::HashMap<git2::Oid, git2::Oid>,
+    /// What each commit picked so far was rewritten to
+    #[serde(default)]
+    #[serde(serialize_with = "serialize_marks")]
+    #[serde(deserialize_with = "deserialize_marks")]
+    picked: std::collections::HashMap<git2::Oid, git2::Oid>,
+    scripts: Vec<Script>,
+    /// The commit that failed to cherry-pick cleanly, for the user to resolve
+    #[serde(default, with = "crate::serde_oid::option")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conflict: Option<git2::Oid>,
+}
+
+impl Continuation {
+    pub fn scripts(&self) -> &[Script] {
+        &self.scripts
+    }
+
+    pub fn conflict(&self) -> Option<git2::Oid> {
+        self.conflict
+    }
+}
+
+fn serialize_marks<S>(
+    marks: &std::collections::HashMap<git2::Oid, git2::Oid>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    use serde::Serialize;
+    let marks: std::collections::BTreeMap<_, _> = marks
+        .iter()
+        .map(|(mark, target)| (mark.to_string(), target.to_string()))
+        .collect();
+    marks.serialize(serializer)
+}
+
+fn deserialize_marks<'de, D>(
+    deserializer: D,
+) -> Result<std::collections::HashMap<git2::Oid, git2::Oid>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::Deserialize;
+    let marks = std::collections::BTreeMap::<String, String>::deserialize(deserializer)?;
+    marks
+        .into_iter()
+        .map(|(mark, target)| Ok((git2::Oid::from_str(&mark)?, git2::Oid::from_str(&target)?)))
+        .collect::<Result<_, git2::Error>>()
+        .map_err(serde::de::Error::custom)
 }
 
 pub struct Executor {
     head_oid: git2::Oid,
     marks: std::collections::HashMap<git2::Oid, git2::Oid>,
-    branches: Vec<(git2::Oid, String)>,
+    /// What each commit picked so far was rewritten to, for the other parents of merges
+    picked: std::collections::HashMap<git2::Oid, git2::Oid>,
+    branches: Vec<(git2::Oid, String, String)>,
     delete_branches: Vec<String>,
+    /// What the next branch is being rebased onto, for the reflog
+    onto: String,
+    /// The local branches pointing at each commit, kept current as branches are moved
+    branch_names: std::collections::HashMap<git2::Oid, std::collections::BTreeSet<String>>,
     dry_run: bool,
+    step: bool,
+    stage_only: bool,
+    rebase_merges: bool,
+    drop_empty: bool,
+    stop_on_conflict: bool,
     detached: bool,
+    stopped: Option<Script>,
+    conflict: Option<git2::Oid>,
+    deferred: Vec<Script>,
+    staged: Vec<(String, Option<git2::Oid>)>,
+    rewritten: usize,
+    dropped: Vec<git2::Oid>,
 }
 
 impl Executor {
     pub fn new(repo: &dyn crate::git::Repo, dry_run: bool) -> Executor {
         let head_oid = repo.head_commit().id;
+        let mut branch_names: std::collections::HashMap<_, std::collections::BTreeSet<_>> =
+            Default::default();
+        for branch in repo.local_branches() {
+            branch_names
+                .entry(branch.id)
+                .or_default()
+                .insert(branch.name);
+        }
         Self {
             head_oid,
             marks: Default::default(),
+            picked: Default::default(),
             branches: Default::default(),
             delete_branches: Default::default(),
+            onto: crate::git::todo::abbrev(repo, head_oid),
+            branch_names,
             dry_run,
+            step: false,
+            stage_only: false,
+            rebase_merges: false,
+            drop_empty: true,
+            stop_on_conflict: false,
             detached: false,
+            stopped: None,
+            conflict: None,
+            deferred: Default::default(),
+            staged: Default::default(),
+            rewritten: 0,
+            dropped: Default::default(),
+        }
+    }
+
+    /// Pick up where a stopped `Executor` left off, from the user's HEAD
+    ///
+    /// Run `Continuation::scripts` to finish.
+    pub fn resume(
+        repo: &dyn crate::git::Repo,
+        continuation: &Continuation,
+        dry_run: bool,
+    ) -> Executor {
+        let mut executor = Self::new(repo, dry_run);
+        executor.marks = continuation.marks.clone();
+        executor.picked = continuation.picked.clone();
+        executor.detached = true;
+        executor
+    }
+
+    /// Stop after each cherry-pick, squash, or branch change
+    pub fn step(&mut self, step: bool) {
+        self.step = step;
+    }
+
+    /// Create commits but only record the branch updates, see `Executor::staged`
+    pub fn stage_only(&mut self, stage_only: bool) {
+        self.stage_only = stage_only;
+    }
+
+    /// Redo merge commits on top of their new first parent, rather than cherry-picking them
+    ///
+    /// Merges are recreated onto the rewritten versions of all their parents.
+    pub fn rebase_merges(&mut self, rebase_merges: bool) {
+        self.rebase_merges = rebase_merges;
+    }
+
+    /// Drop commits that become empty when cherry-picked, like `git rebase`
+    ///
+    /// On by default.  Commits that were empty to begin with are kept.
+    pub fn drop_empty(&mut self, drop_empty: bool) {
+        self.drop_empty = drop_empty;
+    }
+
+    /// Stop at a conflicting cherry-pick, rather than failing the branch, see
+    /// `Continuation::conflict`
+    pub fn stop_on_conflict(&mut self, stop_on_conflict: bool) {
+        self.stop_on_conflict = stop_on_conflict;
+    }
+
+    /// Number of commits created by cherry-picks and squashes
+    pub fn rewritten(&self) -> usize {
+        self.rewritten
+    }
+
+    /// Commits dropped by `Executor::drop_empty`
+    pub fn dropped(&self) -> &[git2::Oid] {
+        &self.dropped
+    }
+
+    /// Branch updates held back by `Executor::stage_only`, with `None` for deletions
+    pub fn staged(&self) -> &[(String, Option<git2::Oid>)] {
+        &self.staged
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.is_some()
+    }
+
+    /// The work left, if stopped
+    pub fn continuation(&mut self) -> Option<Continuation> {
+        let stopped = self.stopped.take()?;
+        let mut scripts = vec![stopped];
+        scripts.append(&mut self.deferred);
+        scripts.retain(|s| !s.is_empty());
+        let conflict = self.conflict.take();
+        if scripts.is_empty() && conflict.is_none() {
+            // Stopped on the very last command
+            return None;
         }
+        Some(Continuation {
+            marks: std::mem::take(&mut self.marks),
+            picked: std::mem::take(&mut self.picked),
+            scripts,
+            conflict,
+        })
     }
 
     pub fn run_script<'s>(
@@ -97,15 +591,45 @@ impl Executor {
         script: &'s Script,
     ) -> Vec<(git2::Error, &'s str, Vec<&'s str>)> {
         let mut failures = Vec::new();
+        if self.is_stopped() {
+            self.deferred.push(script.clone());
+            return failures;
+        }
         let branch_name = script.branch().unwrap_or("detached");
 
         log::trace!("Applying `{}`", branch_name);
         log::trace!("Script: {:#?}", script.commands);
-        let res = script
-            .commands
-            .iter()
-            .try_for_each(|command| self.stage_single(repo, command));
+        let mut res = Ok(());
+        for (index, command) in script.commands.iter().enumerate() {
+            let stoppable = !self.dry_run && !self.stage_only;
+            res = if *command == Command::Break && stoppable {
+                self.stop(repo, script, index + 1)
+            } else if self.step && stoppable && command.is_step() {
+                self.stage_single(repo, command)
+                    .and_then(|_| self.stop(repo, script, index + 1))
+            } else {
+                self.stage_single(repo, command)
+            };
+            res = match (res, command) {
+                (Err(err), Command::CherryPick(cherry_oid))
+                    if self.stop_on_conflict
+                        && stoppable
+                        && err.code() == git2::ErrorCode::Unmerged =>
+                {
+                    log::trace!("         `{}` conflicted: {}", branch_name, err);
+                    self.conflict = Some(*cherry_oid);
+                    self.stop(repo, script, index + 1)
+                }
+                (res, _) => res,
+            };
+            if res.is_err() || self.is_stopped() {
+                break;
+            }
+        }
         match res.and_then(|_| self.commit(repo)) {
+            Ok(()) if self.is_stopped() => {
+                log::trace!("         `{}` stopped", branch_name);
+            }
             Ok(()) => {
                 log::trace!("         `{}` succeeded", branch_name);
                 for dependent in script.dependents.iter() {
@@ -141,6 +665,7 @@ impl Executor {
                 })?;
                 log::trace!("git checkout {}  # {}", oid, commit.summary);
                 self.head_oid = *oid;
+                self.onto = self.describe(repo, *oid);
             }
             Command::RegisterMark(mark_oid) => {
                 let target_oid = self.head_oid;
@@ -155,6 +680,7 @@ impl Executor {
                 let commit = repo.find_commit(oid).unwrap();
                 log::trace!("git checkout {}  # {}", oid, commit.summary);
                 self.head_oid = oid;
+                self.onto = self.describe(repo, oid);
             }
             Command::CherryPick(cherry_oid) => {
                 let cherry_commit = repo.find_commit(*cherry_oid).ok_or_else(|| {
@@ -169,10 +695,37 @@ impl Executor {
                     cherry_oid,
                     cherry_commit.summary
                 );
+                let parent_ids = if self.rebase_merges {
+                    repo.parent_ids(*cherry_oid)?
+                } else {
+                    Vec::new()
+                };
                 if self.dry_run {
                     self.head_oid = *cherry_oid;
+                } else if 1 < parent_ids.len() {
+                    // Merge in the rewritten versions of any other parents that were restacked
+                    let other_ids: Vec<_> = parent_ids[1..]
+                        .iter()
+                        .map(|id| self.picked.get(id).copied().unwrap_or(*id))
+                        .collect();
+                    self.head_oid = repo.remerge(self.head_oid, *cherry_oid, &other_ids)?;
+                    if self.head_oid != *cherry_oid {
+                        self.rewritten += 1;
+                    }
+                    self.picked.insert(*cherry_oid, self.head_oid);
                 } else {
-                    self.head_oid = repo.cherry_pick(self.head_oid, *cherry_oid)?;
+                    let picked_oid = repo.cherry_pick(self.head_oid, *cherry_oid)?;
+                    if self.drop_empty && is_empty(repo, picked_oid) && !is_empty(repo, *cherry_oid)
+                    {
+                        log::trace!("# dropping {}, it became empty", cherry_oid);
+                        self.dropped.push(*cherry_oid);
+                    } else {
+                        self.head_oid = picked_oid;
+                        if self.head_oid != *cherry_oid {
+                            self.rewritten += 1;
+                        }
+                    }
+                    self.picked.insert(*cherry_oid, self.head_oid);
                 }
             }
             Command::Squash(squash_oid) => {
@@ -191,22 +744,79 @@ impl Executor {
                 if self.dry_run {
                     self.head_oid = *squash_oid;
                 } else {
-                    self.head_oid = repo.squash(*squash_oid, self.head_oid)?;
+                    // Only meld in the changes `squash_oid` introduces, even if HEAD was rebased
+                    let picked_oid = repo.cherry_pick(self.head_oid, *squash_oid)?;
+                    self.head_oid = repo.squash(picked_oid, self.head_oid)?;
+                    self.rewritten += 1;
                 }
             }
             Command::CreateBranch(name) => {
                 let branch_oid = self.head_oid;
-                self.branches.push((branch_oid, name.to_owned()));
+                let onto = std::mem::replace(&mut self.onto, name.to_owned());
+                self.branches.push((branch_oid, name.to_owned(), onto));
             }
             Command::DeleteBranch(name) => {
                 self.delete_branches.push(name.to_owned());
             }
+            Command::Break => {
+                log::trace!("git rebase --edit-todo  # break");
+            }
+            Command::Exec(command) => {
+                // Only the branches that moved need checking
+                let unchanged = self.branches.last().filter(|(oid, name, _)| {
+                    repo.find_local_branch(name).map(|b| b.id) == Some(*oid)
+                });
+                match unchanged {
+                    Some((_, name, _)) if !self.dry_run => {
+                        log::trace!("# skipping exec {}, `{}` is unchanged", command, name);
+                    }
+                    _ => {
+                        log::trace!("exec {}", command);
+                        if !self.dry_run {
+                            self.detached = true;
+                            repo.exec(self.head_oid, command)?;
+                        }
+                    }
+                }
+            }
         }
 
         Ok(())
     }
 
+    fn stop(
+        &mut self,
+        repo: &mut dyn crate::git::Repo,
+        script: &Script,
+        resume_at: usize,
+    ) -> Result<(), git2::Error> {
+        self.commit(repo)?;
+
+        let commit = repo.find_commit(self.head_oid).unwrap();
+        log::trace!("git checkout {}  # {}", self.head_oid, commit.summary);
+        repo.switch_commit(self.head_oid)?;
+        self.detached = true;
+
+        self.stopped = Some(Script {
+            commands: script.commands[resume_at..].to_vec(),
+            dependents: script.dependents.clone(),
+        });
+        Ok(())
+    }
+
     pub fn commit(&mut self, repo: &mut dyn crate::git::Repo) -> Result<(), git2::Error> {
+        if self.stage_only {
+            for (oid, name, _) in self.branches.drain(..) {
+                log::trace!("git branch -f {} {}  # staged", name, oid);
+                self.staged.push((name, Some(oid)));
+            }
+            for name in self.delete_branches.drain(..) {
+                log::trace!("git branch -D {}  # staged", name);
+                self.staged.push((name, None));
+            }
+            return Ok(());
+        }
+
         if !self.branches.is_empty() || !self.delete_branches.is_empty() {
             // In case we are changing the branch HEAD is attached to
             if !self.dry_run {
@@ -214,13 +824,27 @@ impl Executor {
                 self.detached = true;
             }
 
-            for (oid, name) in self.branches.iter() {
+            for (oid, name, onto) in self.branches.iter() {
                 let commit = repo.find_commit(*oid).unwrap();
                 log::trace!("git checkout {}  # {}", oid, commit.summary);
                 log::trace!("git switch -c {}", name);
-                if !self.dry_run {
-                    repo.branch(name, *oid)?;
+                if self.dry_run {
+                    continue;
+                }
+                let old_oid = repo.find_local_branch(name).map(|b| b.id);
+                if old_oid == Some(*oid) {
+                    log::trace!("`{}` is unchanged", name);
+                    continue;
+                }
+                let reason = format!("rebase {} onto {}", name, onto);
+                repo.branch(name, *oid, &reason)?;
+                if let Some(old_oid) = old_oid {
+                    forget_branch(&mut self.branch_names, old_oid, name);
                 }
+                self.branch_names
+                    .entry(*oid)
+                    .or_default()
+                    .insert(name.clone());
             }
         }
         self.branches.clear();
@@ -228,7 +852,11 @@ impl Executor {
         for name in self.delete_branches.iter() {
             log::trace!("git branch -D {}", name);
             if !self.dry_run {
+                let old_oid = repo.find_local_branch(name).map(|b| b.id);
                 repo.delete_branch(name)?;
+                if let Some(old_oid) = old_oid {
+                    forget_branch(&mut self.branch_names, old_oid, name);
+                }
             }
         }
         self.delete_branches.clear();
@@ -236,6 +864,15 @@ impl Executor {
         Ok(())
     }
 
+    /// Name a commit by a branch pointing at it, falling back to its id
+    fn describe(&self, repo: &dyn crate::git::Repo, oid: git2::Oid) -> String {
+        self.branch_names
+            .get(&oid)
+            .and_then(|names| names.iter().next())
+            .cloned()
+            .unwrap_or_else(|| crate::git::todo::abbrev(repo, oid))
+    }
+
     pub fn abandon(&mut self, repo: &dyn crate::git::Repo) {
         self.branches.clear();
         self.delete_branches.clear();
@@ -260,3 +897,30 @@ impl Executor {
         Ok(())
     }
 }
+
+fn forget_branch(
+    branch_names: &mut std::collections::HashMap<git2::Oid, std::collections::BTreeSet<String>>,
+    oid: git2::Oid,
+    name: &str,
+) {
+    if let Some(names) = branch_names.get_mut(&oid) {
+        names.remove(name);
+        if names.is_empty() {
+            branch_names.remove(&oid);
+        }
+    }
+}
+
+/// Whether `oid` has the same tree as its first parent
+fn is_empty(repo: &dyn crate::git::Repo, oid: git2::Oid) -> bool {
+    let commit = match repo.find_commit(oid) {
+        Some(commit) => commit,
+        None => return false,
+    };
+    let parent = repo
+        .parent_ids(oid)
+        .ok()
+        .and_then(|ids| ids.first().copied())
+        .and_then(|id| repo.find_commit(id));
+    matches!(parent, Some(parent) if parent.tree_id == commit.tree_id)
+}
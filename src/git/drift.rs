@@ -0,0 +1,121 @@
+//! Find branches left on old versions of the branches they were stacked on, like after amending
+
+/// A branch still on top of an old version of `parent`, from before it was amended or rebased
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Drifted {
+    pub branch: crate::git::Branch,
+    pub parent: crate::git::Branch,
+    /// Where `parent` was when `branch` was started
+    pub fork_id: git2::Oid,
+}
+
+/// Where `refname` used to point, newest first
+///
+/// Where it was created is left out when it was committed on top of, since that is another
+/// branch's commit.
+pub fn past_ids(repo: &git2::Repository, refname: &str) -> Vec<git2::Oid> {
+    let reflog = match repo.reflog(refname) {
+        Ok(reflog) => reflog,
+        Err(err) => {
+            log::trace!("Could not read the reflog for {}: {}", refname, err);
+            return Vec::new();
+        }
+    };
+    let mut ids: Vec<_> = reflog
+        .iter()
+        .map(|entry| entry.id_new())
+        .filter(|id| !id.is_zero())
+        .collect();
+    if let [.., next_id, created_id] = ids.as_slice() {
+        if repo
+            .graph_descendant_of(*next_id, *created_id)
+            .unwrap_or(false)
+        {
+            ids.pop();
+        }
+    }
+    ids
+}
+
+/// Find the branches built on a commit another branch used to point at, per `past_ids`, that it no
+/// longer contains
+///
+/// `branches` are the unprotected branches, each with its `past_ids`.  Commits in the history of
+/// `protected_ids` are never considered old versions, since they landed.  A branch stacked on a
+/// drifted branch is left out, moving with the branch beneath it.
+pub fn find_drifted(
+    repo: &git2::Repository,
+    branches: &[(crate::git::Branch, Vec<git2::Oid>)],
+    protected_ids: &[git2::Oid],
+) -> Vec<Drifted> {
+    let is_ancestor = |ancestor: git2::Oid, descendant: git2::Oid| {
+        ancestor == descendant
+            || repo
+                .graph_descendant_of(descendant, ancestor)
+                .unwrap_or(false)
+    };
+
+    let mut drifted: Vec<Drifted> = Vec::new();
+    for (parent, past_ids) in branches.iter() {
+        let old_ids: Vec<_> = past_ids
+            .iter()
+            .copied()
+            .filter(|id| {
+                !is_ancestor(*id, parent.id)
+                    && !protected_ids
+                        .iter()
+                        .any(|protected_id| is_ancestor(*id, *protected_id))
+            })
+            .collect();
+        if old_ids.is_empty() {
+            continue;
+        }
+
+        for (branch, _) in branches.iter() {
+            if branch.name == parent.name || is_ancestor(parent.id, branch.id) {
+                continue;
+            }
+            let fork_id = match old_ids
+                .iter()
+                .copied()
+                .find(|id| *id != branch.id && is_ancestor(*id, branch.id))
+            {
+                Some(fork_id) => fork_id,
+                None => continue,
+            };
+            match drifted.iter_mut().find(|d| d.branch.name == branch.name) {
+                // Stay on the closest branch it was started from
+                Some(existing) => {
+                    if is_ancestor(existing.fork_id, fork_id) {
+                        existing.parent = parent.clone();
+                        existing.fork_id = fork_id;
+                    }
+                }
+                None => drifted.push(Drifted {
+                    branch: branch.clone(),
+                    parent: parent.clone(),
+                    fork_id,
+                }),
+            }
+        }
+    }
+
+    let stacked: Vec<_> = drifted
+        .iter()
+        .map(|drift| {
+            drifted.iter().any(|other| {
+                other.branch.name != drift.branch.name
+                    && other.parent.name == drift.parent.name
+                    && is_ancestor(other.branch.id, drift.branch.id)
+            })
+        })
+        .collect();
+    let mut drifted: Vec<_> = drifted
+        .into_iter()
+        .zip(stacked)
+        .filter(|(_, stacked)| !stacked)
+        .map(|(drift, _)| drift)
+        .collect();
+    drifted.sort_by(|a, b| a.branch.name.cmp(&b.branch.name));
+    drifted
+}
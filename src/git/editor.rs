@@ -0,0 +1,201 @@
+/// Editors that return in less time than this without changes likely did not wait on the user
+const NON_BLOCKING_THRESHOLD: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// The user's editor for sequence-like files (e.g. rebase todo lists) or commit messages
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Editor {
+    command: String,
+}
+
+impl Editor {
+    /// Resolve the editor the same way git does for `git rebase -i`
+    pub fn sequence_editor(config: &git2::Config) -> Self {
+        Self::resolve(
+            |name| std::env::var(name).ok(),
+            |key| config.get_string(key).ok(),
+            true,
+        )
+    }
+
+    /// Resolve the editor the same way git does for `git commit`
+    pub fn commit_editor(config: &git2::Config) -> Self {
+        Self::resolve(
+            |name| std::env::var(name).ok(),
+            |key| config.get_string(key).ok(),
+            false,
+        )
+    }
+
+    fn resolve(
+        env: impl Fn(&str) -> Option<String>,
+        config: impl Fn(&str) -> Option<String>,
+        sequence: bool,
+    ) -> Self {
+        // Blank values are skipped rather than hiding the ones after them
+        let is_set = |command: &String| !command.trim().is_empty();
+        let env = |name: &str| env(name).filter(is_set);
+        let config = |key: &str| config(key).filter(is_set);
+        let sequence_command = if sequence {
+            env("GIT_SEQUENCE_EDITOR").or_else(|| config("sequence.editor"))
+        } else {
+            None
+        };
+        let command = sequence_command
+            .or_else(|| env("GIT_EDITOR"))
+            .or_else(|| config("core.editor"))
+            .or_else(|| env("VISUAL"))
+            .or_else(|| env("EDITOR"))
+            .unwrap_or_else(|| "vi".to_owned());
+        Self { command }
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Open `path` in the editor, blocking until the user is done
+    pub fn edit(&self, path: &std::path::Path) -> eyre::Result<()> {
+        use eyre::WrapErr;
+
+        if self.command == ":" {
+            // git's convention for accepting the file as-is, e.g. for scripting
+            return Ok(());
+        }
+
+        let before = std::fs::read(path)?;
+        // Like git, defer to the shell so the command can carry its own arguments
+        let start = std::time::Instant::now();
+        let status = crate::git::shell_command(&self.command, &[path.as_os_str()])
+            .status()
+            .wrap_err_with(|| format!("could not launch `{}`", self.command))?;
+        let elapsed = start.elapsed();
+        if !status.success() {
+            eyre::bail!("`{}` exited with {}", self.command, status);
+        }
+
+        let after = std::fs::read(path)?;
+        if before == after && elapsed < NON_BLOCKING_THRESHOLD {
+            eyre::bail!(
+                "`{}` returned immediately without any changes; if it is a graphical editor, configure it to wait (e.g. `code --wait`)",
+                self.command
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Edit `text` in a file under `dir`, returning the result
+    ///
+    /// The file is only removed on success, so the user's edits survive any failure.
+    pub fn edit_text(&self, dir: &std::path::Path, name: &str, text: &str) -> eyre::Result<String> {
+        let path = dir.join(name);
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&path, text)?;
+        let edited = self
+            .edit(&path)
+            .and_then(|_| std::fs::read_to_string(&path).map_err(Into::into))
+            .map_err(|err| eyre::eyre!("{}; your edits are saved in {}", err, path.display()))?;
+        std::fs::remove_file(&path)?;
+        Ok(edited)
+    }
+}
+
+/// Clean up an edited commit message like git's default `strip` mode
+///
+/// Comment lines and trailing whitespace are removed and runs of blank lines are collapsed.
+pub fn cleanup_message(message: &str) -> String {
+    let mut cleaned = String::new();
+    let mut blank = false;
+    for line in message.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if blank && !cleaned.is_empty() {
+            cleaned.push('\n');
+        }
+        blank = false;
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+    cleaned
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn resolve(env: &[(&str, &str)], config: &[(&str, &str)], sequence: bool) -> String {
+        let lookup = |pairs: &[(&str, &str)], key: &str| {
+            pairs
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| (*v).to_owned())
+        };
+        Editor::resolve(|k| lookup(env, k), |k| lookup(config, k), sequence)
+            .command()
+            .to_owned()
+    }
+
+    #[test]
+    fn precedence() {
+        let env = [
+            ("GIT_SEQUENCE_EDITOR", "seq"),
+            ("GIT_EDITOR", "git"),
+            ("VISUAL", "visual"),
+            ("EDITOR", "editor"),
+        ];
+        let config = [("sequence.editor", "seq-config"), ("core.editor", "core")];
+        assert_eq!(resolve(&env, &config, true), "seq");
+        assert_eq!(resolve(&env[1..], &config, true), "seq-config");
+        assert_eq!(resolve(&env[1..], &config[1..], true), "git");
+        assert_eq!(resolve(&env[2..], &config[1..], true), "core");
+        assert_eq!(resolve(&env[2..], &[], true), "visual");
+        assert_eq!(resolve(&env[3..], &[], true), "editor");
+        assert_eq!(resolve(&[], &[], true), "vi");
+    }
+
+    #[test]
+    fn blank_is_unset() {
+        assert_eq!(resolve(&[("GIT_EDITOR", "  ")], &[], true), "vi");
+        let env = [("GIT_EDITOR", ""), ("VISUAL", "visual")];
+        assert_eq!(resolve(&env, &[("core.editor", " ")], true), "visual");
+        assert_eq!(resolve(&env, &[("core.editor", "core")], true), "core");
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn quick_edit_with_changes() {
+        let temp = std::env::temp_dir().join(format!("git-stack-editor-{}", std::process::id()));
+        let editor = Editor {
+            command: "printf 'edited\\n' >".to_owned(),
+        };
+        let edited = editor.edit_text(&temp, "TEST", "original\n").unwrap();
+        assert_eq!(edited, "edited\n");
+
+        let editor = Editor {
+            command: "true".to_owned(),
+        };
+        assert!(editor.edit_text(&temp, "TEST", "original\n").is_err());
+        std::fs::remove_dir_all(&temp).unwrap();
+    }
+
+    #[test]
+    fn commit_precedence() {
+        let env = [("GIT_SEQUENCE_EDITOR", "seq"), ("GIT_EDITOR", "git")];
+        let config = [("sequence.editor", "seq-config"), ("core.editor", "core")];
+        assert_eq!(resolve(&env, &config, false), "git");
+        assert_eq!(resolve(&env[..1], &config, false), "core");
+    }
+
+    #[test]
+    fn cleanup() {
+        let message = "\nSummary  \n\n\n# comment\nBody\n\n# trailing\n";
+        assert_eq!(cleanup_message(message), "Summary\n\nBody\n");
+        assert_eq!(cleanup_message("# only comments\n\n"), "");
+    }
+}
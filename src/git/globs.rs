@@ -0,0 +1,61 @@
+#[derive(Clone, Debug)]
+pub struct BranchGlobs {
+    globs: ignore::gitignore::Gitignore,
+}
+
+impl BranchGlobs {
+    pub fn new<'p>(patterns: impl IntoIterator<Item = &'p str>) -> eyre::Result<Self> {
+        let mut globs = ignore::gitignore::GitignoreBuilder::new("");
+        for pattern in patterns {
+            globs.add_line(None, pattern)?;
+        }
+        let globs = globs.build()?;
+        Ok(Self { globs })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.globs.is_empty()
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let name_match = self.globs.matched_path_or_any_parents(name, false);
+        match name_match {
+            ignore::Match::None => false,
+            ignore::Match::Ignore(glob) => {
+                log::trace!("{}: matched {:?}", name, glob.original());
+                true
+            }
+            ignore::Match::Whitelist(glob) => {
+                log::trace!("{}: excluded by {:?}", name, glob.original());
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty_matches_nothing() {
+        let globs = BranchGlobs::new(None).unwrap();
+        assert!(globs.is_empty());
+        assert!(!globs.matches("feature"));
+    }
+
+    #[test]
+    fn glob() {
+        let globs = BranchGlobs::new(Some("feature-*")).unwrap();
+        assert!(globs.matches("feature-1"));
+        assert!(!globs.matches("fix-1"));
+    }
+
+    #[test]
+    fn folders() {
+        let globs = BranchGlobs::new(vec!["epage/", "!epage/old"]).unwrap();
+        assert!(globs.matches("epage/feature"));
+        assert!(!globs.matches("epage/old"));
+        assert!(!globs.matches("epage"));
+    }
+}
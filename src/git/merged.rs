@@ -0,0 +1,58 @@
+//! Find branches whose work has landed
+
+use super::Repo;
+
+/// Whether everything `branch` adds on top of `onto` is in `onto`, either as-is or as
+/// patch-identical copies
+///
+/// A branch `onto` already contains only counts when it had commits of its own, so one just
+/// created, or only ever restacked, is kept.
+pub fn is_merged(
+    repo: &crate::git::GitRepo,
+    onto: &crate::git::Branch,
+    branch: &crate::git::Branch,
+) -> bool {
+    let base_id = match repo.merge_base(onto.id, branch.id) {
+        Some(base_id) => base_id,
+        None => return false,
+    };
+    if base_id == branch.id {
+        return had_own_commits(repo, onto, branch);
+    }
+
+    let upstream: std::collections::HashSet<_> = repo
+        .commits_from(onto.id)
+        .take_while(|c| c.id != base_id)
+        .filter_map(|c| Repo::patch_id(repo, c.id))
+        .collect();
+    repo.commits_from(branch.id)
+        .take_while(|c| c.id != base_id)
+        .all(|c| matches!(Repo::patch_id(repo, c.id), Some(p) if upstream.contains(&p)))
+}
+
+/// Whether `branch`'s reflog shows it at a commit of its own, rather than only where `onto` was
+fn had_own_commits(
+    repo: &crate::git::GitRepo,
+    onto: &crate::git::Branch,
+    branch: &crate::git::Branch,
+) -> bool {
+    let raw = repo.raw();
+    let reflog = match raw.reflog(&repo.branch_ref(&branch.name)) {
+        Ok(reflog) => reflog,
+        Err(err) => {
+            log::trace!("Could not read the reflog for {}: {}", branch.name, err);
+            return false;
+        }
+    };
+    let mut onto_ids: std::collections::HashSet<_> = raw
+        .reflog(&repo.branch_ref(&onto.name))
+        .map(|reflog| reflog.iter().map(|entry| entry.id_new()).collect())
+        .unwrap_or_default();
+    onto_ids.insert(onto.id);
+
+    // Skipping the oldest entry, where the branch was created
+    reflog
+        .iter()
+        .take(reflog.len().saturating_sub(1))
+        .any(|entry| !onto_ids.contains(&entry.id_new()))
+}
@@ -1,9 +1,35 @@
+mod absorb;
 mod branches;
 mod commands;
+mod drift;
+mod editor;
+mod globs;
+mod merged;
+mod orphans;
 mod protect;
 mod repo;
+mod risk;
+mod shell;
+mod sign;
+mod substitute;
+mod todo;
+mod trailers;
+mod url;
 
+pub use absorb::*;
 pub use branches::*;
 pub use commands::*;
+pub use drift::*;
+pub use editor::*;
+pub use globs::*;
+pub use merged::*;
+pub use orphans::*;
 pub use protect::*;
 pub use repo::*;
+pub use risk::*;
+pub use shell::*;
+pub use sign::*;
+pub use substitute::*;
+pub use todo::*;
+pub use trailers::*;
+pub use url::*;
@@ -0,0 +1,156 @@
+//! Find work no branch reaches anymore, like after an interrupted rewrite
+
+/// Commits only reachable from a reflog or backup
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Orphan {
+    /// Where the newest commit was found, like `HEAD@{3}`
+    pub source: String,
+    /// The orphaned commits, oldest first
+    pub ids: Vec<git2::Oid>,
+    /// The commit they are on top of, if any
+    pub base_id: Option<git2::Oid>,
+}
+
+impl Orphan {
+    pub fn tip_id(&self) -> git2::Oid {
+        *self.ids.last().expect("orphans have at least one commit")
+    }
+}
+
+/// Every commit recorded in the reflogs of `HEAD` and `refs`, newest first
+///
+/// Each is named like `HEAD@{3}`, after its reflog entry.
+pub fn reflog_candidates(
+    repo: &git2::Repository,
+    refs: impl IntoIterator<Item = String>,
+) -> Vec<(String, git2::Oid)> {
+    let mut candidates = Vec::new();
+    for name in std::iter::once("HEAD".to_owned()).chain(refs) {
+        let reflog = match repo.reflog(&name) {
+            Ok(reflog) => reflog,
+            Err(err) => {
+                log::trace!("Could not read the reflog for {}: {}", name, err);
+                continue;
+            }
+        };
+        let short = name.strip_prefix("refs/heads/").unwrap_or(&name);
+        for (i, entry) in reflog.iter().enumerate() {
+            candidates.push((format!("{}@{{{}}}", short, i), entry.id_new()));
+        }
+    }
+    candidates
+}
+
+/// Find the commits in `candidates` that none of `branch_ids` reach
+///
+/// Commits that were rewritten, keeping their author and message, into the history between
+/// `branch_ids` and `protected_ids` are not considered lost, so orphans made up of only those are
+/// skipped.  Orphans are reported in the order of `candidates`, with the first `candidates` to
+/// find a commit naming it.
+pub fn find_orphans(
+    repo: &git2::Repository,
+    candidates: &[(String, git2::Oid)],
+    branch_ids: &[git2::Oid],
+    protected_ids: &[git2::Oid],
+) -> Result<Vec<Orphan>, git2::Error> {
+    let mut walk = repo.revwalk()?;
+    let mut pushed = false;
+    for (_, id) in candidates {
+        // Reflogs can refer to commits that were since garbage collected
+        if repo.find_commit(*id).is_ok() {
+            walk.push(*id)?;
+            pushed = true;
+        }
+    }
+    if !pushed {
+        return Ok(Vec::new());
+    }
+    for id in branch_ids {
+        walk.hide(*id)?;
+    }
+    let orphaned = walk.collect::<Result<std::collections::HashSet<_>, _>>()?;
+
+    let mut chains: Vec<(String, Vec<git2::Oid>)> = Vec::new();
+    for (source, id) in candidates {
+        if !orphaned.contains(id) || chains.iter().any(|(_, chain)| chain[0] == *id) {
+            continue;
+        }
+        let mut chain = vec![*id];
+        let mut commit = repo.find_commit(*id)?;
+        while let Ok(parent) = commit.parent(0) {
+            if !orphaned.contains(&parent.id()) {
+                break;
+            }
+            chain.push(parent.id());
+            commit = parent;
+        }
+        chains.push((source.clone(), chain));
+    }
+
+    let rewritten = rewritten_keys(repo, branch_ids, protected_ids)?;
+    let mut orphans = Vec::new();
+    for (source, chain) in chains.iter() {
+        let tip_id = chain[0];
+        // Older work is part of the newer work on top of it
+        if chains
+            .iter()
+            .any(|(_, other)| other[0] != tip_id && other.contains(&tip_id))
+        {
+            continue;
+        }
+        let mut lost = false;
+        for id in chain.iter() {
+            let commit = repo.find_commit(*id)?;
+            if !rewritten.contains(&commit_key(&commit)) {
+                lost = true;
+                break;
+            }
+        }
+        if !lost {
+            continue;
+        }
+
+        let oldest = repo.find_commit(*chain.last().unwrap())?;
+        let base_id = oldest.parent_id(0).ok();
+        let mut ids = chain.clone();
+        ids.reverse();
+        orphans.push(Orphan {
+            source: source.clone(),
+            ids,
+            base_id,
+        });
+    }
+    Ok(orphans)
+}
+
+type CommitKey = (i64, Vec<u8>, Vec<u8>);
+
+/// What rebasing or amending a commit's content keeps the same
+fn commit_key(commit: &git2::Commit<'_>) -> CommitKey {
+    let author = commit.author();
+    (
+        author.when().seconds(),
+        author.email_bytes().to_owned(),
+        commit.message_bytes().to_owned(),
+    )
+}
+
+fn rewritten_keys(
+    repo: &git2::Repository,
+    branch_ids: &[git2::Oid],
+    protected_ids: &[git2::Oid],
+) -> Result<std::collections::HashSet<CommitKey>, git2::Error> {
+    let mut walk = repo.revwalk()?;
+    for id in branch_ids {
+        walk.push(*id)?;
+    }
+    for id in protected_ids {
+        walk.hide(*id)?;
+    }
+    let mut keys = std::collections::HashSet::new();
+    for id in walk {
+        let commit = repo.find_commit(id?)?;
+        keys.insert(commit_key(&commit));
+    }
+    Ok(keys)
+}
@@ -6,13 +6,26 @@ pub trait Repo {
     fn merge_base(&self, one: git2::Oid, two: git2::Oid) -> Option<git2::Oid>;
 
     fn find_commit(&self, id: git2::Oid) -> Option<std::rc::Rc<Commit>>;
+    /// Identify the change `id` makes, independent of its parent, like `git patch-id`
+    ///
+    /// Returns `None` for empty commits
+    fn patch_id(&self, id: git2::Oid) -> Option<git2::Oid>;
     fn head_commit(&self) -> std::rc::Rc<Commit>;
     fn head_branch(&self) -> Option<Branch>;
+    /// An id telling this repository, and its worktrees, apart from any other
+    fn fingerprint(&self) -> Option<git2::Oid>;
     fn resolve(&self, revspec: &str) -> Option<std::rc::Rc<Commit>>;
     fn commits_from(
         &self,
         head_id: git2::Oid,
     ) -> Box<dyn Iterator<Item = std::rc::Rc<Commit>> + '_>;
+    /// The history to graph stacks from, like `commits_from` but only following first parents
+    /// when rebasing merges, so merged in branches aren't flattened into the stack
+    fn graph_commits_from(
+        &self,
+        head_id: git2::Oid,
+    ) -> Box<dyn Iterator<Item = std::rc::Rc<Commit>> + '_>;
+    fn parent_ids(&self, id: git2::Oid) -> Result<Vec<git2::Oid>, git2::Error>;
     fn contains_commit(
         &self,
         haystack_id: git2::Oid,
@@ -24,13 +37,26 @@ pub trait Repo {
         cherry_id: git2::Oid,
     ) -> Result<git2::Oid, git2::Error>;
     fn squash(&mut self, head_id: git2::Oid, into_id: git2::Oid) -> Result<git2::Oid, git2::Error>;
+    /// Redo the merge `merge_id` on top of `head_id`, merging in `other_parent_ids` in place of its
+    /// other parents
+    fn remerge(
+        &mut self,
+        head_id: git2::Oid,
+        merge_id: git2::Oid,
+        other_parent_ids: &[git2::Oid],
+    ) -> Result<git2::Oid, git2::Error>;
+    fn reword(&mut self, id: git2::Oid, message: &str) -> Result<git2::Oid, git2::Error>;
 
-    fn branch(&mut self, name: &str, id: git2::Oid) -> Result<(), git2::Error>;
+    /// Point `name` at `id`, recording `reason` in the reflog
+    fn branch(&mut self, name: &str, id: git2::Oid, reason: &str) -> Result<(), git2::Error>;
     fn delete_branch(&mut self, name: &str) -> Result<(), git2::Error>;
     fn find_local_branch(&self, name: &str) -> Option<Branch>;
     fn local_branches(&self) -> Box<dyn Iterator<Item = Branch> + '_>;
     fn detach(&mut self) -> Result<(), git2::Error>;
     fn switch(&mut self, name: &str) -> Result<(), git2::Error>;
+    fn switch_commit(&mut self, id: git2::Oid) -> Result<(), git2::Error>;
+    /// Check out `id` and run `command` in the working tree, like `git rebase --exec`
+    fn exec(&mut self, id: git2::Oid, command: &str) -> Result<(), git2::Error>;
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -41,6 +67,15 @@ pub struct Branch {
     pub pull_id: Option<git2::Oid>,
 }
 
+/// Outcome of [`GitRepo::predict_cherry_pick`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PickPrediction {
+    /// Applies cleanly, giving this tree
+    Clean(git2::Oid),
+    /// Conflicts in these paths
+    Conflict(Vec<std::path::PathBuf>),
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Commit {
     pub id: git2::Oid,
@@ -49,10 +84,19 @@ pub struct Commit {
 }
 
 impl Commit {
+    /// The summary of the commit this `fixup!`, `squash!`, or `amend!` commit targets
     pub fn fixup_summary(&self) -> Option<&bstr::BStr> {
-        self.summary
-            .strip_prefix(b"fixup! ")
-            .map(ByteSlice::as_bstr)
+        static FIXUP_PREFIXES: &[&[u8]] = &[b"fixup! ", b"squash! ", b"amend! "];
+
+        let mut summary = self.summary.as_slice();
+        // `git commit --fixup` on a fixup commit stacks the prefixes
+        while let Some(stripped) = FIXUP_PREFIXES
+            .iter()
+            .find_map(|prefix| summary.strip_prefix(*prefix))
+        {
+            summary = stripped;
+        }
+        (summary.len() != self.summary.len()).then(|| summary.as_bstr())
     }
 
     pub fn wip_summary(&self) -> Option<&bstr::BStr> {
@@ -91,16 +135,238 @@ pub struct GitRepo {
     push_remote: Option<String>,
     pull_remote: Option<String>,
     commits: std::cell::RefCell<std::collections::HashMap<git2::Oid, std::rc::Rc<Commit>>>,
+    patch_ids: std::cell::RefCell<std::collections::HashMap<git2::Oid, Option<git2::Oid>>>,
+    repo_id: std::cell::Cell<Option<git2::Oid>>,
+    sandbox: bool,
+    namespace: Option<String>,
+    branch_prefix: String,
+    rebase_merges: bool,
+    max_history: Option<usize>,
+    dirty_check: crate::config::DirtyCheck,
+    signer: Option<crate::git::Signer>,
+    committer_date: crate::config::CommitterDate,
+    reset_author_date: bool,
 }
 
+const SANDBOX_PREFIX: &str = "refs/git-stack/sandbox/";
+/// Where the id telling backups and plans for this repository apart is kept
+const REPO_ID_FIELD: &str = "stack.repo-id";
+/// git's `core.abbrev` when unset or `auto`
+const DEFAULT_ABBREV: usize = 7;
+/// git's minimum `core.abbrev`
+const MIN_ABBREV: usize = 4;
+
 impl GitRepo {
+    /// Wrap `repo`, respecting `GIT_NAMESPACE` like git does
     pub fn new(repo: git2::Repository) -> Self {
-        Self {
+        let mut repo = Self {
             repo,
             commits: Default::default(),
+            patch_ids: Default::default(),
+            repo_id: Default::default(),
             push_remote: None,
             pull_remote: None,
+            sandbox: false,
+            namespace: None,
+            branch_prefix: String::new(),
+            rebase_merges: false,
+            max_history: None,
+            dirty_check: crate::config::DirtyCheck::TrackedOnly,
+            signer: None,
+            committer_date: crate::config::CommitterDate::Now,
+            reset_author_date: false,
+        };
+        let namespace = std::env::var("GIT_NAMESPACE").ok();
+        repo.set_namespace(namespace.as_deref());
+        repo
+    }
+
+    /// Keep branches under `refs/namespaces/<namespace>/`
+    ///
+    /// Nested namespaces are separated by `/`, e.g. `a/b`.
+    pub fn set_namespace(&mut self, namespace: Option<&str>) {
+        let namespace = namespace
+            .map(|ns| ns.trim_matches('/'))
+            .filter(|ns| !ns.is_empty());
+        let mut branch_prefix = String::new();
+        if let Some(namespace) = namespace {
+            for component in namespace.split('/').filter(|c| !c.is_empty()) {
+                branch_prefix.push_str("refs/namespaces/");
+                branch_prefix.push_str(component);
+                branch_prefix.push('/');
+            }
         }
+        branch_prefix.push_str("refs/heads/");
+        self.namespace = namespace.map(ToOwned::to_owned);
+        self.branch_prefix = branch_prefix;
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Full reference name for the local branch `name`
+    pub fn branch_ref(&self, name: &str) -> String {
+        format!("{}{}", self.branch_prefix, name)
+    }
+
+    /// Redirect all local branch operations to `refs/git-stack/sandbox/`
+    ///
+    /// The sandbox starts out as a copy of the local branches, replacing any prior sandbox.
+    /// `HEAD` and the working tree are left untouched.
+    pub fn enter_sandbox(&mut self) -> Result<(), git2::Error> {
+        for reference in self.repo.references_glob(&format!("{}*", SANDBOX_PREFIX))? {
+            reference?.delete()?;
+        }
+        let branches: Vec<_> = self.local_branches().collect();
+        for branch in branches {
+            log::trace!("Sandboxing {}", branch.name);
+            self.repo.reference(
+                &format!("{}{}", SANDBOX_PREFIX, branch.name),
+                branch.id,
+                true,
+                "git-stack: enter sandbox",
+            )?;
+        }
+        self.sandbox = true;
+        Ok(())
+    }
+
+    pub fn is_sandboxed(&self) -> bool {
+        self.sandbox
+    }
+
+    /// Graph stacks by their first parents, keeping merge commits for `remerge` to recreate
+    pub fn set_rebase_merges(&mut self, rebase_merges: bool) {
+        self.rebase_merges = rebase_merges;
+    }
+
+    pub fn rebase_merges(&self) -> bool {
+        self.rebase_merges
+    }
+
+    /// Stop graphing a branch after this many commits, rather than walking back to its base
+    pub fn set_max_history(&mut self, max_history: Option<usize>) {
+        self.max_history = max_history;
+    }
+
+    /// Which changes make the working tree dirty, defaulting to ignoring untracked files
+    pub fn set_dirty_check(&mut self, dirty_check: crate::config::DirtyCheck) {
+        self.dirty_check = dirty_check;
+    }
+
+    /// Sign the commits created from here on, like `commit.gpgsign`
+    pub fn set_signer(&mut self, signer: Option<crate::git::Signer>) {
+        self.signer = signer;
+    }
+
+    /// Date rewritten commits by `committer_date`, rather than the current time
+    pub fn set_committer_date(&mut self, committer_date: crate::config::CommitterDate) {
+        self.committer_date = committer_date;
+    }
+
+    /// Date rewritten commits' authorship to the current time, like `git rebase --ignore-date`
+    pub fn set_reset_author_date(&mut self, reset_author_date: bool) {
+        self.reset_author_date = reset_author_date;
+    }
+
+    /// The author for a rewrite of `original`, the same unless `set_reset_author_date`
+    pub fn author(
+        &self,
+        original: &git2::Commit<'_>,
+    ) -> Result<git2::Signature<'static>, git2::Error> {
+        let author = original.author();
+        if self.reset_author_date {
+            git2::Signature::now(
+                author.name().unwrap_or_default(),
+                author.email().unwrap_or_default(),
+            )
+        } else {
+            Ok(author.to_owned())
+        }
+    }
+
+    /// The committer for a rewrite of `original`, dated per `set_committer_date`
+    pub fn committer(
+        &self,
+        original: &git2::Commit<'_>,
+    ) -> Result<git2::Signature<'static>, git2::Error> {
+        let signature = self.repo.signature()?;
+        let when = match self.committer_date {
+            crate::config::CommitterDate::Now => return Ok(signature),
+            crate::config::CommitterDate::Keep => original.committer().when(),
+            // Like `git rebase --ignore-date --committer-date-is-author-date`
+            crate::config::CommitterDate::Author if self.reset_author_date => return Ok(signature),
+            crate::config::CommitterDate::Author => original.author().when(),
+        };
+        git2::Signature::new(
+            signature.name().unwrap_or_default(),
+            signature.email().unwrap_or_default(),
+            &when,
+        )
+    }
+
+    /// Create a commit, signed if there is a signer
+    fn create_commit(
+        &self,
+        author: &git2::Signature<'_>,
+        committer: &git2::Signature<'_>,
+        message: &str,
+        tree: &git2::Tree<'_>,
+        parents: &[&git2::Commit<'_>],
+    ) -> Result<git2::Oid, git2::Error> {
+        let signer = match self.signer.as_ref() {
+            Some(signer) => signer,
+            None => {
+                return self
+                    .repo
+                    .commit(None, author, committer, message, tree, parents)
+            }
+        };
+        let buffer = self
+            .repo
+            .commit_create_buffer(author, committer, message, tree, parents)?;
+        let buffer = buffer
+            .as_str()
+            .ok_or_else(|| git2::Error::from_str("can only sign commits whose message is UTF-8"))?;
+        let signature = signer.sign(buffer, &self.repo.path().join("git-stack"))?;
+        self.repo.commit_signed(buffer, &signature, None)
+    }
+
+    /// Sign the first-parent chain from `base_id` (exclusive) to `tip_id`, returning the new tip
+    ///
+    /// For commits libgit2 created on its own, like during a rebase.  Without a signer, this
+    /// is `tip_id`.
+    pub fn sign_range(
+        &self,
+        base_id: git2::Oid,
+        tip_id: git2::Oid,
+    ) -> Result<git2::Oid, git2::Error> {
+        if self.signer.is_none() || base_id == tip_id {
+            return Ok(tip_id);
+        }
+        let mut chain = Vec::new();
+        let mut id = tip_id;
+        while id != base_id {
+            let commit = self.repo.find_commit(id)?;
+            id = commit.parent_id(0)?;
+            chain.push(commit);
+        }
+        let mut new_id = base_id;
+        for commit in chain.iter().rev() {
+            let mut parents: Vec<_> = commit.parents().collect();
+            parents[0] = self.repo.find_commit(new_id)?;
+            let parents: Vec<_> = parents.iter().collect();
+            let message = String::from_utf8_lossy(commit.message_bytes());
+            new_id = self.create_commit(
+                &commit.author(),
+                &commit.committer(),
+                &message,
+                &commit.tree()?,
+                &parents,
+            )?;
+        }
+        Ok(new_id)
     }
 
     pub fn set_push_remote(&mut self, remote: &str) {
@@ -129,9 +395,27 @@ impl GitRepo {
             return true;
         }
 
+        // libgit2 can't use fsmonitor or the untracked cache, scanning the whole worktree
+        if self.has_status_cache() {
+            match self.git_status() {
+                Ok(paths) if paths.is_empty() => return false,
+                Ok(paths) => {
+                    log::trace!("Repository is dirty: {}", paths.join(", "));
+                    return true;
+                }
+                Err(err) => {
+                    log::debug!("Falling back from `git status`: {}", err);
+                }
+            }
+        }
+
         let status = self
             .repo
-            .statuses(Some(git2::StatusOptions::new().include_ignored(false)))
+            .statuses(Some(
+                git2::StatusOptions::new()
+                    .include_ignored(false)
+                    .include_untracked(self.dirty_check == crate::config::DirtyCheck::All),
+            ))
             .unwrap();
         if status.is_empty() {
             false
@@ -147,6 +431,71 @@ impl GitRepo {
         }
     }
 
+    /// Whether `git status` can skip scanning the worktree, with `core.fsmonitor` or
+    /// `core.untrackedCache`
+    fn has_status_cache(&self) -> bool {
+        let config = match self.repo.config() {
+            Ok(config) => config,
+            Err(_) => return false,
+        };
+        // Either a boolean, for the builtin daemon, or the hook to run
+        let fsmonitor = match config.get_bool("core.fsmonitor") {
+            Ok(enabled) => enabled,
+            Err(_) => matches!(config.get_string("core.fsmonitor"), Ok(hook) if !hook.is_empty()),
+        };
+        fsmonitor || config.get_bool("core.untrackedCache").unwrap_or(false)
+    }
+
+    /// The paths `git status` reports as changed, and untracked with `DirtyCheck::All`
+    fn git_status(&self) -> Result<Vec<String>, git2::Error> {
+        let workdir = self.repo.workdir().ok_or_else(|| {
+            git2::Error::new(
+                git2::ErrorCode::BareRepo,
+                git2::ErrorClass::Repository,
+                "cannot run `git status` in a bare repository",
+            )
+        })?;
+        let untracked = match self.dirty_check {
+            crate::config::DirtyCheck::TrackedOnly => "--untracked-files=no",
+            crate::config::DirtyCheck::All => "--untracked-files=normal",
+        };
+        log::trace!("git status --porcelain -z {}", untracked);
+        let output = std::process::Command::new("git")
+            .args(["status", "--porcelain", "-z", untracked])
+            .current_dir(workdir)
+            .output()
+            .map_err(|err| {
+                git2::Error::new(
+                    git2::ErrorCode::GenericError,
+                    git2::ErrorClass::Os,
+                    format!("could not launch `git status`: {}", err),
+                )
+            })?;
+        if !output.status.success() {
+            return Err(git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!(
+                    "`git status` failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+        // Entries are `XY path`, with renames and copies followed by the original path
+        let mut paths = Vec::new();
+        let mut entries = output.stdout.split(|b| *b == b'\0');
+        while let Some(entry) = entries.next() {
+            if entry.len() < 4 {
+                continue;
+            }
+            if entry[0] == b'R' || entry[0] == b'C' {
+                entries.next();
+            }
+            paths.push(String::from_utf8_lossy(&entry[3..]).into_owned());
+        }
+        Ok(paths)
+    }
+
     pub fn merge_base(&self, one: git2::Oid, two: git2::Oid) -> Option<git2::Oid> {
         self.repo.merge_base(one, two).ok()
     }
@@ -169,6 +518,12 @@ impl GitRepo {
     }
 
     pub fn head_commit(&self) -> std::rc::Rc<Commit> {
+        if self.sandbox {
+            if let Some(head_branch) = self.head_branch() {
+                return self.find_commit(head_branch.id).unwrap();
+            }
+        }
+
         let head_id = self
             .repo
             .head()
@@ -180,10 +535,65 @@ impl GitRepo {
         self.find_commit(head_id).unwrap()
     }
 
+    /// `stack.repo-id`, if one was generated, see `GitRepo::ensure_fingerprint`
+    pub fn fingerprint(&self) -> Option<git2::Oid> {
+        if let Some(id) = self.repo_id.get() {
+            return Some(id);
+        }
+
+        let id = self
+            .repo
+            .config()
+            .ok()?
+            .get_string(REPO_ID_FIELD)
+            .ok()
+            .and_then(|id| git2::Oid::from_str(&id).ok())?;
+        self.repo_id.set(Some(id));
+        Some(id)
+    }
+
+    /// `stack.repo-id`, generating and saving it if needed, for backups and plans being written
+    pub fn ensure_fingerprint(&self) -> Option<git2::Oid> {
+        if let Some(id) = self.fingerprint() {
+            return Some(id);
+        }
+
+        let mut config = self
+            .repo
+            .config()
+            .and_then(|c| c.open_level(git2::ConfigLevel::Local))
+            .ok()?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let seed = format!(
+            "{} {} {}",
+            self.repo.path().display(),
+            now.as_nanos(),
+            std::process::id()
+        );
+        let id = git2::Oid::hash_object(git2::ObjectType::Blob, seed.as_bytes()).ok()?;
+        if let Err(err) = config.set_str(REPO_ID_FIELD, &id.to_string()) {
+            log::debug!("Could not save {}: {}", REPO_ID_FIELD, err);
+            return None;
+        }
+        log::trace!("Generated {}={}", REPO_ID_FIELD, id);
+        self.repo_id.set(Some(id));
+        Some(id)
+    }
+
     pub fn head_branch(&self) -> Option<Branch> {
         let resolved = self.repo.head().unwrap().resolve().unwrap();
-        let name = resolved.shorthand()?;
-        let id = resolved.target()?;
+        let name = resolved.name()?.strip_prefix(self.branch_prefix.as_str())?;
+        let sandbox_id = if self.sandbox {
+            self.repo
+                .find_reference(&format!("{}{}", SANDBOX_PREFIX, name))
+                .ok()
+                .and_then(|r| r.target())
+        } else {
+            None
+        };
+        let id = sandbox_id.or_else(|| resolved.target())?;
 
         let push_id = self
             .repo
@@ -211,7 +621,14 @@ impl GitRepo {
     }
 
     pub fn resolve(&self, revspec: &str) -> Option<std::rc::Rc<Commit>> {
-        let id = self.repo.revparse_single(revspec).ok()?.id();
+        // Peel annotated tags down to what they tag
+        let id = self
+            .repo
+            .revparse_single(revspec)
+            .ok()?
+            .peel_to_commit()
+            .ok()?
+            .id();
         self.find_commit(id)
     }
 
@@ -227,6 +644,66 @@ impl GitRepo {
             .filter_map(move |oid| self.find_commit(oid))
     }
 
+    pub fn graph_commits_from(
+        &self,
+        head_id: git2::Oid,
+    ) -> impl Iterator<Item = std::rc::Rc<Commit>> + '_ {
+        let mut revwalk = self.repo.revwalk().unwrap();
+        revwalk.push(head_id).unwrap();
+        if self.rebase_merges {
+            revwalk.simplify_first_parent().unwrap();
+        }
+
+        revwalk
+            .take(self.max_history.unwrap_or(usize::MAX))
+            .filter_map(Result::ok)
+            .filter_map(move |oid| self.find_commit(oid))
+    }
+
+    pub fn parent_ids(&self, id: git2::Oid) -> Result<Vec<git2::Oid>, git2::Error> {
+        let commit = self.repo.find_commit(id)?;
+        Ok(commit.parent_ids().collect())
+    }
+
+    /// Identify a commit's changes independent of where they were applied, like `git patch-id`
+    ///
+    /// Empty commits have no changes to identify them by.
+    pub fn patch_id(&self, id: git2::Oid) -> Result<Option<git2::Oid>, git2::Error> {
+        let commit = self.repo.find_commit(id)?;
+        let parent_tree = if 0 < commit.parent_count() {
+            Some(commit.parent(0)?.tree()?)
+        } else {
+            None
+        };
+        let tree = commit.tree()?;
+        let diff = self
+            .repo
+            .diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+        if diff.deltas().len() == 0 {
+            return Ok(None);
+        }
+        diff.patchid(None).map(Some)
+    }
+
+    /// Identify the cumulative changes from `base_id` to `id`, like `patch_id` for a range
+    ///
+    /// An empty range has no changes to identify it by.
+    pub fn range_patch_id(
+        &self,
+        base_id: git2::Oid,
+        id: git2::Oid,
+    ) -> Result<Option<git2::Oid>, git2::Error> {
+        let base_tree = self.repo.find_commit(base_id)?.tree()?;
+        let tree = self.repo.find_commit(id)?.tree()?;
+        let diff = self
+            .repo
+            .diff_tree_to_tree(Some(&base_tree), Some(&tree), None)?;
+        if diff.deltas().len() == 0 {
+            return Ok(None);
+        }
+        diff.patchid(None).map(Some)
+    }
+
     pub fn contains_commit(
         &self,
         haystack_id: git2::Oid,
@@ -309,7 +786,7 @@ impl GitRepo {
 
         let mut tip_id = head_id;
         while let Some(op) = rebase.next() {
-            op.map_err(|e| {
+            let op = op.map_err(|e| {
                 let _ = rebase.abort();
                 e
             })?;
@@ -335,8 +812,10 @@ impl GitRepo {
                 ));
             }
 
-            let sig = self.repo.signature().unwrap();
-            let commit_id = match rebase.commit(None, &sig, None).map_err(|e| {
+            let original = self.repo.find_commit(op.id())?;
+            let author = self.author(&original)?;
+            let sig = self.committer(&original)?;
+            let commit_id = match rebase.commit(Some(&author), &sig, None).map_err(|e| {
                 let _ = rebase.abort();
                 e
             }) {
@@ -345,8 +824,19 @@ impl GitRepo {
                     if err.class() == git2::ErrorClass::Rebase
                         && err.code() == git2::ErrorCode::Applied
                     {
-                        log::trace!("Skipping {}, already applied to {}", cherry_id, head_id);
-                        return Ok(tip_id);
+                        // Like `git cherry-pick --allow-empty`, leaving it to the caller to drop
+                        log::trace!("{} is empty on top of {}", cherry_id, tip_id);
+                        let cherry_commit = self.repo.find_commit(cherry_id)?;
+                        let tip_commit = self.repo.find_commit(tip_id)?;
+                        let empty_id = self.repo.commit(
+                            None,
+                            &author,
+                            &sig,
+                            cherry_commit.message().unwrap_or_default(),
+                            &tip_commit.tree()?,
+                            &[&tip_commit],
+                        )?;
+                        return self.sign_range(head_id, empty_id);
                     }
                     Err(err)
                 }
@@ -354,7 +844,7 @@ impl GitRepo {
             tip_id = commit_id;
         }
         rebase.finish(None)?;
-        Ok(tip_id)
+        self.sign_range(head_id, tip_id)
     }
 
     pub fn squash(
@@ -408,32 +898,751 @@ impl GitRepo {
         }
         let result_id = result_index.write_tree_to(&self.repo)?;
         let result_tree = self.repo.find_tree(result_id)?;
-        let new_id = self.repo.commit(
-            None,
-            &into_commit.author(),
-            &into_commit.committer(),
-            into_commit.message().unwrap(),
+        // `git commit --fixup=amend:` puts the replacement message after the `amend!` summary
+        let message = head_commit
+            .message()
+            .filter(|m| m.starts_with("amend! "))
+            .and_then(|m| m.split_once("\n\n"))
+            .map(|(_, m)| m.trim_start())
+            .filter(|m| !m.is_empty())
+            .or_else(|| into_commit.message())
+            .unwrap();
+        // Folding in a fixup leaves the commit as committed, unless asked to date it otherwise
+        let committer = match self.committer_date {
+            crate::config::CommitterDate::Now => into_commit.committer().to_owned(),
+            _ => self.committer(&into_commit)?,
+        };
+        let new_id = self.create_commit(
+            &self.author(&into_commit)?,
+            &committer,
+            message,
             &result_tree,
             onto_commits,
         )?;
         Ok(new_id)
     }
 
-    pub fn branch(&mut self, name: &str, id: git2::Oid) -> Result<(), git2::Error> {
+    /// Cherry-pick `cherry_id` onto the tree `tree_id` without writing any commits, to see
+    /// whether it applies
+    pub fn predict_cherry_pick(
+        &self,
+        tree_id: git2::Oid,
+        cherry_id: git2::Oid,
+    ) -> Result<PickPrediction, git2::Error> {
+        let cherry_commit = self.repo.find_commit(cherry_id)?;
+        let cherry_tree = cherry_commit.tree()?;
+        let base_tree = match cherry_commit.parent(0) {
+            Ok(parent) => parent.tree()?,
+            Err(_) => {
+                let empty_id = self.repo.treebuilder(None)?.write()?;
+                self.repo.find_tree(empty_id)?
+            }
+        };
+        if base_tree.id() == tree_id {
+            return Ok(PickPrediction::Clean(cherry_tree.id()));
+        }
+        let tree = self.repo.find_tree(tree_id)?;
+
+        let mut result_index = self
+            .repo
+            .merge_trees(&base_tree, &tree, &cherry_tree, None)?;
+        if result_index.has_conflicts() {
+            let mut paths = result_index
+                .conflicts()?
+                .filter_map(|conflict| {
+                    let conflict = conflict.ok()?;
+                    conflict
+                        .our
+                        .as_ref()
+                        .or(conflict.their.as_ref())
+                        .map(|c| bytes2path(&c.path).to_owned())
+                })
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths.dedup();
+            return Ok(PickPrediction::Conflict(paths));
+        }
+        let result_id = result_index.write_tree_to(&self.repo)?;
+        Ok(PickPrediction::Clean(result_id))
+    }
+
+    /// Copy of `id` with a new message, on the same parents
+    pub fn reword(&mut self, id: git2::Oid, message: &str) -> Result<git2::Oid, git2::Error> {
+        let commit = self.repo.find_commit(id)?;
+        let tree = commit.tree()?;
+        let parents: Vec<_> = commit.parents().collect();
+        let parents: Vec<_> = parents.iter().collect();
+        let committer = self.committer(&commit)?;
+        let new_id = self.create_commit(&commit.author(), &committer, message, &tree, &parents)?;
+        Ok(new_id)
+    }
+
+    /// Copy of `id` with a new message and author, on the same parents
+    pub fn recommit(
+        &mut self,
+        id: git2::Oid,
+        message: &str,
+        author: &git2::Signature<'_>,
+    ) -> Result<git2::Oid, git2::Error> {
         let commit = self.repo.find_commit(id)?;
-        self.repo.branch(name, &commit, true)?;
+        let tree = commit.tree()?;
+        let parents: Vec<_> = commit.parents().collect();
+        let parents: Vec<_> = parents.iter().collect();
+        let committer = self.committer(&commit)?;
+        let new_id = self.create_commit(author, &committer, message, &tree, &parents)?;
+        Ok(new_id)
+    }
+
+    /// Split `id` in two, the first with the changes to `paths` and the second with the rest
+    ///
+    /// Both keep the original message and author.
+    pub fn split(
+        &mut self,
+        id: git2::Oid,
+        paths: &[std::path::PathBuf],
+    ) -> Result<(git2::Oid, git2::Oid), git2::Error> {
+        let commit = self.repo.find_commit(id)?;
+        if commit.parent_count() != 1 {
+            return Err(git2::Error::new(
+                git2::ErrorCode::Invalid,
+                git2::ErrorClass::Object,
+                "can only split commits with one parent",
+            ));
+        }
+        let parent = commit.parent(0)?;
+        let parent_tree = parent.tree()?;
+        let tree = commit.tree()?;
+
+        let mut opts = git2::DiffOptions::new();
+        for path in paths {
+            opts.pathspec(path);
+        }
+        let diff = self
+            .repo
+            .diff_tree_to_tree(Some(&parent_tree), Some(&tree), Some(&mut opts))?;
+        if diff.deltas().len() == 0 {
+            return Err(git2::Error::new(
+                git2::ErrorCode::NotFound,
+                git2::ErrorClass::Object,
+                format!("{} doesn't change any of the paths", id),
+            ));
+        }
+        let mut first_index = self.repo.apply_to_tree(&parent_tree, &diff, None)?;
+        let first_tree_id = first_index.write_tree_to(&self.repo)?;
+        if first_tree_id == tree.id() {
+            return Err(git2::Error::new(
+                git2::ErrorCode::Invalid,
+                git2::ErrorClass::Object,
+                format!("{} only changes those paths, nothing to split", id),
+            ));
+        }
+        let first_tree = self.repo.find_tree(first_tree_id)?;
+
+        let author = commit.author();
+        let committer = self.committer(&commit)?;
+        let message = commit.message_bytes().to_str_lossy();
+        let first_id =
+            self.create_commit(&author, &committer, &message, &first_tree, &[&parent])?;
+        let first = self.repo.find_commit(first_id)?;
+        let second_id = self.create_commit(&author, &committer, &message, &tree, &[&first])?;
+        Ok((first_id, second_id))
+    }
+
+    /// Combine `ids`, a first-parent chain from oldest to newest, into one commit
+    ///
+    /// The result has the last commit's tree and the messages of all but the later `fixup!`s,
+    /// keeping the first commit's author.
+    pub fn squash_commits(&mut self, ids: &[git2::Oid]) -> Result<git2::Oid, git2::Error> {
+        let commits = ids
+            .iter()
+            .map(|id| self.repo.find_commit(*id))
+            .collect::<Result<Vec<_>, _>>()?;
+        if commits.len() < 2 {
+            return Err(git2::Error::new(
+                git2::ErrorCode::Invalid,
+                git2::ErrorClass::Object,
+                "nothing to squash, need at least two commits",
+            ));
+        }
+        for pair in commits.windows(2) {
+            if pair[1].parent_count() != 1 || pair[1].parent_id(0)? != pair[0].id() {
+                return Err(git2::Error::new(
+                    git2::ErrorCode::Invalid,
+                    git2::ErrorClass::Object,
+                    format!(
+                        "{} is not the only parent of {}",
+                        pair[0].id(),
+                        pair[1].id()
+                    ),
+                ));
+            }
+        }
+        let first = &commits[0];
+        if first.parent_count() != 1 {
+            return Err(git2::Error::new(
+                git2::ErrorCode::Invalid,
+                git2::ErrorClass::Object,
+                "can only squash commits with one parent",
+            ));
+        }
+        let parent = first.parent(0)?;
+        let tree = commits.last().expect("checked length").tree()?;
+
+        let messages: Vec<_> = commits
+            .iter()
+            .map(|c| c.message_bytes().to_str_lossy().trim().to_owned())
+            .enumerate()
+            .filter(|(i, m)| *i == 0 || !m.starts_with("fixup! "))
+            .map(|(_, m)| m)
+            .collect();
+        let message = format!("{}\n", messages.join("\n\n"));
+
+        let committer = self.committer(first)?;
+        let new_id =
+            self.create_commit(&first.author(), &committer, &message, &tree, &[&parent])?;
+        Ok(new_id)
+    }
+
+    /// Merge `theirs` into `ours`, fast-forwarding when possible
+    ///
+    /// Returns the new tip for `ours`, failing if the merge conflicts
+    pub fn merge(
+        &mut self,
+        ours: git2::Oid,
+        theirs: git2::Oid,
+        message: &str,
+    ) -> Result<git2::Oid, git2::Error> {
+        if theirs == ours || self.repo.graph_descendant_of(ours, theirs)? {
+            return Ok(ours);
+        }
+        if self.repo.graph_descendant_of(theirs, ours)? {
+            return Ok(theirs);
+        }
+
+        let ours_commit = self.repo.find_commit(ours)?;
+        let theirs_commit = self.repo.find_commit(theirs)?;
+        let mut index = self
+            .repo
+            .merge_commits(&ours_commit, &theirs_commit, None)?;
+        if index.has_conflicts() {
+            return Err(git2::Error::new(
+                git2::ErrorCode::MergeConflict,
+                git2::ErrorClass::Merge,
+                format!("merging {} into {} conflicts", theirs, ours),
+            ));
+        }
+        let tree_id = index.write_tree_to(&self.repo)?;
+        let tree = self.repo.find_tree(tree_id)?;
+
+        let signature = self.repo.signature()?;
+        let new_id = self.create_commit(
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &[&ours_commit, &theirs_commit],
+        )?;
+        Ok(new_id)
+    }
+
+    pub fn remerge(
+        &mut self,
+        head_id: git2::Oid,
+        merge_id: git2::Oid,
+        other_parent_ids: &[git2::Oid],
+    ) -> Result<git2::Oid, git2::Error> {
+        let merge_commit = self.repo.find_commit(merge_id)?;
+        let mut parent_ids = vec![head_id];
+        parent_ids.extend_from_slice(other_parent_ids);
+        if merge_commit.parent_ids().eq(parent_ids.iter().copied()) {
+            return Ok(merge_id);
+        }
+        if parent_ids.len() < 2 {
+            return Err(git2::Error::new(
+                git2::ErrorCode::Invalid,
+                git2::ErrorClass::Merge,
+                format!("{} is not a merge commit", merge_id),
+            ));
+        }
+
+        let parents = parent_ids
+            .iter()
+            .map(|id| self.repo.find_commit(*id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let old_head_id = merge_commit.parent_id(0)?;
+        let mut tree = parents[0].tree()?;
+        // Like `git merge-octopus`, merge in the rest one at a time, each against its merge base
+        // with everything merged so far
+        for (i, other_commit) in parents.iter().enumerate().skip(1) {
+            let mut base_ids = vec![other_commit.id()];
+            if merge_commit.parent_id(i).ok() == Some(other_commit.id()) {
+                // Left as-is, it only shares the history `head_id` was rewritten from
+                base_ids.push(old_head_id);
+                base_ids.extend_from_slice(&parent_ids[1..i]);
+            } else {
+                base_ids.extend_from_slice(&parent_ids[..i]);
+            }
+            let base_id = self.repo.merge_base_many(&base_ids)?;
+            let ancestor_tree = self.repo.find_commit(base_id)?.tree()?;
+            let mut index =
+                self.repo
+                    .merge_trees(&ancestor_tree, &tree, &other_commit.tree()?, None)?;
+            if index.has_conflicts() {
+                return Err(git2::Error::new(
+                    git2::ErrorCode::MergeConflict,
+                    git2::ErrorClass::Merge,
+                    format!("redoing merge {} conflicts", merge_id),
+                ));
+            }
+            let tree_id = index.write_tree_to(&self.repo)?;
+            tree = self.repo.find_tree(tree_id)?;
+        }
+
+        let author = self.author(&merge_commit)?;
+        let committer = self.committer(&merge_commit)?;
+        let message = String::from_utf8_lossy(merge_commit.message_bytes());
+        let parents = parents.iter().collect::<Vec<_>>();
+        self.create_commit(&author, &committer, &message, &tree, &parents)
+    }
+
+    /// Cherry-pick `cherry_id` onto `HEAD`, leaving any conflicts in the index and working tree,
+    /// like `git cherry-pick`
+    ///
+    /// Returns the conflicts `git rerere` resolved from a previously recorded resolution.
+    pub fn cherry_pick_to_worktree(
+        &mut self,
+        cherry_id: git2::Oid,
+    ) -> Result<Vec<std::path::PathBuf>, git2::Error> {
+        let commit = self.repo.find_commit(cherry_id)?;
+        self.repo.cherrypick(&commit, None)?;
+
+        let mut index = self.repo.index()?;
+        if !index.has_conflicts() || !self.rerere_enabled() {
+            return Ok(Vec::new());
+        }
+        let conflicts = index
+            .conflicts()?
+            .filter_map(|conflict| {
+                let conflict = conflict.ok()?;
+                conflict
+                    .our
+                    .as_ref()
+                    .or(conflict.their.as_ref())
+                    .map(|c| bytes2path(&c.path).to_owned())
+            })
+            .collect::<std::collections::BTreeSet<_>>();
+        self.rerere(&[])?;
+        let remaining = self.rerere(&["remaining"])?;
+        let remaining = remaining
+            .lines()
+            .map(std::path::PathBuf::from)
+            .collect::<std::collections::BTreeSet<_>>();
+        // `rerere.autoupdate` stages what it resolved
+        index.read(true)?;
+        Ok(conflicts.difference(&remaining).cloned().collect())
+    }
+
+    /// Commit the user's resolution of `cherry_pick_to_worktree`, like `git cherry-pick --continue`
+    ///
+    /// Returns the new commit, or `None` if the user already committed or resolved it to nothing.
+    pub fn continue_cherry_pick(
+        &mut self,
+        cherry_id: git2::Oid,
+    ) -> Result<Option<git2::Oid>, git2::Error> {
+        // Like `git commit`, remember the resolution for the next time this conflicts
+        if self.rerere_enabled() {
+            self.rerere(&[])?;
+        }
+        let mut index = self.repo.index()?;
+        if index.has_conflicts() {
+            return Err(git2::Error::new(
+                git2::ErrorCode::Unmerged,
+                git2::ErrorClass::Index,
+                "there are unresolved conflicts, `git add` the resolved files",
+            ));
+        }
+        if self.repo.state() != git2::RepositoryState::CherryPick {
+            log::trace!("Cherry-pick of {} was already committed", cherry_id);
+            return Ok(None);
+        }
+
+        let head = self.repo.head()?.peel_to_commit()?;
+        let tree_id = index.write_tree()?;
+        let new_id = if tree_id == head.tree_id() {
+            log::trace!("Cherry-pick of {} resolved to no changes", cherry_id);
+            None
+        } else {
+            let cherry = self.repo.find_commit(cherry_id)?;
+            let tree = self.repo.find_tree(tree_id)?;
+            let committer = self.committer(&cherry)?;
+            let message = String::from_utf8_lossy(cherry.message_bytes()).into_owned();
+            let new_id = self.create_commit(
+                &self.author(&cherry)?,
+                &committer,
+                &message,
+                &tree,
+                &[&head],
+            )?;
+            self.repo.head()?.set_target(
+                new_id,
+                &format!("cherry-pick: {}", cherry.summary().unwrap_or_default()),
+            )?;
+            Some(new_id)
+        };
+        self.repo.cleanup_state()?;
+        Ok(new_id)
+    }
+
+    /// Forget the conflicts `git rerere` is waiting to record resolutions for, like
+    /// `git cherry-pick --abort`
+    pub fn rerere_clear(&self) -> Result<(), git2::Error> {
+        if self.rerere_enabled() {
+            self.rerere(&["clear"])?;
+        }
         Ok(())
     }
 
+    /// Like git, `rerere.enabled`, falling back to whether there is an `rr-cache` directory
+    fn rerere_enabled(&self) -> bool {
+        match self
+            .repo
+            .config()
+            .and_then(|c| c.get_bool("rerere.enabled"))
+        {
+            Ok(enabled) => enabled,
+            Err(_) => self.repo.path().join("rr-cache").is_dir(),
+        }
+    }
+
+    /// Run `git rerere`, returning its output
+    fn rerere(&self, args: &[&str]) -> Result<String, git2::Error> {
+        let workdir = self.repo.workdir().ok_or_else(|| {
+            git2::Error::new(
+                git2::ErrorCode::BareRepo,
+                git2::ErrorClass::Repository,
+                "cannot run `git rerere` in a bare repository",
+            )
+        })?;
+        log::trace!("git rerere {}", args.join(" "));
+        let output = std::process::Command::new("git")
+            .arg("rerere")
+            .args(args)
+            .current_dir(workdir)
+            .output()
+            .map_err(|err| {
+                git2::Error::new(
+                    git2::ErrorCode::GenericError,
+                    git2::ErrorClass::Os,
+                    format!("could not launch `git rerere`: {}", err),
+                )
+            })?;
+        if !output.status.success() {
+            return Err(git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!(
+                    "`git rerere` failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    pub fn branch(&mut self, name: &str, id: git2::Oid, reason: &str) -> Result<(), git2::Error> {
+        let refname = if self.sandbox {
+            format!("{}{}", SANDBOX_PREFIX, name)
+        } else {
+            self.branch_ref(name)
+        };
+        let old_id = self
+            .repo
+            .find_reference(&refname)
+            .ok()
+            .and_then(|r| r.target());
+        let message = format!(
+            "git-stack: {} ({} -> {})",
+            reason,
+            old_id
+                .map(|id| self.abbrev_id(id))
+                .unwrap_or_else(|| "new".to_owned()),
+            self.abbrev_id(id)
+        );
+        if self.namespace.is_some() && !self.sandbox && self.log_all_ref_updates() {
+            // libgit2 only creates reflogs on its own for `refs/heads/`
+            self.repo.reference_ensure_log(&refname)?;
+        }
+        self.repo.find_commit(id)?;
+        self.repo.reference(&refname, id, true, &message)?;
+        Ok(())
+    }
+
+    /// The remote-tracking branch `git switch <name>` would create `name` from
+    ///
+    /// Like git, a name on several remotes is ambiguous unless `checkout.defaultRemote` is one
+    /// of them, and `checkout.guess=false` turns this off.
+    pub fn find_remote_branch_dwim(
+        &self,
+        name: &str,
+    ) -> Result<Option<(String, git2::Oid)>, git2::Error> {
+        let config = self.repo.config()?;
+        if !config.get_bool("checkout.guess").unwrap_or(true) {
+            return Ok(None);
+        }
+        let remotes = self.repo.remotes()?;
+        let matches: Vec<_> = remotes
+            .iter()
+            .flatten()
+            .filter_map(|remote| {
+                let id = self
+                    .repo
+                    .find_reference(&format!("refs/remotes/{}/{}", remote, name))
+                    .ok()?
+                    .resolve()
+                    .ok()?
+                    .target()?;
+                Some((remote.to_owned(), id))
+            })
+            .collect();
+        if matches.len() < 2 {
+            return Ok(matches.into_iter().next());
+        }
+        let default_remote = config.get_string("checkout.defaultRemote").ok();
+        if let Some(found) = matches
+            .iter()
+            .find(|(remote, _)| Some(remote) == default_remote.as_ref())
+        {
+            return Ok(Some(found.clone()));
+        }
+        Err(git2::Error::new(
+            git2::ErrorCode::Ambiguous,
+            git2::ErrorClass::Reference,
+            format!(
+                "`{}` is on several remotes ({}), set `checkout.defaultRemote` to pick one",
+                name,
+                matches.iter().map(|(remote, _)| remote.as_str()).join(", ")
+            ),
+        ))
+    }
+
+    /// Create `name` at `remote`'s branch of the same name, tracking it like `git switch` does
+    pub fn track_remote_branch(
+        &mut self,
+        name: &str,
+        remote: &str,
+        id: git2::Oid,
+    ) -> Result<Branch, git2::Error> {
+        self.branch(name, id, &format!("track {}/{}", remote, name))?;
+        if !self.sandbox {
+            let mut config = self.repo.config()?;
+            config.set_str(&format!("branch.{}.remote", name), remote)?;
+            config.set_str(
+                &format!("branch.{}.merge", name),
+                &format!("refs/heads/{}", name),
+            )?;
+        }
+        self.find_local_branch(name).ok_or_else(|| {
+            git2::Error::new(
+                git2::ErrorCode::NotFound,
+                git2::ErrorClass::Reference,
+                format!("could not find branch {:?}", name),
+            )
+        })
+    }
+
+    /// What `name` is for, as written by `git branch --edit-description`
+    pub fn branch_description(&self, name: &str) -> Option<String> {
+        let config = self.repo.config().ok()?;
+        config
+            .get_string(&format!("branch.{}.description", name))
+            .ok()
+            .filter(|description| !description.trim().is_empty())
+    }
+
+    /// Record what `name` is for, or forget it with `None`
+    pub fn set_branch_description(
+        &mut self,
+        name: &str,
+        description: Option<&str>,
+    ) -> Result<(), git2::Error> {
+        let key = format!("branch.{}.description", name);
+        let mut config = self.repo.config()?;
+        match description {
+            Some(description) => config.set_str(&key, description),
+            None => match config.remove(&key) {
+                Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(()),
+                res => res,
+            },
+        }
+    }
+
+    /// Whether `core.logAllRefUpdates` asks for branches to have a reflog
+    fn log_all_ref_updates(&self) -> bool {
+        let config = match self.repo.config() {
+            Ok(config) => config,
+            Err(_) => return !self.repo.is_bare(),
+        };
+        match config.get_string("core.logAllRefUpdates") {
+            Ok(value) if value.eq_ignore_ascii_case("always") => true,
+            Ok(_) => config
+                .get_bool("core.logAllRefUpdates")
+                .unwrap_or_else(|_| !self.repo.is_bare()),
+            Err(_) => !self.repo.is_bare(),
+        }
+    }
+
+    /// Shortest unambiguous prefix of `id`, at least as long as `core.abbrev` asks for
+    pub fn abbrev_id(&self, id: git2::Oid) -> String {
+        let full = id.to_string();
+        let min_len = match self.abbrev_len() {
+            Some(min_len) => min_len,
+            None => return full,
+        };
+        let odb = match self.repo.odb() {
+            Ok(odb) => odb,
+            Err(_) => return full,
+        };
+        (min_len..full.len())
+            .find(|len| odb.exists_prefix(id, *len).is_ok())
+            .map(|len| full[..len].to_owned())
+            .unwrap_or(full)
+    }
+
+    /// Hex digits to abbreviate ids to, per `core.abbrev`, or `None` to not abbreviate
+    fn abbrev_len(&self) -> Option<usize> {
+        let config = match self.repo.config() {
+            Ok(config) => config,
+            Err(_) => return Some(DEFAULT_ABBREV),
+        };
+        match config.get_string("core.abbrev") {
+            Ok(value) if value.eq_ignore_ascii_case("auto") => Some(DEFAULT_ABBREV),
+            Ok(value) => match value.parse::<usize>() {
+                Ok(len) => Some(len.max(MIN_ABBREV)),
+                Err(_) => match config.get_bool("core.abbrev") {
+                    Ok(false) => None,
+                    _ => Some(DEFAULT_ABBREV),
+                },
+            },
+            Err(_) => Some(DEFAULT_ABBREV),
+        }
+    }
+
     pub fn delete_branch(&mut self, name: &str) -> Result<(), git2::Error> {
+        if self.sandbox {
+            let mut reference = self
+                .repo
+                .find_reference(&format!("{}{}", SANDBOX_PREFIX, name))?;
+            return reference.delete();
+        }
+
         // HACK: We shouldn't limit ourselves to `Local`
-        let mut branch = self.repo.find_branch(name, git2::BranchType::Local)?;
-        branch.delete()
+        let mut branch = self.repo.find_reference(&self.branch_ref(name))?;
+        let dangling = branch
+            .name()
+            .map(|target| self.symbolic_refs_to(target))
+            .unwrap_or_default();
+        branch.delete()?;
+        for symbolic in dangling {
+            log::warn!(
+                "`{}` points to `{}` which was deleted, update or remove it",
+                symbolic,
+                name
+            );
+        }
+        Ok(())
+    }
+
+    /// Rename a local branch, bringing along its reflog and `branch.<name>.*` config, like
+    /// `git branch -m`
+    pub fn rename_branch(&mut self, old: &str, new: &str) -> Result<(), git2::Error> {
+        let message = format!("git-stack: rename {} to {}", old, new);
+        if self.sandbox {
+            let mut reference = self
+                .repo
+                .find_reference(&format!("{}{}", SANDBOX_PREFIX, old))?;
+            reference.rename(&format!("{}{}", SANDBOX_PREFIX, new), false, &message)?;
+            return Ok(());
+        }
+
+        let mut reference = self.repo.find_reference(&self.branch_ref(old))?;
+        // libgit2 only moves the config of branches under `refs/heads/`, so move it ourselves for
+        // namespaced branches too
+        reference.rename(&self.branch_ref(new), false, &message)?;
+        self.move_branch_config(old, new)?;
+        Ok(())
+    }
+
+    /// Move the `branch.<old>.*` config section to `branch.<new>.*`
+    fn move_branch_config(&self, old: &str, new: &str) -> Result<(), git2::Error> {
+        // Only the repo's own config is the branch's
+        let mut config = self.repo.config()?.open_level(git2::ConfigLevel::Local)?;
+        let mut entries = Vec::new();
+        {
+            let snapshot = config.snapshot()?;
+            // libgit2 takes POSIX regexes, which don't allow escaping everything `regex` does
+            let mut escaped = String::new();
+            for c in old.chars() {
+                if r".[]{}()\*+?^$|".contains(c) {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            let pattern = format!(r"^branch\.{}\.[^.]+$", escaped);
+            let all = snapshot.entries(Some(&pattern))?;
+            let all_ref = &all;
+            for entry in all_ref.flat_map(|e| e.into_iter()) {
+                if let (Some(name), Some(value)) = (entry.name(), entry.value()) {
+                    entries.push((name.to_owned(), value.to_owned()));
+                }
+            }
+        }
+
+        let prefix = format!("branch.{}.", old);
+        for (name, _) in entries.iter() {
+            // Multi-valued keys have to be removed all at once
+            let _ = config.remove_multivar(name, ".*");
+        }
+        for (name, value) in entries {
+            let key = &name[prefix.len()..];
+            // Never matches an existing value, so each value is added
+            config.set_multivar(&format!("branch.{}.{}", new, key), "$^", &value)?;
+        }
+        Ok(())
+    }
+
+    /// Symbolic refs whose target is `target`
+    ///
+    /// These follow the branch when it moves but dangle when it is deleted.
+    fn symbolic_refs_to(&self, target: &str) -> Vec<String> {
+        self.repo
+            .references()
+            .into_iter()
+            .flat_map(|references| {
+                references
+                    .filter_map(|reference| {
+                        let reference = reference.ok()?;
+                        if reference.symbolic_target() == Some(target) {
+                            reference.name().map(ToOwned::to_owned)
+                        } else {
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
     }
 
     pub fn find_local_branch(&self, name: &str) -> Option<Branch> {
-        let branch = self.repo.find_branch(name, git2::BranchType::Local).ok()?;
-        let id = branch.get().target().unwrap();
+        let id = if self.sandbox {
+            self.repo
+                .find_reference(&format!("{}{}", SANDBOX_PREFIX, name))
+                .ok()?
+                .target()?
+        } else {
+            let branch = self.repo.find_reference(&self.branch_ref(name)).ok()?;
+            // Symbolic refs move with their target rather than being a branch of their own
+            branch.target()?
+        };
 
         let push_id = self
             .repo
@@ -462,9 +1671,38 @@ impl GitRepo {
 
     pub fn local_branches(&self) -> impl Iterator<Item = Branch> + '_ {
         log::trace!("Loading branches");
+        // Both the sandbox and namespaces live outside of `refs/heads/`
+        let prefix = if self.sandbox {
+            Some(SANDBOX_PREFIX)
+        } else if self.namespace.is_some() {
+            Some(self.branch_prefix.as_str())
+        } else {
+            None
+        };
+        let prefixed_names: Vec<String> = prefix
+            .map(|prefix| {
+                self.repo
+                    .references_glob(&format!("{}*", prefix))
+                    .into_iter()
+                    .flat_map(|mut references| {
+                        references
+                            .names()
+                            .filter_map(|name| {
+                                name.ok()?.strip_prefix(prefix).map(ToOwned::to_owned)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let prefixed_branches = prefixed_names
+            .into_iter()
+            .filter_map(move |name| self.find_local_branch(&name));
+
         self.repo
             .branches(Some(git2::BranchType::Local))
             .into_iter()
+            .filter(move |_| prefix.is_none())
             .flatten()
             .flat_map(move |branch| {
                 let (branch, _) = branch.ok()?;
@@ -477,7 +1715,16 @@ impl GitRepo {
                     );
                     return None;
                 };
-                let id = branch.get().target().unwrap();
+                let id = if let Some(id) = branch.get().target() {
+                    id
+                } else {
+                    log::debug!(
+                        "Ignoring symbolic branch {} (-> {})",
+                        name,
+                        branch.get().symbolic_target().unwrap_or_default()
+                    );
+                    return None;
+                };
 
                 let push_id = self
                     .repo
@@ -503,9 +1750,38 @@ impl GitRepo {
                     pull_id,
                 })
             })
+            .chain(prefixed_branches)
+    }
+
+    /// Tag names with the commit each points to, skipping tags of anything but a commit
+    pub fn tags(&self) -> impl Iterator<Item = (String, git2::Oid)> + '_ {
+        log::trace!("Loading tags");
+        let names = self.repo.tag_names(None).ok();
+        names
+            .into_iter()
+            .flat_map(|names| {
+                names
+                    .iter()
+                    .flatten()
+                    .map(ToOwned::to_owned)
+                    .collect::<Vec<_>>()
+            })
+            .filter_map(move |name| {
+                let commit = self
+                    .repo
+                    .find_reference(&format!("refs/tags/{}", name))
+                    .and_then(|r| r.peel_to_commit())
+                    .ok()?;
+                Some((name, commit.id()))
+            })
     }
 
     pub fn detach(&mut self) -> Result<(), git2::Error> {
+        if self.sandbox {
+            log::trace!("Leaving HEAD alone in the sandbox");
+            return Ok(());
+        }
+
         let head_id = self
             .repo
             .head()
@@ -519,14 +1795,124 @@ impl GitRepo {
     }
 
     pub fn switch(&mut self, name: &str) -> Result<(), git2::Error> {
+        if self.sandbox {
+            log::trace!("Leaving HEAD alone in the sandbox");
+            self.repo
+                .find_reference(&format!("{}{}", SANDBOX_PREFIX, name))?;
+            return Ok(());
+        }
+
         // HACK: We shouldn't limit ourselves to `Local`
-        let branch = self.repo.find_branch(name, git2::BranchType::Local)?;
-        self.repo.set_head(branch.get().name().unwrap())?;
+        let branch = self.repo.find_reference(&self.branch_ref(name))?;
+        self.check_untracked(&branch.peel_to_tree()?)?;
+        if self.namespace.is_some() {
+            // `set_head` detaches for anything outside of `refs/heads/`
+            self.repo.reference_symbolic(
+                "HEAD",
+                branch.name().unwrap(),
+                true,
+                &format!("git-stack: switch to {}", name),
+            )?;
+        } else {
+            self.repo.set_head(branch.name().unwrap())?;
+        }
+        let mut builder = git2::build::CheckoutBuilder::new();
+        builder.force();
+        self.repo.checkout_head(Some(&mut builder))?;
+        Ok(())
+    }
+
+    pub fn switch_commit(&mut self, id: git2::Oid) -> Result<(), git2::Error> {
+        if self.sandbox {
+            return Err(git2::Error::new(
+                git2::ErrorCode::Invalid,
+                git2::ErrorClass::Checkout,
+                "cannot check out commits in the sandbox",
+            ));
+        }
+
+        self.check_untracked(&self.repo.find_commit(id)?.tree()?)?;
+        self.repo.set_head_detached(id)?;
         let mut builder = git2::build::CheckoutBuilder::new();
         builder.force();
         self.repo.checkout_head(Some(&mut builder))?;
         Ok(())
     }
+
+    /// Refuse to check out `tree` over untracked files, which a forced checkout would overwrite
+    fn check_untracked(&self, tree: &git2::Tree<'_>) -> Result<(), git2::Error> {
+        let statuses = self.repo.statuses(Some(
+            git2::StatusOptions::new()
+                .include_untracked(true)
+                .recurse_untracked_dirs(true)
+                .include_ignored(false),
+        ))?;
+        let overwritten: Vec<_> = statuses
+            .iter()
+            .filter(|entry| entry.status().contains(git2::Status::WT_NEW))
+            .filter_map(|entry| entry.path().map(|path| path.to_owned()))
+            .filter(|path| {
+                // A file in the tree at the path, or in place of one of its directories
+                let path = std::path::Path::new(path);
+                path.ancestors()
+                    .filter(|ancestor| !ancestor.as_os_str().is_empty())
+                    .any(|ancestor| match tree.get_path(ancestor) {
+                        Ok(entry) => {
+                            ancestor == path || entry.kind() != Some(git2::ObjectType::Tree)
+                        }
+                        Err(_) => false,
+                    })
+            })
+            .collect();
+        if overwritten.is_empty() {
+            Ok(())
+        } else {
+            Err(git2::Error::new(
+                git2::ErrorCode::Conflict,
+                git2::ErrorClass::Checkout,
+                format!(
+                    "untracked working tree files would be overwritten: {}",
+                    overwritten.join(", ")
+                ),
+            ))
+        }
+    }
+
+    pub fn exec(&mut self, id: git2::Oid, command: &str) -> Result<(), git2::Error> {
+        self.switch_commit(id)?;
+        let workdir = self.repo.workdir().ok_or_else(|| {
+            git2::Error::new(
+                git2::ErrorCode::BareRepo,
+                git2::ErrorClass::Repository,
+                "cannot run commands in a bare repository",
+            )
+        })?;
+
+        // Like git, defer to the shell so the command can carry its own arguments
+        let status = crate::git::shell_command(command, &[])
+            .current_dir(workdir)
+            .status()
+            .map_err(|err| {
+                git2::Error::new(
+                    git2::ErrorCode::GenericError,
+                    git2::ErrorClass::Os,
+                    format!("could not launch `{}`: {}", command, err),
+                )
+            })?;
+        if !status.success() {
+            return Err(git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!(
+                    "`{}` failed at {} ({})",
+                    command,
+                    self.abbrev_id(id),
+                    status
+                ),
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl Repo for GitRepo {
@@ -542,6 +1928,16 @@ impl Repo for GitRepo {
         self.find_commit(id)
     }
 
+    fn patch_id(&self, id: git2::Oid) -> Option<git2::Oid> {
+        // Diffing is slow and the same protected commits are checked for each stack
+        *self.patch_ids.borrow_mut().entry(id).or_insert_with(|| {
+            self.patch_id(id).unwrap_or_else(|err| {
+                log::debug!("Could not compute the patch-id of {}: {}", id, err);
+                None
+            })
+        })
+    }
+
     fn head_commit(&self) -> std::rc::Rc<Commit> {
         self.head_commit()
     }
@@ -550,6 +1946,10 @@ impl Repo for GitRepo {
         self.head_branch()
     }
 
+    fn fingerprint(&self) -> Option<git2::Oid> {
+        self.fingerprint()
+    }
+
     fn resolve(&self, revspec: &str) -> Option<std::rc::Rc<Commit>> {
         self.resolve(revspec)
     }
@@ -561,6 +1961,17 @@ impl Repo for GitRepo {
         Box::new(self.commits_from(head_id))
     }
 
+    fn graph_commits_from(
+        &self,
+        head_id: git2::Oid,
+    ) -> Box<dyn Iterator<Item = std::rc::Rc<Commit>> + '_> {
+        Box::new(self.graph_commits_from(head_id))
+    }
+
+    fn parent_ids(&self, id: git2::Oid) -> Result<Vec<git2::Oid>, git2::Error> {
+        self.parent_ids(id)
+    }
+
     fn contains_commit(
         &self,
         haystack_id: git2::Oid,
@@ -581,8 +1992,21 @@ impl Repo for GitRepo {
         self.squash(head_id, into_id)
     }
 
-    fn branch(&mut self, name: &str, id: git2::Oid) -> Result<(), git2::Error> {
-        self.branch(name, id)
+    fn remerge(
+        &mut self,
+        head_id: git2::Oid,
+        merge_id: git2::Oid,
+        other_parent_ids: &[git2::Oid],
+    ) -> Result<git2::Oid, git2::Error> {
+        self.remerge(head_id, merge_id, other_parent_ids)
+    }
+
+    fn reword(&mut self, id: git2::Oid, message: &str) -> Result<git2::Oid, git2::Error> {
+        self.reword(id, message)
+    }
+
+    fn branch(&mut self, name: &str, id: git2::Oid, reason: &str) -> Result<(), git2::Error> {
+        self.branch(name, id, reason)
     }
 
     fn delete_branch(&mut self, name: &str) -> Result<(), git2::Error> {
@@ -604,12 +2028,21 @@ impl Repo for GitRepo {
     fn switch(&mut self, name: &str) -> Result<(), git2::Error> {
         self.switch(name)
     }
+
+    fn switch_commit(&mut self, id: git2::Oid) -> Result<(), git2::Error> {
+        self.switch_commit(id)
+    }
+
+    fn exec(&mut self, id: git2::Oid, command: &str) -> Result<(), git2::Error> {
+        self.exec(id, command)
+    }
 }
 
 pub struct InMemoryRepo {
     commits: std::collections::HashMap<git2::Oid, (Option<git2::Oid>, std::rc::Rc<Commit>)>,
     branches: std::collections::HashMap<String, Branch>,
     head_id: Option<git2::Oid>,
+    max_history: Option<usize>,
 
     last_id: std::sync::atomic::AtomicUsize,
 }
@@ -620,6 +2053,7 @@ impl InMemoryRepo {
             commits: Default::default(),
             branches: Default::default(),
             head_id: Default::default(),
+            max_history: None,
             last_id: std::sync::atomic::AtomicUsize::new(1),
         }
     }
@@ -632,7 +2066,8 @@ impl InMemoryRepo {
         let last_id = self
             .last_id
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-        let sha = format!("{:x}", last_id);
+        // Left-pad, since `Oid::from_str` right-pads and `0x10` would collide with `0x1`
+        let sha = format!("{:040x}", last_id);
         git2::Oid::from_str(&sha).unwrap()
     }
 
@@ -654,6 +2089,10 @@ impl InMemoryRepo {
         self.head_id = Some(head_id)
     }
 
+    pub fn set_max_history(&mut self, max_history: Option<usize>) {
+        self.max_history = max_history;
+    }
+
     pub fn mark_branch(&mut self, branch: Branch) {
         assert!(self.commits.contains_key(&branch.id));
         self.branches.insert(branch.name.clone(), branch);
@@ -675,10 +2114,23 @@ impl InMemoryRepo {
         self.commits.get(&id).map(|c| c.1.clone())
     }
 
+    pub fn patch_id(&self, id: git2::Oid) -> Option<git2::Oid> {
+        // Cherry-picks copy the tree id, so it stands in for the change a commit makes
+        self.find_commit(id).map(|c| c.tree_id)
+    }
+
     pub fn head_commit(&self) -> std::rc::Rc<Commit> {
         self.commits.get(&self.head_id.unwrap()).cloned().unwrap().1
     }
 
+    pub fn fingerprint(&self) -> Option<git2::Oid> {
+        let mut id = self.head_id?;
+        while let Some(parent_id) = self.commits.get(&id)?.0 {
+            id = parent_id;
+        }
+        Some(id)
+    }
+
     pub fn head_branch(&self) -> Option<Branch> {
         self.branches
             .values()
@@ -687,8 +2139,19 @@ impl InMemoryRepo {
     }
 
     pub fn resolve(&self, revspec: &str) -> Option<std::rc::Rc<Commit>> {
-        let branch = self.branches.get(revspec)?;
-        self.find_commit(branch.id)
+        if let Some(branch) = self.branches.get(revspec) {
+            return self.find_commit(branch.id);
+        }
+
+        // Abbreviated ids, like `git rev-parse`
+        let mut matches = self
+            .commits
+            .keys()
+            .filter(|id| id.to_string().starts_with(revspec));
+        match (matches.next(), matches.next()) {
+            (Some(id), None) => self.find_commit(*id),
+            _ => None,
+        }
     }
 
     pub fn commits_from(
@@ -702,6 +2165,17 @@ impl InMemoryRepo {
         }
     }
 
+    pub fn parent_ids(&self, id: git2::Oid) -> Result<Vec<git2::Oid>, git2::Error> {
+        let (parent_id, _) = self.commits.get(&id).ok_or_else(|| {
+            git2::Error::new(
+                git2::ErrorCode::NotFound,
+                git2::ErrorClass::Reference,
+                format!("could not find commit {:?}", id),
+            )
+        })?;
+        Ok(parent_id.iter().copied().collect())
+    }
+
     pub fn contains_commit(
         &self,
         haystack_id: git2::Oid,
@@ -769,7 +2243,25 @@ impl InMemoryRepo {
         Ok(new_id)
     }
 
-    fn branch(&mut self, name: &str, id: git2::Oid) -> Result<(), git2::Error> {
+    pub fn reword(&mut self, id: git2::Oid, message: &str) -> Result<git2::Oid, git2::Error> {
+        let (parent, commit) = self.commits.get(&id).cloned().ok_or_else(|| {
+            git2::Error::new(
+                git2::ErrorCode::NotFound,
+                git2::ErrorClass::Reference,
+                format!("could not find commit {:?}", id),
+            )
+        })?;
+
+        let mut reworded_commit = Commit::clone(&commit);
+        let new_id = self.gen_id();
+        reworded_commit.id = new_id;
+        reworded_commit.summary = message.lines().next().unwrap_or_default().into();
+        self.commits
+            .insert(new_id, (parent, std::rc::Rc::new(reworded_commit)));
+        Ok(new_id)
+    }
+
+    fn branch(&mut self, name: &str, id: git2::Oid, _reason: &str) -> Result<(), git2::Error> {
         self.branches.insert(
             name.to_owned(),
             Branch {
@@ -815,6 +2307,27 @@ impl InMemoryRepo {
         self.head_id = Some(branch.id);
         Ok(())
     }
+
+    pub fn switch_commit(&mut self, id: git2::Oid) -> Result<(), git2::Error> {
+        self.find_commit(id).ok_or_else(|| {
+            git2::Error::new(
+                git2::ErrorCode::NotFound,
+                git2::ErrorClass::Reference,
+                format!("could not find commit {:?}", id),
+            )
+        })?;
+        self.head_id = Some(id);
+        Ok(())
+    }
+
+    pub fn exec(&mut self, id: git2::Oid, command: &str) -> Result<(), git2::Error> {
+        self.switch_commit(id)?;
+        Err(git2::Error::new(
+            git2::ErrorCode::Invalid,
+            git2::ErrorClass::Repository,
+            format!("cannot run `{}` without a working tree", command),
+        ))
+    }
 }
 
 impl Default for InMemoryRepo {
@@ -855,6 +2368,10 @@ impl Repo for InMemoryRepo {
         self.find_commit(id)
     }
 
+    fn patch_id(&self, id: git2::Oid) -> Option<git2::Oid> {
+        self.patch_id(id)
+    }
+
     fn head_commit(&self) -> std::rc::Rc<Commit> {
         self.head_commit()
     }
@@ -870,6 +2387,21 @@ impl Repo for InMemoryRepo {
         Box::new(self.commits_from(head_id))
     }
 
+    fn graph_commits_from(
+        &self,
+        head_id: git2::Oid,
+    ) -> Box<dyn Iterator<Item = std::rc::Rc<Commit>> + '_> {
+        // Commits only have one parent, so there is nothing to flatten
+        Box::new(
+            self.commits_from(head_id)
+                .take(self.max_history.unwrap_or(usize::MAX)),
+        )
+    }
+
+    fn parent_ids(&self, id: git2::Oid) -> Result<Vec<git2::Oid>, git2::Error> {
+        self.parent_ids(id)
+    }
+
     fn contains_commit(
         &self,
         haystack_id: git2::Oid,
@@ -890,12 +2422,33 @@ impl Repo for InMemoryRepo {
         self.squash(head_id, into_id)
     }
 
+    fn remerge(
+        &mut self,
+        _head_id: git2::Oid,
+        merge_id: git2::Oid,
+        _other_parent_ids: &[git2::Oid],
+    ) -> Result<git2::Oid, git2::Error> {
+        Err(git2::Error::new(
+            git2::ErrorCode::Invalid,
+            git2::ErrorClass::Merge,
+            format!("{} is not a merge commit", merge_id),
+        ))
+    }
+
+    fn reword(&mut self, id: git2::Oid, message: &str) -> Result<git2::Oid, git2::Error> {
+        self.reword(id, message)
+    }
+
     fn head_branch(&self) -> Option<Branch> {
         self.head_branch()
     }
 
-    fn branch(&mut self, name: &str, id: git2::Oid) -> Result<(), git2::Error> {
-        self.branch(name, id)
+    fn fingerprint(&self) -> Option<git2::Oid> {
+        self.fingerprint()
+    }
+
+    fn branch(&mut self, name: &str, id: git2::Oid, reason: &str) -> Result<(), git2::Error> {
+        self.branch(name, id, reason)
     }
 
     fn delete_branch(&mut self, name: &str) -> Result<(), git2::Error> {
@@ -917,6 +2470,14 @@ impl Repo for InMemoryRepo {
     fn switch(&mut self, name: &str) -> Result<(), git2::Error> {
         self.switch(name)
     }
+
+    fn switch_commit(&mut self, id: git2::Oid) -> Result<(), git2::Error> {
+        self.switch_commit(id)
+    }
+
+    fn exec(&mut self, id: git2::Oid, command: &str) -> Result<(), git2::Error> {
+        self.exec(id, command)
+    }
 }
 
 // From git2 crate
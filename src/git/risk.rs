@@ -0,0 +1,274 @@
+//! Rate how risky planned changes to branches are, for `stack.risk-gate`
+
+use std::collections::HashSet;
+
+use itertools::Itertools;
+
+use super::Repo;
+
+/// How risky a change to a branch is
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Risk {
+    /// Fast-forwards and restacks that can't change the content
+    Low,
+    /// Restacks predicted to conflict, or that may change the content
+    Medium,
+    /// Deleting branches and force-pushing over commits only on the remote
+    High,
+}
+
+impl std::fmt::Display for Risk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Risk::Low => "low risk".fmt(f),
+            Risk::Medium => "medium risk".fmt(f),
+            Risk::High => "high risk".fmt(f),
+        }
+    }
+}
+
+/// A planned change to a branch, and how risky it is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BranchChange {
+    pub branch: String,
+    pub description: String,
+    pub risk: Risk,
+}
+
+/// What to do about `changes`, per `risk_gate`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Gate {
+    Apply,
+    /// Ask before applying changes this risky
+    Ask(Risk),
+    /// Refuse high risk changes
+    Refuse,
+}
+
+impl Gate {
+    pub fn for_changes(risk_gate: crate::config::RiskGate, changes: &[BranchChange]) -> Self {
+        if risk_gate == crate::config::RiskGate::Off {
+            return Gate::Apply;
+        }
+        match changes.iter().map(|c| c.risk).max() {
+            Some(Risk::High) if risk_gate == crate::config::RiskGate::Strict => Gate::Refuse,
+            Some(risk) if Risk::Low < risk => Gate::Ask(risk),
+            _ => Gate::Apply,
+        }
+    }
+}
+
+/// The commits and branches a plan touches, to rate its changes to each branch by
+pub struct PlannedChanges {
+    picked: HashSet<git2::Oid>,
+    picked_patches: Option<HashSet<git2::Oid>>,
+    created: HashSet<String>,
+    deleted: HashSet<String>,
+}
+
+impl PlannedChanges {
+    pub fn new(scripts: &[crate::git::Script]) -> Self {
+        let mut picked = HashSet::new();
+        let mut created = HashSet::new();
+        let mut deleted = HashSet::new();
+        for script in scripts {
+            script.walk(&mut |command| match command {
+                crate::git::Command::CherryPick(id) | crate::git::Command::Squash(id) => {
+                    picked.insert(*id);
+                }
+                crate::git::Command::CreateBranch(name) => {
+                    created.insert(name.clone());
+                }
+                crate::git::Command::DeleteBranch(name) => {
+                    deleted.insert(name.clone());
+                }
+                _ => {}
+            });
+        }
+        Self {
+            picked,
+            picked_patches: None,
+            created,
+            deleted,
+        }
+    }
+
+    /// Rate the plan's change to each of `branches`, restacked from `base_id` onto `onto`
+    pub fn assess(
+        &mut self,
+        repo: &crate::git::GitRepo,
+        base_id: git2::Oid,
+        onto: &crate::git::Branch,
+        branches: &[&crate::git::Branch],
+    ) -> Vec<BranchChange> {
+        let mut changes = Vec::new();
+        for branch in branches {
+            let mut change = |description: String, risk: Risk| {
+                changes.push(BranchChange {
+                    branch: branch.name.clone(),
+                    description,
+                    risk,
+                })
+            };
+            if self.deleted.contains(&branch.name) {
+                change("deleted".to_owned(), Risk::High);
+                continue;
+            }
+            if !self.created.contains(&branch.name) {
+                continue;
+            }
+            let old_base = match repo.merge_base(branch.id, base_id) {
+                Some(old_base) => old_base,
+                None => continue,
+            };
+
+            let own = commit_range(repo, old_base, branch.id);
+            if own.is_empty() {
+                change("fast-forwards".to_owned(), Risk::Low);
+                continue;
+            }
+            let dropped = own
+                .iter()
+                .copied()
+                .filter(|id| !self.is_picked(repo, *id))
+                .count();
+            if dropped != 0 {
+                change(
+                    format!("changes content, drops {} commit(s)", dropped),
+                    Risk::Medium,
+                );
+                continue;
+            }
+
+            if let Some(paths) = predict_conflict(repo, onto.id, &own) {
+                change(
+                    format!(
+                        "conflicts in {}",
+                        paths.iter().map(|p| p.display()).join(", ")
+                    ),
+                    Risk::Medium,
+                );
+                continue;
+            }
+            let upstream_paths = changed_paths(repo, old_base, onto.id);
+            let mut overlap: Vec<_> = changed_paths(repo, old_base, branch.id)
+                .into_iter()
+                .filter(|p| upstream_paths.contains(p))
+                .collect();
+            if overlap.is_empty() {
+                change("preserves content".to_owned(), Risk::Low);
+            } else {
+                overlap.sort();
+                change(
+                    format!(
+                        "may change content, {} also changed on `{}`",
+                        overlap.iter().map(|p| p.display()).join(", "),
+                        onto.name
+                    ),
+                    Risk::Medium,
+                );
+            }
+        }
+        changes
+    }
+
+    /// A commit deduplicated with a copy on a sibling branch is still picked, just as the copy
+    fn is_picked(&mut self, repo: &crate::git::GitRepo, id: git2::Oid) -> bool {
+        if self.picked.contains(&id) {
+            return true;
+        }
+        let picked = &self.picked;
+        let picked_patches = self.picked_patches.get_or_insert_with(|| {
+            picked
+                .iter()
+                .filter_map(|id| Repo::patch_id(repo, *id))
+                .collect()
+        });
+        matches!(Repo::patch_id(repo, id), Some(patch_id) if picked_patches.contains(&patch_id))
+    }
+}
+
+/// Rate force-pushing `branch` over its remote, when that discards commits only on the remote
+pub fn assess_push(
+    repo: &crate::git::GitRepo,
+    branch: &crate::git::Branch,
+) -> Option<BranchChange> {
+    let push_id = branch.push_id.filter(|push_id| *push_id != branch.id)?;
+    let base = repo.merge_base(push_id, branch.id)?;
+    let local: HashSet<_> = commit_range(repo, base, branch.id)
+        .into_iter()
+        .filter_map(|id| Repo::patch_id(repo, id))
+        .collect();
+    let discarded = commit_range(repo, base, push_id)
+        .into_iter()
+        .filter(|id| !matches!(Repo::patch_id(repo, *id), Some(p) if local.contains(&p)))
+        .count();
+    if discarded == 0 {
+        return None;
+    }
+    Some(BranchChange {
+        branch: branch.name.clone(),
+        description: format!("discards {} commit(s) only on the remote", discarded),
+        risk: Risk::High,
+    })
+}
+
+/// Where cherry-picking `ids`, newest first, onto `onto_id` would conflict, per
+/// `GitRepo::predict_cherry_pick`
+fn predict_conflict(
+    repo: &crate::git::GitRepo,
+    onto_id: git2::Oid,
+    ids: &[git2::Oid],
+) -> Option<Vec<std::path::PathBuf>> {
+    let mut tree_id = Repo::find_commit(repo, onto_id)?.tree_id;
+    for id in ids.iter().rev() {
+        match repo.predict_cherry_pick(tree_id, *id) {
+            Ok(crate::git::PickPrediction::Clean(next)) => {
+                tree_id = next;
+            }
+            Ok(crate::git::PickPrediction::Conflict(paths)) => {
+                return Some(paths);
+            }
+            Err(err) => {
+                log::debug!("Could not predict cherry-picking {}: {}", id, err.message());
+                return None;
+            }
+        }
+    }
+    None
+}
+
+/// Commits reachable from `id` but not `base_id`, newest first
+fn commit_range(repo: &crate::git::GitRepo, base_id: git2::Oid, id: git2::Oid) -> Vec<git2::Oid> {
+    let mut revwalk = match repo.raw().revwalk() {
+        Ok(revwalk) => revwalk,
+        Err(_) => return Vec::new(),
+    };
+    if revwalk.set_sorting(git2::Sort::TOPOLOGICAL).is_err()
+        || revwalk.push(id).is_err()
+        || revwalk.hide(base_id).is_err()
+    {
+        return Vec::new();
+    }
+    revwalk.filter_map(Result::ok).collect()
+}
+
+/// The paths that differ between the trees of `from_id` and `to_id`
+pub fn changed_paths(
+    repo: &crate::git::GitRepo,
+    from_id: git2::Oid,
+    to_id: git2::Oid,
+) -> HashSet<std::path::PathBuf> {
+    let tree = |id| repo.raw().find_commit(id).and_then(|c| c.tree()).ok();
+    let diff = match (tree(from_id), tree(to_id)) {
+        (Some(from), Some(to)) => repo.raw().diff_tree_to_tree(Some(&from), Some(&to), None),
+        _ => return Default::default(),
+    };
+    diff.map(|diff| {
+        diff.deltas()
+            .filter_map(|d| d.new_file().path().or_else(|| d.old_file().path()))
+            .map(|p| p.to_owned())
+            .collect()
+    })
+    .unwrap_or_default()
+}
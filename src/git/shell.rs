@@ -0,0 +1,51 @@
+//! Run user-configured commands through the shell, like git does
+
+/// Run `script` through the shell, `sh -c` or `cmd /C` on Windows, so it can carry its own
+/// arguments
+///
+/// `args` are appended to the script, as `"$@"` under `sh`.
+pub fn shell_command(script: &str, args: &[&std::ffi::OsStr]) -> std::process::Command {
+    log::trace!("{} {:?}", script, args);
+
+    #[cfg(windows)]
+    {
+        let mut command = std::process::Command::new("cmd");
+        command.arg("/C").arg(script).args(args);
+        command
+    }
+
+    #[cfg(not(windows))]
+    {
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c");
+        if args.is_empty() {
+            command.arg(script);
+        } else {
+            // `$0` is the script, as git does
+            command
+                .arg(format!("{} \"$@\"", script))
+                .arg(script)
+                .args(args);
+        }
+        command
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exit_status() {
+        let status = shell_command("exit 3", &[]).status().unwrap();
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn args_follow_the_script() {
+        let arg = std::ffi::OsStr::new("with space");
+        let output = shell_command("printf '%s|'", &[arg, arg]).output().unwrap();
+        assert_eq!(output.stdout, b"with space|with space|");
+    }
+}
@@ -0,0 +1,215 @@
+/// Signs commits the way `git commit` does, per `commit.gpgsign`, `gpg.format`, and
+/// `user.signingkey`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signer {
+    format: SignFormat,
+    program: String,
+    key: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum SignFormat {
+    OpenPgp,
+    X509,
+    Ssh,
+}
+
+impl Signer {
+    /// The signer git would use, if `commit.gpgsign` is on
+    ///
+    /// `ident` is the committer, like `Name <email>`, which gpg falls back to for a key.
+    pub fn from_config(config: &git2::Config, ident: Option<&str>) -> Result<Option<Self>, String> {
+        if !config.get_bool("commit.gpgsign").unwrap_or(false) {
+            return Ok(None);
+        }
+        Self::resolve(|key| config.get_string(key).ok(), ident).map(Some)
+    }
+
+    fn resolve(
+        config: impl Fn(&str) -> Option<String>,
+        ident: Option<&str>,
+    ) -> Result<Self, String> {
+        let format = match config("gpg.format").as_deref() {
+            None | Some("openpgp") => SignFormat::OpenPgp,
+            Some("x509") => SignFormat::X509,
+            Some("ssh") => SignFormat::Ssh,
+            Some(other) => return Err(format!("unsupported `gpg.format={}`", other)),
+        };
+        let program = match format {
+            SignFormat::OpenPgp => config("gpg.openpgp.program")
+                .or_else(|| config("gpg.program"))
+                .unwrap_or_else(|| "gpg".to_owned()),
+            SignFormat::X509 => config("gpg.x509.program").unwrap_or_else(|| "gpgsm".to_owned()),
+            SignFormat::Ssh => config("gpg.ssh.program").unwrap_or_else(|| "ssh-keygen".to_owned()),
+        };
+        let key = match (config("user.signingkey"), format) {
+            (Some(key), _) => key,
+            (None, SignFormat::Ssh) => {
+                return Err("`gpg.format=ssh` needs `user.signingkey` to sign with".to_owned())
+            }
+            (None, _) => ident
+                .map(ToOwned::to_owned)
+                .ok_or_else(|| "could not tell who to sign as, set `user.signingkey`".to_owned())?,
+        };
+        Ok(Self {
+            format,
+            program,
+            key,
+        })
+    }
+
+    /// Sign a commit buffer, returning the signature to embed in it
+    ///
+    /// `scratch` holds the files `ssh-keygen` needs.
+    pub fn sign(&self, buffer: &str, scratch: &std::path::Path) -> Result<String, git2::Error> {
+        match self.format {
+            SignFormat::OpenPgp | SignFormat::X509 => self.sign_gpg(buffer),
+            SignFormat::Ssh => self.sign_ssh(buffer, scratch),
+        }
+    }
+
+    fn sign_gpg(&self, buffer: &str) -> Result<String, git2::Error> {
+        use std::io::Write;
+
+        log::trace!("{} --status-fd=2 -bsau {}", self.program, self.key);
+        let mut child = std::process::Command::new(&self.program)
+            .arg("--status-fd=2")
+            .arg("-bsau")
+            .arg(&self.key)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .map_err(|err| sign_error(format!("could not launch `{}`: {}", self.program, err)))?;
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(buffer.as_bytes())
+            .map_err(|err| sign_error(format!("could not write to `{}`: {}", self.program, err)))?;
+        let output = child
+            .wait_with_output()
+            .map_err(|err| sign_error(format!("`{}` failed: {}", self.program, err)))?;
+        // Like git, trust the status line over the exit code
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() || !stderr.contains("[GNUPG:] SIG_CREATED ") {
+            return Err(sign_error(format!(
+                "`{}` could not sign with `{}`: {}",
+                self.program,
+                self.key,
+                stderr.trim()
+            )));
+        }
+        String::from_utf8(output.stdout)
+            .map_err(|_| sign_error(format!("`{}` wrote a non-UTF-8 signature", self.program)))
+    }
+
+    fn sign_ssh(&self, buffer: &str, scratch: &std::path::Path) -> Result<String, git2::Error> {
+        let io_error = |err: std::io::Error| sign_error(format!("could not sign: {}", err));
+        std::fs::create_dir_all(scratch).map_err(io_error)?;
+        let buffer_path = scratch.join("SIGN_BUFFER");
+        let signature_path = scratch.join("SIGN_BUFFER.sig");
+        let key_path = scratch.join("SIGN_KEY");
+        std::fs::write(&buffer_path, buffer).map_err(io_error)?;
+        let _ = std::fs::remove_file(&signature_path);
+
+        let mut command = std::process::Command::new(&self.program);
+        command.arg("-Y").arg("sign").arg("-n").arg("git");
+        // Like git, a literal public key means the private key is in `ssh-agent`
+        let literal = self
+            .key
+            .strip_prefix("key::")
+            .or_else(|| Some(self.key.as_str()).filter(|k| k.starts_with("ssh-")));
+        match literal {
+            Some(literal) => {
+                std::fs::write(&key_path, literal).map_err(io_error)?;
+                command.arg("-f").arg(&key_path).arg("-U");
+            }
+            None => {
+                command.arg("-f").arg(expand_home(&self.key));
+            }
+        }
+        command.arg(&buffer_path);
+        log::trace!("{:?}", command);
+        let output = command
+            .output()
+            .map_err(|err| sign_error(format!("could not launch `{}`: {}", self.program, err)));
+        let signature = output.and_then(|output| {
+            if output.status.success() {
+                std::fs::read_to_string(&signature_path).map_err(io_error)
+            } else {
+                Err(sign_error(format!(
+                    "`{}` could not sign with `{}`: {}",
+                    self.program,
+                    self.key,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )))
+            }
+        });
+        let _ = std::fs::remove_file(&buffer_path);
+        let _ = std::fs::remove_file(&signature_path);
+        let _ = std::fs::remove_file(&key_path);
+        signature
+    }
+}
+
+fn expand_home(path: &str) -> std::path::PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => std::path::Path::new(&home).join(rest),
+        _ => std::path::PathBuf::from(path),
+    }
+}
+
+fn sign_error(message: String) -> git2::Error {
+    git2::Error::new(git2::ErrorCode::GenericError, git2::ErrorClass::Os, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn resolve(config: &[(&str, &str)], ident: Option<&str>) -> Result<Signer, String> {
+        let lookup = |key: &str| {
+            config
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| (*v).to_owned())
+        };
+        Signer::resolve(lookup, ident)
+    }
+
+    #[test]
+    fn openpgp_defaults_to_committer() {
+        let signer = resolve(&[], Some("Ed <ed@example.com>")).unwrap();
+        assert_eq!(signer.format, SignFormat::OpenPgp);
+        assert_eq!(signer.program, "gpg");
+        assert_eq!(signer.key, "Ed <ed@example.com>");
+    }
+
+    #[test]
+    fn program_precedence() {
+        let config = [
+            ("gpg.openpgp.program", "gpg2"),
+            ("gpg.program", "other-gpg"),
+            ("user.signingkey", "ABCD"),
+        ];
+        assert_eq!(resolve(&config, None).unwrap().program, "gpg2");
+        assert_eq!(resolve(&config[1..], None).unwrap().program, "other-gpg");
+        assert_eq!(resolve(&config[2..], None).unwrap().key, "ABCD");
+    }
+
+    #[test]
+    fn ssh_needs_key() {
+        let config = [("gpg.format", "ssh"), ("user.signingkey", "~/.ssh/id.pub")];
+        let signer = resolve(&config, Some("Ed <ed@example.com>")).unwrap();
+        assert_eq!(signer.format, SignFormat::Ssh);
+        assert_eq!(signer.program, "ssh-keygen");
+        assert_eq!(signer.key, "~/.ssh/id.pub");
+        assert!(resolve(&config[..1], Some("Ed <ed@example.com>")).is_err());
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert!(resolve(&[("gpg.format", "pgp")], None).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+/// A `sed`-style `s/pattern/replacement/flags` edit of commit messages
+///
+/// `pattern` is a regex, matching `^` and `$` at line boundaries, and `replacement` refers to
+/// capture groups as `$1` or `${name}`.  Any punctuation can stand in for `/`, and is escaped with
+/// a `\`.  Flags are `g` to replace every match rather than the first and `i` to ignore case.
+#[derive(Clone, Debug)]
+pub struct Substitution {
+    regex: regex::Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl Substitution {
+    /// Edit `message`, returning `None` if nothing matched
+    pub fn apply(&self, message: &str) -> Option<String> {
+        if !self.regex.is_match(message) {
+            return None;
+        }
+        let limit = if self.global { 0 } else { 1 };
+        let replaced = self
+            .regex
+            .replacen(message, limit, self.replacement.as_str());
+        Some(replaced.into_owned())
+    }
+}
+
+impl std::str::FromStr for Substitution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        if chars.next() != Some('s') {
+            return Err(format!("expected `s/pattern/replacement/`, got `{}`", s));
+        }
+        let delimiter = match chars.next() {
+            Some(c) if c.is_ascii_punctuation() && c != '\\' => c,
+            _ => {
+                return Err(format!(
+                    "expected a delimiter like `/` after `s` in `{}`",
+                    s
+                ))
+            }
+        };
+
+        let mut parts = vec![String::new()];
+        while let Some(c) = chars.next() {
+            if c == delimiter {
+                parts.push(String::new());
+            } else if c == '\\' {
+                let part = parts.last_mut().unwrap();
+                match chars.next() {
+                    Some(next) if next == delimiter => part.push(next),
+                    Some(next) => {
+                        part.push(c);
+                        part.push(next);
+                    }
+                    None => part.push(c),
+                }
+            } else {
+                parts.last_mut().unwrap().push(c);
+            }
+        }
+        if parts.len() != 3 {
+            return Err(format!(
+                "expected `s{d}pattern{d}replacement{d}`, got `{}`",
+                s,
+                d = delimiter
+            ));
+        }
+        let flags = parts.pop().unwrap();
+        let replacement = parts.pop().unwrap();
+        let pattern = parts.pop().unwrap();
+        if pattern.is_empty() {
+            return Err(format!("empty pattern in `{}`", s));
+        }
+
+        let mut global = false;
+        let mut case_insensitive = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => case_insensitive = true,
+                _ => return Err(format!("unknown flag `{}` in `{}`", flag, s)),
+            }
+        }
+
+        let regex = regex::RegexBuilder::new(&pattern)
+            .multi_line(true)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|err| err.to_string())?;
+        Ok(Self {
+            regex,
+            replacement,
+            global,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn substitute(expr: &str, message: &str) -> Option<String> {
+        expr.parse::<Substitution>().unwrap().apply(message)
+    }
+
+    #[test]
+    fn first_match() {
+        assert_eq!(
+            substitute("s/JIRA-123/JIRA-456/", "JIRA-123: Fix it\n\nSee JIRA-123\n").unwrap(),
+            "JIRA-456: Fix it\n\nSee JIRA-123\n"
+        );
+    }
+
+    #[test]
+    fn global() {
+        assert_eq!(
+            substitute(
+                "s/JIRA-123/JIRA-456/g",
+                "JIRA-123: Fix it\n\nSee JIRA-123\n"
+            )
+            .unwrap(),
+            "JIRA-456: Fix it\n\nSee JIRA-456\n"
+        );
+    }
+
+    #[test]
+    fn no_match() {
+        assert_eq!(substitute("s/JIRA-123/JIRA-456/", "Fix it\n"), None);
+    }
+
+    #[test]
+    fn captures_and_flags() {
+        assert_eq!(
+            substitute(r"s/^fix (\w+)/Fix ${1}s/gi", "FIX bug\nfix typo\n").unwrap(),
+            "Fix bugs\nFix typos\n"
+        );
+    }
+
+    #[test]
+    fn escaped_delimiter() {
+        assert_eq!(
+            substitute(r"s#issue \#12#issue \#34#", "Fix issue #12\n").unwrap(),
+            "Fix issue #34\n"
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!("JIRA-123".parse::<Substitution>().is_err());
+        assert!("s/JIRA-123/JIRA-456".parse::<Substitution>().is_err());
+        assert!("s//JIRA-456/".parse::<Substitution>().is_err());
+        assert!("s/JIRA-123/JIRA-456/x".parse::<Substitution>().is_err());
+        assert!("s/(/x/".parse::<Substitution>().is_err());
+    }
+}
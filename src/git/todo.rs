@@ -0,0 +1,332 @@
+//! Editable text form of `Script`s, modeled on `git rebase --rebase-merges`
+//!
+//! Each `reset` to a `label` starts a dependent of the script that defined the label, so the text
+//! is flat while still round-tripping the tree of transactions.
+
+/// Name of the file the todo list is edited in
+pub const TODO_FILE: &str = "git-stack-todo";
+
+const ERROR_PREFIX: &str = "# error: ";
+
+const HELP: &str = "
+# Commands:
+# p, pick <commit> = use commit
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = meld commit into the previous one
+# b, break = stop here (continue with `git stack --continue`)
+# x, exec <command> = run command (the rest of the line) using shell, failing the branch if it fails
+# branch <name> = point branch at the current commit
+# delete-branch <name> = delete branch
+# l, label <label> = label the current commit
+# t, reset <label or commit> = continue from a label or commit
+#
+# Lines can be re-ordered; they are executed from top to bottom.
+#
+# If you remove a line here THAT COMMIT WILL BE LOST.
+#
+# However, if you remove everything, the rebase will be aborted.
+";
+
+/// Render `scripts` for the user to edit
+pub fn to_todo(scripts: &[crate::git::Script], repo: &dyn crate::git::Repo) -> String {
+    let mut todo = String::new();
+    for script in scripts {
+        if !todo.is_empty() {
+            todo.push('\n');
+        }
+        write_script(&mut todo, script, repo);
+    }
+    todo.push_str(HELP);
+    todo
+}
+
+fn write_script(todo: &mut String, script: &crate::git::Script, repo: &dyn crate::git::Repo) {
+    for command in script.commands.iter() {
+        let line = match command {
+            crate::git::Command::SwitchCommit(oid) => {
+                format!("reset {}{}", abbrev(repo, *oid), summary(repo, *oid))
+            }
+            crate::git::Command::RegisterMark(oid) => format!("label {}", abbrev(repo, *oid)),
+            crate::git::Command::SwitchMark(oid) => format!("reset {}", abbrev(repo, *oid)),
+            crate::git::Command::CherryPick(oid) => {
+                format!("pick {}{}", abbrev(repo, *oid), summary(repo, *oid))
+            }
+            crate::git::Command::Squash(oid) => {
+                format!("squash {}{}", abbrev(repo, *oid), summary(repo, *oid))
+            }
+            crate::git::Command::CreateBranch(name) => format!("branch {}", name),
+            crate::git::Command::DeleteBranch(name) => format!("delete-branch {}", name),
+            crate::git::Command::Break => "break".to_owned(),
+            crate::git::Command::Exec(command) => format!("exec {}", command),
+        };
+        todo.push_str(&line);
+        todo.push('\n');
+    }
+    for dependent in script.dependents.iter() {
+        todo.push('\n');
+        write_script(todo, dependent, repo);
+    }
+}
+
+/// Shortest unambiguous id, starting from git's default of 7
+pub(crate) fn abbrev(repo: &dyn crate::git::Repo, oid: git2::Oid) -> String {
+    let full = oid.to_string();
+    (7..full.len())
+        .map(|len| &full[..len])
+        .find(|short| repo.resolve(short).map(|c| c.id) == Some(oid))
+        .unwrap_or(&full)
+        .to_owned()
+}
+
+fn summary(repo: &dyn crate::git::Repo, oid: git2::Oid) -> String {
+    repo.find_commit(oid)
+        .map(|c| format!(" # {}", c.summary))
+        .unwrap_or_default()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TodoError {
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column, in characters
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for TodoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for TodoError {}
+
+/// Parse an edited todo list, reporting every problem found
+pub fn parse_todo(
+    todo: &str,
+    repo: &dyn crate::git::Repo,
+) -> Result<Vec<crate::git::Script>, Vec<TodoError>> {
+    // Scripts with the index of the script they depend on, in the order they are started
+    let mut scripts: Vec<(crate::git::Script, Option<usize>)> = Vec::new();
+    let mut labels: std::collections::HashMap<&str, (git2::Oid, usize)> = Default::default();
+    let mut errors = Vec::new();
+
+    for (index, line) in todo.lines().enumerate() {
+        let line_number = index + 1;
+        let mut tokens = tokens(line);
+        let (command_column, command) = match tokens.next() {
+            Some(token) => token,
+            None => continue,
+        };
+        if command.starts_with('#') {
+            continue;
+        }
+        let error = |column: usize, message: String| TodoError {
+            line: line_number,
+            column,
+            message,
+        };
+        let (arg_column, arg) = match tokens.next() {
+            Some(token) => token,
+            None if matches!(command, "b" | "break") => (0, ""),
+            None => {
+                let column = command_column + command.chars().count();
+                errors.push(error(
+                    column,
+                    format!("`{}` is missing an argument", command),
+                ));
+                continue;
+            }
+        };
+        let resolve_commit = |arg: &str| {
+            repo.resolve(arg)
+                .map(|c| c.id)
+                .ok_or_else(|| error(arg_column, format!("unknown commit `{}`", arg)))
+        };
+        let valid_branch = |arg: &str| {
+            if git2::Reference::is_valid_name(&format!("refs/heads/{}", arg)) {
+                Ok(arg.to_owned())
+            } else {
+                Err(error(arg_column, format!("invalid branch name `{}`", arg)))
+            }
+        };
+
+        let parsed = match command {
+            "t" | "reset" => {
+                if let Some((mark, parent)) = labels.get(arg) {
+                    let mut script = crate::git::Script::new();
+                    script.commands.push(crate::git::Command::SwitchMark(*mark));
+                    scripts.push((script, Some(*parent)));
+                    Ok(vec![])
+                } else {
+                    resolve_commit(arg).map(|oid| {
+                        let command = crate::git::Command::SwitchCommit(oid);
+                        match scripts.last_mut() {
+                            // Walking the base forward before any work is done
+                            Some((script, None))
+                                if script
+                                    .commands
+                                    .iter()
+                                    .all(|c| matches!(c, crate::git::Command::SwitchCommit(_))) =>
+                            {
+                                script.commands.push(command);
+                            }
+                            _ => {
+                                let mut script = crate::git::Script::new();
+                                script.commands.push(command);
+                                scripts.push((script, None));
+                            }
+                        }
+                        vec![]
+                    })
+                }
+            }
+            "l" | "label" => {
+                if labels.contains_key(arg) {
+                    Err(error(
+                        arg_column,
+                        format!("label `{}` already defined", arg),
+                    ))
+                } else {
+                    // Prefer the commit's id so that unedited plans round-trip exactly
+                    let mark = repo.resolve(arg).map(|c| c.id).unwrap_or_else(|| {
+                        git2::Oid::hash_object(git2::ObjectType::Blob, arg.as_bytes())
+                            .expect("hashing in-memory content is infallible")
+                    });
+                    if let Some(current) = scripts.len().checked_sub(1) {
+                        labels.insert(arg, (mark, current));
+                    }
+                    Ok(vec![crate::git::Command::RegisterMark(mark)])
+                }
+            }
+            "p" | "pick" => {
+                resolve_commit(arg).map(|oid| vec![crate::git::Command::CherryPick(oid)])
+            }
+            "e" | "edit" => resolve_commit(arg).map(|oid| {
+                vec![
+                    crate::git::Command::CherryPick(oid),
+                    crate::git::Command::Break,
+                ]
+            }),
+            "s" | "squash" => resolve_commit(arg).map(|oid| vec![crate::git::Command::Squash(oid)]),
+            "b" | "break" => Ok(vec![crate::git::Command::Break]),
+            // The command is the rest of the line, `#`s and all
+            "x" | "exec" => Ok(vec![crate::git::Command::Exec(
+                line.trim_start()[command.len()..].trim().to_owned(),
+            )]),
+            "branch" => valid_branch(arg).map(|name| vec![crate::git::Command::CreateBranch(name)]),
+            "delete-branch" => {
+                valid_branch(arg).map(|name| vec![crate::git::Command::DeleteBranch(name)])
+            }
+            _ => Err(error(
+                command_column,
+                format!("unknown command `{}`", command),
+            )),
+        };
+        match parsed {
+            Ok(commands) if commands.is_empty() => {}
+            Ok(commands) => match scripts.last_mut() {
+                Some((script, _)) => script.commands.extend(commands),
+                None => errors.push(error(
+                    command_column,
+                    "expected a `reset` to a commit first".to_owned(),
+                )),
+            },
+            Err(err) => errors.push(err),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // Dependents are always started after what they depend on, so assemble from the end
+    let mut roots = Vec::new();
+    let mut slots: Vec<_> = scripts.into_iter().map(Some).collect();
+    for index in (0..slots.len()).rev() {
+        let (script, parent) = slots[index].take().expect("each script is taken once");
+        match parent {
+            Some(parent) => {
+                let (parent, _) = slots[parent].as_mut().expect("parents come first");
+                parent.dependents.insert(0, script);
+            }
+            None => roots.insert(0, script),
+        }
+    }
+    Ok(roots)
+}
+
+/// Whitespace-separated tokens with their 1-based column, ignoring trailing comments
+fn tokens(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut column = 1;
+    let mut rest = line;
+    std::iter::from_fn(move || {
+        let trimmed = rest.trim_start();
+        column += rest[..rest.len() - trimmed.len()].chars().count();
+        if trimmed.is_empty() {
+            return None;
+        }
+        let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+        let token = &trimmed[..end];
+        let token_column = column;
+        column += token.chars().count();
+        rest = &trimmed[end..];
+        Some((token_column, token))
+    })
+    .enumerate()
+    // A comment can only follow the command and its argument
+    .take_while(|(i, (_, token))| *i == 0 || !token.starts_with('#'))
+    .map(|(_, token)| token)
+}
+
+/// Annotate `todo` with `errors`, replacing any prior annotations
+pub fn annotate_todo(todo: &str, errors: &[TodoError]) -> String {
+    let mut annotated = String::new();
+    for (index, line) in todo.lines().enumerate() {
+        if line.starts_with(ERROR_PREFIX) {
+            continue;
+        }
+        annotated.push_str(line);
+        annotated.push('\n');
+        for error in errors.iter().filter(|e| e.line == index + 1) {
+            annotated.push_str(&format!(
+                "{}column {}: {}\n",
+                ERROR_PREFIX, error.column, error.message
+            ));
+        }
+    }
+    annotated
+}
+
+/// Have the user edit `todo`, re-opening the editor with annotations until it parses
+pub fn edit_todo(
+    editor: &crate::git::Editor,
+    dir: &std::path::Path,
+    todo: &str,
+    repo: &dyn crate::git::Repo,
+) -> eyre::Result<Vec<crate::git::Script>> {
+    let mut todo = todo.to_owned();
+    loop {
+        let edited = editor.edit_text(dir, TODO_FILE, &todo)?;
+        match parse_todo(&edited, repo) {
+            Ok(scripts) => return Ok(scripts),
+            Err(errors) => {
+                for error in errors.iter() {
+                    log::error!("{}", error);
+                }
+                let annotated = annotate_todo(&edited, &errors);
+                if annotated == todo {
+                    // Nothing was fixed, so don't keep the user in a loop
+                    let path = dir.join(TODO_FILE);
+                    std::fs::write(&path, &annotated)?;
+                    eyre::bail!("invalid plan; your edits are saved in {}", path.display());
+                }
+                todo = annotated;
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+/// Append a `Signed-off-by: <identity>` trailer to `message`, like `git commit --signoff`
+///
+/// Returns `None` if `message` already has it.
+pub fn sign_off(message: &str, identity: &str) -> Option<String> {
+    let trailer = format!("Signed-off-by: {}", identity);
+    let message = message.trim_end();
+    let last_paragraph = message.rsplit("\n\n").next().unwrap_or("");
+    let has_trailers = message.contains("\n\n") && last_paragraph.lines().all(is_trailer);
+    if has_trailers && last_paragraph.lines().any(|l| l.trim_end() == trailer) {
+        return None;
+    }
+
+    let separator = if has_trailers { "\n" } else { "\n\n" };
+    Some(format!("{}{}{}\n", message, separator, trailer))
+}
+
+fn is_trailer(line: &str) -> bool {
+    match line.split_once(": ") {
+        Some((token, _)) => {
+            !token.is_empty() && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const IDENTITY: &str = "Jane Doe <jane@example.com>";
+
+    #[test]
+    fn summary_only() {
+        assert_eq!(
+            sign_off("Fix it\n", IDENTITY).unwrap(),
+            "Fix it\n\nSigned-off-by: Jane Doe <jane@example.com>\n"
+        );
+    }
+
+    #[test]
+    fn appends_to_trailers() {
+        assert_eq!(
+            sign_off("Fix it\n\nBody\n\nFixes: #12\n", IDENTITY).unwrap(),
+            "Fix it\n\nBody\n\nFixes: #12\nSigned-off-by: Jane Doe <jane@example.com>\n"
+        );
+    }
+
+    #[test]
+    fn body_is_not_trailers() {
+        assert_eq!(
+            sign_off("Fix it\n\nNote: this is prose, not a trailer\nreally\n", IDENTITY).unwrap(),
+            "Fix it\n\nNote: this is prose, not a trailer\nreally\n\nSigned-off-by: Jane Doe <jane@example.com>\n"
+        );
+    }
+
+    #[test]
+    fn already_signed_off() {
+        assert_eq!(
+            sign_off(
+                "Fix it\n\nSigned-off-by: Jane Doe <jane@example.com>\n",
+                IDENTITY
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn signed_off_by_someone_else() {
+        assert_eq!(
+            sign_off("Fix it\n\nSigned-off-by: Someone <a@b.c>\n", IDENTITY).unwrap(),
+            "Fix it\n\nSigned-off-by: Someone <a@b.c>\nSigned-off-by: Jane Doe <jane@example.com>\n"
+        );
+    }
+}
@@ -0,0 +1,179 @@
+/// The URL git fetches `remote` from or pushes it to, after `url.<base>.insteadOf` and
+/// `url.<base>.pushInsteadOf` rewrites
+pub fn remote_url(
+    repo: &git2::Repository,
+    remote: &str,
+    direction: git2::Direction,
+) -> Option<String> {
+    let config = repo.config().ok()?.snapshot().ok()?;
+    let rules = RewriteRules::from_config(&config);
+    let url = config.get_string(&format!("remote.{}.url", remote)).ok();
+    match direction {
+        git2::Direction::Fetch => url.map(|url| rules.fetch_url(&url)),
+        git2::Direction::Push => {
+            // Like git, an explicit `pushurl` is only subject to `insteadOf`
+            match config.get_string(&format!("remote.{}.pushurl", remote)) {
+                Ok(push_url) => Some(rules.fetch_url(&push_url)),
+                Err(_) => url.map(|url| rules.push_url(&url)),
+            }
+        }
+    }
+}
+
+/// `url.<base>.insteadOf` and `url.<base>.pushInsteadOf` rules, as `(base, prefix)` pairs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RewriteRules {
+    instead_of: Vec<(String, String)>,
+    push_instead_of: Vec<(String, String)>,
+}
+
+impl RewriteRules {
+    pub fn from_config(config: &git2::Config) -> Self {
+        let mut rules = Self::default();
+        let entries = match config.entries(Some(r"^url\..*\.(push)?insteadof$")) {
+            Ok(entries) => entries,
+            Err(_) => return rules,
+        };
+        for entry in &entries {
+            let (name, prefix) = match entry.as_ref().map(|e| (e.name(), e.value())) {
+                Ok((Some(name), Some(prefix))) => (name, prefix),
+                _ => continue,
+            };
+            rules.insert(name, prefix);
+        }
+        rules
+    }
+
+    /// Add a rule from a config entry, like `url.<base>.insteadof=<prefix>`
+    fn insert(&mut self, name: &str, prefix: &str) {
+        let name = match name.strip_prefix("url.") {
+            Some(name) => name,
+            None => return,
+        };
+        let (base, key) = match name.rsplit_once('.') {
+            Some(split) => split,
+            None => return,
+        };
+        let rule = (base.to_owned(), prefix.to_owned());
+        if key.eq_ignore_ascii_case("insteadof") {
+            self.instead_of.push(rule);
+        } else if key.eq_ignore_ascii_case("pushinsteadof") {
+            self.push_instead_of.push(rule);
+        }
+    }
+
+    pub fn fetch_url(&self, url: &str) -> String {
+        rewrite(&self.instead_of, url).unwrap_or_else(|| url.to_owned())
+    }
+
+    /// `pushInsteadOf` wins over `insteadOf`
+    pub fn push_url(&self, url: &str) -> String {
+        rewrite(&self.push_instead_of, url).unwrap_or_else(|| self.fetch_url(url))
+    }
+}
+
+/// The host `url` points at, for `scheme://[user@]host[:port]/path` and scp-like
+/// `[user@]host:path` URLs
+pub fn url_host(url: &str) -> Option<&str> {
+    let authority = match url.split_once("://") {
+        Some((_, rest)) => rest.split('/').next()?,
+        None => {
+            let (authority, _) = url.split_once(':')?;
+            // A local path, like `../repo` or `C:\repo`
+            if authority.contains('/') || authority.len() <= 1 {
+                return None;
+            }
+            authority
+        }
+    };
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = host.split(':').next()?;
+    if host.is_empty() {
+        None
+    } else {
+        Some(host)
+    }
+}
+
+/// Like git, the longest matching prefix wins
+fn rewrite(rules: &[(String, String)], url: &str) -> Option<String> {
+    rules
+        .iter()
+        .filter(|(_, prefix)| url.starts_with(prefix.as_str()))
+        .max_by_key(|(_, prefix)| prefix.len())
+        .map(|(base, prefix)| format!("{}{}", base, &url[prefix.len()..]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rules(entries: &[(&str, &str)]) -> RewriteRules {
+        let mut rules = RewriteRules::default();
+        for (name, prefix) in entries {
+            rules.insert(name, prefix);
+        }
+        rules
+    }
+
+    #[test]
+    fn instead_of() {
+        let rules = rules(&[("url.git@github.com:.insteadof", "gh:")]);
+        assert_eq!(rules.fetch_url("gh:me/repo"), "git@github.com:me/repo");
+        assert_eq!(rules.push_url("gh:me/repo"), "git@github.com:me/repo");
+        assert_eq!(
+            rules.fetch_url("https://example.com/repo"),
+            "https://example.com/repo"
+        );
+    }
+
+    #[test]
+    fn push_instead_of() {
+        let rules = rules(&[
+            ("url.https://github.com/.insteadOf", "gh:"),
+            ("url.git@github.com:.pushInsteadOf", "https://github.com/"),
+            ("url.ssh://mirror/.pushinsteadof", "gh:"),
+        ]);
+        assert_eq!(rules.fetch_url("gh:me/repo"), "https://github.com/me/repo");
+        assert_eq!(rules.push_url("gh:me/repo"), "ssh://mirror/me/repo");
+        assert_eq!(
+            rules.push_url("https://github.com/me/repo"),
+            "git@github.com:me/repo"
+        );
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let rules = rules(&[
+            ("url.https://example.com/.insteadof", "ex:"),
+            (
+                "url.https://internal.example.com/.insteadof",
+                "ex:internal/",
+            ),
+        ]);
+        assert_eq!(
+            rules.fetch_url("ex:internal/repo"),
+            "https://internal.example.com/repo"
+        );
+        assert_eq!(rules.fetch_url("ex:repo"), "https://example.com/repo");
+    }
+
+    #[test]
+    fn host() {
+        assert_eq!(url_host("https://github.com/me/repo"), Some("github.com"));
+        assert_eq!(
+            url_host("ssh://git@gitlab.example.com:2222/me/repo.git"),
+            Some("gitlab.example.com")
+        );
+        assert_eq!(url_host("git@gitlab.com:me/repo.git"), Some("gitlab.com"));
+        assert_eq!(
+            url_host("https://github.com/org/gitlab-sync"),
+            Some("github.com")
+        );
+        assert_eq!(url_host("../repo"), None);
+        assert_eq!(url_host("/srv/git/repo"), None);
+        assert_eq!(url_host("file:///srv/git/repo"), None);
+    }
+}
@@ -23,3 +23,38 @@ impl Action {
         matches!(self, Action::Delete)
     }
 }
+
+/// Why a branch isn't pushable
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PushBlocker {
+    Wip,
+    Fixup,
+    /// Stacked on top of a branch blocked by a WIP commit
+    StackedOnWip,
+    /// Stacked on top of a branch blocked by a fixup commit
+    StackedOnFixup,
+}
+
+impl PushBlocker {
+    pub fn is_wip(&self) -> bool {
+        matches!(self, PushBlocker::Wip | PushBlocker::StackedOnWip)
+    }
+
+    pub(crate) fn stacked(self) -> Self {
+        match self {
+            PushBlocker::Wip | PushBlocker::StackedOnWip => PushBlocker::StackedOnWip,
+            PushBlocker::Fixup | PushBlocker::StackedOnFixup => PushBlocker::StackedOnFixup,
+        }
+    }
+}
+
+impl std::fmt::Display for PushBlocker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PushBlocker::Wip => "contains WIP commit".fmt(f),
+            PushBlocker::Fixup => "contains fixup commit".fmt(f),
+            PushBlocker::StackedOnWip => "stacked on WIP commit".fmt(f),
+            PushBlocker::StackedOnFixup => "stacked on fixup commit".fmt(f),
+        }
+    }
+}
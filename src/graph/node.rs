@@ -6,6 +6,8 @@ pub struct Node {
     pub branches: Vec<crate::git::Branch>,
     pub action: crate::graph::Action,
     pub pushable: bool,
+    /// Why the branches aren't pushable, per `pushable`
+    pub push_blocker: Option<crate::graph::PushBlocker>,
     pub children: BTreeMap<git2::Oid, Node>,
 }
 
@@ -23,6 +25,7 @@ impl Node {
             branches,
             action: crate::graph::Action::Pick,
             pushable: false,
+            push_blocker: None,
             children,
         }
     }
@@ -41,6 +44,9 @@ impl Node {
         let branch_commit = repo.find_commit(branch_id).unwrap();
         let mut root = Self::new(branch_commit, &mut branches);
         for branch_id in branch_ids {
+            if root.is_unrelated(repo, branch_id, &mut branches) {
+                continue;
+            }
             let branch_commit = repo.find_commit(branch_id).unwrap();
             root = root.insert_commit(repo, branch_commit, &mut branches)?;
         }
@@ -56,7 +62,13 @@ impl Node {
     ) -> eyre::Result<Self> {
         let merge_base_id = repo
             .merge_base(self.local_commit.id, local_commit.id)
-            .ok_or_else(|| eyre::eyre!("Could not find merge base"))?;
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "Could not find merge base, {} and {} share no history",
+                    self.local_commit.id,
+                    local_commit.id
+                )
+            })?;
 
         if merge_base_id != self.local_commit.id {
             let prefix = Node::populate(
@@ -90,6 +102,9 @@ impl Node {
             let mut branch_ids: Vec<_> = branches.oids().collect();
             branch_ids.sort_by_key(|id| &branches.get(*id).unwrap()[0].name);
             for branch_id in branch_ids {
+                if self.is_unrelated(repo, branch_id, &mut branches) {
+                    continue;
+                }
                 let branch_commit = repo.find_commit(branch_id).unwrap();
                 self = self.insert_commit(repo, branch_commit, &mut branches)?;
             }
@@ -98,13 +113,40 @@ impl Node {
         Ok(self)
     }
 
+    /// Leave out branches with a history of their own, like `gh-pages`, which can't be graphed
+    /// alongside this one
+    fn is_unrelated(
+        &self,
+        repo: &dyn crate::git::Repo,
+        branch_id: git2::Oid,
+        branches: &mut crate::git::Branches,
+    ) -> bool {
+        if repo.merge_base(self.local_commit.id, branch_id).is_some() {
+            return false;
+        }
+        for branch in branches.remove(branch_id).unwrap_or_default() {
+            log::info!(
+                "Leaving out `{}`, it shares no history with {}",
+                branch.name,
+                self.local_commit.id
+            );
+        }
+        true
+    }
+
     pub fn extend(mut self, repo: &dyn crate::git::Repo, mut other: Self) -> eyre::Result<Self> {
         if let Some(node) = self.find_commit_mut(other.local_commit.id) {
             node.merge(other)
         } else {
             let merge_base_id = repo
                 .merge_base(self.local_commit.id, other.local_commit.id)
-                .ok_or_else(|| eyre::eyre!("Could not find merge base"))?;
+                .ok_or_else(|| {
+                    eyre::eyre!(
+                        "Could not find merge base, {} and {} share no history",
+                        self.local_commit.id,
+                        other.local_commit.id
+                    )
+                })?;
             let mut possible_branches = crate::git::Branches::default();
             if merge_base_id != self.local_commit.id {
                 let prefix = Node::populate(
@@ -164,20 +206,68 @@ impl Node {
         let mut root = Node::new(head_commit, branches);
         root.action = default_action;
 
-        let mut commits = repo.commits_from(head_oid);
+        let mut commits = repo.graph_commits_from(head_oid);
         // Already added head_oid
         let first = commits.next().expect("always at lead HEAD");
         assert_eq!(first.id, head_oid);
 
         if head_oid != base_oid {
+            let mut chain = Vec::new();
             for commit in commits {
+                let is_base = commit.id == base_oid;
+                chain.push(commit);
+                if is_base {
+                    break;
+                }
+            }
+            // `stack.max-history` stopped the walk short of base
+            let elided = match chain.last() {
+                Some(oldest) if oldest.id == base_oid => None,
+                Some(oldest) => elided_commit(repo, oldest.id),
+                None => elided_commit(repo, head_oid),
+            };
+            let elided = match elided {
+                Some(elided) if elided.id == base_oid => {
+                    // Stopped right before base, nothing was left out
+                    chain.extend(repo.find_commit(base_oid));
+                    None
+                }
+                elided => elided,
+            };
+            if let Some(elided) = elided {
+                log::debug!(
+                    "Eliding history of {} past {}, leaving it in place",
+                    head_oid,
+                    elided.id
+                );
+                // Without the elided commits, nothing here can be safely moved
+                root.action = crate::graph::Action::Protected;
+                chain.push(elided);
+                if let Some(base) = repo.find_commit(base_oid) {
+                    chain.push(base);
+                }
+                for commit in chain {
+                    let child = root;
+                    root = Node::new(commit, branches);
+                    root.action = crate::graph::Action::Protected;
+                    root.children.insert(child.local_commit.id, child);
+                }
+                return Ok(root);
+            }
+
+            // The other parents of an octopus merge interleave with the stack, so only follow
+            // first parents, like when rebasing merges
+            if chain.iter().any(|commit| is_octopus(repo, commit.id)) {
+                if let Some(first_parents) = first_parents(repo, head_oid, base_oid) {
+                    chain = first_parents;
+                }
+            }
+
+            for commit in chain {
                 let child = root;
                 root = Node::new(commit, branches);
                 root.action = default_action;
                 root.children.insert(child.local_commit.id, child);
-                if root.local_commit.id == base_oid {
-                    break;
-                }
             }
         }
 
@@ -214,3 +304,39 @@ impl Node {
         }
     }
 }
+
+/// Whether `id` merges in more than one other branch
+pub(crate) fn is_octopus(repo: &dyn crate::git::Repo, id: git2::Oid) -> bool {
+    matches!(repo.parent_ids(id), Ok(parent_ids) if 2 < parent_ids.len())
+}
+
+/// Stand in for the history before `oldest_id`, under the first parent's id
+fn elided_commit(
+    repo: &dyn crate::git::Repo,
+    oldest_id: git2::Oid,
+) -> Option<std::rc::Rc<crate::git::Commit>> {
+    let parent_id = *repo.parent_ids(oldest_id).ok()?.first()?;
+    let parent = repo.find_commit(parent_id)?;
+    Some(std::rc::Rc::new(crate::git::Commit {
+        id: parent.id,
+        tree_id: parent.tree_id,
+        summary: bstr::BString::from("... (older commits elided, see `stack.max-history`)"),
+    }))
+}
+
+/// The first-parent history below `head_id`, down to and including `base_id`
+///
+/// `None` if `base_id` is only reachable through other parents.
+fn first_parents(
+    repo: &dyn crate::git::Repo,
+    head_id: git2::Oid,
+    base_id: git2::Oid,
+) -> Option<Vec<std::rc::Rc<crate::git::Commit>>> {
+    let mut chain = Vec::new();
+    let mut id = head_id;
+    while id != base_id {
+        id = *repo.parent_ids(id).ok()?.first()?;
+        chain.push(repo.find_commit(id)?);
+    }
+    Some(chain)
+}
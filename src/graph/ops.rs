@@ -44,6 +44,53 @@ fn protect_branches_node(
     is_protected
 }
 
+/// Protect the commits in the history of `protected_ids`, like tagged releases
+pub fn protect_commits(root: &mut Node, repo: &dyn crate::git::Repo, protected_ids: &[git2::Oid]) {
+    let root_id = root.local_commit.id;
+    let mut graphed = std::collections::HashSet::new();
+    // Commits outside of the graph still protect what they were built on top of
+    let mut descendants = Vec::new();
+    for id in protected_ids.iter().copied() {
+        if root.find_commit_mut(id).is_some() {
+            graphed.insert(id);
+        } else if repo.merge_base(root_id, id) == Some(root_id) {
+            descendants.push(id);
+        }
+    }
+    if graphed.is_empty() && descendants.is_empty() {
+        return;
+    }
+
+    protect_commits_node(root, repo, &graphed, &descendants);
+}
+
+fn protect_commits_node(
+    node: &mut Node,
+    repo: &dyn crate::git::Repo,
+    graphed: &std::collections::HashSet<git2::Oid>,
+    descendants: &[git2::Oid],
+) -> bool {
+    // Can't short-circuit since we need to ensure all nodes are marked.
+    let mut is_protected = false;
+    for child in node.children.values_mut() {
+        is_protected |= protect_commits_node(child, repo, graphed, descendants);
+    }
+
+    let id = node.local_commit.id;
+    is_protected |= graphed.contains(&id);
+    if !is_protected {
+        is_protected = descendants
+            .iter()
+            .any(|descendant| repo.merge_base(id, *descendant) == Some(id));
+    }
+
+    if is_protected {
+        node.action = crate::graph::Action::Protected;
+    }
+
+    is_protected
+}
+
 /// Pre-requisites:
 /// - Running protect_branches
 ///
@@ -52,12 +99,17 @@ fn protect_branches_node(
 /// - If `new_base_id` doesn't exist
 /// - If `new_base_id` isn't protected
 pub fn rebase_branches(node: &mut Node, new_base_id: git2::Oid) {
+    rebase_branches_except(node, new_base_id, &[]);
+}
+
+/// Like `rebase_branches`, but leaving the stacks on `base_ids`, like tagged releases, on them
+pub fn rebase_branches_except(node: &mut Node, new_base_id: git2::Oid, base_ids: &[git2::Oid]) {
     debug_assert_eq!(
         node.find_commit_mut(new_base_id).unwrap().action,
         crate::graph::Action::Protected
     );
     let mut rebaseable = Vec::new();
-    pop_rebaseable_stacks(node, &mut rebaseable);
+    pop_rebaseable_stacks(node, base_ids, &mut rebaseable);
 
     let new_base = node.find_commit_mut(new_base_id).unwrap();
     new_base
@@ -65,65 +117,506 @@ pub fn rebase_branches(node: &mut Node, new_base_id: git2::Oid) {
         .extend(rebaseable.into_iter().map(|n| (n.local_commit.id, n)));
 }
 
-fn pop_rebaseable_stacks(node: &mut Node, rebaseable: &mut Vec<Node>) {
+fn pop_rebaseable_stacks(node: &mut Node, base_ids: &[git2::Oid], rebaseable: &mut Vec<Node>) {
     if !node.action.is_protected() {
         // The parent is responsible for popping this node
         return;
     }
 
-    let mut base_ids = Vec::new();
+    let is_base = base_ids.contains(&node.local_commit.id);
+    let mut stack_ids = Vec::new();
     for (child_id, child) in node.children.iter_mut() {
         if child.action.is_protected() {
-            pop_rebaseable_stacks(child, rebaseable);
-        } else {
-            base_ids.push(*child_id);
+            pop_rebaseable_stacks(child, base_ids, rebaseable);
+        } else if !is_base {
+            stack_ids.push(*child_id);
         }
     }
-    for base_id in base_ids {
-        let child = node.children.remove(&base_id).unwrap();
+    for stack_id in stack_ids {
+        let child = node.children.remove(&stack_id).unwrap();
         rebaseable.push(child);
     }
 }
 
-pub fn pushable(node: &mut Node) {
+/// Swap `id` for its reworded copy, so everything on top of it is restacked
+///
+/// Returns `false` if `id` isn't in the graph.
+pub fn reword(node: &mut Node, id: git2::Oid, reworded: std::rc::Rc<crate::git::Commit>) -> bool {
+    match node.find_commit_mut(id) {
+        Some(node) => {
+            node.local_commit = reworded;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Move the commits exclusive to the branch at `branch_id` onto `onto_id`, dragging along
+/// everything on top of them
+///
+/// A branch's commits start after the closest ancestor that has a branch, is protected, or is
+/// shared with another stack.
+///
+/// Pre-requisites:
+/// - Running protect_branches
+///
+/// Returns `false` if either commit isn't in the graph or `onto_id` would move with the branch.
+pub fn move_branch(node: &mut Node, branch_id: git2::Oid, onto_id: git2::Oid) -> bool {
+    if node.find_commit_mut(onto_id).is_none() {
+        return false;
+    }
+    let path = match path_to(node, branch_id) {
+        Some(path) => path,
+        None => return false,
+    };
+
+    // `path[0]` is the root which can't move
+    let mut start = path.len() - 1;
+    while 1 < start {
+        let parent = node.find_commit_mut(path[start - 1]).unwrap();
+        if !parent.branches.is_empty() || parent.action.is_protected() || 1 < parent.children.len()
+        {
+            break;
+        }
+        start -= 1;
+    }
+    if start == 0 {
+        return false;
+    }
+
+    let parent = node.find_commit_mut(path[start - 1]).unwrap();
+    let mut moved = parent.children.remove(&path[start]).unwrap();
+    if moved.find_commit_mut(onto_id).is_some() {
+        let parent = node.find_commit_mut(path[start - 1]).unwrap();
+        parent.children.insert(path[start], moved);
+        return false;
+    }
+    let onto = node.find_commit_mut(onto_id).unwrap();
+    onto.children.insert(moved.local_commit.id, moved);
+    true
+}
+
+/// Move the commits after `fork_id` up to `branch_id` onto `onto_id`
+///
+/// This is for a branch recorded as being on top of another, like `git rebase --fork-point`,
+/// where `fork_id` is where the other branch was when this one was started.  Commits the move
+/// leaves behind without a branch or anything else on top of them are dropped, as they are an
+/// old version of the other branch.
+///
+/// Pre-requisites:
+/// - Running protect_branches
+///
+/// Returns `false` if the commits aren't in the graph, are protected, or `onto_id` would move with
+/// them.
+pub fn reparent(
+    node: &mut Node,
+    branch_id: git2::Oid,
+    fork_id: git2::Oid,
+    onto_id: git2::Oid,
+) -> bool {
+    if node.find_commit_mut(onto_id).is_none() {
+        return false;
+    }
+    let path = match path_to(node, branch_id) {
+        Some(path) => path,
+        None => return false,
+    };
+    let fork = match path.iter().position(|id| *id == fork_id) {
+        Some(fork) if fork + 1 < path.len() => fork,
+        _ => return false,
+    };
+    let start = fork + 1;
+    if path[start..]
+        .iter()
+        .any(|id| node.find_commit_mut(*id).unwrap().action.is_protected())
+    {
+        return false;
+    }
+
+    let parent = node.find_commit_mut(path[fork]).unwrap();
+    let mut moved = parent.children.remove(&path[start]).unwrap();
+    if moved.find_commit_mut(onto_id).is_some() {
+        let parent = node.find_commit_mut(path[fork]).unwrap();
+        parent.children.insert(path[start], moved);
+        return false;
+    }
+    let onto = node.find_commit_mut(onto_id).unwrap();
+    onto.children.insert(moved.local_commit.id, moved);
+
+    // `path[0]` is the root which can't be dropped
+    for i in (1..start).rev() {
+        let abandoned = node.find_commit_mut(path[i]).unwrap();
+        if !abandoned.children.is_empty()
+            || !abandoned.branches.is_empty()
+            || abandoned.action.is_protected()
+        {
+            break;
+        }
+        node.find_commit_mut(path[i - 1])
+            .unwrap()
+            .children
+            .remove(&path[i]);
+    }
+    true
+}
+
+fn path_to(node: &Node, id: git2::Oid) -> Option<Vec<git2::Oid>> {
+    if node.local_commit.id == id {
+        return Some(vec![id]);
+    }
+    for child in node.children.values() {
+        if let Some(mut path) = path_to(child, id) {
+            path.insert(0, node.local_commit.id);
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Swap `id` for the commits it was split into, restacking everything on top of it onto the
+/// last of them
+///
+/// Returns `false` if `id` isn't in the graph.
+pub fn split(
+    node: &mut Node,
+    id: git2::Oid,
+    commits: Vec<std::rc::Rc<crate::git::Commit>>,
+) -> bool {
+    let target = match node.find_commit_mut(id) {
+        Some(target) => target,
+        None => return false,
+    };
+
+    let mut commits = commits.into_iter().rev();
+    let last = commits.next().expect("split into at least one commit");
+    let mut split = Node::new(last, &mut Default::default());
+    split.action = target.action;
+    split.pushable = target.pushable;
+    split.branches = std::mem::take(&mut target.branches);
+    split.children = std::mem::take(&mut target.children);
+    for commit in commits {
+        let mut parent = Node::new(commit, &mut Default::default());
+        parent.action = target.action;
+        parent.children.insert(split.local_commit.id, split);
+        split = parent;
+    }
+    *target = split;
+    true
+}
+
+/// Swap the chain `ids`, oldest first, for the one commit they were squashed into, restacking
+/// everything on top of them onto it
+///
+/// Returns `false` if `ids` isn't a chain in the graph or anything besides the last commit has
+/// branches or other children.
+pub fn squash_commits(
+    node: &mut Node,
+    ids: &[git2::Oid],
+    squashed: std::rc::Rc<crate::git::Commit>,
+) -> bool {
+    let (last_id, rest) = match ids.split_last() {
+        Some(split) => split,
+        None => return false,
+    };
+    let path = match path_to(node, *last_id) {
+        Some(path) => path,
+        None => return false,
+    };
+    if path.len() <= rest.len() || path[path.len() - 1 - rest.len()..path.len() - 1] != *rest {
+        return false;
+    }
+    for id in rest {
+        let middle = node.find_commit_mut(*id).unwrap();
+        if !middle.branches.is_empty() || middle.children.len() != 1 {
+            return false;
+        }
+    }
+
+    let last = node.find_commit_mut(*last_id).unwrap();
+    let action = last.action;
+    let pushable = last.pushable;
+    let branches = std::mem::take(&mut last.branches);
+    let children = std::mem::take(&mut last.children);
+    let first = node.find_commit_mut(ids[0]).unwrap();
+    let mut combined = Node::new(squashed, &mut Default::default());
+    combined.action = action;
+    combined.pushable = pushable;
+    combined.branches = branches;
+    combined.children = children;
+    *first = combined;
+    true
+}
+
+/// Put the chain `ids`, oldest first, in the order of `order`, keeping its branches and
+/// everything else on top of it on top of the new last commit
+///
+/// Returns `false` if `order` isn't a reordering of `ids`, `ids` isn't a chain in the graph, or
+/// anything besides the last commit has branches or other children.
+pub fn reorder(node: &mut Node, ids: &[git2::Oid], order: &[git2::Oid]) -> bool {
+    let mut sorted_ids = ids.to_vec();
+    sorted_ids.sort();
+    let mut sorted_order = order.to_vec();
+    sorted_order.sort();
+    if sorted_ids != sorted_order {
+        return false;
+    }
+    let (last_id, rest) = match ids.split_last() {
+        Some(split) => split,
+        None => return false,
+    };
+    let path = match path_to(node, *last_id) {
+        Some(path) => path,
+        None => return false,
+    };
+    if path.len() <= rest.len() || path[path.len() - 1 - rest.len()..path.len() - 1] != *rest {
+        return false;
+    }
+    for id in rest {
+        let middle = node.find_commit_mut(*id).unwrap();
+        if !middle.branches.is_empty() || middle.children.len() != 1 {
+            return false;
+        }
+    }
+
+    let parent = node
+        .find_commit_mut(path[path.len() - 1 - ids.len()])
+        .unwrap();
+    let mut next = parent.children.remove(&ids[0]);
+    let mut chain = std::collections::HashMap::new();
+    while let Some(mut current) = next {
+        next = if current.local_commit.id == *last_id {
+            None
+        } else {
+            let child_id = *current.children.keys().next().unwrap();
+            current.children.remove(&child_id)
+        };
+        chain.insert(current.local_commit.id, current);
+    }
+
+    let last = chain.get_mut(last_id).unwrap();
+    let pushable = last.pushable;
+    let branches = std::mem::take(&mut last.branches);
+    let children = std::mem::take(&mut last.children);
+    let mut order = order.iter().rev();
+    let mut reordered = chain.remove(order.next().unwrap()).unwrap();
+    reordered.pushable = pushable;
+    reordered.branches = branches;
+    reordered.children = children;
+    for id in order {
+        let mut current = chain.remove(id).unwrap();
+        current
+            .children
+            .insert(reordered.local_commit.id, reordered);
+        reordered = current;
+    }
+    let parent = node
+        .find_commit_mut(path[path.len() - 1 - ids.len()])
+        .unwrap();
+    parent.children.insert(reordered.local_commit.id, reordered);
+    true
+}
+
+/// Drop `id`, restacking everything on top of it onto its parent
+///
+/// Branches at `id` move to its parent, which must be picked for them to be updated.
+///
+/// Pre-requisites:
+/// - Running protect_branches
+///
+/// Returns `false` if `id` isn't in the graph, is protected, or has branches with nowhere to go.
+pub fn drop_commit(node: &mut Node, id: git2::Oid) -> bool {
+    let path = match path_to(node, id) {
+        Some(path) => path,
+        None => return false,
+    };
+    if path.len() < 2 {
+        // The root is the base and is never moved
+        return false;
+    }
+
+    let target = node.find_commit_mut(id).unwrap();
+    if target.action.is_protected() {
+        return false;
+    }
+    if !target.branches.is_empty() {
+        // The root's branches are never updated
+        if path.len() < 3 {
+            return false;
+        }
+        let parent = node.find_commit_mut(path[path.len() - 2]).unwrap();
+        if !(parent.action.is_pick() || parent.action.is_squash()) {
+            return false;
+        }
+        let target = node.find_commit_mut(id).unwrap();
+        let branches = std::mem::take(&mut target.branches);
+        let parent = node.find_commit_mut(path[path.len() - 2]).unwrap();
+        parent.branches.extend(branches);
+    }
+
+    let target = node.find_commit_mut(id).unwrap();
+    target.action = crate::graph::Action::Delete;
+    true
+}
+
+/// Limit mutations to the selected branches
+///
+/// Anything that can't be moved without also moving an unselected branch is removed from the
+/// graph, leaving it in place.  The branches left in place are returned.
+///
+/// Pre-requisites:
+/// - Running protect_branches
+pub fn retain_branches(
+    node: &mut Node,
+    is_selected: &dyn Fn(&crate::git::Branch) -> bool,
+) -> Vec<crate::git::Branch> {
+    // The root is the base and is never moved, whether protected or not
+    let mut skipped = Vec::new();
+    retain_children(node, is_selected, &mut skipped);
+    skipped
+}
+
+fn retain_children(
+    node: &mut Node,
+    is_selected: &dyn Fn(&crate::git::Branch) -> bool,
+    skipped: &mut Vec<crate::git::Branch>,
+) {
+    let mut removed = Vec::new();
+    for (child_id, child) in node.children.iter_mut() {
+        if child.action.is_protected() {
+            retain_children(child, is_selected, skipped);
+        } else if !retain_node(child, is_selected, skipped) {
+            removed.push(*child_id);
+        }
+    }
+    for child_id in removed {
+        let child = node.children.remove(&child_id).unwrap();
+        all_branches(&child, skipped);
+    }
+}
+
+fn retain_node(
+    node: &mut Node,
+    is_selected: &dyn Fn(&crate::git::Branch) -> bool,
+    skipped: &mut Vec<crate::git::Branch>,
+) -> bool {
+    assert!(!node.action.is_protected());
+
+    if !node.branches.is_empty() && !node.branches.iter().all(is_selected) {
+        // Moving any descendant would move this branch
+        return false;
+    }
+
+    let mut removed = Vec::new();
+    for (child_id, child) in node.children.iter_mut() {
+        if !retain_node(child, is_selected, skipped) {
+            removed.push(*child_id);
+        }
+    }
+    for child_id in removed {
+        let child = node.children.remove(&child_id).unwrap();
+        all_branches(&child, skipped);
+    }
+
+    !node.branches.is_empty() || !node.children.is_empty()
+}
+
+/// The branches stacked on an octopus merge, which can only be recreated when rebasing merges
+pub fn octopus_branches(node: &Node, repo: &dyn crate::git::Repo) -> Vec<crate::git::Branch> {
+    let mut branches = Vec::new();
+    octopus_branches_node(node, repo, &mut branches);
+    branches
+}
+
+fn octopus_branches_node(
+    node: &Node,
+    repo: &dyn crate::git::Repo,
+    branches: &mut Vec<crate::git::Branch>,
+) {
+    if !node.action.is_protected() && crate::graph::node::is_octopus(repo, node.local_commit.id) {
+        all_branches(node, branches);
+        return;
+    }
+    for child in node.children.values() {
+        octopus_branches_node(child, repo, branches);
+    }
+}
+
+fn all_branches(node: &Node, branches: &mut Vec<crate::git::Branch>) {
+    branches.extend(node.branches.iter().cloned());
+    for child in node.children.values() {
+        all_branches(child, branches);
+    }
+}
+
+/// Mark the branches to push, recording why the rest can't be
+///
+/// A WIP or fixup commit blocks its branch and everything stacked on it, unless `push_wip` allows
+/// WIP commits.
+pub fn pushable(
+    node: &mut Node,
+    policy: crate::config::PushPolicy,
+    push_wip: crate::config::PushWip,
+) {
     if node.action.is_protected() {
         for child in node.children.values_mut() {
-            pushable_node(child, None);
+            pushable_node(child, policy, push_wip, None, true);
         }
     } else {
         // No idea if a parent commit invalidates our results
     }
 }
 
-fn pushable_node(node: &mut Node, mut cause: Option<&str>) {
+/// `is_bottom` is whether no branch is beneath `node` in its stack
+fn pushable_node(
+    node: &mut Node,
+    policy: crate::config::PushPolicy,
+    push_wip: crate::config::PushWip,
+    mut cause: Option<crate::graph::PushBlocker>,
+    mut is_bottom: bool,
+) {
     if node.action.is_protected() {
         assert_eq!(cause, None);
         for child in node.children.values_mut() {
-            pushable_node(child, cause);
+            pushable_node(child, policy, push_wip, cause, true);
         }
         return;
     }
 
-    if node.local_commit.wip_summary().is_some() {
-        cause = Some("contains WIP commit");
+    if cause.is_some() {
+        // Already blocked beneath us
+    } else if push_wip != crate::config::PushWip::Allow && node.local_commit.wip_summary().is_some()
+    {
+        cause = Some(crate::graph::PushBlocker::Wip);
+    } else if policy == crate::config::PushPolicy::Ready
+        && node.local_commit.fixup_summary().is_some()
+    {
+        cause = Some(crate::graph::PushBlocker::Fixup);
     }
 
     if !node.branches.is_empty() {
         let branch = &node.branches[0];
+        node.pushable = false;
+        node.push_blocker = cause;
         if let Some(cause) = cause {
             log::debug!("{} isn't pushable, {}", branch.name, cause);
+        } else if policy == crate::config::PushPolicy::Bottom && !is_bottom {
+            log::debug!(
+                "{} isn't pushable, only the first branch of a stack is",
+                branch.name
+            );
         } else if node.branches.iter().all(|b| Some(b.id) == b.push_id) {
             log::debug!("{} is already pushed", branch.name);
         } else {
             log::debug!("{} is pushable", branch.name);
             node.pushable = true;
         }
-        // Bail out, only the first branch of a stack is up for consideration
-        return;
+        is_bottom = false;
+        // Everything above this branch is stacked on the blocked branch
+        cause = cause.map(|c| c.stacked());
     }
 
     for stack in node.children.values_mut() {
-        pushable_node(stack, cause);
+        pushable_node(stack, policy, push_wip, cause, is_bottom);
     }
 }
 
@@ -216,75 +709,339 @@ fn drop_first_branch_by_tree_id(
     }
 }
 
-pub fn fixup(node: &mut Node, effect: crate::config::Fixup) {
-    if effect == crate::config::Fixup::Ignore {
-        return;
+/// Drop commits whose changes are already upstream
+///
+/// `drop_by_tree_id` only catches a branch that was squash-merged as-is.  Comparing patch-ids
+/// also catches commits that were cherry-picked upstream, even with more changes landing on top of
+/// them.  Only the protected commits the stack wasn't already based on are compared against.
+///
+/// Branches on a dropped commit move to its parent, like `drop_commit`, unless the parent is
+/// protected, in which case the branch was merged and is deleted, like with `drop_by_tree_id`.
+///
+/// Pre-requisites:
+/// - Running protect_branches
+/// - Running rebase_branches
+pub fn drop_by_patch_id(node: &mut Node, repo: &dyn crate::git::Repo) {
+    if node.action.is_protected() {
+        track_protected_ids(node, repo, Vec::new());
     }
+}
 
-    let mut outstanding = std::collections::BTreeMap::new();
-    fixup_nodes(node, effect, &mut outstanding);
-    if !outstanding.is_empty() {
-        assert!(!node.action.is_protected());
-        for nodes in outstanding.into_values() {
-            for mut other in nodes.into_iter() {
-                std::mem::swap(node, &mut other);
-                node.children.insert(other.local_commit.id, other);
+fn track_protected_ids(
+    node: &mut Node,
+    repo: &dyn crate::git::Repo,
+    mut protected_ids: Vec<git2::Oid>,
+) {
+    assert!(node.action.is_protected());
+    protected_ids.push(node.local_commit.id);
+
+    match node.children.len() {
+        0 => (),
+        1 => {
+            let child = node.children.values_mut().next().unwrap();
+            if child.action.is_protected() {
+                track_protected_ids(child, repo, protected_ids);
+            } else {
+                let upstream = upstream_patch_ids(repo, &protected_ids, child.local_commit.id);
+                drop_picked_by_patch_id(child, repo, &upstream);
+            }
+        }
+        _ => {
+            for child in node.children.values_mut() {
+                if child.action.is_protected() {
+                    track_protected_ids(child, repo, protected_ids.clone());
+                } else {
+                    let upstream = upstream_patch_ids(repo, &protected_ids, child.local_commit.id);
+                    drop_picked_by_patch_id(child, repo, &upstream);
+                }
             }
         }
     }
 }
 
-fn fixup_nodes(
+/// Patch-ids of the protected commits, ordered from the root, that `stack_id` isn't based on
+fn upstream_patch_ids(
+    repo: &dyn crate::git::Repo,
+    protected_ids: &[git2::Oid],
+    stack_id: git2::Oid,
+) -> std::collections::HashSet<git2::Oid> {
+    let parent_id = repo
+        .parent_ids(stack_id)
+        .ok()
+        .and_then(|parent_ids| parent_ids.first().copied());
+    // Each protected commit is an ancestor of the next, so what the stack was based on is a prefix
+    let start = match parent_id {
+        Some(parent_id) => {
+            protected_ids.partition_point(|id| repo.merge_base(*id, parent_id) == Some(*id))
+        }
+        None => 0,
+    };
+    protected_ids[start..]
+        .iter()
+        .filter_map(|id| repo.patch_id(*id))
+        .collect()
+}
+
+fn drop_picked_by_patch_id(
     node: &mut Node,
-    effect: crate::config::Fixup,
-    outstanding: &mut std::collections::BTreeMap<bstr::BString, Vec<Node>>,
-) {
-    let mut fixups = Vec::new();
-    for (id, child) in node.children.iter_mut() {
-        fixup_nodes(child, effect, outstanding);
+    repo: &dyn crate::git::Repo,
+    upstream: &std::collections::HashSet<git2::Oid>,
+) -> bool {
+    assert!(!node.action.is_protected());
+    if upstream.is_empty() {
+        return false;
+    }
 
-        if child.action.is_protected() || child.action.is_delete() {
-            continue;
+    let mut orphaned = Vec::new();
+    for child in node.children.values_mut() {
+        if drop_picked_by_patch_id(child, repo, upstream) && node.action.is_pick() {
+            orphaned.append(&mut child.branches);
         }
-        if let Some(summary) = node.local_commit.fixup_summary() {
-            fixups.push((*id, summary.to_owned()));
+    }
+    node.branches.extend(orphaned);
+
+    if !node.action.is_pick() {
+        return false;
+    }
+    match repo.patch_id(node.local_commit.id) {
+        Some(patch_id) if upstream.contains(&patch_id) => {
+            node.action = crate::graph::Action::Delete;
+            true
         }
+        _ => false,
     }
+}
 
-    for (id, summary) in fixups {
-        let mut child = node.children.remove(&id).unwrap();
+/// Share one copy of a commit cherry-picked onto sibling branches
+///
+/// When children of the same commit make the same change, by patch-id, the later ones are folded
+/// into the first, moving their branches and dependents on top of it.  Copies further up a
+/// sibling's stack, which can't be shared, are dropped from all but the first stack with them,
+/// moving their branches to the commit beneath.
+/// Otherwise, restacking keeps a copy for each branch.
+///
+/// Pre-requisites:
+/// - Running protect_branches
+pub fn dedup_cherry_picks(node: &mut Node, repo: &dyn crate::git::Repo) {
+    if 1 < node.children.len() {
+        let mut firsts: std::collections::HashMap<git2::Oid, git2::Oid> = Default::default();
+        let mut duplicates = Vec::new();
+        for (child_id, child) in node.children.iter() {
+            if !child.action.is_pick() {
+                continue;
+            }
+            if let Some(patch_id) = repo.patch_id(*child_id) {
+                match firsts.entry(patch_id) {
+                    std::collections::hash_map::Entry::Occupied(first) => {
+                        duplicates.push((*first.get(), *child_id));
+                    }
+                    std::collections::hash_map::Entry::Vacant(first) => {
+                        first.insert(*child_id);
+                    }
+                }
+            }
+        }
+        for (first_id, duplicate_id) in duplicates {
+            let mut duplicate = node.children.remove(&duplicate_id).unwrap();
+            let first = node.children.get_mut(&first_id).unwrap();
+            first.branches.append(&mut duplicate.branches);
+            first.children.append(&mut duplicate.children);
+        }
 
-        let mut new_children = Default::default();
-        std::mem::swap(&mut child.children, &mut new_children);
-        node.children.extend(new_children);
+        let mut seen = std::collections::HashSet::new();
+        for child in node.children.values_mut() {
+            if !child.action.is_pick() {
+                continue;
+            }
+            let mut patch_ids = std::collections::HashSet::new();
+            picked_patch_ids(child, repo, &mut patch_ids);
+            // The first commit was folded above, if it could be, and its branches would have
+            // nowhere to go without it
+            let mut orphaned = Vec::new();
+            for grandchild in child.children.values_mut() {
+                if drop_picked_by_patch_id(grandchild, repo, &seen) {
+                    orphaned.append(&mut grandchild.branches);
+                }
+            }
+            child.branches.extend(orphaned);
+            seen.extend(patch_ids);
+        }
+    }
 
-        let mut new_branches = Default::default();
-        std::mem::swap(&mut child.branches, &mut new_branches);
-        node.branches.extend(new_branches);
+    for child in node.children.values_mut() {
+        dedup_cherry_picks(child, repo);
+    }
+}
 
-        outstanding
-            .entry(summary)
-            .or_insert_with(Default::default)
-            .push(child);
+fn picked_patch_ids(
+    node: &Node,
+    repo: &dyn crate::git::Repo,
+    patch_ids: &mut std::collections::HashSet<git2::Oid>,
+) {
+    if node.action.is_pick() {
+        patch_ids.extend(repo.patch_id(node.local_commit.id));
     }
+    for child in node.children.values() {
+        picked_patch_ids(child, repo, patch_ids);
+    }
+}
 
-    if let Some(mut fixups) = outstanding.remove(&node.local_commit.summary) {
-        if effect == crate::config::Fixup::Squash {
-            for fixup in fixups.iter_mut() {
-                assert!(fixup.action == crate::graph::Action::Pick);
-                fixup.action = crate::graph::Action::Squash;
+/// Move `fixup!`, `squash!`, and `amend!` commits after their targets, squashing them in with
+/// `Fixup::Squash`
+///
+/// Targets are matched by summary or by commit id, like `fixup! 1a2b3c4`, looking first at the
+/// commits beneath the fixup and then at the rest of the graph, so a fixup committed on top of a
+/// sibling branch still finds its target.  Fixups without a target are moved to the bottom of
+/// their stack.
+///
+/// Errors if a fixup targets a protected commit, which can't be rewritten.
+pub fn fixup(node: &mut Node, effect: crate::config::Fixup) -> eyre::Result<()> {
+    if effect == crate::config::Fixup::Ignore {
+        return Ok(());
+    }
+
+    // Find every target before moving anything, so moves don't hide targets from later fixups
+    let mut moves = Vec::new();
+    plan_fixups(node, node, &mut Vec::new(), &mut moves)?;
+
+    let mut outstanding = Vec::new();
+    let mut ends: std::collections::HashMap<git2::Oid, git2::Oid> = Default::default();
+    for (fixup_id, target) in moves {
+        let mut fixup = take_node(node, fixup_id).expect("planned fixups are in the graph");
+        match target {
+            FixupTarget::Commit(target_id) | FixupTarget::Bottom(target_id) => {
+                if matches!(target, FixupTarget::Commit(_))
+                    && effect == crate::config::Fixup::Squash
+                {
+                    assert!(fixup.action == crate::graph::Action::Pick);
+                    fixup.action = crate::graph::Action::Squash;
+                }
+                // After any fixups already moved there, to keep their order
+                let after_id = ends.get(&target_id).copied().unwrap_or(target_id);
+                let after = node
+                    .find_commit_mut(after_id)
+                    .expect("fixup targets stay in the graph");
+                splice_after(after, vec![fixup]);
+                ends.insert(target_id, fixup_id);
+            }
+            FixupTarget::Root => {
+                outstanding.push(fixup);
             }
         }
-        splice_after(node, fixups);
-    } else if (node.action.is_protected() || node.action.is_delete()) && !outstanding.is_empty() {
-        let mut local = Default::default();
-        std::mem::swap(&mut local, outstanding);
+    }
+
+    if !outstanding.is_empty() {
+        assert!(!node.action.is_protected());
+        for mut other in outstanding.into_iter() {
+            std::mem::swap(node, &mut other);
+            node.children.insert(other.local_commit.id, other);
+        }
+    }
+
+    Ok(())
+}
+
+/// Where a fixup is moved to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FixupTarget {
+    /// The commit it fixes
+    Commit(git2::Oid),
+    /// No target was found, so the bottom of its stack, after this protected or dropped commit
+    Bottom(git2::Oid),
+    /// No target was found and the whole graph is the stack
+    Root,
+}
+
+fn plan_fixups<'n>(
+    root: &Node,
+    node: &'n Node,
+    ancestors: &mut Vec<&'n Node>,
+    moves: &mut Vec<(git2::Oid, FixupTarget)>,
+) -> eyre::Result<()> {
+    ancestors.push(node);
+    for (id, child) in node.children.iter() {
+        if child.action.is_protected() || child.action.is_delete() {
+            plan_fixups(root, child, ancestors, moves)?;
+            continue;
+        }
+        if let Some(summary) = child.local_commit.fixup_summary() {
+            let target = ancestors
+                .iter()
+                .rev()
+                .copied()
+                .find(|n| is_fixup_target(n, summary))
+                .or_else(|| find_fixup_target(root, *id, summary));
+            let target = match target {
+                Some(target) if target.action.is_protected() => {
+                    eyre::bail!(
+                        "cannot apply `{}` ({}), its target {} is protected",
+                        child.local_commit.summary,
+                        id,
+                        target.local_commit.id
+                    );
+                }
+                Some(target) => FixupTarget::Commit(target.local_commit.id),
+                None => ancestors
+                    .iter()
+                    .rev()
+                    .find(|n| n.action.is_protected() || n.action.is_delete())
+                    .map(|n| FixupTarget::Bottom(n.local_commit.id))
+                    .unwrap_or(FixupTarget::Root),
+            };
+            moves.push((*id, target));
+        }
+        plan_fixups(root, child, ancestors, moves)?;
+    }
+    ancestors.pop();
+    Ok(())
+}
+
+/// Look for the target anywhere in the graph, besides on top of the fixup
+fn find_fixup_target<'n>(
+    node: &'n Node,
+    fixup_id: git2::Oid,
+    summary: &bstr::BStr,
+) -> Option<&'n Node> {
+    if node.local_commit.id == fixup_id {
+        return None;
+    }
+    if is_fixup_target(node, summary) {
+        return Some(node);
+    }
+    node.children
+        .values()
+        .find_map(|child| find_fixup_target(child, fixup_id, summary))
+}
+
+fn is_fixup_target(node: &Node, summary: &bstr::BStr) -> bool {
+    if node.action.is_delete() || node.local_commit.fixup_summary().is_some() {
+        return false;
+    }
+    if node.local_commit.summary == summary {
+        return true;
+    }
+    // `git commit --fixup` always uses the summary but `git rebase --autosquash` also accepts ids
+    const MIN_ID_LEN: usize = 4;
+    MIN_ID_LEN <= summary.len()
+        && summary.iter().all(u8::is_ascii_hexdigit)
+        && node
+            .local_commit
+            .id
+            .to_string()
+            .as_bytes()
+            .starts_with(summary.to_ascii_lowercase().as_slice())
+}
 
-        let mut outstanding = local.into_values();
-        let mut fixups = outstanding.next().unwrap();
-        fixups.extend(outstanding.flatten());
-        splice_after(node, fixups);
+/// Remove `id` from the graph, leaving its children and branches with its parent
+fn take_node(node: &mut Node, id: git2::Oid) -> Option<Node> {
+    if let Some(mut taken) = node.children.remove(&id) {
+        node.children.append(&mut taken.children);
+        node.branches.append(&mut taken.branches);
+        return Some(taken);
     }
+    node.children
+        .values_mut()
+        .find_map(|child| take_node(child, id))
 }
 
 fn splice_after(node: &mut Node, fixups: Vec<Node>) -> &mut Node {
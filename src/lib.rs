@@ -3,8 +3,10 @@
 #[macro_use]
 extern crate clap;
 
+pub mod audit;
 pub mod config;
 pub mod git;
 pub mod graph;
 pub mod log;
+pub mod serde_oid;
 pub mod stash;
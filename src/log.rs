@@ -1,9 +1,18 @@
 use std::io::Write;
 
-pub fn init_logging(mut level: clap_verbosity_flag::Verbosity, colored: bool) {
+pub fn init_logging(level: clap_verbosity_flag::Verbosity, colored: bool) {
+    init_logging_with(level, colored, None);
+}
+
+/// Like `init_logging`, also keeping every record, whatever the level shown, in `recent`
+pub fn init_logging_with(
+    mut level: clap_verbosity_flag::Verbosity,
+    colored: bool,
+    recent: Option<RecentLog>,
+) {
     level.set_default(Some(log::Level::Info));
 
-    if let Some(level) = level.log_level() {
+    let inner = level.log_level().map(|level| {
         let mut builder = env_logger::Builder::new();
         builder.write_style(if colored {
             env_logger::WriteStyle::Always
@@ -25,6 +34,86 @@ pub fn init_logging(mut level: clap_verbosity_flag::Verbosity, colored: bool) {
             });
         }
 
-        builder.init();
+        builder.build()
+    });
+
+    let max_level = match (&inner, &recent) {
+        (_, Some(_)) => log::LevelFilter::Trace,
+        (Some(inner), None) => inner.filter(),
+        (None, None) => return,
+    };
+    let logger = Logger { inner, recent };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// The most recent log records, for crash reports
+#[derive(Clone, Debug, Default)]
+pub struct RecentLog {
+    records: std::sync::Arc<std::sync::Mutex<std::collections::VecDeque<String>>>,
+}
+
+impl RecentLog {
+    const CAPACITY: usize = 500;
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The records, oldest first
+    pub fn records(&self) -> Vec<String> {
+        match self.records.lock() {
+            Ok(records) => records.iter().cloned().collect(),
+            // Panicked while logging, what was kept is still good
+            Err(poisoned) => poisoned.into_inner().iter().cloned().collect(),
+        }
+    }
+
+    fn push(&self, record: &log::Record<'_>) {
+        if let Ok(mut records) = self.records.lock() {
+            if Self::CAPACITY <= records.len() {
+                records.pop_front();
+            }
+            records.push_back(format!(
+                "[{} {}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            ));
+        }
+    }
+}
+
+struct Logger {
+    inner: Option<env_logger::Logger>,
+    recent: Option<RecentLog>,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        self.recent.is_some()
+            || self
+                .inner
+                .as_ref()
+                .map(|inner| inner.enabled(metadata))
+                .unwrap_or(false)
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if let Some(recent) = self.recent.as_ref() {
+            recent.push(record);
+        }
+        if let Some(inner) = self.inner.as_ref() {
+            if inner.matches(record) {
+                inner.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(inner) = self.inner.as_ref() {
+            inner.flush();
+        }
     }
 }
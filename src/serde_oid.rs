@@ -0,0 +1,40 @@
+//! Serialize `git2::Oid`s as hex strings, for `#[serde(with = "git_stack::serde_oid")]`
+
+pub fn serialize<S>(id: &git2::Oid, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let id = id.to_string();
+    serializer.serialize_str(&id)
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<git2::Oid, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::Deserialize;
+    let s = String::deserialize(deserializer)?;
+    git2::Oid::from_str(&s).map_err(serde::de::Error::custom)
+}
+
+/// The same for `Option<git2::Oid>`, as a hex string or `null`
+pub mod option {
+    pub fn serialize<S>(id: &Option<git2::Oid>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let id = id.map(|id| id.to_string());
+        serde::Serialize::serialize(&id, serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<git2::Oid>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::Deserialize;
+        let s = Option::<String>::deserialize(deserializer)?;
+        s.map(|s| git2::Oid::from_str(&s))
+            .transpose()
+            .map_err(serde::de::Error::custom)
+    }
+}
@@ -1,6 +1,8 @@
+mod quarantine;
 #[allow(clippy::module_inception)]
 mod snapshot;
 mod stack;
 
+pub use quarantine::*;
 pub use snapshot::*;
 pub use stack::*;
@@ -0,0 +1,145 @@
+/// Branches `git stack` deleted, kept restorable until they are purged
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Quarantine {
+    pub branches: Vec<QuarantinedBranch>,
+}
+
+impl Quarantine {
+    /// Where the refs keeping quarantined commits from being garbage collected live
+    pub const PREFIX: &'static str = "refs/quarantine/";
+    const FILE: &'static str = "quarantine.json";
+
+    pub fn load(repo: &crate::git::GitRepo) -> Result<Self, std::io::Error> {
+        let path = quarantine_path(repo);
+        let file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+        let reader = std::io::BufReader::new(file);
+        let quarantine = serde_json::from_reader(reader)?;
+        Ok(quarantine)
+    }
+
+    pub fn save(&self, repo: &crate::git::GitRepo) -> Result<(), std::io::Error> {
+        let path = quarantine_path(repo);
+        if self.branches.is_empty() {
+            return match std::fs::remove_file(&path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            };
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let s = serde_json::to_string_pretty(self)?;
+        std::fs::write(&path, &s)?;
+        Ok(())
+    }
+
+    /// Record a deleted branch, with a ref to keep its commits around
+    ///
+    /// `deleted` is in seconds since the epoch.
+    pub fn insert(
+        &mut self,
+        repo: &crate::git::GitRepo,
+        name: &str,
+        id: git2::Oid,
+        deleted: i64,
+    ) -> Result<(), git2::Error> {
+        // The same name can be deleted more than once a second, like with `git stack undo`
+        let reference = format!("{}{}/{}/{}", Self::PREFIX, deleted, id, name);
+        repo.raw()
+            .reference(&reference, id, true, &format!("quarantine {}", name))?;
+        self.branches.push(QuarantinedBranch {
+            name: name.to_owned(),
+            id,
+            reference,
+            deleted,
+        });
+        Ok(())
+    }
+
+    /// The most recently deleted branch by this name
+    pub fn get(&self, name: &str) -> Option<&QuarantinedBranch> {
+        self.branches
+            .iter()
+            .filter(|b| b.name == name)
+            .max_by_key(|b| b.deleted)
+    }
+
+    /// Stop tracking the most recently deleted branch by this name, dropping its ref
+    pub fn remove(
+        &mut self,
+        repo: &crate::git::GitRepo,
+        name: &str,
+    ) -> Result<Option<QuarantinedBranch>, git2::Error> {
+        let index = self
+            .branches
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.name == name)
+            .max_by_key(|(_, b)| b.deleted)
+            .map(|(i, _)| i);
+        let branch = match index {
+            Some(index) => self.branches.remove(index),
+            None => return Ok(None),
+        };
+        self.release(repo, &branch)?;
+        Ok(Some(branch))
+    }
+
+    /// Finally remove the branches deleted before `cutoff`, in seconds since the epoch
+    pub fn purge(
+        &mut self,
+        repo: &crate::git::GitRepo,
+        cutoff: i64,
+    ) -> Result<Vec<QuarantinedBranch>, git2::Error> {
+        let (expired, kept): (Vec<_>, Vec<_>) =
+            self.branches.drain(..).partition(|b| b.deleted < cutoff);
+        self.branches = kept;
+        for branch in expired.iter() {
+            self.release(repo, branch)?;
+        }
+        Ok(expired)
+    }
+
+    /// Delete the ref of a branch no longer tracked, unless another entry still shares it
+    fn release(
+        &self,
+        repo: &crate::git::GitRepo,
+        branch: &QuarantinedBranch,
+    ) -> Result<(), git2::Error> {
+        if self
+            .branches
+            .iter()
+            .any(|b| b.reference == branch.reference)
+        {
+            return Ok(());
+        }
+        delete_reference(repo, &branch.reference)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct QuarantinedBranch {
+    pub name: String,
+    #[serde(with = "crate::serde_oid")]
+    pub id: git2::Oid,
+    /// Ref keeping the commits from being garbage collected
+    pub reference: String,
+    /// When the branch was deleted, in seconds since the epoch
+    pub deleted: i64,
+}
+
+fn quarantine_path(repo: &crate::git::GitRepo) -> std::path::PathBuf {
+    super::stack::stacks_root(repo.raw().path()).join(Quarantine::FILE)
+}
+
+fn delete_reference(repo: &crate::git::GitRepo, name: &str) -> Result<(), git2::Error> {
+    match repo.raw().find_reference(name) {
+        Ok(mut reference) => reference.delete(),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
@@ -37,7 +37,13 @@ impl Snapshot {
             })
             .collect();
         branches.sort_unstable();
-        let metadata = Default::default();
+        let mut metadata = std::collections::BTreeMap::new();
+        if let Some(fingerprint) = repo.fingerprint() {
+            metadata.insert(
+                "repo-id".to_owned(),
+                serde_json::Value::String(fingerprint.to_string()),
+            );
+        }
         Ok(Self { branches, metadata })
     }
 
@@ -52,17 +58,25 @@ impl Snapshot {
                 if head_branch_name == Some(branch.name.as_str()) {
                     log::debug!("Restoring {} (HEAD)", branch.name);
                     repo.detach()?;
-                    repo.branch(&branch.name, branch.id)?;
+                    repo.branch(&branch.name, branch.id, "restore from backup")?;
                     repo.switch(&branch.name)?;
                 } else {
                     log::debug!("Restoring {}", branch.name);
-                    repo.branch(&branch.name, branch.id)?;
+                    repo.branch(&branch.name, branch.id, "restore from backup")?;
                 }
             }
         }
         Ok(())
     }
 
+    /// The `Repo::fingerprint` of the repository this was taken in, if recorded
+    pub fn fingerprint(&self) -> Option<git2::Oid> {
+        self.metadata
+            .get("repo-id")
+            .and_then(|r| r.as_str())
+            .and_then(|r| git2::Oid::from_str(r).ok())
+    }
+
     pub fn insert_message(&mut self, message: &str) {
         self.metadata.insert(
             "message".to_owned(),
@@ -70,6 +84,15 @@ impl Snapshot {
         );
     }
 
+    pub fn insert_rename(&mut self, old: &str, new: &str) {
+        for branch in self.branches.iter_mut().filter(|b| b.name == old) {
+            branch.metadata.insert(
+                "renamed-to".to_owned(),
+                serde_json::Value::String(new.to_owned()),
+            );
+        }
+    }
+
     pub fn insert_parent(
         &mut self,
         repo: &dyn crate::git::Repo,
@@ -92,31 +115,13 @@ impl Snapshot {
 #[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Branch {
     pub name: String,
-    #[serde(serialize_with = "serialize_oid")]
-    #[serde(deserialize_with = "deserialize_oid")]
+    #[serde(with = "crate::serde_oid")]
     pub id: git2::Oid,
     #[serde(default)]
     #[serde(skip_serializing_if = "std::collections::BTreeMap::is_empty")]
     pub metadata: std::collections::BTreeMap<String, serde_json::Value>,
 }
 
-fn serialize_oid<S>(id: &git2::Oid, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: serde::Serializer,
-{
-    let id = id.to_string();
-    serializer.serialize_str(&id)
-}
-
-fn deserialize_oid<'de, D>(deserializer: D) -> Result<git2::Oid, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    use serde::Deserialize;
-    let s = String::deserialize(deserializer)?;
-    git2::Oid::from_str(&s).map_err(serde::de::Error::custom)
-}
-
 impl PartialOrd for Branch {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some((&self.name, self.id).cmp(&(&other.name, other.id)))
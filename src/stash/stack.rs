@@ -128,7 +128,7 @@ impl Stack {
     }
 }
 
-fn stacks_root(repo: &std::path::Path) -> std::path::PathBuf {
+pub(crate) fn stacks_root(repo: &std::path::Path) -> std::path::PathBuf {
     repo.join("branch-stash")
 }
 
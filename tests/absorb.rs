@@ -0,0 +1,98 @@
+mod fixture;
+
+use git_stack::git::*;
+
+fn stage(repo: &git2::Repository, content: &str) {
+    let blob = repo.blob(content.as_bytes()).unwrap();
+    let mut index = repo.index().unwrap();
+    let mut entry = index.get_path(std::path::Path::new("a.txt"), 0).unwrap();
+    entry.id = blob;
+    entry.file_size = content.len() as u32;
+    index.add(&entry).unwrap();
+    index.write().unwrap();
+}
+
+fn stack_ids(repo: &git2::Repository) -> (git2::Oid, git2::Oid) {
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    let one = head.parent(0).unwrap();
+    (one.id(), head.id())
+}
+
+#[test]
+fn attribute_to_last_change() {
+    let (_temp, repo) = fixture::init_repo("absorb.yml");
+    let (one_id, three_id) = stack_ids(&repo);
+    stage(&repo, "ONE\n2\nTHREE\n");
+
+    let candidates = vec![one_id, three_id].into_iter().collect();
+    let actual: Vec<_> = attribute_staged(&repo, three_id, &candidates)
+        .unwrap()
+        .into_iter()
+        .map(|(hunk, attribution)| (hunk.new_start, attribution))
+        .collect();
+    assert_eq!(
+        actual,
+        vec![
+            (1, Attribution::Commit(one_id)),
+            (3, Attribution::Commit(three_id)),
+        ]
+    );
+
+    // Insertions go by their neighbors
+    stage(&repo, "one\n1.5\n2\nthree\n");
+    let actual: Vec<_> = attribute_staged(&repo, three_id, &candidates)
+        .unwrap()
+        .into_iter()
+        .map(|(_, attribution)| attribution)
+        .collect();
+    assert_eq!(
+        actual,
+        vec![Attribution::Skip(
+            "changes lines from multiple commits".to_owned()
+        )]
+    );
+
+    // Protected commits are never targeted
+    stage(&repo, "one\n22\nthree\n");
+    let actual: Vec<_> = attribute_staged(&repo, three_id, &candidates)
+        .unwrap()
+        .into_iter()
+        .map(|(_, attribution)| attribution)
+        .collect();
+    assert_eq!(
+        actual,
+        vec![Attribution::Skip(
+            "last changed outside of the stack".to_owned()
+        )]
+    );
+}
+
+#[test]
+fn commit_fixups_per_target() {
+    let (_temp, repo) = fixture::init_repo("absorb.yml");
+    let (one_id, three_id) = stack_ids(&repo);
+    stage(&repo, "ONE\n2\nTHREE\n");
+
+    let candidates = vec![one_id, three_id].into_iter().collect();
+    let attributed = attribute_staged(&repo, three_id, &candidates).unwrap();
+    let fixups: Vec<_> = attributed
+        .into_iter()
+        .map(|(hunk, attribution)| match attribution {
+            Attribution::Commit(id) => (id, vec![hunk]),
+            Attribution::Skip(reason) => panic!("unexpected skip: {}", reason),
+        })
+        .collect();
+    let tip_id = commit_fixups(&repo, three_id, &fixups).unwrap();
+
+    let tip = repo.find_commit(tip_id).unwrap();
+    assert_eq!(tip.summary(), Some("fixup! three"));
+    let index_tree = repo.index().unwrap().write_tree().unwrap();
+    assert_eq!(tip.tree_id(), index_tree);
+
+    let first = tip.parent(0).unwrap();
+    assert_eq!(first.summary(), Some("fixup! one"));
+    assert_eq!(first.parent_id(0).unwrap(), three_id);
+    let content = first.tree().unwrap().get_name("a.txt").unwrap().id();
+    let content = repo.find_blob(content).unwrap();
+    assert_eq!(content.content(), b"ONE\n2\nthree\n");
+}
@@ -65,6 +65,52 @@ mod test_branches {
         );
     }
 
+    #[test]
+    fn test_related() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+        // Like `gh-pages`, with a history of its own
+        let pages_id = repo.gen_id();
+        repo.push_commit(
+            None,
+            Commit {
+                id: pages_id,
+                tree_id: pages_id,
+                summary: "pages".into(),
+            },
+        );
+        repo.mark_branch(Branch {
+            name: "gh-pages".to_owned(),
+            id: pages_id,
+            push_id: None,
+            pull_id: None,
+        });
+
+        let master_oid = repo.resolve("master").unwrap().id;
+
+        let branches = Branches::new(repo.local_branches());
+        let result = branches.related(&repo, master_oid);
+        let mut names: Vec<_> = result
+            .iter()
+            .flat_map(|(_, b)| b.iter().map(|b| b.name.as_str()))
+            .collect();
+        names.sort_unstable();
+
+        assert_eq!(
+            names,
+            [
+                "base",
+                "feature1",
+                "feature2",
+                "initial",
+                "master",
+                "off_master"
+            ]
+        );
+    }
+
     #[test]
     fn test_dependents() {
         let mut repo = git_stack::git::InMemoryRepo::new();
@@ -183,3 +229,72 @@ mod test_find_protected_base {
         assert!(branch.is_some());
     }
 }
+
+mod test_find_next {
+    use super::*;
+
+    #[test]
+    fn test_fork() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let branches = Branches::new(repo.local_branches());
+
+        let head_oid = repo.resolve("base").unwrap().id;
+
+        let next: Vec<_> = find_next(&repo, &branches, head_oid)
+            .into_iter()
+            .map(|b| b.name.as_str())
+            .collect();
+        assert_eq!(next, vec!["feature1", "master"]);
+    }
+
+    #[test]
+    fn test_stack() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let branches = Branches::new(repo.local_branches());
+
+        let head_oid = repo.resolve("feature1").unwrap().id;
+        let next: Vec<_> = find_next(&repo, &branches, head_oid)
+            .into_iter()
+            .map(|b| b.name.as_str())
+            .collect();
+        assert_eq!(next, vec!["feature2"]);
+
+        let head_oid = repo.resolve("feature2").unwrap().id;
+        let next = find_next(&repo, &branches, head_oid);
+        assert!(next.is_empty());
+    }
+}
+
+mod test_find_prev {
+    use super::*;
+
+    #[test]
+    fn test_stack() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let branches = Branches::new(repo.local_branches());
+
+        let head_oid = repo.resolve("feature2").unwrap().id;
+        let prev = find_prev(&repo, &branches, head_oid);
+        assert_eq!(prev[0].name, "feature1");
+
+        let head_oid = repo.resolve("feature1").unwrap().id;
+        let prev = find_prev(&repo, &branches, head_oid);
+        assert_eq!(prev[0].name, "base");
+
+        let head_oid = repo.resolve("initial").unwrap().id;
+        let prev = find_prev(&repo, &branches, head_oid);
+        assert!(prev.is_empty());
+    }
+}
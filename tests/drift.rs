@@ -0,0 +1,121 @@
+mod fixture;
+
+use git_stack::git::*;
+
+fn branch(repo: &git2::Repository, name: &str) -> Branch {
+    let id = repo
+        .find_branch(name, git2::BranchType::Local)
+        .unwrap()
+        .get()
+        .target()
+        .unwrap();
+    Branch {
+        name: name.to_owned(),
+        id,
+        push_id: None,
+        pull_id: None,
+    }
+}
+
+/// Recreate `name`'s commit on `parent_id`, like amending or rebasing it
+fn rewrite(repo: &git2::Repository, name: &str, parent_id: git2::Oid) -> git2::Oid {
+    let old = repo.find_commit(branch(repo, name).id).unwrap();
+    let parent = repo.find_commit(parent_id).unwrap();
+    let new_id = repo
+        .commit(
+            None,
+            &old.author(),
+            &old.committer(),
+            &format!("{} (rewritten)", old.message().unwrap()),
+            &old.tree().unwrap(),
+            &[&parent],
+        )
+        .unwrap();
+    repo.reference(&format!("refs/heads/{}", name), new_id, true, "rewrite")
+        .unwrap();
+    new_id
+}
+
+fn unprotected(repo: &git2::Repository, names: &[&str]) -> Vec<(Branch, Vec<git2::Oid>)> {
+    names
+        .iter()
+        .map(|name| {
+            let past_ids = past_ids(repo, &format!("refs/heads/{}", name));
+            (branch(repo, name), past_ids)
+        })
+        .collect()
+}
+
+#[test]
+fn amended_parent() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let old_id = branch(&repo, "feature1").id;
+    let base_id = branch(&repo, "base").id;
+    rewrite(&repo, "feature1", base_id);
+
+    let branches = unprotected(&repo, &["feature1", "feature2"]);
+    let drifted = find_drifted(&repo, &branches, &[branch(&repo, "master").id]);
+    assert_eq!(
+        drifted,
+        vec![Drifted {
+            branch: branch(&repo, "feature2"),
+            parent: branch(&repo, "feature1"),
+            fork_id: old_id,
+        }]
+    );
+}
+
+#[test]
+fn rebased_parent() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let old_id = branch(&repo, "feature1").id;
+    let master_id = branch(&repo, "master").id;
+    rewrite(&repo, "feature1", master_id);
+
+    let branches = unprotected(&repo, &["feature1", "feature2"]);
+    let drifted = find_drifted(&repo, &branches, &[master_id]);
+    assert_eq!(
+        drifted,
+        vec![Drifted {
+            branch: branch(&repo, "feature2"),
+            parent: branch(&repo, "feature1"),
+            fork_id: old_id,
+        }]
+    );
+}
+
+#[test]
+fn reset_back_parent() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let base_id = branch(&repo, "base").id;
+    repo.reference("refs/heads/feature1", base_id, true, "reset")
+        .unwrap();
+
+    // `feature2` still contains `feature1`, with the commit it dropped beneath it
+    let branches = unprotected(&repo, &["feature1", "feature2"]);
+    let drifted = find_drifted(&repo, &branches, &[branch(&repo, "master").id]);
+    assert_eq!(drifted, vec![]);
+}
+
+#[test]
+fn stacked_on_drifted_branch() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let old_id = branch(&repo, "feature1").id;
+    let feature2 = repo.find_commit(branch(&repo, "feature2").id).unwrap();
+    let middle = feature2.parent(0).unwrap();
+    repo.branch("feature1b", &middle, false).unwrap();
+    let base_id = branch(&repo, "base").id;
+    rewrite(&repo, "feature1", base_id);
+
+    // `feature2` moves along with `feature1b`
+    let branches = unprotected(&repo, &["feature1", "feature1b", "feature2"]);
+    let drifted = find_drifted(&repo, &branches, &[branch(&repo, "master").id]);
+    assert_eq!(
+        drifted,
+        vec![Drifted {
+            branch: branch(&repo, "feature1b"),
+            parent: branch(&repo, "feature1"),
+            fork_id: old_id,
+        }]
+    );
+}
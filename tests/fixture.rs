@@ -1,5 +1,38 @@
+#![allow(dead_code)]
+
 use bstr::ByteSlice;
 
+/// Run the `tests/fixtures/` plan `name` in a new temporary directory
+pub fn init_repo(name: &str) -> (assert_fs::TempDir, git2::Repository) {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let path = std::path::Path::new("tests/fixtures").join(name);
+    let plan = git_fixture::Dag::load(&path).unwrap();
+    plan.run(temp.path()).unwrap();
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    (temp, repo)
+}
+
+/// Commit `content` to `path` on top of `parents`, keeping the rest of the first parent's tree
+pub fn commit(
+    repo: &git2::Repository,
+    parents: &[git2::Oid],
+    path: &str,
+    content: &str,
+) -> git2::Oid {
+    let parents: Vec<_> = parents
+        .iter()
+        .map(|id| repo.find_commit(*id).unwrap())
+        .collect();
+    let mut builder = repo.treebuilder(Some(&parents[0].tree().unwrap())).unwrap();
+    let blob_id = repo.blob(content.as_bytes()).unwrap();
+    builder.insert(path, blob_id, 0o100644).unwrap();
+    let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+    let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+    let parents: Vec<_> = parents.iter().collect();
+    repo.commit(None, &signature, &signature, path, &tree, &parents)
+        .unwrap()
+}
+
 pub fn populate_repo(repo: &mut git_stack::git::InMemoryRepo, fixture: git_fixture::Dag) {
     if fixture.init {
         repo.clear();
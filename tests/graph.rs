@@ -93,4 +93,1142 @@ mod test_rebase {
         dbg!(&feature1_branch.id);
         assert!(ancestors.contains(&feature1_branch.id));
     }
+
+    #[test]
+    fn rebase_onto_unprotected() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let master_branch = repo.find_local_branch("master").unwrap();
+        let off_master_branch = repo.find_local_branch("off_master").unwrap();
+
+        // `--onto` treats its branch as protected so it can serve as the new base
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+        protected_branches.insert(off_master_branch.clone());
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(repo.find_local_branch("base").unwrap());
+        graph_branches.insert(off_master_branch.clone());
+        graph_branches.insert(repo.find_local_branch("feature1").unwrap());
+        graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        git_stack::graph::protect_branches(&mut root, &repo, &protected_branches);
+        git_stack::graph::rebase_branches(&mut root, off_master_branch.id);
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "feature2").unwrap();
+
+        assert_eq!(
+            repo.find_local_branch("off_master").unwrap().id,
+            off_master_branch.id
+        );
+
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        let feature1_parent = repo.commits_from(feature1_branch.id).nth(1).unwrap();
+        assert_eq!(feature1_parent.id, off_master_branch.id);
+
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let ancestors: Vec<_> = repo
+            .commits_from(feature2_branch.id)
+            .map(|c| c.id)
+            .collect();
+        assert!(ancestors.contains(&feature1_branch.id));
+    }
+
+    #[test]
+    fn rebase_selected() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let master_branch = repo.find_local_branch("master").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(master_branch.clone());
+        graph_branches.insert(repo.find_local_branch("feature1").unwrap());
+        graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+        let master_commit = repo.find_commit(master_branch.id).unwrap();
+        let old_feature2_branch = repo.find_local_branch("feature2").unwrap();
+
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        git_stack::graph::protect_branches(&mut root, &repo, &protected_branches);
+        let skipped = git_stack::graph::retain_branches(&mut root, &|b| b.name == "feature1");
+        let skipped: Vec<_> = skipped.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(skipped, ["feature2"]);
+        git_stack::graph::rebase_branches(&mut root, master_commit.id);
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "off_master").unwrap();
+
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        let ancestors: Vec<_> = repo
+            .commits_from(feature1_branch.id)
+            .map(|c| c.id)
+            .collect();
+        assert!(ancestors.contains(&master_commit.id));
+
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        assert_eq!(feature2_branch.id, old_feature2_branch.id);
+    }
+
+    #[test]
+    fn rebase_selected_blocked_by_parent() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let master_branch = repo.find_local_branch("master").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(master_branch.clone());
+        graph_branches.insert(repo.find_local_branch("feature1").unwrap());
+        graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        git_stack::graph::protect_branches(&mut root, &repo, &protected_branches);
+        let skipped = git_stack::graph::retain_branches(&mut root, &|b| b.name == "feature2");
+        let mut skipped: Vec<_> = skipped.iter().map(|b| b.name.as_str()).collect();
+        skipped.sort_unstable();
+        assert_eq!(skipped, ["feature1", "feature2"]);
+    }
+
+    #[test]
+    fn rebase_protected_commit() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let master_branch = repo.find_local_branch("master").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(master_branch.clone());
+        graph_branches.insert(repo.find_local_branch("feature1").unwrap());
+        graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+        let master_commit = repo.find_commit(master_branch.id).unwrap();
+        // Like a release tagged on top of `feature1`
+        let old_feature1_branch = repo.find_local_branch("feature1").unwrap();
+
+        let tag_ids = [old_feature1_branch.id];
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        git_stack::graph::protect_branches(&mut root, &repo, &protected_branches);
+        git_stack::graph::protect_commits(&mut root, &repo, &tag_ids);
+        git_stack::graph::rebase_branches_except(&mut root, master_commit.id, &tag_ids);
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "off_master").unwrap();
+
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        assert_eq!(feature1_branch.id, old_feature1_branch.id);
+
+        // The tagged commit is the base of what is on top of it
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let ancestors: Vec<_> = repo
+            .commits_from(feature2_branch.id)
+            .map(|c| c.id)
+            .collect();
+        assert!(!ancestors.contains(&master_commit.id));
+        assert!(ancestors.contains(&feature1_branch.id));
+    }
+
+    #[test]
+    fn drop_cherry_picked_upstream() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let picked = repo.commits_from(feature2_branch.id).nth(2).unwrap();
+        assert_eq!(picked.summary, "8");
+
+        // Upstream took one commit from the middle of the stack and kept going
+        let master_branch = repo.find_local_branch("master").unwrap();
+        let upstream_id = repo.cherry_pick(master_branch.id, picked.id).unwrap();
+        let later_id = repo.gen_id();
+        repo.push_commit(
+            Some(upstream_id),
+            git_stack::git::Commit {
+                id: later_id,
+                tree_id: later_id,
+                summary: "11".into(),
+            },
+        );
+        repo.mark_branch(git_stack::git::Branch {
+            name: "master".to_owned(),
+            id: later_id,
+            push_id: None,
+            pull_id: None,
+        });
+        let master_branch = repo.find_local_branch("master").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(master_branch.clone());
+        graph_branches.insert(repo.find_local_branch("feature1").unwrap());
+        graph_branches.insert(feature2_branch);
+
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        git_stack::graph::protect_branches(&mut root, &repo, &protected_branches);
+        git_stack::graph::rebase_branches(&mut root, master_branch.id);
+        git_stack::graph::drop_by_patch_id(&mut root, &repo);
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "feature2").unwrap();
+
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let stacked: Vec<_> = repo
+            .commits_from(feature2_branch.id)
+            .take_while(|c| c.id != master_branch.id)
+            .map(|c| c.summary.to_string())
+            .collect();
+        assert_eq!(stacked, ["10", "9", "7"]);
+    }
+
+    #[test]
+    fn dedup_sibling_cherry_picks() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        // `feature1`'s commit, also picked onto a branch next to it
+        let base_branch = repo.find_local_branch("base").unwrap();
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        let picked_id = repo
+            .cherry_pick(base_branch.id, feature1_branch.id)
+            .unwrap();
+        let sibling_id = repo.gen_id();
+        repo.push_commit(
+            Some(picked_id),
+            git_stack::git::Commit {
+                id: sibling_id,
+                tree_id: sibling_id,
+                summary: "11".into(),
+            },
+        );
+        repo.mark_branch(git_stack::git::Branch {
+            name: "sibling".to_owned(),
+            id: sibling_id,
+            push_id: None,
+            pull_id: None,
+        });
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(base_branch.clone());
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(base_branch);
+        graph_branches.insert(feature1_branch.clone());
+        graph_branches.insert(repo.find_local_branch("sibling").unwrap());
+
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        git_stack::graph::protect_branches(&mut root, &repo, &protected_branches);
+        git_stack::graph::dedup_cherry_picks(&mut root, &repo);
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "sibling").unwrap();
+
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        let sibling_branch = repo.find_local_branch("sibling").unwrap();
+        let ancestors: Vec<_> = repo.commits_from(sibling_branch.id).map(|c| c.id).collect();
+        assert!(ancestors.contains(&feature1_branch.id));
+        assert!(!ancestors.contains(&picked_id));
+    }
+
+    #[test]
+    fn dedup_cherry_picks_up_the_stack() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        // A commit from the middle of `feature2`, picked onto a branch next to it
+        let base_branch = repo.find_local_branch("base").unwrap();
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let middle_id = repo.parent_ids(feature2_branch.id).unwrap()[0];
+        let sibling_id = repo.gen_id();
+        repo.push_commit(
+            Some(base_branch.id),
+            git_stack::git::Commit {
+                id: sibling_id,
+                tree_id: sibling_id,
+                summary: "11".into(),
+            },
+        );
+        let picked_id = repo.cherry_pick(sibling_id, middle_id).unwrap();
+        repo.mark_branch(git_stack::git::Branch {
+            name: "sibling".to_owned(),
+            id: picked_id,
+            push_id: None,
+            pull_id: None,
+        });
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(base_branch.clone());
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(base_branch);
+        graph_branches.insert(feature2_branch);
+        graph_branches.insert(repo.find_local_branch("sibling").unwrap());
+
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        git_stack::graph::protect_branches(&mut root, &repo, &protected_branches);
+        git_stack::graph::dedup_cherry_picks(&mut root, &repo);
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "sibling").unwrap();
+
+        let sibling_branch = repo.find_local_branch("sibling").unwrap();
+        let summaries: Vec<_> = repo
+            .commits_from(sibling_branch.id)
+            .map(|c| c.summary.to_string())
+            .collect();
+        assert_eq!(summaries, ["11", "3", "2", "1"]);
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let summaries: Vec<_> = repo
+            .commits_from(feature2_branch.id)
+            .map(|c| c.summary.to_string())
+            .collect();
+        assert_eq!(summaries, ["10", "9", "8", "7", "3", "2", "1"]);
+    }
+}
+
+mod test_fixup {
+    use super::*;
+
+    fn summaries(repo: &git_stack::git::InMemoryRepo, branch: &str) -> Vec<String> {
+        let master_id = repo.find_local_branch("master").unwrap().id;
+        let branch_id = repo.find_local_branch(branch).unwrap().id;
+        repo.commits_from(branch_id)
+            .take_while(|c| c.id != master_id)
+            .map(|c| c.summary.to_string())
+            .collect()
+    }
+
+    fn rebase(repo: &mut git_stack::git::InMemoryRepo, effect: git_stack::config::Fixup) {
+        let master_branch = repo.find_local_branch("master").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(master_branch.clone());
+        graph_branches.insert(repo.find_local_branch("feature1").unwrap());
+        graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+        let mut root = Node::from_branches(repo, graph_branches).unwrap();
+        git_stack::graph::protect_branches(&mut root, repo, &protected_branches);
+        git_stack::graph::rebase_branches(&mut root, master_branch.id);
+        git_stack::graph::fixup(&mut root, effect).unwrap();
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(repo, false);
+        let result = executor.run_script(repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(repo, "feature2").unwrap();
+    }
+
+    #[test]
+    fn move_next_to_target() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/fixup.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        rebase(&mut repo, git_stack::config::Fixup::Move);
+
+        assert_eq!(
+            summaries(&repo, "feature2"),
+            vec!["Add c", "fixup! Add b", "Add b"]
+        );
+        assert_eq!(
+            repo.find_local_branch("feature1").unwrap().id,
+            repo.find_local_branch("feature2").unwrap().id
+        );
+    }
+
+    #[test]
+    fn squash_into_target() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/fixup.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        rebase(&mut repo, git_stack::config::Fixup::Squash);
+
+        assert_eq!(summaries(&repo, "feature2"), vec!["Add c", "Add b"]);
+        assert_eq!(
+            repo.find_local_branch("feature1").unwrap().id,
+            repo.find_local_branch("feature2").unwrap().id
+        );
+    }
+
+    #[test]
+    fn move_to_target_on_sibling() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/fixup_sibling.yml"))
+            .unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        rebase(&mut repo, git_stack::config::Fixup::Move);
+
+        assert_eq!(summaries(&repo, "feature1"), vec!["fixup! Add b", "Add b"]);
+        assert_eq!(summaries(&repo, "feature2"), vec!["Add c"]);
+    }
+
+    #[test]
+    fn protected_target() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/fixup_sibling.yml"))
+            .unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let master_branch = repo.find_local_branch("master").unwrap();
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+        protected_branches.insert(feature1_branch.clone());
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(master_branch.clone());
+        graph_branches.insert(feature1_branch);
+        graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        git_stack::graph::protect_branches(&mut root, &repo, &protected_branches);
+        git_stack::graph::rebase_branches(&mut root, master_branch.id);
+        let result = git_stack::graph::fixup(&mut root, git_stack::config::Fixup::Squash);
+        assert!(result.is_err());
+    }
+}
+
+mod test_pushable {
+    use super::*;
+
+    fn graph(repo: &git_stack::git::InMemoryRepo) -> Node {
+        let master_branch = repo.find_local_branch("master").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(master_branch);
+        graph_branches.insert(repo.find_local_branch("feature1").unwrap());
+        graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+        let mut root = Node::from_branches(repo, graph_branches).unwrap();
+        git_stack::graph::protect_branches(&mut root, repo, &protected_branches);
+        root
+    }
+
+    fn find(node: &Node, id: git2::Oid) -> Option<&Node> {
+        if node.local_commit.id == id {
+            return Some(node);
+        }
+        node.children.values().find_map(|child| find(child, id))
+    }
+
+    #[test]
+    fn wip_blocks_dependents() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/wip.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+        let feature1_id = repo.find_local_branch("feature1").unwrap().id;
+        let feature2_id = repo.find_local_branch("feature2").unwrap().id;
+
+        let mut root = graph(&repo);
+        git_stack::graph::pushable(
+            &mut root,
+            git_stack::config::PushPolicy::Ready,
+            git_stack::config::PushWip::Never,
+        );
+
+        let feature1 = find(&root, feature1_id).unwrap();
+        assert!(!feature1.pushable);
+        assert_eq!(
+            feature1.push_blocker,
+            Some(git_stack::graph::PushBlocker::Wip)
+        );
+        let feature2 = find(&root, feature2_id).unwrap();
+        assert!(!feature2.pushable);
+        assert_eq!(
+            feature2.push_blocker,
+            Some(git_stack::graph::PushBlocker::StackedOnWip)
+        );
+    }
+
+    #[test]
+    fn allow_wip() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/wip.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+        let feature1_id = repo.find_local_branch("feature1").unwrap().id;
+        let feature2_id = repo.find_local_branch("feature2").unwrap().id;
+
+        let mut root = graph(&repo);
+        git_stack::graph::pushable(
+            &mut root,
+            git_stack::config::PushPolicy::Ready,
+            git_stack::config::PushWip::Allow,
+        );
+
+        for id in [feature1_id, feature2_id].iter() {
+            let node = find(&root, *id).unwrap();
+            assert!(node.pushable);
+            assert_eq!(node.push_blocker, None);
+        }
+    }
+
+    #[test]
+    fn bottom_blocks_dependents() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/wip.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+        let feature1_id = repo.find_local_branch("feature1").unwrap().id;
+        let feature2_id = repo.find_local_branch("feature2").unwrap().id;
+
+        let mut root = graph(&repo);
+        git_stack::graph::pushable(
+            &mut root,
+            git_stack::config::PushPolicy::Bottom,
+            git_stack::config::PushWip::Never,
+        );
+
+        let feature1 = find(&root, feature1_id).unwrap();
+        assert!(!feature1.pushable);
+        assert_eq!(
+            feature1.push_blocker,
+            Some(git_stack::graph::PushBlocker::Wip)
+        );
+        let feature2 = find(&root, feature2_id).unwrap();
+        assert!(!feature2.pushable);
+        assert_eq!(
+            feature2.push_blocker,
+            Some(git_stack::graph::PushBlocker::StackedOnWip)
+        );
+    }
+
+    #[test]
+    fn bottom_only() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/wip.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+        let feature1_id = repo.find_local_branch("feature1").unwrap().id;
+        let feature2_id = repo.find_local_branch("feature2").unwrap().id;
+
+        let mut root = graph(&repo);
+        git_stack::graph::pushable(
+            &mut root,
+            git_stack::config::PushPolicy::Bottom,
+            git_stack::config::PushWip::Allow,
+        );
+
+        let feature1 = find(&root, feature1_id).unwrap();
+        assert!(feature1.pushable);
+        let feature2 = find(&root, feature2_id).unwrap();
+        assert!(!feature2.pushable);
+        assert_eq!(feature2.push_blocker, None);
+    }
+}
+
+mod test_reword {
+    use super::*;
+
+    #[test]
+    fn restack_dependents() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let base_branch = repo.find_local_branch("base").unwrap();
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+
+        let reworded_id = repo.reword(feature1_branch.id, "seven\n").unwrap();
+        let reworded = repo.find_commit(reworded_id).unwrap();
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(base_branch.clone());
+        graph_branches.insert(feature1_branch.clone());
+        graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        assert!(git_stack::graph::reword(
+            &mut root,
+            feature1_branch.id,
+            reworded
+        ));
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "feature2").unwrap();
+
+        let feature1_commit = repo
+            .find_commit(repo.find_local_branch("feature1").unwrap().id)
+            .unwrap();
+        assert_eq!(feature1_commit.summary, "seven");
+        assert_eq!(repo.find_local_branch("base").unwrap().id, base_branch.id);
+
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let ancestors: Vec<_> = repo
+            .commits_from(feature2_branch.id)
+            .map(|c| c.id)
+            .collect();
+        assert!(ancestors.contains(&feature1_commit.id));
+    }
+}
+
+mod test_drop {
+    use super::*;
+
+    fn graph(repo: &git_stack::git::InMemoryRepo) -> Node {
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(repo.find_local_branch("base").unwrap());
+        graph_branches.insert(repo.find_local_branch("feature1").unwrap());
+        graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+        Node::from_branches(repo, graph_branches).unwrap()
+    }
+
+    fn summaries(repo: &git_stack::git::InMemoryRepo, branch: &str) -> Vec<String> {
+        let branch = repo.find_local_branch(branch).unwrap();
+        repo.commits_from(branch.id)
+            .take(4)
+            .map(|c| c.summary.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn restack_dependents() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let dropped_id = repo.commits_from(feature2_branch.id).nth(1).unwrap().id;
+
+        let mut root = graph(&repo);
+        assert!(git_stack::graph::drop_commit(&mut root, dropped_id));
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "feature2").unwrap();
+
+        assert_eq!(summaries(&repo, "feature2"), ["10", "8", "7", "3"]);
+        assert_eq!(summaries(&repo, "feature1"), ["7", "3", "2", "1"]);
+    }
+
+    #[test]
+    fn branch_moves_to_parent() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+
+        let mut root = graph(&repo);
+        assert!(git_stack::graph::drop_commit(&mut root, feature2_branch.id));
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "feature2").unwrap();
+
+        assert_eq!(summaries(&repo, "feature2"), ["9", "8", "7", "3"]);
+    }
+
+    #[test]
+    fn branch_on_root() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let base_branch = repo.find_local_branch("base").unwrap();
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+
+        let mut root = graph(&repo);
+        assert!(!git_stack::graph::drop_commit(&mut root, base_branch.id));
+        assert!(!git_stack::graph::drop_commit(
+            &mut root,
+            feature1_branch.id
+        ));
+    }
+}
+
+mod test_squash_commits {
+    use super::*;
+
+    #[test]
+    fn restack_dependents() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let base_branch = repo.find_local_branch("base").unwrap();
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let mut ids: Vec<_> = repo
+            .commits_from(feature2_branch.id)
+            .skip(1)
+            .take(2)
+            .map(|c| c.id)
+            .collect();
+        ids.reverse();
+
+        let squashed = git_stack::git::Commit {
+            id: repo.gen_id(),
+            tree_id: repo.find_commit(ids[1]).unwrap().tree_id,
+            summary: "8".into(),
+        };
+        let squashed_id = squashed.id;
+        repo.push_commit(Some(feature1_branch.id), squashed);
+        let squashed = repo.find_commit(squashed_id).unwrap();
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(base_branch);
+        graph_branches.insert(feature1_branch.clone());
+        graph_branches.insert(feature2_branch);
+
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        assert!(!git_stack::graph::squash_commits(
+            &mut root,
+            &[feature1_branch.id, ids[0]],
+            squashed.clone()
+        ));
+        assert!(git_stack::graph::squash_commits(&mut root, &ids, squashed));
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "feature2").unwrap();
+
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let summaries: Vec<_> = repo
+            .commits_from(feature2_branch.id)
+            .take(4)
+            .map(|c| c.summary.to_string())
+            .collect();
+        assert_eq!(summaries, ["10", "8", "7", "3"]);
+    }
+}
+
+mod test_reorder {
+    use super::*;
+
+    #[test]
+    fn restack_branch() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let base_branch = repo.find_local_branch("base").unwrap();
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let mut ids: Vec<_> = repo
+            .commits_from(feature2_branch.id)
+            .take(3)
+            .map(|c| c.id)
+            .collect();
+        ids.reverse();
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(base_branch);
+        graph_branches.insert(feature1_branch.clone());
+        graph_branches.insert(feature2_branch);
+
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        assert!(!git_stack::graph::reorder(
+            &mut root,
+            &[feature1_branch.id, ids[0]],
+            &[ids[0], feature1_branch.id]
+        ));
+        assert!(!git_stack::graph::reorder(
+            &mut root,
+            &ids,
+            &[ids[2], ids[0]]
+        ));
+        assert!(git_stack::graph::reorder(
+            &mut root,
+            &ids,
+            &[ids[2], ids[0], ids[1]]
+        ));
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "feature2").unwrap();
+
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let summaries: Vec<_> = repo
+            .commits_from(feature2_branch.id)
+            .take(4)
+            .map(|c| c.summary.to_string())
+            .collect();
+        assert_eq!(summaries, ["9", "8", "10", "7"]);
+    }
+}
+
+mod test_move {
+    use super::*;
+
+    #[test]
+    fn drag_dependents() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let base_branch = repo.find_local_branch("base").unwrap();
+        let master_branch = repo.find_local_branch("master").unwrap();
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(base_branch.clone());
+        graph_branches.insert(master_branch.clone());
+        graph_branches.insert(feature1_branch.clone());
+        graph_branches.insert(feature2_branch.clone());
+
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        git_stack::graph::protect_branches(&mut root, &repo, &protected_branches);
+        // `feature1` is below `feature2`
+        assert!(!git_stack::graph::move_branch(
+            &mut root,
+            feature1_branch.id,
+            feature2_branch.id
+        ));
+        assert!(git_stack::graph::move_branch(
+            &mut root,
+            feature2_branch.id,
+            master_branch.id
+        ));
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "feature2").unwrap();
+
+        assert_eq!(
+            repo.find_local_branch("master").unwrap().id,
+            master_branch.id
+        );
+
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let ancestors: Vec<_> = repo
+            .commits_from(feature2_branch.id)
+            .map(|c| c.id)
+            .collect();
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        assert!(ancestors.contains(&master_branch.id));
+        assert!(!ancestors.contains(&feature1_branch.id));
+        // The commits between `feature1` and `feature2` came along
+        assert_eq!(ancestors.len(), 3 + 5);
+    }
+}
+
+mod test_split {
+    use super::*;
+
+    #[test]
+    fn restack_dependents() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let base_branch = repo.find_local_branch("base").unwrap();
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        let feature1_commit = repo.find_commit(feature1_branch.id).unwrap();
+
+        let mut commits = Vec::new();
+        let mut parent_id = base_branch.id;
+        for summary in ["first", "second"].iter() {
+            let commit = git_stack::git::Commit {
+                id: repo.gen_id(),
+                tree_id: feature1_commit.tree_id,
+                summary: (*summary).into(),
+            };
+            let id = commit.id;
+            repo.push_commit(Some(parent_id), commit);
+            commits.push(repo.find_commit(id).unwrap());
+            parent_id = id;
+        }
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(base_branch.clone());
+        graph_branches.insert(feature1_branch.clone());
+        graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        assert!(git_stack::graph::split(
+            &mut root,
+            feature1_branch.id,
+            commits
+        ));
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "feature2").unwrap();
+
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        let summaries: Vec<_> = repo
+            .commits_from(feature1_branch.id)
+            .take(3)
+            .map(|c| c.summary.to_string())
+            .collect();
+        assert_eq!(summaries, ["second", "first", "3"]);
+
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let ancestors: Vec<_> = repo
+            .commits_from(feature2_branch.id)
+            .map(|c| c.id)
+            .collect();
+        assert!(ancestors.contains(&feature1_branch.id));
+    }
+}
+
+mod test_reparent {
+    use super::*;
+
+    fn summaries(repo: &git_stack::git::InMemoryRepo, branch: &str) -> Vec<String> {
+        let branch = repo.find_local_branch(branch).unwrap();
+        repo.commits_from(branch.id)
+            .take(5)
+            .map(|c| c.summary.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn drop_abandoned_parent() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let off_master_branch = repo.find_local_branch("off_master").unwrap();
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+
+        // Without `feature1`, its commit is like the old version of a rewritten parent
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(repo.find_local_branch("base").unwrap());
+        graph_branches.insert(off_master_branch.clone());
+        graph_branches.insert(feature2_branch.clone());
+        let mut root = Node::from_branches(&repo, graph_branches).unwrap();
+        // `feature2` can't be moved onto itself
+        assert!(!git_stack::graph::reparent(
+            &mut root,
+            feature2_branch.id,
+            feature1_branch.id,
+            feature2_branch.id
+        ));
+        assert!(git_stack::graph::reparent(
+            &mut root,
+            feature2_branch.id,
+            feature1_branch.id,
+            off_master_branch.id
+        ));
+        let script = git_stack::graph::to_script(&root);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "feature2").unwrap();
+
+        assert_eq!(summaries(&repo, "feature2"), ["10", "9", "8", "6", "5"]);
+        assert_eq!(summaries(&repo, "off_master"), ["6", "5", "4", "3", "2"]);
+    }
+}
+
+mod test_max_history {
+    use super::*;
+
+    fn graph(repo: &git_stack::git::InMemoryRepo) -> Node {
+        let master_branch = repo.find_local_branch("master").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(master_branch);
+        graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+        let mut root = Node::from_branches(repo, graph_branches).unwrap();
+        git_stack::graph::protect_branches(&mut root, repo, &protected_branches);
+        root
+    }
+
+    fn find(node: &Node, id: git2::Oid) -> Option<&Node> {
+        if node.local_commit.id == id {
+            return Some(node);
+        }
+        node.children.values().find_map(|child| find(child, id))
+    }
+
+    #[test]
+    fn elide_older_commits() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+        // `feature2` and the commit under it
+        repo.set_max_history(Some(2));
+
+        let feature1_id = repo.find_local_branch("feature1").unwrap().id;
+        let feature2_id = repo.find_local_branch("feature2").unwrap().id;
+        let feature2_ids: Vec<_> = repo
+            .commits_from(feature2_id)
+            .take(3)
+            .map(|c| c.id)
+            .collect();
+        let elided_id = feature2_ids[2];
+        assert_eq!(repo.parent_ids(elided_id).unwrap(), [feature1_id]);
+
+        let root = graph(&repo);
+        // The elided commit stands in for `feature1` and everything before it
+        assert!(find(&root, feature1_id).is_none());
+        let elided = find(&root, elided_id).unwrap();
+        assert!(elided.local_commit.summary.to_string().contains("elided"));
+        for id in [elided_id, feature2_ids[1], feature2_ids[0]].iter() {
+            let node = find(&root, *id).unwrap();
+            assert_eq!(node.action, Action::Protected, "{}", node.local_commit.id);
+        }
+    }
+
+    #[test]
+    fn stop_right_before_base() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+        // All of `feature2`'s stack, but not `base`
+        repo.set_max_history(Some(4));
+
+        let feature1_id = repo.find_local_branch("feature1").unwrap().id;
+        let feature2_id = repo.find_local_branch("feature2").unwrap().id;
+
+        let root = graph(&repo);
+        let feature1 = find(&root, feature1_id).unwrap();
+        assert_eq!(feature1.local_commit.summary.to_string(), "7");
+        assert_eq!(feature1.action, Action::Pick);
+        assert_eq!(find(&root, feature2_id).unwrap().action, Action::Pick);
+    }
+}
+
+mod test_unrelated {
+    use super::*;
+
+    #[test]
+    fn leave_out_unrelated_history() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        // Like `gh-pages`, with a root commit of its own
+        let pages_id = repo.gen_id();
+        repo.push_commit(
+            None,
+            git_stack::git::Commit {
+                id: pages_id,
+                tree_id: pages_id,
+                summary: bstr::BString::from("pages"),
+            },
+        );
+        repo.mark_branch(git_stack::git::Branch {
+            name: "gh-pages".to_owned(),
+            id: pages_id,
+            push_id: None,
+            pull_id: None,
+        });
+
+        let mut graph_branches = git_stack::git::Branches::default();
+        graph_branches.insert(repo.find_local_branch("master").unwrap());
+        graph_branches.insert(repo.find_local_branch("feature1").unwrap());
+        graph_branches.insert(repo.find_local_branch("gh-pages").unwrap());
+
+        let root = Node::from_branches(&repo, graph_branches).unwrap();
+        let mut names = Vec::new();
+        collect_branches(&root, &mut names);
+        names.sort();
+        assert_eq!(names, ["feature1", "master"]);
+    }
+
+    fn collect_branches(node: &Node, names: &mut Vec<String>) {
+        names.extend(node.branches.iter().map(|b| b.name.clone()));
+        for child in node.children.values() {
+            collect_branches(child, names);
+        }
+    }
 }
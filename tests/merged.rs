@@ -0,0 +1,62 @@
+mod fixture;
+
+use git_stack::git::*;
+
+#[test]
+fn new_branch_is_not_merged() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let mut repo = GitRepo::new(repo);
+    let master = repo.find_local_branch("master").unwrap();
+    repo.branch("new", master.id, "create").unwrap();
+    let new = repo.find_local_branch("new").unwrap();
+
+    assert!(!is_merged(&repo, &master, &new));
+}
+
+#[test]
+fn restacked_new_branch_is_not_merged() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let mut repo = GitRepo::new(repo);
+    let master = repo.find_local_branch("master").unwrap();
+    repo.branch("new", master.id, "create").unwrap();
+
+    let master_id = fixture::commit(repo.raw(), &[master.id], "upstream.txt", "upstream");
+    repo.branch("master", master_id, "pull").unwrap();
+    repo.branch("new", master_id, "restack").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    let new = repo.find_local_branch("new").unwrap();
+
+    assert!(!is_merged(&repo, &master, &new));
+}
+
+#[test]
+fn merged_branch_is_merged() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let mut repo = GitRepo::new(repo);
+    let master = repo.find_local_branch("master").unwrap();
+    repo.branch("done", master.id, "create").unwrap();
+    let done_id = fixture::commit(repo.raw(), &[master.id], "done.txt", "done");
+    repo.branch("done", done_id, "commit").unwrap();
+
+    let merge_id = fixture::commit(repo.raw(), &[master.id, done_id], "done.txt", "done");
+    repo.branch("master", merge_id, "pull").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    let done = repo.find_local_branch("done").unwrap();
+
+    assert!(is_merged(&repo, &master, &done));
+}
+
+#[test]
+fn cherry_picked_branch_is_merged() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let mut repo = GitRepo::new(repo);
+    let master = repo.find_local_branch("master").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    assert!(!is_merged(&repo, &master, &feature1));
+
+    let picked_id = repo.cherry_pick(master.id, feature1.id).unwrap();
+    repo.branch("master", picked_id, "pull").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+
+    assert!(is_merged(&repo, &master, &feature1));
+}
@@ -0,0 +1,77 @@
+mod fixture;
+
+use git_stack::git::*;
+
+fn branch_ids(repo: &git2::Repository) -> Vec<git2::Oid> {
+    repo.branches(Some(git2::BranchType::Local))
+        .unwrap()
+        .map(|b| b.unwrap().0.get().target().unwrap())
+        .collect()
+}
+
+fn branch_id(repo: &git2::Repository, name: &str) -> git2::Oid {
+    repo.find_branch(name, git2::BranchType::Local)
+        .unwrap()
+        .get()
+        .target()
+        .unwrap()
+}
+
+#[test]
+fn find_lost_commits() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let feature2_id = branch_id(&repo, "feature2");
+    let feature2 = repo.find_commit(feature2_id).unwrap();
+    let signature = git2::Signature::now("Lost", "lost@example.com").unwrap();
+    let lost_id = repo
+        .commit(
+            None,
+            &signature,
+            &signature,
+            "Lost work",
+            &feature2.tree().unwrap(),
+            &[&feature2],
+        )
+        .unwrap();
+
+    let candidates = vec![
+        ("HEAD@{0}".to_owned(), feature2_id),
+        ("HEAD@{1}".to_owned(), lost_id),
+    ];
+    let master_id = branch_id(&repo, "master");
+    let orphans = find_orphans(&repo, &candidates, &branch_ids(&repo), &[master_id]).unwrap();
+    assert_eq!(
+        orphans,
+        vec![Orphan {
+            source: "HEAD@{1}".to_owned(),
+            ids: vec![lost_id],
+            base_id: Some(feature2_id),
+        }]
+    );
+}
+
+#[test]
+fn skip_rewritten_commits() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let old_id = branch_id(&repo, "off_master");
+    let old = repo.find_commit(old_id).unwrap();
+    let base = repo.find_commit(branch_id(&repo, "base")).unwrap();
+    // Like rebasing `off_master` onto `base`
+    let new_id = repo
+        .commit(
+            None,
+            &old.author(),
+            &old.committer(),
+            old.message().unwrap(),
+            &old.tree().unwrap(),
+            &[&base],
+        )
+        .unwrap();
+    repo.reference("refs/heads/off_master", new_id, true, "rebase")
+        .unwrap();
+
+    let candidates = vec![("off_master@{1}".to_owned(), old_id)];
+    let master_id = branch_id(&repo, "master");
+    let orphans = find_orphans(&repo, &candidates, &branch_ids(&repo), &[master_id]).unwrap();
+    assert_eq!(orphans, vec![]);
+}
@@ -0,0 +1,122 @@
+mod fixture;
+
+fn rebase_script(repo: &git_stack::git::InMemoryRepo) -> git_stack::git::Script {
+    let master_branch = repo.find_local_branch("master").unwrap();
+
+    let mut protected_branches = git_stack::git::Branches::default();
+    protected_branches.insert(master_branch.clone());
+
+    let mut graph_branches = git_stack::git::Branches::default();
+    graph_branches.insert(master_branch.clone());
+    graph_branches.insert(repo.find_local_branch("feature1").unwrap());
+    graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+    let mut root = git_stack::graph::Node::from_branches(repo, graph_branches).unwrap();
+    git_stack::graph::protect_branches(&mut root, repo, &protected_branches);
+    git_stack::graph::rebase_branches(&mut root, master_branch.id);
+    git_stack::graph::to_script(&root)
+}
+
+#[test]
+fn round_trip() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let script = rebase_script(&repo);
+    let plan = git_stack::git::Plan::new(&repo, vec![script], "feature2".to_owned());
+    let names: Vec<_> = plan.branches.iter().map(|b| b.name.as_str()).collect();
+    assert_eq!(names, vec!["feature1", "feature2"]);
+
+    let json = serde_json::to_string(&plan).unwrap();
+    let parsed: git_stack::git::Plan = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, plan);
+    parsed.validate(&repo).unwrap();
+
+    let mut executor = git_stack::git::Executor::new(&repo, false);
+    for script in parsed.scripts.iter() {
+        assert_eq!(executor.run_script(&mut repo, script), vec![]);
+    }
+    executor.close(&mut repo, &parsed.restore_branch).unwrap();
+    let master_branch = repo.find_local_branch("master").unwrap();
+    let feature1_branch = repo.find_local_branch("feature1").unwrap();
+    assert_eq!(
+        repo.merge_base(master_branch.id, feature1_branch.id),
+        Some(master_branch.id)
+    );
+}
+
+#[test]
+fn moved_branches_are_rejected() {
+    use git_stack::git::Repo;
+
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let script = rebase_script(&repo);
+    let plan = git_stack::git::Plan::new(&repo, vec![script], "feature2".to_owned());
+
+    let base_branch = repo.find_local_branch("base").unwrap();
+    repo.branch("feature1", base_branch.id, "test").unwrap();
+    let err = plan.validate(&repo).unwrap_err().to_string();
+    assert!(err.contains("`feature1` is at"), "{}", err);
+}
+
+#[test]
+fn modified_plans_are_rejected() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let script = rebase_script(&repo);
+    let mut plan = git_stack::git::Plan::new(&repo, vec![script], "feature2".to_owned());
+    plan.scripts[0].commands.pop();
+    let err = plan.validate(&repo).unwrap_err().to_string();
+    assert!(err.contains("was modified"), "{}", err);
+}
+
+#[test]
+fn other_repositories_are_rejected() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let script = rebase_script(&repo);
+    let mut plan = git_stack::git::Plan::new(&repo, vec![script], "feature2".to_owned());
+    assert!(plan.repo.is_some());
+    plan.repo = Some(git2::Oid::from_str("1234").unwrap());
+    let err = plan.validate(&repo).unwrap_err().to_string();
+    assert!(err.contains("another repository"), "{}", err);
+}
+
+#[test]
+fn reword_ids_follow_the_messages() {
+    let id = git2::Oid::from_str("1234").unwrap();
+    let reworded = git_stack::git::reword_plan_id(&[(id, "fix: one\n".to_owned())]);
+    assert_eq!(
+        reworded,
+        git_stack::git::reword_plan_id(&[(id, "fix: one\n".to_owned())])
+    );
+    assert_ne!(
+        reworded,
+        git_stack::git::reword_plan_id(&[(id, "fix: two\n".to_owned())])
+    );
+    assert!(git_stack::git::matches_plan_id(reworded, &reworded.to_string()[..7]).unwrap());
+}
+
+#[test]
+fn expected_ids_must_not_be_short() {
+    let id = git_stack::git::plan_id(&[]);
+    let full = id.to_string();
+
+    assert!(git_stack::git::matches_plan_id(id, &full).unwrap());
+    assert!(git_stack::git::matches_plan_id(id, &full[..7]).unwrap());
+    assert!(git_stack::git::matches_plan_id(id, &full[..7].to_ascii_uppercase()).unwrap());
+    assert!(!git_stack::git::matches_plan_id(id, "0000000").unwrap());
+
+    for short in ["", &full[..1], &full[..6], "zzzzzzz"] {
+        let err = git_stack::git::matches_plan_id(id, short).unwrap_err();
+        assert!(err.to_string().contains("at least 7"), "{}", err);
+    }
+}
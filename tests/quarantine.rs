@@ -0,0 +1,131 @@
+mod fixture;
+
+use git_stack::git::GitRepo;
+use git_stack::stash::Quarantine;
+
+fn has_reference(repo: &GitRepo, name: &str) -> bool {
+    repo.raw().find_reference(name).is_ok()
+}
+
+#[test]
+fn insert_keeps_commits() {
+    let (temp, repo) = fixture::init_repo("branches.yml");
+    let repo = GitRepo::new(repo);
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+
+    let mut quarantine = Quarantine::default();
+    quarantine
+        .insert(&repo, "feature1", feature1.id, 100)
+        .unwrap();
+    let branch = quarantine.get("feature1").unwrap();
+    assert_eq!(branch.id, feature1.id);
+    assert_eq!(branch.deleted, 100);
+    assert!(branch.reference.starts_with(Quarantine::PREFIX));
+    let reference = repo.raw().find_reference(&branch.reference).unwrap();
+    assert_eq!(reference.target(), Some(feature1.id));
+    assert_eq!(quarantine.get("feature2"), None);
+
+    quarantine.save(&repo).unwrap();
+    assert_eq!(Quarantine::load(&repo).unwrap(), quarantine);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn restore_most_recent() {
+    let (temp, repo) = fixture::init_repo("branches.yml");
+    let repo = GitRepo::new(repo);
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let feature2 = repo.find_local_branch("feature2").unwrap();
+
+    let mut quarantine = Quarantine::default();
+    quarantine
+        .insert(&repo, "feature", feature1.id, 100)
+        .unwrap();
+    quarantine
+        .insert(&repo, "feature", feature2.id, 200)
+        .unwrap();
+    assert_eq!(quarantine.get("feature").unwrap().id, feature2.id);
+
+    let restored = quarantine.remove(&repo, "feature").unwrap().unwrap();
+    assert_eq!(restored.id, feature2.id);
+    assert!(!has_reference(&repo, &restored.reference));
+    let older = quarantine.get("feature").unwrap();
+    assert_eq!(older.id, feature1.id);
+    assert!(has_reference(&repo, &older.reference));
+
+    assert_eq!(quarantine.remove(&repo, "missing").unwrap(), None);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn same_name_same_second() {
+    let (temp, repo) = fixture::init_repo("branches.yml");
+    let repo = GitRepo::new(repo);
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let feature2 = repo.find_local_branch("feature2").unwrap();
+
+    let mut quarantine = Quarantine::default();
+    quarantine
+        .insert(&repo, "feature", feature1.id, 100)
+        .unwrap();
+    quarantine
+        .insert(&repo, "feature", feature2.id, 100)
+        .unwrap();
+    let references: Vec<_> = quarantine
+        .branches
+        .iter()
+        .map(|b| b.reference.clone())
+        .collect();
+    assert_ne!(references[0], references[1]);
+
+    let removed = quarantine.remove(&repo, "feature").unwrap().unwrap();
+    let kept = &quarantine.branches[0];
+    assert_ne!(removed.id, kept.id);
+    let reference = repo.raw().find_reference(&kept.reference).unwrap();
+    assert_eq!(reference.target(), Some(kept.id));
+
+    // Entries for the very same commit share a ref, which outlives all but the last of them
+    quarantine.insert(&repo, "feature", kept.id, 100).unwrap();
+    let shared = quarantine.remove(&repo, "feature").unwrap().unwrap();
+    assert!(has_reference(&repo, &shared.reference));
+    quarantine.remove(&repo, "feature").unwrap().unwrap();
+    assert!(!has_reference(&repo, &shared.reference));
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn purge_before_cutoff() {
+    let (temp, repo) = fixture::init_repo("branches.yml");
+    let repo = GitRepo::new(repo);
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let feature2 = repo.find_local_branch("feature2").unwrap();
+
+    let mut quarantine = Quarantine::default();
+    quarantine
+        .insert(&repo, "feature1", feature1.id, 100)
+        .unwrap();
+    quarantine
+        .insert(&repo, "feature2", feature2.id, 200)
+        .unwrap();
+
+    let purged = quarantine.purge(&repo, 200).unwrap();
+    assert_eq!(purged.len(), 1);
+    assert_eq!(purged[0].name, "feature1");
+    assert!(!has_reference(&repo, &purged[0].reference));
+    assert_eq!(quarantine.get("feature1"), None);
+    let kept = quarantine.get("feature2").unwrap();
+    assert!(has_reference(&repo, &kept.reference));
+
+    let purged = quarantine.purge(&repo, 201).unwrap();
+    assert_eq!(purged.len(), 1);
+    assert!(quarantine.branches.is_empty());
+
+    // Nothing left to keep
+    quarantine.save(&repo).unwrap();
+    assert_eq!(Quarantine::load(&repo).unwrap(), Quarantine::default());
+
+    temp.close().unwrap();
+}
@@ -249,6 +249,149 @@ fn contains_commit_pr_squashed() {
     temp.close().unwrap();
 }
 
+#[test]
+fn patch_id() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new(
+        "tests/fixtures/pr-semi-linear-merge.yml",
+    ))
+    .unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let repo = GitRepo::new(repo);
+
+    let master = repo.find_local_branch("master").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let feature2 = repo.find_local_branch("feature2").unwrap();
+    let rebased1 = repo.resolve("master~3").unwrap();
+    assert_ne!(feature1.id, rebased1.id);
+
+    let feature1_patch = repo.patch_id(feature1.id).unwrap();
+    assert!(feature1_patch.is_some());
+    assert_eq!(feature1_patch, repo.patch_id(rebased1.id).unwrap());
+    assert_eq!(
+        repo.patch_id(feature2.id).unwrap(),
+        repo.patch_id(master.id).unwrap()
+    );
+    assert_ne!(feature1_patch, repo.patch_id(feature2.id).unwrap());
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn range_patch_id() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new(
+        "tests/fixtures/pr-semi-linear-merge.yml",
+    ))
+    .unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let repo = GitRepo::new(repo);
+
+    let base = repo.find_local_branch("base").unwrap();
+    let old_master = repo.find_local_branch("old_master").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let feature2 = repo.find_local_branch("feature2").unwrap();
+
+    assert_eq!(repo.range_patch_id(base.id, base.id).unwrap(), None);
+    assert_eq!(
+        repo.range_patch_id(base.id, feature1.id).unwrap(),
+        repo.patch_id(feature1.id).unwrap()
+    );
+    let feature2_patch = repo.range_patch_id(base.id, feature2.id).unwrap();
+    assert!(feature2_patch.is_some());
+    assert_eq!(
+        feature2_patch,
+        repo.range_patch_id(old_master.id, master.id).unwrap()
+    );
+    assert_ne!(
+        feature2_patch,
+        repo.range_patch_id(base.id, feature1.id).unwrap()
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn abbrev_id() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let repo = GitRepo::new(repo);
+
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let full = feature1.id.to_string();
+    let mut config = repo.raw().config().unwrap();
+
+    assert_eq!(repo.abbrev_id(feature1.id), full[..7]);
+
+    config.set_str("core.abbrev", "12").unwrap();
+    assert_eq!(repo.abbrev_id(feature1.id), full[..12]);
+
+    config.set_str("core.abbrev", "auto").unwrap();
+    assert_eq!(repo.abbrev_id(feature1.id), full[..7]);
+
+    config.set_str("core.abbrev", "no").unwrap();
+    assert_eq!(repo.abbrev_id(feature1.id), full);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn fingerprint() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let repo = GitRepo::new(repo);
+    // Only generated when asked to, reading it leaves the config be
+    assert_eq!(repo.fingerprint(), None);
+    assert!(repo
+        .raw()
+        .config()
+        .unwrap()
+        .get_string("stack.repo-id")
+        .is_err());
+    let expected = repo.ensure_fingerprint().unwrap();
+
+    // Independent of what is checked out, even an unrelated history
+    {
+        let raw = repo.raw();
+        let signature = raw.signature().unwrap();
+        let tree = raw
+            .find_tree(raw.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        raw.commit(
+            Some("refs/heads/gh-pages"),
+            &signature,
+            &signature,
+            "pages",
+            &tree,
+            &[],
+        )
+        .unwrap();
+        raw.set_head("refs/heads/gh-pages").unwrap();
+    }
+    let repo = GitRepo::new(git2::Repository::discover(temp.path()).unwrap());
+    assert_eq!(repo.fingerprint(), Some(expected));
+
+    let other = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(other.path()).unwrap();
+    let other_repo = GitRepo::new(git2::Repository::discover(other.path()).unwrap());
+    assert_ne!(other_repo.ensure_fingerprint(), Some(expected));
+
+    temp.close().unwrap();
+    other.close().unwrap();
+}
+
 #[test]
 fn cherry_pick_clean() {
     let temp = assert_fs::TempDir::new().unwrap();
@@ -279,6 +422,162 @@ fn cherry_pick_clean() {
     temp.close().unwrap();
 }
 
+#[test]
+fn switch_keeps_untracked() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+
+    repo.switch("base").unwrap();
+    let scratch = temp.child("file_b.txt");
+    scratch.write_str("scratch").unwrap();
+    assert!(!repo.is_dirty());
+
+    let master = repo.find_local_branch("master").unwrap();
+    assert!(repo.switch("master").is_err());
+    assert!(repo.switch_commit(master.id).is_err());
+    scratch.assert("scratch");
+    assert_eq!(repo.head_branch().unwrap().name, "base");
+
+    // Untracked files out of the way don't get in it
+    std::fs::remove_file(scratch.path()).unwrap();
+    temp.child("scratch.txt").write_str("scratch").unwrap();
+    repo.switch("master").unwrap();
+    assert_eq!(repo.head_branch().unwrap().name, "master");
+    temp.child("scratch.txt").assert("scratch");
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn cherry_pick_keeps_author() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+
+    let base = repo.find_local_branch("off_master").unwrap();
+    let source = repo.find_local_branch("feature1").unwrap();
+    let when = git2::Time::new(1_000_000_000, 90);
+    let author = git2::Signature::new("Ed", "ed@example.com", &when).unwrap();
+    let source_id = repo.recommit(source.id, "7\n", &author).unwrap();
+
+    {
+        let dest_id = repo.cherry_pick(base.id, source_id).unwrap();
+        let dest_commit = repo.raw().find_commit(dest_id).unwrap();
+        let dest_author = dest_commit.author();
+        assert_eq!(dest_author.name(), Some("Ed"));
+        assert_eq!(dest_author.email(), Some("ed@example.com"));
+        assert_eq!(dest_author.when().seconds(), when.seconds());
+        assert_eq!(dest_author.when().offset_minutes(), when.offset_minutes());
+    }
+
+    repo.set_reset_author_date(true);
+    {
+        let dest_id = repo.cherry_pick(base.id, source_id).unwrap();
+        let dest_commit = repo.raw().find_commit(dest_id).unwrap();
+        let dest_author = dest_commit.author();
+        assert_eq!(dest_author.name(), Some("Ed"));
+        assert_eq!(dest_author.email(), Some("ed@example.com"));
+        assert!(when.seconds() < dest_author.when().seconds());
+    }
+
+    temp.close().unwrap();
+}
+
+/// Recreate `id` as authored and committed long ago, at different times
+fn backdate(repo: &GitRepo, id: git2::Oid) -> (git2::Oid, git2::Time, git2::Time) {
+    let commit = repo.raw().find_commit(id).unwrap();
+    let authored = git2::Time::new(1_000_000_000, 90);
+    let committed = git2::Time::new(1_100_000_000, 0);
+    let author = git2::Signature::new("Ed", "ed@example.com", &authored).unwrap();
+    let committer = git2::Signature::new("Ed", "ed@example.com", &committed).unwrap();
+    let parents: Vec<_> = commit.parents().collect();
+    let parents: Vec<_> = parents.iter().collect();
+    let backdated_id = repo
+        .raw()
+        .commit(
+            None,
+            &author,
+            &committer,
+            commit.message().unwrap(),
+            &commit.tree().unwrap(),
+            &parents,
+        )
+        .unwrap();
+    (backdated_id, authored, committed)
+}
+
+fn committed(repo: &GitRepo, id: git2::Oid) -> i64 {
+    repo.raw()
+        .find_commit(id)
+        .unwrap()
+        .committer()
+        .when()
+        .seconds()
+}
+
+#[test]
+fn cherry_pick_committer_date() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+
+    let base = repo.find_local_branch("off_master").unwrap();
+    let source = repo.find_local_branch("feature1").unwrap();
+    let (source_id, authored, committed_at) = backdate(&repo, source.id);
+
+    repo.set_committer_date(git_stack::config::CommitterDate::Keep);
+    let kept_id = repo.cherry_pick(base.id, source_id).unwrap();
+    assert_eq!(committed(&repo, kept_id), committed_at.seconds());
+    assert_eq!(repo.cherry_pick(base.id, source_id).unwrap(), kept_id);
+
+    repo.set_committer_date(git_stack::config::CommitterDate::Author);
+    let author_id = repo.cherry_pick(base.id, source_id).unwrap();
+    assert_eq!(committed(&repo, author_id), authored.seconds());
+    assert_eq!(repo.cherry_pick(base.id, source_id).unwrap(), author_id);
+    assert_ne!(author_id, kept_id);
+
+    repo.set_committer_date(git_stack::config::CommitterDate::Now);
+    let now_id = repo.cherry_pick(base.id, source_id).unwrap();
+    assert!(committed_at.seconds() < committed(&repo, now_id));
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn squash_committer_date() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+
+    let master = repo.find_local_branch("master").unwrap();
+    let source = repo.find_local_branch("feature2").unwrap();
+    let (into_id, authored, committed_at) = backdate(&repo, master.id);
+
+    // Folding in a fixup leaves the commit as committed by default
+    let now_id = repo.squash(source.id, into_id).unwrap();
+    assert_eq!(committed(&repo, now_id), committed_at.seconds());
+
+    repo.set_committer_date(git_stack::config::CommitterDate::Author);
+    let author_id = repo.squash(source.id, into_id).unwrap();
+    assert_eq!(committed(&repo, author_id), authored.seconds());
+    assert_eq!(repo.squash(source.id, into_id).unwrap(), author_id);
+
+    temp.close().unwrap();
+}
+
 #[test]
 fn cherry_pick_conflict() {
     let temp = assert_fs::TempDir::new().unwrap();
@@ -302,6 +601,35 @@ fn cherry_pick_conflict() {
     temp.close().unwrap();
 }
 
+#[test]
+fn predict_cherry_pick() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/conflict.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let repo = GitRepo::new(repo);
+
+    let base = repo.find_local_branch("base").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let base_tree = repo.find_commit(base.id).unwrap().tree_id;
+    let master_tree = repo.find_commit(master.id).unwrap().tree_id;
+    let feature1_tree = repo.find_commit(feature1.id).unwrap().tree_id;
+
+    assert_eq!(
+        repo.predict_cherry_pick(base_tree, feature1.id).unwrap(),
+        PickPrediction::Clean(feature1_tree)
+    );
+    assert_eq!(
+        repo.predict_cherry_pick(master_tree, feature1.id).unwrap(),
+        PickPrediction::Conflict(vec![std::path::PathBuf::from("file_a.txt")])
+    );
+    assert!(!repo.is_dirty());
+
+    temp.close().unwrap();
+}
+
 #[test]
 fn squash_clean() {
     let temp = assert_fs::TempDir::new().unwrap();
@@ -318,13 +646,117 @@ fn squash_clean() {
         let source = repo.find_local_branch("feature2").unwrap();
         let dest_id = repo.squash(source.id, base.id).unwrap();
 
-        repo.branch("squashed", dest_id).unwrap();
+        repo.branch("squashed", dest_id, "test").unwrap();
         assert!(!repo.is_dirty());
     }
 
     temp.close().unwrap();
 }
 
+#[test]
+fn squash_commits() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let feature2 = repo.find_local_branch("feature2").unwrap();
+    let mut ids: Vec<_> = repo
+        .commits_from(feature2.id)
+        .take_while(|c| c.id != feature1.id)
+        .map(|c| c.id)
+        .collect();
+    ids.reverse();
+
+    {
+        let squashed_id = repo.squash_commits(&ids).unwrap();
+        let squashed = repo.raw().find_commit(squashed_id).unwrap();
+        let tip = repo.raw().find_commit(feature2.id).unwrap();
+
+        assert_eq!(squashed.parent_id(0).unwrap(), feature1.id);
+        assert_eq!(squashed.tree_id(), tip.tree_id());
+        assert_eq!(squashed.message(), Some("8\n\n9\n\n10\n"));
+    }
+
+    {
+        let actual = repo.squash_commits(&ids[..1]);
+        assert!(actual.is_err());
+
+        let actual = repo.squash_commits(&[ids[0], ids[2]]);
+        assert!(actual.is_err());
+    }
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn split() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    // Change two files in one commit
+    let both_id = {
+        let master = repo
+            .revparse_single("master")
+            .unwrap()
+            .peel_to_commit()
+            .unwrap();
+        let mut builder = repo.treebuilder(Some(&master.tree().unwrap())).unwrap();
+        builder
+            .insert("file_a.txt", repo.blob(b"4").unwrap(), 0o100644)
+            .unwrap();
+        builder
+            .insert("file_d.txt", repo.blob(b"1").unwrap(), 0o100644)
+            .unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let signature = repo.signature().unwrap();
+        repo.commit(None, &signature, &signature, "both\n", &tree, &[&master])
+            .unwrap()
+    };
+    let mut repo = GitRepo::new(repo);
+
+    {
+        let (first_id, second_id) = repo
+            .split(both_id, &[std::path::PathBuf::from("file_d.txt")])
+            .unwrap();
+        let first = repo.raw().find_commit(first_id).unwrap();
+        let second = repo.raw().find_commit(second_id).unwrap();
+        let both = repo.raw().find_commit(both_id).unwrap();
+
+        assert_eq!(first.parent_id(0).unwrap(), both.parent_id(0).unwrap());
+        assert_eq!(second.parent_id(0).unwrap(), first_id);
+        assert_eq!(second.tree_id(), both.tree_id());
+        assert_eq!(first.message(), Some("both\n"));
+        assert_eq!(second.message(), Some("both\n"));
+
+        let first_tree = first.tree().unwrap();
+        assert!(first_tree.get_name("file_d.txt").is_some());
+        let file_a = first_tree.get_name("file_a.txt").unwrap().id();
+        assert_eq!(repo.raw().find_blob(file_a).unwrap().content(), b"3");
+    }
+
+    {
+        let actual = repo.split(both_id, &[std::path::PathBuf::from("file_b.txt")]);
+        assert!(actual.is_err());
+
+        let actual = repo.split(
+            both_id,
+            &[
+                std::path::PathBuf::from("file_a.txt"),
+                std::path::PathBuf::from("file_d.txt"),
+            ],
+        );
+        assert!(actual.is_err());
+    }
+
+    temp.close().unwrap();
+}
+
 #[test]
 fn branch() {
     let temp = assert_fs::TempDir::new().unwrap();
@@ -335,14 +767,14 @@ fn branch() {
     let mut repo = GitRepo::new(repo);
 
     {
-        let actual = repo.branch("new", git2::Oid::zero());
+        let actual = repo.branch("new", git2::Oid::zero(), "test");
         assert!(actual.is_err());
     }
 
     // Add new branch
     {
         let base = repo.find_local_branch("base").unwrap();
-        repo.branch("new", base.id).unwrap();
+        repo.branch("new", base.id, "test").unwrap();
         let actual = repo.find_local_branch("new").unwrap();
         assert_eq!(base.id, actual.id);
 
@@ -366,7 +798,7 @@ fn branch() {
     {
         let old = repo.find_local_branch("feature1").unwrap();
         let target = repo.find_local_branch("off_master").unwrap();
-        repo.branch("feature1", target.id).unwrap();
+        repo.branch("feature1", target.id, "test").unwrap();
         let new = repo.find_local_branch("feature1").unwrap();
         assert_eq!(new.id, target.id);
         assert_ne!(new.id, old.id);
@@ -375,6 +807,364 @@ fn branch() {
     temp.close().unwrap();
 }
 
+#[test]
+fn rename_branch() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+    let mut config = repo.raw().config().unwrap();
+    config.set_str("branch.feature1.remote", "origin").unwrap();
+
+    let old = repo.find_local_branch("feature1").unwrap();
+    repo.rename_branch("feature1", "renamed").unwrap();
+    assert_eq!(repo.find_local_branch("feature1"), None);
+    let new = repo.find_local_branch("renamed").unwrap();
+    assert_eq!(new.id, old.id);
+
+    let config = repo.raw().config().unwrap().snapshot().unwrap();
+    assert_eq!(config.get_str("branch.renamed.remote").unwrap(), "origin");
+    assert!(config.get_str("branch.feature1.remote").is_err());
+
+    let actual = repo.rename_branch("renamed", "master");
+    assert!(actual.is_err());
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn rename_namespaced_branch() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    repo.set_namespace(Some("ns"));
+    repo.branch("my-topic", feature1.id, "test").unwrap();
+    let mut config = repo.raw().config().unwrap();
+    config.set_str("branch.my-topic.remote", "origin").unwrap();
+    config
+        .set_multivar("branch.my-topic.description", "$^", "one")
+        .unwrap();
+    config
+        .set_multivar("branch.my-topic.description", "$^", "two")
+        .unwrap();
+
+    repo.rename_branch("my-topic", "renamed").unwrap();
+    assert_eq!(repo.find_local_branch("my-topic"), None);
+    assert_eq!(repo.find_local_branch("renamed").unwrap().id, feature1.id);
+
+    let config = repo.raw().config().unwrap().snapshot().unwrap();
+    assert_eq!(config.get_str("branch.renamed.remote").unwrap(), "origin");
+    assert!(config.get_str("branch.my-topic.remote").is_err());
+    let descriptions = config.multivar("branch.renamed.description", None).unwrap();
+    let descriptions_ref = &descriptions;
+    let descriptions: Vec<_> = descriptions_ref
+        .flat_map(|e| e.into_iter())
+        .filter_map(|e| e.value().map(ToOwned::to_owned))
+        .collect();
+    assert_eq!(descriptions, ["one", "two"]);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn find_remote_branch_dwim() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+    let base = repo.find_local_branch("base").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    for remote in ["origin", "upstream"] {
+        repo.raw()
+            .remote(remote, &format!("https://example.com/{}.git", remote))
+            .unwrap();
+    }
+    repo.raw()
+        .reference("refs/remotes/origin/release", base.id, true, "test")
+        .unwrap();
+
+    // Only on one remote
+    {
+        let actual = repo.find_remote_branch_dwim("release").unwrap();
+        assert_eq!(actual, Some(("origin".to_owned(), base.id)));
+        assert_eq!(repo.find_remote_branch_dwim("missing").unwrap(), None);
+    }
+
+    // On several remotes
+    {
+        repo.raw()
+            .reference("refs/remotes/upstream/release", master.id, true, "test")
+            .unwrap();
+        assert!(repo.find_remote_branch_dwim("release").is_err());
+
+        let mut config = repo.raw().config().unwrap();
+        config
+            .set_str("checkout.defaultRemote", "upstream")
+            .unwrap();
+        let actual = repo.find_remote_branch_dwim("release").unwrap();
+        assert_eq!(actual, Some(("upstream".to_owned(), master.id)));
+
+        config.set_bool("checkout.guess", false).unwrap();
+        assert_eq!(repo.find_remote_branch_dwim("release").unwrap(), None);
+    }
+
+    // Tracking it
+    {
+        let branch = repo
+            .track_remote_branch("release", "upstream", master.id)
+            .unwrap();
+        assert_eq!(branch.id, master.id);
+        let config = repo.raw().config().unwrap().snapshot().unwrap();
+        assert_eq!(config.get_str("branch.release.remote").unwrap(), "upstream");
+        assert_eq!(
+            config.get_str("branch.release.merge").unwrap(),
+            "refs/heads/release"
+        );
+    }
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn merge() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+    let base = repo.find_local_branch("base").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+
+    // Fast-forward
+    let actual = repo.merge(base.id, feature1.id, "unused").unwrap();
+    assert_eq!(actual, feature1.id);
+
+    // Already merged
+    let actual = repo.merge(feature1.id, base.id, "unused").unwrap();
+    assert_eq!(actual, feature1.id);
+
+    let merge_id = repo
+        .merge(master.id, feature1.id, "Merge feature1\n")
+        .unwrap();
+    let merge = repo.raw().find_commit(merge_id).unwrap();
+    let parents: Vec<_> = merge.parent_ids().collect();
+    assert_eq!(parents, vec![master.id, feature1.id]);
+    assert_eq!(merge.message(), Some("Merge feature1\n"));
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn conflict_stops_and_resumes() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/conflict.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    let todo = "reset master\npick feature1\nbranch feature1\n";
+    let scripts = parse_todo(todo, &repo).unwrap();
+
+    let mut executor = Executor::new(&repo, false);
+    executor.stop_on_conflict(true);
+    for script in scripts.iter() {
+        assert_eq!(executor.run_script(&mut repo, script), vec![]);
+    }
+    let continuation = executor.continuation().unwrap();
+    assert_eq!(continuation.conflict(), Some(feature1.id));
+    assert_eq!(repo.head_commit().id, master.id);
+
+    repo.cherry_pick_to_worktree(feature1.id).unwrap();
+    assert!(repo.continue_cherry_pick(feature1.id).is_err());
+    temp.child("file_a.txt").write_str("resolved").unwrap();
+    let mut index = repo.raw().index().unwrap();
+    index.add_path(std::path::Path::new("file_a.txt")).unwrap();
+    index.write().unwrap();
+    let resolved_id = repo.continue_cherry_pick(feature1.id).unwrap().unwrap();
+
+    let mut executor = Executor::resume(&repo, &continuation, false);
+    for script in continuation.scripts() {
+        assert_eq!(executor.run_script(&mut repo, script), vec![]);
+    }
+    executor.close(&mut repo, "feature1").unwrap();
+    assert_eq!(repo.find_local_branch("feature1").unwrap().id, resolved_id);
+    let resolved = repo.raw().find_commit(resolved_id).unwrap();
+    assert_eq!(resolved.parent_id(0).unwrap(), master.id);
+    let original = repo.raw().find_commit(feature1.id).unwrap();
+    assert_eq!(resolved.message(), original.message());
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn rerere_replays_resolution() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/conflict.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    repo.config()
+        .unwrap()
+        .set_bool("rerere.enabled", true)
+        .unwrap();
+    let mut repo = GitRepo::new(repo);
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    repo.switch_commit(master.id).unwrap();
+
+    assert_eq!(
+        repo.cherry_pick_to_worktree(feature1.id).unwrap(),
+        Vec::<std::path::PathBuf>::new()
+    );
+    temp.child("file_a.txt").write_str("resolved").unwrap();
+    let mut index = repo.raw().index().unwrap();
+    index.add_path(std::path::Path::new("file_a.txt")).unwrap();
+    index.write().unwrap();
+    repo.continue_cherry_pick(feature1.id).unwrap().unwrap();
+
+    {
+        let master_commit = repo.raw().find_commit(master.id).unwrap();
+        repo.raw()
+            .reset(master_commit.as_object(), git2::ResetType::Hard, None)
+            .unwrap();
+    }
+    assert_eq!(
+        repo.cherry_pick_to_worktree(feature1.id).unwrap(),
+        vec![std::path::PathBuf::from("file_a.txt")]
+    );
+    temp.child("file_a.txt").assert("resolved");
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn symbolic_branch() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    repo.reference_symbolic(
+        "refs/heads/current-release",
+        "refs/heads/feature1",
+        false,
+        "test",
+    )
+    .unwrap();
+    let mut repo = GitRepo::new(repo);
+
+    // Not a branch of its own
+    {
+        let mut actual: Vec<_> = repo.local_branches().map(|b| b.name).collect();
+        actual.sort_unstable();
+        assert_eq!(
+            actual,
+            &[
+                "base",
+                "feature1",
+                "feature2",
+                "initial",
+                "master",
+                "off_master"
+            ]
+        );
+        assert!(repo.find_local_branch("current-release").is_none());
+    }
+
+    // Follows its target
+    {
+        let target = repo.find_local_branch("off_master").unwrap();
+        repo.branch("feature1", target.id, "test").unwrap();
+        let actual = repo
+            .raw()
+            .find_reference("refs/heads/current-release")
+            .unwrap()
+            .resolve()
+            .unwrap();
+        assert_eq!(actual.target(), Some(target.id));
+    }
+
+    // Deleting the target leaves it dangling
+    {
+        repo.delete_branch("feature1").unwrap();
+        assert!(repo.find_local_branch("feature1").is_none());
+        let mut actual: Vec<_> = repo.local_branches().map(|b| b.name).collect();
+        actual.sort_unstable();
+        assert_eq!(
+            actual,
+            &["base", "feature2", "initial", "master", "off_master"]
+        );
+    }
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn namespace() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let feature1_id = repo
+        .find_reference("refs/heads/feature1")
+        .unwrap()
+        .target()
+        .unwrap();
+    repo.reference(
+        "refs/namespaces/ns/refs/heads/topic",
+        feature1_id,
+        false,
+        "test",
+    )
+    .unwrap();
+    let mut repo = GitRepo::new(repo);
+    repo.set_namespace(Some("ns"));
+    assert_eq!(repo.namespace(), Some("ns"));
+
+    {
+        let actual: Vec<_> = repo.local_branches().map(|b| b.name).collect();
+        assert_eq!(actual, &["topic"]);
+        assert!(repo.find_local_branch("feature1").is_none());
+        assert_eq!(repo.find_local_branch("topic").unwrap().id, feature1_id);
+    }
+
+    {
+        let base = repo.raw().revparse_single("base").unwrap().id();
+        repo.branch("new", base, "test").unwrap();
+        let actual = repo
+            .raw()
+            .find_reference("refs/namespaces/ns/refs/heads/new")
+            .unwrap();
+        assert_eq!(actual.target(), Some(base));
+        assert!(repo.raw().find_reference("refs/heads/new").is_err());
+    }
+
+    {
+        repo.switch("topic").unwrap();
+        assert_eq!(repo.head_branch().unwrap().name, "topic");
+    }
+
+    {
+        repo.delete_branch("new").unwrap();
+        assert!(repo.find_local_branch("new").is_none());
+    }
+
+    temp.close().unwrap();
+}
+
 #[test]
 fn switch() {
     let temp = assert_fs::TempDir::new().unwrap();
@@ -401,3 +1191,466 @@ fn switch() {
 
     temp.close().unwrap();
 }
+
+#[test]
+fn sandbox() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+    let old_head = repo.head_branch().unwrap();
+    let old_feature1 = repo.find_local_branch("feature1").unwrap();
+    let target = repo.find_local_branch("off_master").unwrap();
+
+    repo.enter_sandbox().unwrap();
+    assert!(repo.is_sandboxed());
+
+    {
+        let mut actual: Vec<_> = repo.local_branches().map(|b| b.name).collect();
+        actual.sort_unstable();
+        assert_eq!(
+            actual,
+            &[
+                "base",
+                "feature1",
+                "feature2",
+                "initial",
+                "master",
+                "off_master"
+            ]
+        );
+    }
+
+    {
+        repo.detach().unwrap();
+        repo.branch("feature1", target.id, "test").unwrap();
+        repo.switch("feature1").unwrap();
+        let new = repo.find_local_branch("feature1").unwrap();
+        assert_eq!(new.id, target.id);
+        assert_eq!(repo.head_branch().unwrap().name, old_head.name);
+    }
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let repo = GitRepo::new(repo);
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    assert_eq!(feature1.id, old_feature1.id);
+    assert_eq!(repo.head_branch().unwrap(), old_head);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn reflog() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let (base_id, base_short) = {
+        let base = repo.revparse_single("base").unwrap();
+        let short = base.short_id().unwrap().as_str().unwrap().to_owned();
+        (base.id(), short)
+    };
+    let (master_id, master_short) = {
+        let master = repo.revparse_single("master").unwrap();
+        let short = master.short_id().unwrap().as_str().unwrap().to_owned();
+        (master.id(), short)
+    };
+    let mut repo = GitRepo::new(repo);
+
+    {
+        repo.branch("new", base_id, "test").unwrap();
+        repo.branch("new", master_id, "rebase new onto master")
+            .unwrap();
+        let reflog = repo.raw().reflog("refs/heads/new").unwrap();
+        let actual: Vec<_> = reflog
+            .iter()
+            .map(|e| e.message().unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            actual,
+            vec![
+                format!(
+                    "git-stack: rebase new onto master ({} -> {})",
+                    base_short, master_short
+                ),
+                format!("git-stack: test (new -> {})", base_short),
+            ]
+        );
+    }
+
+    {
+        repo.set_namespace(Some("ns"));
+        repo.branch("logged", base_id, "test").unwrap();
+        assert!(repo
+            .raw()
+            .reference_has_log("refs/namespaces/ns/refs/heads/logged")
+            .unwrap());
+
+        repo.raw()
+            .config()
+            .unwrap()
+            .set_bool("core.logAllRefUpdates", false)
+            .unwrap();
+        repo.branch("unlogged", base_id, "test").unwrap();
+        assert!(!repo
+            .raw()
+            .reference_has_log("refs/namespaces/ns/refs/heads/unlogged")
+            .unwrap());
+    }
+}
+
+#[test]
+fn exec_failure_blocks_dependents() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let feature2 = repo.find_local_branch("feature2").unwrap();
+
+    let todo = |exec: &str| {
+        format!(
+            "reset master\npick feature1\nbranch feature1\nexec {}\nlabel feature1\n\n\
+             reset feature1\npick feature2~2\npick feature2~1\npick feature2\nbranch feature2\n",
+            exec
+        )
+    };
+
+    {
+        let scripts =
+            git_stack::git::parse_todo(&todo("test -f file_b.txt && exit 3"), &repo).unwrap();
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let failures = executor.run_script(&mut repo, &scripts[0]);
+        let failures: Vec<_> = failures
+            .iter()
+            .map(|(_, name, dependents)| (*name, dependents.clone()))
+            .collect();
+        assert_eq!(failures, vec![("feature1", vec!["feature2"])]);
+        executor.close(&mut repo, "feature2").unwrap();
+        assert_eq!(repo.find_local_branch("feature1").unwrap().id, feature1.id);
+        assert_eq!(repo.find_local_branch("feature2").unwrap().id, feature2.id);
+        assert_eq!(repo.head_branch().unwrap().name, "feature2");
+    }
+
+    {
+        let scripts = git_stack::git::parse_todo(&todo("test -f file_b.txt"), &repo).unwrap();
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        assert_eq!(executor.run_script(&mut repo, &scripts[0]), vec![]);
+        executor.close(&mut repo, "feature2").unwrap();
+        let master = repo.find_local_branch("master").unwrap();
+        let feature1 = repo.find_local_branch("feature1").unwrap();
+        assert_eq!(repo.merge_base(master.id, feature1.id), Some(master.id));
+        assert_eq!(repo.head_branch().unwrap().name, "feature2");
+        assert!(!repo.is_dirty());
+    }
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn branch_description() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+
+    assert_eq!(repo.branch_description("feature1"), None);
+    repo.set_branch_description("feature1", Some("Add a feature\n"))
+        .unwrap();
+    assert_eq!(
+        repo.branch_description("feature1").as_deref(),
+        Some("Add a feature\n")
+    );
+    assert_eq!(repo.branch_description("feature2"), None);
+
+    repo.set_branch_description("feature1", None).unwrap();
+    assert_eq!(repo.branch_description("feature1"), None);
+    // Forgetting a missing description is fine
+    repo.set_branch_description("feature1", None).unwrap();
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn rerun_keeps_ids() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+    let todo = "reset master\npick feature1\nbranch feature1\nlabel feature1\n\n\
+                reset feature1\npick feature2~2\npick feature2~1\npick feature2\nbranch feature2\n";
+
+    let run = |repo: &mut GitRepo| {
+        let scripts = git_stack::git::parse_todo(todo, repo).unwrap();
+        let mut executor = git_stack::git::Executor::new(repo, false);
+        for script in scripts.iter() {
+            assert_eq!(executor.run_script(repo, script), vec![]);
+        }
+        executor.close(repo, "feature2").unwrap();
+        (
+            repo.find_local_branch("feature1").unwrap().id,
+            repo.find_local_branch("feature2").unwrap().id,
+        )
+    };
+    let first = run(&mut repo);
+    let second = run(&mut repo);
+    assert_eq!(first, second);
+
+    let mut scripts = git_stack::git::parse_todo(todo, &repo).unwrap();
+    let mut up_to_date = Vec::new();
+    for script in scripts.iter_mut() {
+        up_to_date.extend(script.prune_up_to_date(&repo));
+    }
+    up_to_date.sort();
+    assert_eq!(up_to_date, ["feature1", "feature2"]);
+    assert!(scripts.iter().all(|s| s.is_empty()));
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn rebase_merges_with_restacked_parent() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+    let base = repo.find_local_branch("base").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+
+    // `side` and `feature1` are both based on `base`, with `side` merged into `feature1`
+    let (side_id, merge_id) = {
+        let raw = repo.raw();
+        let signature = raw.signature().unwrap();
+        let base_commit = raw.find_commit(base.id).unwrap();
+        let feature1_commit = raw.find_commit(feature1.id).unwrap();
+        let blob_id = raw.blob(b"1").unwrap();
+        let mut builder = raw.treebuilder(Some(&base_commit.tree().unwrap())).unwrap();
+        builder.insert("file_d.txt", blob_id, 0o100644).unwrap();
+        let tree = raw.find_tree(builder.write().unwrap()).unwrap();
+        let side_id = raw
+            .commit(None, &signature, &signature, "11", &tree, &[&base_commit])
+            .unwrap();
+        let side_commit = raw.find_commit(side_id).unwrap();
+        let mut builder = raw
+            .treebuilder(Some(&feature1_commit.tree().unwrap()))
+            .unwrap();
+        builder.insert("file_d.txt", blob_id, 0o100644).unwrap();
+        let tree = raw.find_tree(builder.write().unwrap()).unwrap();
+        let merge_id = raw
+            .commit(
+                None,
+                &signature,
+                &signature,
+                "Merge side",
+                &tree,
+                &[&feature1_commit, &side_commit],
+            )
+            .unwrap();
+        (side_id, merge_id)
+    };
+    repo.branch("side", side_id, "test").unwrap();
+    repo.branch("merged", merge_id, "test").unwrap();
+
+    let script = Script {
+        commands: vec![
+            Command::SwitchCommit(master.id),
+            Command::CherryPick(side_id),
+            Command::CreateBranch("side".to_owned()),
+            Command::SwitchCommit(master.id),
+            Command::CherryPick(feature1.id),
+            Command::CherryPick(merge_id),
+            Command::CreateBranch("merged".to_owned()),
+        ],
+        dependents: Vec::new(),
+    };
+    let mut executor = Executor::new(&repo, false);
+    executor.rebase_merges(true);
+    assert_eq!(executor.run_script(&mut repo, &script), vec![]);
+    executor.close(&mut repo, "master").unwrap();
+
+    let new_side = repo.find_local_branch("side").unwrap();
+    let new_merged = repo.find_local_branch("merged").unwrap();
+    assert_ne!(new_side.id, side_id);
+    let raw = repo.raw();
+    let new_merge = raw.find_commit(new_merged.id).unwrap();
+    assert_eq!(new_merge.summary(), Some("Merge side"));
+    assert_eq!(new_merge.parent_count(), 2);
+    let new_feature1 = new_merge.parent(0).unwrap();
+    assert_eq!(new_feature1.summary(), Some("7"));
+    assert_eq!(new_feature1.parent_id(0).unwrap(), master.id);
+    assert_eq!(new_merge.parent_id(1).unwrap(), new_side.id);
+    let tree = new_merge.tree().unwrap();
+    for (path, content) in [
+        ("file_b.txt", "2"),
+        ("file_c.txt", "1"),
+        ("file_d.txt", "1"),
+    ] {
+        let blob = raw.find_blob(tree.get_name(path).unwrap().id()).unwrap();
+        assert_eq!(blob.content(), content.as_bytes(), "{}", path);
+    }
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn remerge_unchanged_side_of_rewritten_history() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+    let master = repo.find_local_branch("master").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+
+    // `side` edits what `feature1` added, and is merged into a commit on top of `feature1`
+    let (ours_id, side_id, merge_id) = {
+        let raw = repo.raw();
+        let signature = raw.signature().unwrap();
+        let feature1_commit = raw.find_commit(feature1.id).unwrap();
+        let feature1_tree = feature1_commit.tree().unwrap();
+        let commit = |path: &str, content: &[u8], tree: &git2::Tree<'_>| {
+            let mut builder = raw.treebuilder(Some(tree)).unwrap();
+            builder
+                .insert(path, raw.blob(content).unwrap(), 0o100644)
+                .unwrap();
+            raw.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let side_tree = commit("file_c.txt", b"side", &feature1_tree);
+        let side_id = raw
+            .commit(
+                None,
+                &signature,
+                &signature,
+                "side",
+                &side_tree,
+                &[&feature1_commit],
+            )
+            .unwrap();
+        let ours_tree = commit("file_d.txt", b"1", &feature1_tree);
+        let ours_id = raw
+            .commit(
+                None,
+                &signature,
+                &signature,
+                "ours",
+                &ours_tree,
+                &[&feature1_commit],
+            )
+            .unwrap();
+        let merge_tree = commit("file_c.txt", b"side", &ours_tree);
+        let merge_id = raw
+            .commit(
+                None,
+                &signature,
+                &signature,
+                "Merge side",
+                &merge_tree,
+                &[
+                    &raw.find_commit(ours_id).unwrap(),
+                    &raw.find_commit(side_id).unwrap(),
+                ],
+            )
+            .unwrap();
+        (ours_id, side_id, merge_id)
+    };
+
+    // Restack everything but `side` onto `master`
+    let new_feature1_id = repo.cherry_pick(master.id, feature1.id).unwrap();
+    let new_ours_id = repo.cherry_pick(new_feature1_id, ours_id).unwrap();
+    let new_id = repo.remerge(new_ours_id, merge_id, &[side_id]).unwrap();
+
+    let raw = repo.raw();
+    let new_commit = raw.find_commit(new_id).unwrap();
+    assert_eq!(
+        new_commit.parent_ids().collect::<Vec<_>>(),
+        [new_ours_id, side_id]
+    );
+    let tree = new_commit.tree().unwrap();
+    for (path, content) in [
+        ("file_b.txt", "2"),
+        ("file_c.txt", "side"),
+        ("file_d.txt", "1"),
+    ] {
+        let blob = raw.find_blob(tree.get_name(path).unwrap().id()).unwrap();
+        assert_eq!(blob.content(), content.as_bytes(), "{}", path);
+    }
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn remerge_octopus() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+    let base = repo.find_local_branch("base").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+
+    let (side_id, merge_id) = {
+        let raw = repo.raw();
+        let signature = raw.signature().unwrap();
+        let base_commit = raw.find_commit(base.id).unwrap();
+        let master_commit = raw.find_commit(master.id).unwrap();
+        let blob_id = raw.blob(b"1").unwrap();
+        let mut builder = raw.treebuilder(Some(&base_commit.tree().unwrap())).unwrap();
+        builder.insert("file_d.txt", blob_id, 0o100644).unwrap();
+        let tree = raw.find_tree(builder.write().unwrap()).unwrap();
+        let side_id = raw
+            .commit(None, &signature, &signature, "11", &tree, &[&base_commit])
+            .unwrap();
+        let side_commit = raw.find_commit(side_id).unwrap();
+        let merge_id = raw
+            .commit(
+                None,
+                &signature,
+                &signature,
+                "Merge master and side",
+                &base_commit.tree().unwrap(),
+                &[&base_commit, &master_commit, &side_commit],
+            )
+            .unwrap();
+        (side_id, merge_id)
+    };
+
+    let new_id = repo
+        .remerge(feature1.id, merge_id, &[master.id, side_id])
+        .unwrap();
+    assert_ne!(new_id, merge_id);
+
+    let raw = repo.raw();
+    let new_commit = raw.find_commit(new_id).unwrap();
+    assert_eq!(
+        new_commit.parent_ids().collect::<Vec<_>>(),
+        [feature1.id, master.id, side_id]
+    );
+    assert_eq!(new_commit.summary(), Some("Merge master and side"));
+    let tree = new_commit.tree().unwrap();
+    for (path, content) in [
+        ("file_a.txt", "3"),
+        ("file_b.txt", "2"),
+        ("file_c.txt", "1"),
+        ("file_d.txt", "1"),
+    ] {
+        let blob = raw.find_blob(tree.get_name(path).unwrap().id()).unwrap();
+        assert_eq!(blob.content(), content.as_bytes(), "{}", path);
+    }
+
+    temp.close().unwrap();
+}
@@ -0,0 +1,55 @@
+mod fixture;
+
+fn git(dir: &std::path::Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {}", args.join(" "));
+}
+
+fn git_stack(dir: &std::path::Path, args: &[&str]) -> std::process::Output {
+    std::process::Command::new(env!("CARGO_BIN_EXE_git-stack"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn drop_reuses_recorded_resolution() {
+    let (temp, repo) = fixture::init_repo("rerere.yml");
+    let path = temp.path();
+    git(path, &["config", "rerere.enabled", "true"]);
+    git(path, &["config", "rerere.autoupdate", "true"]);
+    git(path, &["checkout", "-q", "feature1"]);
+
+    // Dropping "2" leaves "3" conflicting with "1"
+    let output = git_stack(path, &["drop", "HEAD~"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Could not apply"), "{}", stderr);
+    assert!(repo.index().unwrap().has_conflicts());
+    std::fs::write(path.join("file_a.txt"), "3\n").unwrap();
+    git(path, &["add", "file_a.txt"]);
+    let output = git_stack(path, &["--continue"]);
+    assert!(output.status.success(), "{:?}", output);
+
+    let output = git_stack(path, &["undo", "--yes"]);
+    assert!(output.status.success(), "{:?}", output);
+
+    let output = git_stack(path, &["drop", "HEAD~"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Resolved by rerere: file_a.txt"),
+        "{}",
+        stderr
+    );
+    let mut index = repo.index().unwrap();
+    index.read(true).unwrap();
+    assert!(!index.has_conflicts());
+    assert_eq!(
+        std::fs::read_to_string(path.join("file_a.txt")).unwrap(),
+        "3\n"
+    );
+}
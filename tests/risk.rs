@@ -0,0 +1,239 @@
+mod fixture;
+
+use git_stack::config::RiskGate;
+use git_stack::git::*;
+
+fn script(commands: Vec<Command>) -> Script {
+    Script {
+        commands,
+        dependents: Vec::new(),
+    }
+}
+
+fn change(branch: &str, description: &str, risk: Risk) -> BranchChange {
+    BranchChange {
+        branch: branch.to_owned(),
+        description: description.to_owned(),
+        risk,
+    }
+}
+
+#[test]
+fn restack_without_overlap_preserves_content() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let repo = GitRepo::new(repo);
+    let base = repo.find_local_branch("base").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+
+    let scripts = vec![script(vec![
+        Command::SwitchCommit(master.id),
+        Command::CherryPick(feature1.id),
+        Command::CreateBranch("feature1".to_owned()),
+    ])];
+    let changes = PlannedChanges::new(&scripts).assess(&repo, base.id, &master, &[&feature1]);
+    assert_eq!(
+        changes,
+        vec![change("feature1", "preserves content", Risk::Low)]
+    );
+}
+
+#[test]
+fn restack_with_conflict_is_medium_risk() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let mut repo = GitRepo::new(repo);
+    let base = repo.find_local_branch("base").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    let conflict_id = fixture::commit(repo.raw(), &[base.id], "file_b.txt", "conflict");
+    repo.branch("conflict", conflict_id, "test").unwrap();
+    let conflict = repo.find_local_branch("conflict").unwrap();
+
+    let scripts = vec![script(vec![
+        Command::SwitchCommit(master.id),
+        Command::CherryPick(conflict_id),
+        Command::CreateBranch("conflict".to_owned()),
+    ])];
+    let changes = PlannedChanges::new(&scripts).assess(&repo, base.id, &master, &[&conflict]);
+    assert_eq!(
+        changes,
+        vec![change("conflict", "conflicts in file_b.txt", Risk::Medium)]
+    );
+}
+
+#[test]
+fn restack_with_overlap_may_change_content() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let mut repo = GitRepo::new(repo);
+    let base = repo.find_local_branch("base").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    // The same change as on `master`, so it applies cleanly
+    let overlap_id = fixture::commit(repo.raw(), &[base.id], "file_b.txt", "2");
+    repo.branch("overlap", overlap_id, "test").unwrap();
+    let overlap = repo.find_local_branch("overlap").unwrap();
+
+    let scripts = vec![script(vec![
+        Command::SwitchCommit(master.id),
+        Command::CherryPick(overlap_id),
+        Command::CreateBranch("overlap".to_owned()),
+    ])];
+    let changes = PlannedChanges::new(&scripts).assess(&repo, base.id, &master, &[&overlap]);
+    assert_eq!(
+        changes,
+        vec![change(
+            "overlap",
+            "may change content, file_b.txt also changed on `master`",
+            Risk::Medium
+        )]
+    );
+}
+
+#[test]
+fn fast_forward_is_low_risk() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let mut repo = GitRepo::new(repo);
+    let base = repo.find_local_branch("base").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    repo.branch("empty", base.id, "test").unwrap();
+    let empty = repo.find_local_branch("empty").unwrap();
+
+    let scripts = vec![script(vec![
+        Command::SwitchCommit(master.id),
+        Command::CreateBranch("empty".to_owned()),
+    ])];
+    let changes = PlannedChanges::new(&scripts).assess(&repo, base.id, &master, &[&empty]);
+    assert_eq!(changes, vec![change("empty", "fast-forwards", Risk::Low)]);
+}
+
+#[test]
+fn dropping_commits_is_medium_risk() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let repo = GitRepo::new(repo);
+    let base = repo.find_local_branch("base").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    let feature2 = repo.find_local_branch("feature2").unwrap();
+    let feature2_commits: Vec<_> = repo
+        .commits_from(feature2.id)
+        .take(4)
+        .map(|c| c.id)
+        .collect();
+
+    // Dropping `feature1`'s commit, and deduplicating one against a patch-identical copy
+    let original = repo.raw().find_commit(feature2_commits[1]).unwrap();
+    let copy_id = {
+        let raw = repo.raw();
+        let signature = git2::Signature::now("Copy", "copy@example.com").unwrap();
+        raw.commit(
+            None,
+            &signature,
+            &signature,
+            "copy",
+            &original.tree().unwrap(),
+            &[&original.parent(0).unwrap()],
+        )
+        .unwrap()
+    };
+    let scripts = vec![script(vec![
+        Command::SwitchCommit(master.id),
+        Command::CherryPick(copy_id),
+        Command::CherryPick(feature2_commits[2]),
+        Command::CherryPick(feature2_commits[0]),
+        Command::CreateBranch("feature2".to_owned()),
+    ])];
+    let changes = PlannedChanges::new(&scripts).assess(&repo, base.id, &master, &[&feature2]);
+    assert_eq!(
+        changes,
+        vec![change(
+            "feature2",
+            "changes content, drops 1 commit(s)",
+            Risk::Medium
+        )]
+    );
+}
+
+#[test]
+fn deleting_is_high_risk() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let repo = GitRepo::new(repo);
+    let base = repo.find_local_branch("base").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+
+    let scripts = vec![script(vec![Command::DeleteBranch("feature1".to_owned())])];
+    let changes = PlannedChanges::new(&scripts).assess(&repo, base.id, &master, &[&feature1]);
+    assert_eq!(changes, vec![change("feature1", "deleted", Risk::High)]);
+}
+
+#[test]
+fn untouched_branches_are_not_rated() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let repo = GitRepo::new(repo);
+    let base = repo.find_local_branch("base").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+
+    let changes = PlannedChanges::new(&[]).assess(&repo, base.id, &master, &[&feature1]);
+    assert_eq!(changes, vec![]);
+}
+
+#[test]
+fn force_push_over_remote_commits_is_high_risk() {
+    let (_temp, repo) = fixture::init_repo("branches.yml");
+    let repo = GitRepo::new(repo);
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let feature2 = repo.find_local_branch("feature2").unwrap();
+
+    // Behind the remote, pushing discards the remote's commits
+    let behind = Branch {
+        push_id: Some(feature2.id),
+        ..feature1.clone()
+    };
+    assert_eq!(
+        assess_push(&repo, &behind),
+        Some(change(
+            "feature1",
+            "discards 3 commit(s) only on the remote",
+            Risk::High
+        ))
+    );
+
+    // Ahead of the remote, pushing only adds to it
+    let ahead = Branch {
+        push_id: Some(feature1.id),
+        ..feature2
+    };
+    assert_eq!(assess_push(&repo, &ahead), None);
+}
+
+#[test]
+fn gate_tiers() {
+    let low = change("low", "preserves content", Risk::Low);
+    let medium = change("medium", "changes content, drops 1 commit(s)", Risk::Medium);
+    let high = change("high", "deleted", Risk::High);
+    let only_low = [low.clone()];
+    let up_to_medium = [low, medium.clone()];
+    let only_medium = [medium.clone()];
+    let up_to_high = [medium, high];
+
+    assert_eq!(Gate::for_changes(RiskGate::Off, &up_to_high), Gate::Apply);
+
+    assert_eq!(Gate::for_changes(RiskGate::Prompt, &[]), Gate::Apply);
+    assert_eq!(Gate::for_changes(RiskGate::Prompt, &only_low), Gate::Apply);
+    assert_eq!(
+        Gate::for_changes(RiskGate::Prompt, &up_to_medium),
+        Gate::Ask(Risk::Medium)
+    );
+    assert_eq!(
+        Gate::for_changes(RiskGate::Prompt, &up_to_high),
+        Gate::Ask(Risk::High)
+    );
+
+    assert_eq!(Gate::for_changes(RiskGate::Strict, &only_low), Gate::Apply);
+    assert_eq!(
+        Gate::for_changes(RiskGate::Strict, &only_medium),
+        Gate::Ask(Risk::Medium)
+    );
+    assert_eq!(
+        Gate::for_changes(RiskGate::Strict, &up_to_high),
+        Gate::Refuse
+    );
+}
@@ -0,0 +1,166 @@
+mod fixture;
+
+fn rebase_script(repo: &git_stack::git::InMemoryRepo) -> git_stack::git::Script {
+    let master_branch = repo.find_local_branch("master").unwrap();
+
+    let mut protected_branches = git_stack::git::Branches::default();
+    protected_branches.insert(master_branch.clone());
+
+    let mut graph_branches = git_stack::git::Branches::default();
+    graph_branches.insert(master_branch.clone());
+    graph_branches.insert(repo.find_local_branch("feature1").unwrap());
+    graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+    let mut root = git_stack::graph::Node::from_branches(repo, graph_branches).unwrap();
+    git_stack::graph::protect_branches(&mut root, repo, &protected_branches);
+    git_stack::graph::rebase_branches(&mut root, master_branch.id);
+    git_stack::graph::to_script(&root)
+}
+
+#[test]
+fn round_trip() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let script = rebase_script(&repo);
+    let todo = git_stack::git::to_todo(std::slice::from_ref(&script), &repo);
+    let parsed = git_stack::git::parse_todo(&todo, &repo).unwrap();
+    assert_eq!(parsed, vec![script]);
+}
+
+#[test]
+fn errors_point_at_columns() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let todo = "\
+# A comment
+
+reset master
+  pik feature1
+pick
+pick bogus # Summary
+branch feature..2
+";
+    let errors = git_stack::git::parse_todo(todo, &repo).unwrap_err();
+    let errors: Vec<_> = errors
+        .iter()
+        .map(|e| (e.line, e.column, e.message.as_str()))
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            (4, 3, "unknown command `pik`"),
+            (5, 5, "`pick` is missing an argument"),
+            (6, 6, "unknown commit `bogus`"),
+            (7, 8, "invalid branch name `feature..2`"),
+        ]
+    );
+}
+
+#[test]
+fn annotations_are_replaced() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let todo = "reset master\npik feature1\n";
+    let errors = git_stack::git::parse_todo(todo, &repo).unwrap_err();
+    let annotated = git_stack::git::annotate_todo(todo, &errors);
+    assert_eq!(
+        annotated,
+        "reset master\npik feature1\n# error: column 1: unknown command `pik`\n"
+    );
+
+    let fixed = annotated.replace("pik", "pick");
+    let parsed = git_stack::git::parse_todo(&fixed, &repo).unwrap();
+    assert_eq!(parsed.len(), 1);
+    let reannotated = git_stack::git::annotate_todo(&fixed, &[]);
+    assert_eq!(reannotated, "reset master\npick feature1\n");
+}
+
+#[test]
+fn edit_stops_and_resumes() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let todo = "reset master\nedit feature1\nbranch feature1\n";
+    let scripts = git_stack::git::parse_todo(todo, &repo).unwrap();
+
+    let mut executor = git_stack::git::Executor::new(&repo, false);
+    for script in scripts.iter() {
+        assert_eq!(executor.run_script(&mut repo, script), vec![]);
+    }
+    let continuation = executor.continuation().unwrap();
+    let stopped_id = repo.head_commit().id;
+    assert_ne!(stopped_id, feature1.id);
+    assert_eq!(repo.find_local_branch("feature1").unwrap().id, feature1.id);
+
+    // Round-trip the continuation like `--continue` does
+    let continuation: git_stack::git::Continuation =
+        serde_json::from_str(&serde_json::to_string(&continuation).unwrap()).unwrap();
+    let mut executor = git_stack::git::Executor::resume(&repo, &continuation, false);
+    for script in continuation.scripts() {
+        assert_eq!(executor.run_script(&mut repo, script), vec![]);
+    }
+    assert!(!executor.is_stopped());
+    executor.close(&mut repo, "feature1").unwrap();
+    assert_eq!(repo.find_local_branch("feature1").unwrap().id, stopped_id);
+}
+
+#[test]
+fn exec_round_trips() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let mut script = rebase_script(&repo);
+    script.exec_after_branches("cargo check # quietly");
+    let mut execs = 0;
+    script.walk(&mut |command| {
+        if let git_stack::git::Command::Exec(exec) = command {
+            assert_eq!(exec, "cargo check # quietly");
+            execs += 1;
+        }
+    });
+    assert_eq!(execs, 2);
+
+    let todo = git_stack::git::to_todo(std::slice::from_ref(&script), &repo);
+    let parsed = git_stack::git::parse_todo(&todo, &repo).unwrap();
+    assert_eq!(parsed, vec![script]);
+}
+
+#[test]
+fn prune_up_to_date() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let mut script = rebase_script(&repo);
+    let expected = script.clone();
+    assert_eq!(script.prune_up_to_date(&repo), Vec::<String>::new());
+    assert_eq!(script, expected);
+
+    // Already on top of `base`
+    let base_branch = repo.find_local_branch("base").unwrap();
+    let mut protected_branches = git_stack::git::Branches::default();
+    protected_branches.insert(base_branch.clone());
+    let mut graph_branches = git_stack::git::Branches::default();
+    graph_branches.insert(base_branch.clone());
+    graph_branches.insert(repo.find_local_branch("feature1").unwrap());
+    graph_branches.insert(repo.find_local_branch("feature2").unwrap());
+    let mut root = git_stack::graph::Node::from_branches(&repo, graph_branches).unwrap();
+    git_stack::graph::protect_branches(&mut root, &repo, &protected_branches);
+    git_stack::graph::rebase_branches(&mut root, base_branch.id);
+    let mut script = git_stack::graph::to_script(&root);
+    assert!(!script.is_empty());
+
+    let mut up_to_date = script.prune_up_to_date(&repo);
+    up_to_date.sort();
+    assert_eq!(up_to_date, ["feature1", "feature2"]);
+    assert!(script.is_empty());
+}